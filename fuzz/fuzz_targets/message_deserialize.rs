@@ -0,0 +1,16 @@
+//! Feeds arbitrary bytes to the postcard decoder of every network message.
+//!
+//! Decoding must either succeed or return a clean error, a panic or a stack
+//! overflow is a finding. Run it with `cargo fuzz run message_deserialize`.
+
+#![no_main]
+
+use dake::network::{AckMessage, DaemonMessage, FetcherMessage, Message, ProcessMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = postcard::from_bytes::<Message<DaemonMessage>>(data);
+    let _ = postcard::from_bytes::<Message<ProcessMessage>>(data);
+    let _ = postcard::from_bytes::<Message<AckMessage>>(data);
+    let _ = postcard::from_bytes::<Message<FetcherMessage>>(data);
+});
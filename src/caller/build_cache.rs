@@ -0,0 +1,149 @@
+//! # Build Cache
+//!
+//! Remembers the exit code of the builds run by the caller, so an unchanged build
+//! can be answered without contacting the daemon.
+//!
+//! Entries are keyed on the hash of the project directory, of the Makefile and of the
+//! make arguments, and persisted as JSON in `<dake_path>/cache/results.json`. An entry is only reused if it
+//! is younger than the configured ttl and no file of the build directory changed since.
+
+use std::{
+    collections::HashMap,
+    fs::{self, read_dir, read_to_string},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::daemon::fs::get_cache_dir;
+
+const RESULTS_NAME: &str = "results.json";
+
+/// Cached outcome of a build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    created_at: SystemTime,
+    exit_code: i32,
+}
+
+type Results = HashMap<String, CacheEntry>;
+
+fn path() -> Result<PathBuf> {
+    Ok(get_cache_dir()?.join(RESULTS_NAME))
+}
+
+fn load() -> Result<Results> {
+    let path = path()?;
+    if !path.exists() {
+        return Ok(Results::new());
+    }
+    let data =
+        read_to_string(&path).context(format!("Failed to read build cache at {:?}", path))?;
+    serde_json::from_str(&data).context("Failed to parse the build cache.")
+}
+
+fn save(results: &Results) -> Result<()> {
+    let path = path()?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(results)?)
+        .context("Failed to write build cache temp file")?;
+    fs::rename(tmp, path).context("Failed to atomically replace build cache file")
+}
+
+/// Computes the cache key of a build from its project directory, Makefile content and
+/// make arguments.
+///
+/// The directory is part of the key, two projects with the same Makefile are different
+/// builds. So is the order of the arguments, `make clean all` is not `make all clean`.
+pub fn cache_key(project_dir: &Path, makefile: &Path, args: &[String]) -> Result<String> {
+    let project_dir = project_dir
+        .canonicalize()
+        .context(format!("Failed to resolve {project_dir:?}"))?;
+    let content = fs::read(makefile).context(format!("Failed to read {makefile:?}"))?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(project_dir.as_os_str().as_encoded_bytes());
+    hasher.update(&[0]);
+    hasher.update(&content);
+    for arg in args {
+        hasher.update(arg.as_bytes());
+        hasher.update(&[0]);
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Returns whether a file of `dir`, searched recursively, was modified after `time`.
+fn modified_since(dir: &Path, time: SystemTime) -> Result<bool> {
+    for entry in read_dir(dir).context(format!("Failed to read {dir:?}"))? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            if modified_since(&entry.path(), time)? {
+                return Ok(true);
+            }
+        } else if metadata.modified()? > time {
+            info!("{:?} changed since the cached build", entry.path());
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the cached exit code of the build `key`, if it is still valid.
+pub fn lookup(key: &str, ttl: Duration, build_dir: &Path) -> Result<Option<i32>> {
+    let Some(entry) = load()?.remove(key) else {
+        info!("No cached result for this build");
+        return Ok(None);
+    };
+
+    let age = entry.created_at.elapsed().unwrap_or(Duration::MAX);
+    if age > ttl {
+        info!("The cached result is outdated ({age:?} old)");
+        return Ok(None);
+    }
+    if modified_since(build_dir, entry.created_at)? {
+        return Ok(None);
+    }
+    Ok(Some(entry.exit_code))
+}
+
+/// Records the exit code of the build `key`, dropping the entries older than `ttl`.
+pub fn store(key: String, exit_code: i32, ttl: Duration) -> Result<()> {
+    let mut results = load()?;
+    results.retain(|_, entry| entry.created_at.elapsed().is_ok_and(|age| age <= ttl));
+    results.insert(
+        key,
+        CacheEntry {
+            created_at: SystemTime::now(),
+            exit_code,
+        },
+    );
+    save(&results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cache_key;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn keys_depend_on_the_project_and_the_argument_order() {
+        let projects = [tempdir().unwrap(), tempdir().unwrap()];
+        for project in &projects {
+            write(project.path().join("Makefile"), "all:\n\ttrue\n").unwrap();
+        }
+        let key = |project: usize, args: &[&str]| {
+            let dir = projects[project].path();
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            cache_key(dir, &dir.join("Makefile"), &args).unwrap()
+        };
+
+        assert_eq!(key(0, &["clean", "all"]), key(0, &["clean", "all"]));
+        assert_ne!(key(0, &["clean", "all"]), key(1, &["clean", "all"]));
+        assert_ne!(key(0, &["clean", "all"]), key(0, &["all", "clean"]));
+    }
+}
@@ -0,0 +1,52 @@
+use anyhow::{Result, bail};
+use tracing::info;
+
+use crate::{
+    constants::{CANCEL_ALL_TIMEOUT, CANCEL_TIMEOUT},
+    network::{
+        AckMessage, DaemonMessage, Message, MessageKind, get_daemon_unix_sock,
+        send_message_and_await_response,
+    },
+    process_id::ProcessId,
+};
+
+/// Asks the local daemon to cancel `pid` and waits for its acknowledgment.
+///
+/// A fresh connection is used, the build stream is busy with the running process.
+pub async fn cancel_process(pid: ProcessId) -> Result<()> {
+    let msg = Message::new(DaemonMessage::CancelProcess { pid: pid.clone() }, pid);
+    info!("Sending the cancellation request to the daemon.");
+
+    let msg: Message<AckMessage> = send_message_and_await_response(
+        msg,
+        get_daemon_unix_sock()?,
+        MessageKind::AckMessage,
+        CANCEL_TIMEOUT,
+    )
+    .await?;
+    match msg.inner {
+        AckMessage::Ok => Ok(()),
+        ack => bail!("The daemon refused the cancellation: {ack:?}"),
+    }
+}
+
+/// Asks the local daemon to cancel every build in progress, see [`State::kill_all`], and
+/// waits for its acknowledgment.
+///
+/// [`State::kill_all`]: crate::daemon::State::kill_all
+pub async fn cancel_all(reason: String) -> Result<()> {
+    let msg = Message::new(DaemonMessage::CancelAll { reason }, ProcessId::default());
+    info!("Sending the cancellation of every build to the daemon.");
+
+    let msg: Message<AckMessage> = send_message_and_await_response(
+        msg,
+        get_daemon_unix_sock()?,
+        MessageKind::AckMessage,
+        CANCEL_ALL_TIMEOUT,
+    )
+    .await?;
+    match msg.inner {
+        AckMessage::Ok => Ok(()),
+        ack => bail!("The daemon failed to cancel every build: {ack:?}"),
+    }
+}
@@ -0,0 +1,217 @@
+//! # Build Events
+//!
+//! Machine-readable events of a build, for the tools driving dake (CI, IDEs).
+//!
+//! With `--events-fd N`, the caller writes one JSON object per line to the file
+//! descriptor `N`, such as `{"type":"end","exit_code":0,"duration_ms":1234}`.
+//! Failing to write an event never fails the build, it is only logged.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    mem::take,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    network::{OutputKind, SocketAddr},
+    process_id::ProcessId,
+};
+
+/// An event of the build, serialized with its kind in the `type` field.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BuildEvent<'a> {
+    Start {
+        pid: String,
+        targets: &'a [String],
+    },
+    /// A line of the output of one of the make processes.
+    Log {
+        stream: &'static str,
+        line: &'a str,
+    },
+    /// A host is done with the build.
+    NodeDone {
+        node: String,
+        exit_code: i32,
+    },
+    End {
+        exit_code: i32,
+        duration_ms: u64,
+    },
+}
+
+/// Names of the output streams in the [`BuildEvent::Log`]s, by index in `partial_lines`.
+const STREAMS: [&str; 2] = ["stdout", "stderr"];
+
+/// Writes the [`BuildEvent`]s of a build as newline-delimited JSON.
+pub struct EventWriter {
+    file: File,
+    started: Instant,
+    /// The unterminated last line of each output stream, completed by the next logs.
+    partial_lines: [String; 2],
+}
+
+impl EventWriter {
+    /// Opens the file descriptor `fd`, inherited from the process running dake.
+    ///
+    /// # Errors
+    /// Fails if `fd` is not open for writing.
+    pub fn from_fd(fd: u32) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(format!("/dev/fd/{fd}"))
+            .context(format!("The file descriptor {fd} is not open for writing."))?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+            partial_lines: Default::default(),
+        })
+    }
+
+    fn write(&mut self, event: BuildEvent) {
+        let mut line = match serde_json::to_vec(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize a build event: {e}");
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(e) = self.file.write_all(&line) {
+            warn!("Failed to write a build event: {e}");
+        }
+    }
+
+    /// Emits the start of the build of `targets`, the duration of the build starts now.
+    pub fn start(&mut self, pid: &ProcessId, targets: &[String]) {
+        self.started = Instant::now();
+        self.write(BuildEvent::Start {
+            pid: pid.to_string(),
+            targets,
+        });
+    }
+
+    /// Emits one event per line completed by `log`, progress reports are not logs.
+    ///
+    /// Logs are chunks of output cut anywhere, the end of a line may come in the next
+    /// one. The unterminated lines are emitted at the end of the build.
+    pub fn log(&mut self, kind: &OutputKind, log: &str) {
+        let index = match kind {
+            OutputKind::Stdout => 0,
+            OutputKind::Stderr => 1,
+            OutputKind::Progress { .. } => return,
+        };
+        let mut pending = take(&mut self.partial_lines[index]);
+        pending.push_str(log);
+
+        let mut rest = pending.as_str();
+        while let Some((line, next)) = rest.split_once('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            self.write(BuildEvent::Log {
+                stream: STREAMS[index],
+                line,
+            });
+            rest = next;
+        }
+        self.partial_lines[index] = rest.to_string();
+    }
+
+    pub fn node_done(&mut self, node: &SocketAddr, exit_code: i32) {
+        self.write(BuildEvent::NodeDone {
+            node: node.to_string(),
+            exit_code,
+        });
+    }
+
+    pub fn end(&mut self, exit_code: i32) {
+        for (stream, index) in STREAMS.into_iter().zip(0..) {
+            let line = take(&mut self.partial_lines[index]);
+            if !line.is_empty() {
+                self.write(BuildEvent::Log {
+                    stream,
+                    line: &line,
+                });
+            }
+        }
+        let duration_ms = self.started.elapsed().as_millis() as u64;
+        self.write(BuildEvent::End {
+            exit_code,
+            duration_ms,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventWriter;
+    use crate::{network::OutputKind, process_id::ProcessId};
+    use std::{fs::read_to_string, os::fd::AsRawFd};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn events_are_json_lines() {
+        let output = NamedTempFile::new().unwrap();
+        let fd = output.as_file().as_raw_fd() as u32;
+        let mut events = EventWriter::from_fd(fd).unwrap();
+
+        let pid = ProcessId::test_local(1);
+        events.start(&pid, &["all".to_string()]);
+        events.log(&OutputKind::Stderr, "first\nsecond\n");
+        events.node_done(&"127.0.0.1:1808".parse().unwrap(), 2);
+        events.end(2);
+
+        let content = read_to_string(output.path()).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 5, "{content}");
+        assert_eq!(
+            lines[0],
+            format!(r#"{{"type":"start","pid":"{pid}","targets":["all"]}}"#)
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"type":"log","stream":"stderr","line":"first"}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"type":"log","stream":"stderr","line":"second"}"#
+        );
+        assert_eq!(
+            lines[3],
+            r#"{"type":"node_done","node":"127.0.0.1:1808","exit_code":2}"#
+        );
+        assert!(lines[4].starts_with(r#"{"type":"end","exit_code":2,"duration_ms":"#));
+    }
+
+    #[test]
+    fn lines_cut_across_logs_are_joined() {
+        let output = NamedTempFile::new().unwrap();
+        let fd = output.as_file().as_raw_fd() as u32;
+        let mut events = EventWriter::from_fd(fd).unwrap();
+
+        events.log(&OutputKind::Stdout, "fir");
+        events.log(&OutputKind::Stderr, "err");
+        events.log(&OutputKind::Stdout, "st\r\nsec");
+        events.log(&OutputKind::Stdout, "ond\nunterminated");
+        events.end(0);
+
+        let content = read_to_string(output.path()).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(
+            lines[..4],
+            [
+                r#"{"type":"log","stream":"stdout","line":"first"}"#,
+                r#"{"type":"log","stream":"stdout","line":"second"}"#,
+                r#"{"type":"log","stream":"stdout","line":"unterminated"}"#,
+                r#"{"type":"log","stream":"stderr","line":"err"}"#,
+            ],
+            "{content}"
+        );
+        assert!(lines[4].starts_with(r#"{"type":"end","exit_code":0,"#));
+    }
+}
@@ -1,41 +1,28 @@
 use anyhow::{Result, bail};
-use tracing::warn;
 
 use crate::{
-    dec,
+    constants::REQUEST_TIMEOUT,
     network::{
-        DaemonMessage, Message, MessageKind, ProcessMessage, Stream, read_next_message,
-        write_message,
+        DaemonMessage, Message, MessageKind, ProcessMessage, SocketAddr,
+        send_message_and_await_response,
     },
     process_id::{ProcessId, ProjectId},
 };
 
-pub async fn fetch_fresh_id(stream: &mut Stream, pid: ProjectId) -> Result<ProcessId> {
+pub async fn fetch_fresh_id(daemon_sock: SocketAddr, pid: ProjectId) -> Result<ProcessId> {
     let default_pid = ProcessId::process_less(pid);
-    let inner = DaemonMessage::FreshId;
-    let msg = Message::new(inner, default_pid);
+    let msg = Message::new(DaemonMessage::FreshId, default_pid);
 
-    write_message(stream, msg).await?;
+    let msg: Message<ProcessMessage> = send_message_and_await_response(
+        msg,
+        daemon_sock,
+        MessageKind::ProcessMessage,
+        REQUEST_TIMEOUT,
+    )
+    .await?;
 
-    let pid = loop {
-        // Read next message from daemon
-        let msg = match read_next_message(stream, MessageKind::ProcessMessage).await {
-            Ok(Some(msg)) => msg,
-            Ok(None) => {
-                bail!("Caller connection closed naturally; Was waiting for the fresh process id.");
-            }
-            Err(e) => {
-                warn!("Failed to receive a message from the daemon: {e}");
-                continue;
-            }
-        };
-
-        let msg: Message<ProcessMessage> = dec!(msg)?;
-        match msg.inner {
-            ProcessMessage::FreshId => break msg.pid,
-            _ => warn!("Was waiting for a fresh pid, received {msg:?}"),
-        }
-    };
-
-    Ok(pid)
+    match msg.inner {
+        ProcessMessage::FreshId => Ok(msg.pid),
+        _ => bail!("Was waiting for a fresh pid, received {msg:?}"),
+    }
 }
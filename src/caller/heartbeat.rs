@@ -0,0 +1,84 @@
+//! # Heartbeat
+//!
+//! Pings the daemon while a build is running, so a daemon that died mid-build is
+//! noticed even if the build stream stays open.
+
+use std::{env::var, time::Duration};
+
+use anyhow::{Result, bail};
+use tokio::time::interval;
+use tracing::{info, warn};
+
+use crate::{
+    constants::{DEFAULT_HEARTBEAT_INTERVAL, HEARTBEAT_MAX_MISSES, HEARTBEAT_TIMEOUT},
+    env_variables::EnvVariable,
+    network::{
+        DaemonMessage, Message, MessageKind, ProcessMessage, SocketAddr,
+        send_message_and_await_response,
+    },
+    process_id::ProcessId,
+};
+
+/// Returns the time between two pings, from [`EnvVariable::HeartbeatInterval`] if set.
+fn heartbeat_interval() -> Duration {
+    let variable = EnvVariable::HeartbeatInterval;
+    match var(variable.to_string()) {
+        Ok(content) => content
+            .parse()
+            .map(Duration::from_secs)
+            .inspect_err(|e| warn!("Failed to parse the content of {variable}: {e}"))
+            .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL),
+        Err(_) => DEFAULT_HEARTBEAT_INTERVAL,
+    }
+}
+
+/// Pings the daemon at `daemon_sock` at regular intervals, each ping must be
+/// answered within [`HEARTBEAT_TIMEOUT`].
+///
+/// Only returns once [`HEARTBEAT_MAX_MISSES`] pings in a row went unanswered,
+/// with an error telling the daemon is considered dead.
+pub async fn heartbeat(daemon_sock: SocketAddr, pid: ProcessId) -> Result<()> {
+    let mut ticks = interval(heartbeat_interval());
+    // The first tick completes at once, the daemon just answered the build request.
+    ticks.tick().await;
+
+    let mut misses = 0;
+    loop {
+        ticks.tick().await;
+        let ping = Message::new(
+            DaemonMessage::Ping,
+            ProcessId::process_less(pid.project_id.clone()),
+        );
+        let response: Result<Message<ProcessMessage>> = send_message_and_await_response(
+            ping,
+            daemon_sock.clone(),
+            MessageKind::ProcessMessage,
+            HEARTBEAT_TIMEOUT,
+        )
+        .await;
+
+        match response {
+            Ok(Message {
+                inner:
+                    ProcessMessage::Pong {
+                        uptime_secs,
+                        active_processes,
+                    },
+                ..
+            }) => {
+                info!(
+                    "The daemon is alive, up for {uptime_secs}s with {active_processes} processes"
+                );
+                misses = 0;
+                continue;
+            }
+            Ok(msg) => warn!("Unexpected answer to the heartbeat: {msg:?}"),
+            Err(e) => warn!("The daemon did not answer the heartbeat: {e:?}"),
+        }
+
+        misses += 1;
+        if misses == HEARTBEAT_MAX_MISSES {
+            bail!("The daemon missed {misses} heartbeats in a row, it is considered dead.");
+        }
+    }
+}
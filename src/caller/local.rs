@@ -0,0 +1,102 @@
+//! # Local Build
+//!
+//! Runs a build whose targets are all local directly with `make`, without any
+//! daemon: no process id, no distribution and no connection.
+
+use std::{
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    spawn,
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use crate::{caller::events::EventWriter, constants::EXIT_CODE_FAILURE, network::OutputKind};
+
+/// Prints each line of `output` on the terminal, on stderr for [`OutputKind::Stderr`],
+/// and emits it in `events` if set.
+fn forward_lines<R>(
+    output: R,
+    kind: OutputKind,
+    events: Option<Arc<Mutex<EventWriter>>>,
+) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    match kind {
+                        OutputKind::Stderr => eprintln!("{line}"),
+                        _ => println!("{line}"),
+                    }
+                    match events.as_ref().map(|events| events.lock()) {
+                        Some(Ok(mut events)) => events.log(&kind, &format!("{line}\n")),
+                        Some(Err(e)) => warn!("The build events are poisoned: {e}"),
+                        None => {}
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read the output of make: {e}");
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Runs `make` with `args` in the current directory and returns its exit code.
+///
+/// The output of make is streamed to the terminal line by line, and emitted in
+/// `events` if set. The start and the end of the build are left to the caller.
+#[tracing::instrument(skip(events))]
+pub async fn run_local(args: Vec<String>, events: &mut Option<EventWriter>) -> Result<i32> {
+    info!("Running the build in-process, without the daemon");
+
+    let mut process = Command::new("make")
+        .args(&args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn make.")?;
+
+    // Shared by both forwarders, given back once they are over.
+    let shared_events = events.take().map(|events| Arc::new(Mutex::new(events)));
+    let mut handlers = Vec::new();
+    if let Some(stdout) = process.stdout.take() {
+        handlers.push(forward_lines(
+            stdout,
+            OutputKind::Stdout,
+            shared_events.clone(),
+        ));
+    }
+    if let Some(stderr) = process.stderr.take() {
+        handlers.push(forward_lines(
+            stderr,
+            OutputKind::Stderr,
+            shared_events.clone(),
+        ));
+    }
+
+    let exit_status = process.wait().await.context("Failed to wait for make.")?;
+    for handle in handlers {
+        if let Err(e) = handle.await {
+            warn!("One of the output forwarders panicked: {e:?}");
+        }
+    }
+    *events = shared_events
+        .and_then(Arc::into_inner)
+        .and_then(|events| events.into_inner().ok());
+
+    info!("make exited with {exit_status}");
+    Ok(exit_status.code().unwrap_or(EXIT_CODE_FAILURE))
+}
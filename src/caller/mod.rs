@@ -1,5 +1,13 @@
+mod build_cache;
+mod cancel;
+mod events;
 mod fetch_id;
+mod heartbeat;
+mod local;
 mod run;
 mod start;
 
-pub use run::make;
+pub use {
+    cancel::{cancel_all, cancel_process},
+    run::make,
+};
@@ -5,28 +5,149 @@
 //! contacts the daemon, and waits for build completion.
 //!
 //! This module acts as the entrypoint for distributed builds when the user
-//! executes `dake <make-args>`.
+//! executes `dake <make-args>`. Builds without remote targets skip the daemon.
 
-use std::{env::current_dir, fs::write};
+use std::{
+    collections::HashMap,
+    env::{current_dir, var},
+    fs::{remove_file, write},
+    io::ErrorKind,
+    net::SocketAddr,
+    time::Duration,
+};
 
 use crate::{
-    caller::{fetch_id::fetch_fresh_id, start::start},
-    daemon::DaemonId,
+    caller::{
+        build_cache, cancel::cancel_process, events::EventWriter, fetch_id::fetch_fresh_id,
+        heartbeat::heartbeat, local::run_local, start::start,
+    },
+    constants::EXIT_CODE_INTERRUPTED,
+    daemon::{DaemonConfig, DaemonId},
+    env_variables::EnvVariable,
     lexer::guess_path_and_lex,
-    makefile::RemoteMakefileSet,
-    network::{connect_with_daemon_or_start_it, get_daemon_tcp_sock, get_daemon_unix_sock},
-    process_id::ProjectId,
+    makefile::{RemoteMakefile, RemoteMakefileSet},
+    network::{
+        Negotiated, connect_with_daemon_or_start_it, get_daemon_tcp_sock, get_daemon_unix_sock,
+    },
+    process_id::{ProcessId, ProjectId},
+    utils::is_jobserver_flag,
+};
+use anyhow::{Context, Result, bail};
+use tokio::{
+    select,
+    signal::unix::{Signal, SignalKind, signal},
+    spawn,
 };
-use anyhow::{Context, Result};
-use tokio::fs::remove_file;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Variable holding the options of make, passed down to the sub-makes.
+const MAKEFLAGS: &str = "MAKEFLAGS";
 
 /// Name of the temporary makefile generated for the local build.
-const TMP_MAKEFILE_NAME: &'static str = "dake_tmp_makefile";
+const TMP_MAKEFILE_NAME: &str = "dake_tmp_makefile";
+
+/// Returns the `MAKEFLAGS` of the caller, forwarded to every host so the options of
+/// `dake -j8`, set by make when dake runs in a recipe, apply to the remote makes too.
+///
+/// The jobserver options are dropped, the jobserver only exists on this host.
+/// Returns `None` if nothing is left.
+fn forwarded_makeflags() -> Option<String> {
+    let flags = var(MAKEFLAGS).ok()?;
+    let flags: Vec<&str> = flags
+        .split_whitespace()
+        .filter(|word| !is_jobserver_flag(word))
+        .collect();
+    (!flags.is_empty()).then(|| flags.join(" "))
+}
+
+/// Prints the makefile of every host of `makefiles`, starting with the one of the
+/// caller host `sock`, written as [`TMP_MAKEFILE_NAME`].
+fn print_dry_run(makefiles: &RemoteMakefileSet, sock: SocketAddr) {
+    println!("# {sock} ({TMP_MAKEFILE_NAME})");
+    println!("{}", makefiles.my_makefile());
+    for makefile in makefiles.remote_makefiles() {
+        println!("# {}", makefile.sock());
+        println!("{}", makefile.makefile());
+    }
+}
+
+/// Removes the temporary makefile when dropped, however the build ends.
+///
+/// The build result is known by then, failing to remove the file is only logged.
+struct RemoveOnDrop;
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        match remove_file(TMP_MAKEFILE_NAME) {
+            Ok(()) => info!("Temporary makefile `{TMP_MAKEFILE_NAME}` removed"),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to remove the temporary makefile `{TMP_MAKEFILE_NAME}`: {e}"),
+        }
+    }
+}
+
+/// Writes the makefile of the caller host and points the make arguments to it.
+///
+/// The file is removed once the returned guard is dropped.
+fn write_tmp_makefile(makefile: &str, args: &mut Vec<String>) -> Result<RemoveOnDrop> {
+    // Created first, a partially written file is removed too.
+    let guard = RemoveOnDrop;
+    write(TMP_MAKEFILE_NAME, makefile).context("Failed to write temporary dake makefile")?;
+    info!("Temporary makefile `{}` written", TMP_MAKEFILE_NAME);
+
+    args.append(&mut vec![
+        String::from("--file"),
+        String::from(TMP_MAKEFILE_NAME),
+    ]);
+    info!("Arguments for make prepared: {:?}", args);
+    Ok(guard)
+}
+
+/// Returns the words of the make arguments that are neither options nor variables.
+fn make_targets(args: &[String]) -> Vec<String> {
+    args.iter()
+        .filter(|arg| !arg.starts_with('-') && !arg.contains('='))
+        .cloned()
+        .collect()
+}
+
+/// Waits for the caller to receive SIGINT or SIGTERM and returns the name of the signal.
+async fn interrupted(interrupt: &mut Signal, terminate: &mut Signal) -> &'static str {
+    select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
 
 /// Initiates a distributed build request.
+///
+/// If the build cache is enabled and `no_cache` is not set, an unchanged build
+/// returns its cached exit code without contacting the daemon.
+///
+/// A build whose targets are all local runs `make` directly, without the daemon,
+/// unless `DAKE_NO_DAEMON` is `0`. `no_daemon` forces this mode and fails if a
+/// target is remote.
+///
+/// With `dry_run`, the makefiles of every host are printed instead, see
+/// [`print_dry_run`], without any network activity.
+///
+/// With `events_fd`, the events of the build are written to this file descriptor, see
+/// [`EventWriter`]. Builds run without the daemon have no process id and report no node.
+///
+/// A SIGINT or SIGTERM received before the daemon starts the process exits with
+/// [`EXIT_CODE_INTERRUPTED`], one received later cancels the process.
 #[tracing::instrument]
-pub async fn make(mut args: Vec<String>) -> Result<i32> {
+pub async fn make(
+    mut args: Vec<String>,
+    no_cache: bool,
+    no_daemon: bool,
+    dry_run: bool,
+    events_fd: Option<u32>,
+) -> Result<i32> {
+    // Registered first so a Ctrl-C never kills the caller while remote makes are running.
+    let mut interrupt = signal(SignalKind::interrupt()).context("Failed to listen for SIGINT.")?;
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM.")?;
+
     let daemon_unix_sock = get_daemon_unix_sock()?;
     info!("Fetched daemon_unix_sock successfully: {daemon_unix_sock}");
     let daemon_tcp_sock = get_daemon_tcp_sock()?
@@ -37,44 +158,141 @@ pub async fn make(mut args: Vec<String>) -> Result<i32> {
     let caller_dir = current_dir()?;
     info!("Caller started in directory: {:?}", caller_dir);
 
+    // Opened first, a bad descriptor fails before anything is built
+    let mut events = events_fd.map(EventWriter::from_fd).transpose()?;
+
     // Step 1: Lexing makefile
     info!("Lexing makefile..");
     let tokens = guess_path_and_lex()?;
     info!("Successfully lexed Makefile into {} tokens", tokens.len());
 
-    // Step 2: Connecting with daemon
-    info!("Connecting to the daemon from the caller...");
-    let mut stream = connect_with_daemon_or_start_it(daemon_unix_sock).await?;
-    info!("Connected to the daemon successfully.");
+    if dry_run {
+        let project_id = ProjectId::new(DaemonId::default(), caller_dir);
+        let makefiles = RemoteMakefileSet::generate(
+            tokens,
+            daemon_tcp_sock,
+            ProcessId::process_less(project_id),
+        )
+        .context("Failed to generate makefiles.")?;
+        print_dry_run(&makefiles, daemon_tcp_sock);
+        return Ok(0);
+    }
 
-    // Step 3: Fetch a fresh process id
-    let tmp_project_id = ProjectId::new(DaemonId::default(), caller_dir.clone());
+    // Step 1.5: Looking for a cached result
+    let cache_ttl = Duration::from_secs(DaemonConfig::load_build_cache_ttl_secs());
+    let cache_key = if no_cache || cache_ttl.is_zero() {
+        None
+    } else {
+        let makefile = RemoteMakefile::guess_path(caller_dir.clone())
+            .context("Failed to find the makefile to hash.")?;
+        let key = build_cache::cache_key(&caller_dir, &makefile, &args)?;
+        match build_cache::lookup(&key, cache_ttl, &caller_dir) {
+            Ok(Some(exit_code)) => {
+                info!("Unchanged build, reusing the cached exit code {exit_code}");
+                return Ok(exit_code);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read the build cache: {e:?}"),
+        }
+        Some(key)
+    };
 
-    info!("Fetching pid for project {tmp_project_id:?}.");
-    let pid = fetch_fresh_id(&mut stream, tmp_project_id).await?;
+    // Step 2: Checking whether the daemon is needed
+    let project_id = ProjectId::new(DaemonId::default(), caller_dir.clone());
+    let local_makefiles = RemoteMakefileSet::generate(
+        tokens.clone(),
+        daemon_tcp_sock,
+        ProcessId::process_less(project_id.clone()),
+    )
+    .context("Failed to generate makefiles.")?;
+    if no_daemon && !local_makefiles.is_local_only() {
+        bail!("--no-daemon requires every target to be built on this host.");
+    }
+    let in_process = local_makefiles.is_local_only()
+        && (no_daemon || var(EnvVariable::NoDaemon.to_string()).map_or(true, |v| v != "0"));
 
-    // Step 4: Generate makefiles
-    let makefiles = RemoteMakefileSet::generate(tokens, daemon_tcp_sock, pid.clone())
-        .context("Failed to generate makefiles.")?;
-    info!("Generated RemoteMakefileSet for daemon");
+    let exit_code = if in_process {
+        // Step 3: Running make directly
+        if let Some(events) = &mut events {
+            events.start(&ProcessId::process_less(project_id), &make_targets(&args));
+        }
+        let _tmp_makefile = write_tmp_makefile(local_makefiles.my_makefile(), &mut args)?;
+        let exit_code = run_local(args, &mut events).await?;
+        if let Some(events) = &mut events {
+            events.end(exit_code);
+        }
+        exit_code
+    } else {
+        let registered = async {
+            // Step 3: Connecting with daemon
+            info!("Connecting to the daemon from the caller...");
+            let stream = connect_with_daemon_or_start_it(daemon_unix_sock.clone(), None).await?;
+            info!(
+                "Connected to the daemon successfully with protocol version {}.",
+                stream.negotiated().version
+            );
 
-    write(TMP_MAKEFILE_NAME, makefiles.my_makefile())
-        .context("Failed to write temporary dake makefile")?;
-    info!("Temporary makefile `{}` written", TMP_MAKEFILE_NAME);
+            // Step 4: Fetch a fresh process id
+            info!("Fetching pid for project {project_id:?}.");
+            let pid = fetch_fresh_id(daemon_unix_sock.clone(), project_id).await?;
+            anyhow::Ok((stream, pid))
+        };
+        // The signals received since the start are pending, nothing is built yet.
+        let (mut stream, pid) = select! {
+            registered = registered => registered?,
+            signal = interrupted(&mut interrupt, &mut terminate) => {
+                info!("Received {signal} before the build started, exiting");
+                return Ok(EXIT_CODE_INTERRUPTED);
+            }
+        };
 
-    // Step 5: Modifying arguments
-    args.append(&mut vec![
-        String::from("--file"),
-        String::from(TMP_MAKEFILE_NAME),
-    ]);
-    info!("Arguments for make prepared: {:?}", args);
+        // Step 5: Generate makefiles
+        let makefiles = RemoteMakefileSet::generate(tokens, daemon_tcp_sock, pid.clone())
+            .context("Failed to generate makefiles.")?;
+        info!("Generated RemoteMakefileSet for daemon");
+        if let Some(events) = &mut events {
+            events.start(&pid, &make_targets(&args));
+        }
+        let _tmp_makefile = write_tmp_makefile(makefiles.my_makefile(), &mut args)?;
+
+        // Step 6: Collecting the environment forwarded to the make processes
+        let env: HashMap<String, String> = DaemonConfig::load_env_passthrough()
+            .into_iter()
+            .filter_map(|key| var(&key).ok().map(|value| (key, value)))
+            .collect();
+        info!("Forwarding the variables {:?}", env.keys());
+        let env_overrides = forwarded_makeflags()
+            .map(|flags| HashMap::from([(MAKEFLAGS.to_string(), flags)]))
+            .unwrap_or_default();
 
-    // Step 6: Starting the process.
-    let exit_code = start(&mut stream, pid, makefiles, args).await?;
+        // Step 7: Starting the process, cancelling it if the caller gets interrupted.
+        let mut heartbeat = spawn(heartbeat(daemon_unix_sock, pid.clone()));
+        let exit_code = select! {
+            exit_code = start(&mut stream, pid.clone(), makefiles, args, env, env_overrides, events) => exit_code?,
+            // Failing instead of returning an exit code keeps the result out of the build cache.
+            res = &mut heartbeat => {
+                res.context("The heartbeat task failed.")??;
+                bail!("The heartbeat stopped before the end of the build.")
+            }
+            signal = interrupted(&mut interrupt, &mut terminate) => {
+                info!("Received {signal}, cancelling {pid:?}");
+                match cancel_process(pid).await {
+                    Ok(()) => info!("The daemon acknowledged the cancellation"),
+                    Err(e) => warn!("Failed to cancel the process: {e:?}"),
+                }
+                EXIT_CODE_INTERRUPTED
+            }
+        };
+        heartbeat.abort();
+        exit_code
+    };
 
-    remove_file(TMP_MAKEFILE_NAME)
-        .await
-        .context("Failed to remove tmp makefile at the end of the process.")?;
+    if let Some(key) = cache_key
+        && exit_code != EXIT_CODE_INTERRUPTED
+        && let Err(e) = build_cache::store(key, exit_code, cache_ttl)
+    {
+        warn!("Failed to store the build result in the cache: {e:?}");
+    }
 
     info!("Caller finished execution");
     Ok(exit_code)
@@ -1,25 +1,54 @@
+use std::{collections::HashMap, env::var};
+
 use anyhow::Result;
 use tracing::{error, info, warn};
 
 use crate::{
+    caller::events::EventWriter,
     dec,
+    env_variables::EnvVariable,
     makefile::RemoteMakefileSet,
-    network::{DaemonMessage, Message, MessageKind, ProcessMessage, read_next_message},
-    network::{Stream, write_message},
+    network::{DaemonMessage, Message, MessageKind, OutputKind, ProcessMessage, read_next_message},
+    network::{TimedStream, write_message},
     process_id::ProcessId,
 };
 
-#[tracing::instrument(skip(stream, makefiles))]
+/// Logs held back by the caller, printed in the order they were produced on their hosts
+/// once dropped.
+///
+/// Printing on drop keeps them when [`start`] fails or is cancelled midway, on a signal
+/// or a heartbeat failure.
+#[derive(Default)]
+struct HeldLogs(Vec<(u64, OutputKind, String)>);
+
+impl Drop for HeldLogs {
+    fn drop(&mut self) {
+        self.0.sort_by_key(|(timestamp_ms, _, _)| *timestamp_ms);
+        for (_, kind, log) in self.0.drain(..) {
+            match kind {
+                OutputKind::Stderr => eprint!("{log}"),
+                _ => print!("{log}"),
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip(stream, makefiles, env, events))]
 pub async fn start(
-    stream: &mut Stream,
+    stream: &mut TimedStream,
     pid: ProcessId,
     makefiles: RemoteMakefileSet,
     args: Vec<String>,
+    env: HashMap<String, String>,
+    env_overrides: HashMap<String, String>,
+    mut events: Option<EventWriter>,
 ) -> Result<i32> {
     let message = Message::new(
         DaemonMessage::NewProcess {
             makefiles: makefiles.drop_makefiles(),
             args,
+            env,
+            env_overrides,
         },
         pid,
     );
@@ -29,10 +58,15 @@ pub async fn start(
     write_message(stream, message).await?;
     info!("NewProcess message delivered successfully");
 
+    // Logs may be reordered on their way from the remote hosts. With DAKE_SORT_LOGS=1 they
+    // are held back and printed sorted by timestamp once the build is over.
+    let sort_logs = var(EnvVariable::SortLogs.to_string()).is_ok_and(|v| v == "1");
+    let mut held_logs = HeldLogs::default();
+
     info!("Caller connected to daemon stream, awaiting messages...");
     let exit_code = loop {
         // Read next message from daemon
-        let msg = match read_next_message(stream, MessageKind::ProcessMessage).await {
+        let msg = match read_next_message(stream, MessageKind::ProcessMessage, None).await {
             Ok(Some(msg)) => msg,
             Ok(None) => {
                 error!("Caller connection closed naturally; expected closure via End message.");
@@ -46,15 +80,35 @@ pub async fn start(
 
         // Deserialize into ProcessMessage
         let msg: Message<ProcessMessage> = dec!(msg)?;
+        if let Some(events) = &mut events {
+            match &msg.inner {
+                ProcessMessage::StdoutLog { log, .. } => events.log(&OutputKind::Stdout, log),
+                ProcessMessage::StderrLog { log, .. } => events.log(&OutputKind::Stderr, log),
+                ProcessMessage::NodeDone { node, exit_code } => events.node_done(node, *exit_code),
+                ProcessMessage::End { exit_code } => events.end(*exit_code),
+                _ => {}
+            }
+        }
         match msg.inner {
             ProcessMessage::End { exit_code } => {
                 info!("Caller received End message from daemon, build completed");
                 break exit_code;
             }
-            ProcessMessage::StdoutLog { log } => print!("{log}"),
-            ProcessMessage::StderrLog { log } => eprint!("{log}"),
+            ProcessMessage::StdoutLog { log, timestamp_ms } if sort_logs => {
+                held_logs.0.push((timestamp_ms, OutputKind::Stdout, log))
+            }
+            ProcessMessage::StderrLog { log, timestamp_ms } if sort_logs => {
+                held_logs.0.push((timestamp_ms, OutputKind::Stderr, log))
+            }
+            ProcessMessage::StdoutLog { log, .. } => print!("{log}"),
+            ProcessMessage::StderrLog { log, .. } => eprint!("{log}"),
+            ProcessMessage::Progress { percent, target } => eprintln!("[{percent:>3}%] {target}"),
+            ProcessMessage::NodeDone { node, exit_code } => {
+                info!("{node} is done with the exit code {exit_code}")
+            }
             _ => warn!("Caller should not receiv {msg:?} at this point."),
         }
     };
+
     Ok(exit_code)
 }
@@ -1,12 +1,42 @@
 use std::time::Duration;
 
 pub const MUTEX_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
-pub const DAEMON_STARTUP_TIMEOUT: Duration = Duration::from_secs(3);
-pub const DAEMON_RETRY_INTERVAL: Duration = Duration::from_millis(5);
-pub const FETCH_FAILURE_DELAY: Duration = Duration::from_secs(90);
+pub const DAEMON_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DAEMON_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// Interval at which a failed fetch checks whether its process is over, to cancel the fetcher.
+pub const FETCH_CANCEL_POLL_INTERVAL: Duration = Duration::from_secs(1);
 pub const DONE_NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(3);
+pub const LOG_BATCH_INTERVAL: Duration = Duration::from_millis(50);
+pub const CANCEL_TIMEOUT: Duration = Duration::from_secs(2);
+/// The daemon stops its builds one after the other before answering `dake kill --all`.
+pub const CANCEL_ALL_TIMEOUT: Duration = Duration::from_secs(60);
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+pub const PING_TIMEOUT: Duration = Duration::from_secs(2);
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const ARTIFACT_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Build folders younger than this are never pruned, they may belong to an active build.
+pub const ARTIFACT_PRUNE_MIN_AGE: Duration = Duration::from_secs(10 * 60);
+pub const STALE_PROCESS_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DRAIN_END_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub const CHUNK_SIZE: usize = 8 * 1024;
+pub const MESSAGE_BUFFER_CAPACITY: usize = 64 * 1024;
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+pub const DEFAULT_COMPRESS_THRESHOLD: usize = 4 * 1024;
+pub const LARGE_FILE_THRESHOLD: u64 = 10 * 1024 * 1024;
+pub const FETCH_YIELD_INTERVAL: u64 = 1024 * 1024;
+/// Appended to the path of a target while it is being fetched, so an interrupted fetch can resume.
+pub const PARTIAL_FETCH_SUFFIX: &str = ".part";
 pub const INITIAL_PROCESS_ID: u64 = 1;
+pub const MIN_UNPRIVILEGED_PORT: u16 = 1024;
+pub const DEFAULT_METRICS_PORT: u16 = 9091;
+pub const HEARTBEAT_MAX_MISSES: u32 = 3;
 pub const EXIT_CODE_FAILURE: i32 = 1;
+pub const EXIT_CODE_INTERRUPTED: i32 = 130;
 pub const CHANNEL_SIZE: usize = 100;
+pub const POOL_MAX_PER_ADDR: usize = 8;
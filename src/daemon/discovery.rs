@@ -0,0 +1,130 @@
+//! # Discovery
+//!
+//! Daemons advertise themselves on the local network over mDNS (DNS-SD), under the
+//! service type [`DAKE_SERVICE_TYPE`], so the other hosts can find them without any
+//! configuration. The TXT record holds the version of the daemon.
+
+use std::{
+    net::{IpAddr, SocketAddr as TcpSocketAddr},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::{Stream, stream};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use sysinfo::System;
+use tracing::{info, warn};
+
+use crate::network::SocketAddr;
+
+/// DNS-SD service type of the dake daemons.
+pub const DAKE_SERVICE_TYPE: &str = "_dake._tcp.local.";
+
+/// Time `dake nodes` listens for the advertisements.
+pub const DISCOVERY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Key of the TXT record holding the version of the daemon.
+const VERSION_KEY: &str = "version";
+
+/// A daemon advertised on the local network.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiscoveredNode {
+    /// Name the daemon advertises itself with, its host name and its port.
+    pub name: String,
+    pub sock: SocketAddr,
+    /// Version of dake the daemon runs, if it advertised it.
+    pub version: Option<String>,
+}
+
+impl DiscoveredNode {
+    /// Builds a node from a resolved service, `None` if it advertised no address.
+    fn from_info(info: &ServiceInfo) -> Option<Self> {
+        // IPv4 first, the labels of the makefiles mostly use them.
+        let ip = info
+            .get_addresses_v4()
+            .into_iter()
+            .next()
+            .map(|ip| IpAddr::V4(*ip))
+            .or_else(|| info.get_addresses().iter().next().copied())?;
+        let name = info
+            .get_fullname()
+            .strip_suffix(DAKE_SERVICE_TYPE)
+            .unwrap_or(info.get_fullname())
+            .trim_end_matches('.')
+            .to_string();
+        Some(Self {
+            name,
+            sock: SocketAddr::from(TcpSocketAddr::new(ip, info.get_port())),
+            version: info.get_property_val_str(VERSION_KEY).map(str::to_string),
+        })
+    }
+}
+
+/// Advertises the daemon listening on `sock` on the local network.
+///
+/// The advertisement lasts as long as the returned [`ServiceDaemon`].
+///
+/// # Errors
+/// Fails if the mDNS socket cannot be opened or the service registered.
+pub fn advertise(sock: TcpSocketAddr) -> Result<ServiceDaemon> {
+    let host = System::host_name().unwrap_or_else(|| "dake".to_string());
+    let instance = format!("{host}-{}", sock.port());
+    let properties = [(VERSION_KEY, env!("CARGO_PKG_VERSION"))];
+
+    // A daemon reached through loopback or any address advertises its interfaces instead.
+    let auto_addr = sock.ip().is_loopback() || sock.ip().is_unspecified();
+    let addrs: &[IpAddr] = if auto_addr { &[] } else { &[sock.ip()] };
+    let mut service = ServiceInfo::new(
+        DAKE_SERVICE_TYPE,
+        &instance,
+        &format!("{host}.local."),
+        addrs,
+        sock.port(),
+        &properties[..],
+    )
+    .context("Failed to build the mDNS service of the daemon.")?;
+    if auto_addr {
+        service = service.enable_addr_auto();
+    }
+
+    let mdns = ServiceDaemon::new().context("Failed to start the mDNS responder.")?;
+    mdns.register(service)
+        .context("Failed to advertise the daemon over mDNS.")?;
+    info!("Advertising the daemon as {instance} over mDNS");
+    Ok(mdns)
+}
+
+/// Lists the daemons advertised on the local network, as they are resolved.
+///
+/// The stream never ends on its own, the caller decides how long to listen.
+///
+/// # Errors
+/// Fails if the mDNS socket cannot be opened.
+pub fn browse() -> Result<impl Stream<Item = DiscoveredNode>> {
+    let mdns = ServiceDaemon::new().context("Failed to start the mDNS browser.")?;
+    let events = mdns
+        .browse(DAKE_SERVICE_TYPE)
+        .context("Failed to browse the daemons over mDNS.")?;
+
+    // The browser lives along the stream, its events stop once it is dropped.
+    Ok(stream::unfold(
+        (mdns, events),
+        |(mdns, events)| async move {
+            loop {
+                match events.recv_async().await {
+                    Ok(ServiceEvent::ServiceResolved(info)) => {
+                        match DiscoveredNode::from_info(&info) {
+                            Some(node) => return Some((node, (mdns, events))),
+                            None => warn!("{} advertised no address", info.get_fullname()),
+                        }
+                    }
+                    Ok(event) => info!("mDNS event: {event:?}"),
+                    Err(e) => {
+                        warn!("The mDNS browser stopped: {e}");
+                        return None;
+                    }
+                }
+            }
+        },
+    ))
+}
@@ -0,0 +1,43 @@
+//! # Daemon Errors
+//!
+//! Errors the daemon reports to its callers, they travel inside [`anyhow::Error`]
+//! and can be recovered with `downcast_ref`.
+
+use std::fmt;
+
+use crate::process_id::ProcessId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DakeError {
+    /// Each process of the cycle waits for a target held by the next one, the last
+    /// waiting for the first.
+    DeadlockDetected { cycle: Vec<ProcessId> },
+    /// The processes were still running when the daemon gave up waiting for them.
+    DrainTimeout { running: Vec<ProcessId> },
+}
+
+impl fmt::Display for DakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DakeError::DeadlockDetected { cycle } => {
+                write!(f, "Deadlock detected between the target locks of")?;
+                for pid in cycle {
+                    write!(f, " {pid} ->")?;
+                }
+                match cycle.first() {
+                    Some(first) => write!(f, " {first}"),
+                    None => Ok(()),
+                }
+            }
+            DakeError::DrainTimeout { running } => {
+                write!(f, "Timed out waiting for the processes")?;
+                for pid in running {
+                    write!(f, " {pid}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DakeError {}
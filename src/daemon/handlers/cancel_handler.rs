@@ -0,0 +1,56 @@
+//! # Cancel Handler
+//!
+//! Handles [`DaemonMessage::CancelProcess`](crate::network::DaemonMessage::CancelProcess),
+//! sent by the caller when the user interrupts a build, and
+//! [`DaemonMessage::CancelAll`](crate::network::DaemonMessage::CancelAll), sent by
+//! `dake kill --all`.
+//!
+//! Every make of the process is stopped through [`State::kill_all_for_pid`], or of every
+//! process through [`State::kill_all`], then the cancellation is acknowledged.
+//!
+//! [`State::kill_all_for_pid`]: crate::daemon::State::kill_all_for_pid
+//! [`State::kill_all`]: crate::daemon::State::kill_all
+
+use tracing::{info, warn};
+
+use crate::{
+    daemon::MessageCtx,
+    network::{AckMessage, Message, write_message},
+    process_id::ProcessId,
+};
+
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_cancel_process<'a>(
+    MessageCtx { state, stream, .. }: MessageCtx<'a>,
+    pid: ProcessId,
+) {
+    info!("Cancelling {pid:?} on the user request.");
+    state.kill_all_for_pid(pid.clone()).await;
+
+    if let Err(e) = write_message(stream, Message::new(AckMessage::Ok, pid.clone())).await {
+        warn!("Failed to acknowledge the cancellation of {pid:?}: {e}");
+    } else {
+        info!("Cancellation of {pid:?} acknowledged");
+    }
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn handle_cancel_all<'a>(
+    MessageCtx {
+        state, stream, pid, ..
+    }: MessageCtx<'a>,
+    reason: String,
+) {
+    let ack = match state.kill_all(reason).await {
+        Ok(()) => AckMessage::Ok,
+        Err(e) => {
+            warn!("Failed to cancel every build: {e:?}");
+            AckMessage::Failure
+        }
+    };
+    if let Err(e) = write_message(stream, Message::new(ack, pid)).await {
+        warn!("Failed to acknowledge the cancellation of every build: {e}");
+    } else {
+        info!("Cancellation of every build acknowledged");
+    }
+}
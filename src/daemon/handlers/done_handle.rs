@@ -3,13 +3,19 @@ use tracing::{info, warn};
 
 use crate::{
     constants::DONE_NOTIFICATION_TIMEOUT,
-    daemon::{MessageCtx, Notif},
+    daemon::{MessageCtx, Notif, archive_completed_build},
     lock,
     network::{AckMessage, Message, write_message},
 };
 
-#[tracing::instrument]
-pub async fn handle_done<'a>(MessageCtx { pid, state, stream }: MessageCtx<'a>) {
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_done<'a>(
+    MessageCtx {
+        pid, state, stream, ..
+    }: MessageCtx<'a>,
+) {
+    // Done does not tell how the build ended, the build history is recorded by the
+    // handlers knowing its exit code: the end of `new_process` and `handle_error`.
     match state.remove_process(&pid).await {
         Ok(Some(data)) => {
             info!("Successfuly removed {pid:?} from the processes database, got {data:?}.")
@@ -18,6 +24,20 @@ pub async fn handle_done<'a>(MessageCtx { pid, state, stream }: MessageCtx<'a>)
         Err(e) => warn!("Failed to lock processes database: {e:?}"),
     }
 
+    // The id counter of the project is kept: restarting it would reuse the ids of the
+    // processes already in the build history and in the archives.
+    match state.has_active_process(&pid.project_id).await {
+        Ok(true) => info!("Other processes of the project are still running."),
+        Ok(false) => {
+            if let Err(e) = state.unlock_all_targets_for_project(&pid.project_id).await {
+                warn!("Failed to release the target locks of the project: {e:?}");
+            }
+        }
+        Err(e) => warn!("Failed to lock processes database: {e:?}"),
+    }
+
+    archive_completed_build(&state, &pid);
+
     let waiter = {
         let hub = state.notifier_hub();
         match lock!(hub).await {
@@ -41,10 +61,10 @@ pub async fn handle_done<'a>(MessageCtx { pid, state, stream }: MessageCtx<'a>)
             }
         }
     };
-    if let Some(w) = waiter {
-        if let Err(e) = w.wait(Some(DONE_NOTIFICATION_TIMEOUT)).await {
-            warn!("Failed to wait for done notif publication: {e:?}")
-        }
+    if let Some(w) = waiter
+        && let Err(e) = w.wait(Some(DONE_NOTIFICATION_TIMEOUT)).await
+    {
+        warn!("Failed to wait for done notif publication: {e:?}")
     }
 
     if let Err(e) = write_message(stream, Message::new(AckMessage::Ok, pid.clone())).await {
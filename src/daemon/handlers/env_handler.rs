@@ -0,0 +1,28 @@
+use tracing::{info, warn};
+
+use crate::{
+    daemon::MessageCtx,
+    network::{Message, ProcessMessage, write_message},
+};
+
+/// Responds with the environment forwarded to the process, for diagnostic purposes.
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_get_process_env<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+) {
+    let env = match state.read_process_data(&pid).await {
+        Ok(datas) => datas.map(|datas| datas.env),
+        Err(e) => {
+            warn!("Failed to read the process datas of {pid:?}: {e:?}");
+            None
+        }
+    };
+    info!("Sending the env of {pid:?}: {env:?}");
+
+    let msg = Message::new(ProcessMessage::Env { env }, pid.clone());
+    if let Err(e) = write_message(stream, msg).await {
+        warn!("Failed to send the env of {pid:?}: {e}");
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use tracing::warn;
+use tracing::{info, warn};
 
 use crate::{
     daemon::{MessageCtx, Notif},
@@ -8,12 +8,28 @@ use crate::{
     network::SocketAddr,
 };
 
-#[tracing::instrument]
+#[tracing::instrument(skip_all, fields(pid = %pid, guilty_node = %guilty_node))]
 pub async fn handle_error<'a>(
     MessageCtx { pid, state, .. }: MessageCtx<'a>,
     guilty_node: SocketAddr,
     exit_code: i32,
 ) {
+    // The process stays registered, the caller daemon still needs it to broadcast the done.
+    match state.read_process_data(&pid).await {
+        Ok(Some(datas)) => {
+            if let Err(e) = state.record_build(&datas, exit_code).await {
+                warn!("Failed to record {pid:?} in the build history: {e:?}");
+            }
+        }
+        Ok(None) => info!("{pid:?} is not registered, not recording it in the history."),
+        Err(e) => warn!("Failed to read the process datas of {pid:?}: {e:?}"),
+    }
+
+    // Unblock the builds of the project waiting on a target this process will never release.
+    if let Err(e) = state.unlock_all_targets_for_process(&pid).await {
+        warn!("Failed to release the target locks of {pid:?}: {e:?}");
+    }
+
     let notif = Notif::Error {
         guilty_node,
         exit_code,
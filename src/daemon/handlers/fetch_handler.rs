@@ -1,94 +1,208 @@
 use std::{
-    fs::File,
-    io::{BufReader, Read},
-    path::PathBuf,
+    future::pending,
+    io::{Cursor, SeekFrom},
+    path::{Path, PathBuf},
 };
 
+use anyhow::{Context, Result};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader},
+    select,
+    task::{spawn, spawn_blocking, yield_now},
+    time::sleep,
+};
 use tracing::{info, warn};
 
 use crate::{
-    constants::{CHUNK_SIZE, EXIT_CODE_FAILURE},
-    daemon::{MessageCtx, execute_make, fs::get_makefile_path},
-    network::{DaemonMessage, FetcherMessage, Message, Stream, send_message, write_message},
+    constants::{
+        CHANNEL_SIZE, CHUNK_SIZE, EXIT_CODE_FAILURE, FETCH_CANCEL_POLL_INTERVAL,
+        FETCH_YIELD_INTERVAL, LARGE_FILE_THRESHOLD,
+    },
+    daemon::{
+        DakeError, MessageCtx, Notif, State, execute_make,
+        fs::{get_makefile_path, store_artifact},
+    },
+    lock,
+    network::{
+        DaemonMessage, FetchRequest, FetcherMessage, Message, SocketAddr, Stream, write_message,
+    },
+    process_id::ProcessId,
+    utils::now_ms,
 };
 
-/// Handles a "fetch" request.
-/// Log internal errors, sends stderr messages to the client,
-/// and reports build failures to the main daemon. It never panics.
-#[tracing::instrument]
-pub async fn handle_fetch<'a>(
-    MessageCtx { pid, stream, state }: MessageCtx<'a>,
-    target: String,
-    labeled_path: Option<PathBuf>,
-) {
-    let caller_sock = match state.read_process_data(&pid).await {
-        Ok(Some(sock)) => sock,
-        _ => {
-            warn!("Failed to fetch the caller socket.");
-            return;
-        }
-    }
-    .caller_daemon;
+/// Computes the blake3 hash of the content of `file`, leaving its cursor at the end.
+async fn hash_file(file: &File) -> Result<blake3::Hash> {
+    let file = file.try_clone().await?.into_std().await;
+    spawn_blocking(move || {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(file)?;
+        Ok(hasher.finalize())
+    })
+    .await?
+}
 
-    let daemon_sock = state.daemon_sock();
+/// Stores the content of `file`, hashing to `hash`, see [`store_artifact`], and opens
+/// the stored copy.
+async fn open_stored(file: &File, hash: blake3::Hash) -> Result<File> {
+    let mut source = file.try_clone().await?.into_std().await;
+    let stored = spawn_blocking(move || store_artifact(&mut source, &hash)).await??;
+    File::open(&stored)
+        .await
+        .context(format!("Failed to open the stored artifact {stored:?}"))
+}
 
-    // Helper closure to send both a user-facing error message
-    // and a `MakeError` to the daemon.
-    let forward_error = |stream: &'a mut Stream, user_message: String| async {
-        let sock = caller_sock.clone();
-        let msg = Message::new(FetcherMessage::Failed, pid.clone());
+/// Archives the directory `dir` in memory, its entries under `name`.
+///
+/// This is blocking, async callers should run it in `spawn_blocking`.
+fn tar_directory(dir: &Path, name: &str) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+        .append_dir_all(name, dir)
+        .context(format!("Failed to archive {dir:?}"))?;
+    Ok(builder.into_inner()?)
+}
 
-        if let Err(e) = write_message(stream, msg).await {
-            warn!("Failed to send the Failed message to the fetcher: {e:?}");
+/// Waits for the process `pid` to be over on this daemon, then tells the fetcher to exit.
+///
+/// The process is over once its [`Notif::Done`] is published, or once it is not
+/// registered anymore, the caller daemon forgets its processes without a done.
+async fn cancel_when_over(stream: &mut Stream, state: &State, pid: &ProcessId) {
+    let mut subscriber = match lock!(state.notifier_hub()).await {
+        Ok(mut hub) => Some(hub.subscribe(pid, CHANNEL_SIZE)),
+        Err(e) => {
+            warn!("Failed to subscribe to {pid:?}, polling its registration instead: {e:?}");
+            None
         }
+    };
 
-        let msg = Message::new(DaemonMessage::StderrLog { log: user_message }, pid.clone());
-
-        if let Err(e) = send_message(msg, sock.clone()).await {
-            warn!("Failed to send stderr log to {sock}: {e:?}");
+    let done = async {
+        match &mut subscriber {
+            Some(subscriber) => {
+                while let Some(notif) = subscriber.recv().await {
+                    if matches!(notif.as_ref(), Notif::Done) {
+                        return;
+                    }
+                }
+            }
+            None => pending().await,
         }
-
-        let msg = Message::new(
-            DaemonMessage::MakeError {
-                guilty_node: daemon_sock.clone(),
-                exit_code: EXIT_CODE_FAILURE,
-            },
-            pid.clone(),
-        );
-
-        if let Err(e) = send_message(msg, sock.clone()).await {
-            warn!("Failed to forward MakeError to {sock}: {e:?}");
+    };
+    let unregistered = async {
+        while state.process_is_registered(pid).await.unwrap_or(true) {
+            sleep(FETCH_CANCEL_POLL_INTERVAL).await;
         }
     };
+    select! {
+        _ = done => info!("{pid:?} is done, cancelling the fetcher"),
+        _ = unregistered => info!("{pid:?} is not registered anymore, cancelling the fetcher"),
+    }
 
-    // Macro to both log an internal warning and forward a user-facing error.
-    macro_rules! warn_and_forward {
-        ($msg:expr) => {{
-            warn!($msg);
-            forward_error(stream, "Dake encountered an internal error.".into()).await;
-            return;
-        }};
-        ($msg:expr, $user:expr) => {{
-            warn!($msg);
-            forward_error(stream, $user.into()).await;
-            return;
-        }};
+    if let Err(e) = write_message(stream, Message::new(FetcherMessage::Cancel, pid.clone())).await {
+        warn!("Failed to cancel the fetcher: {e:?}");
     }
+}
 
-    info!("Fetcher started for target '{target}' requested by the client");
+/// Sends both a user-facing error message and a `MakeError` to the caller daemon.
+async fn report_failure(
+    state: &State,
+    pid: &ProcessId,
+    caller_sock: &SocketAddr,
+    user_message: String,
+) {
+    let msg = Message::new(
+        DaemonMessage::StderrLog {
+            log: user_message,
+            timestamp_ms: now_ms(),
+        },
+        pid.clone(),
+    );
+
+    if let Err(e) = state.send_message(msg, caller_sock.clone()).await {
+        warn!("Failed to send stderr log to {caller_sock}: {e:?}");
+    }
+
+    let msg = Message::new(
+        DaemonMessage::MakeError {
+            guilty_node: state.daemon_sock().clone(),
+            exit_code: EXIT_CODE_FAILURE,
+        },
+        pid.clone(),
+    );
+
+    if let Err(e) = state.send_message(msg, caller_sock.clone()).await {
+        warn!("Failed to forward MakeError to {caller_sock}: {e:?}");
+    }
+}
+
+/// Tells the fetcher the fetch failed, then reports the failure to the caller daemon,
+/// see [`report_failure`].
+///
+/// The fetcher is then kept waiting until the process is over, see [`cancel_when_over`].
+async fn forward_error(
+    stream: &mut Stream,
+    state: &State,
+    pid: &ProcessId,
+    caller_sock: &SocketAddr,
+    user_message: String,
+) {
+    let msg = Message::new(FetcherMessage::Failed, pid.clone());
 
+    if let Err(e) = write_message(stream, msg).await {
+        warn!("Failed to send the Failed message to the fetcher: {e:?}");
+    }
+
+    report_failure(state, pid, caller_sock, user_message).await;
+    cancel_when_over(stream, state, pid).await;
+}
+
+/// Why a target could not be built.
+struct BuildFailure {
+    /// Logged by the daemon.
+    log: String,
+    /// Forwarded to the user.
+    user: String,
+}
+
+impl BuildFailure {
+    /// A failure the user can do nothing about.
+    fn internal(log: String) -> Self {
+        Self {
+            log,
+            user: "Dake encountered an internal error.".into(),
+        }
+    }
+}
+
+/// Builds `target` with make for the build of `parent`, see [`execute_make`], and returns the path of the artifact and whether it
+/// is a directory, `None` if make was aborted by a done.
+///
+/// A make exiting with an error is reported to the caller daemon, the artifact may
+/// still be there.
+async fn build_target(
+    state: &State,
+    pid: &ProcessId,
+    caller_sock: &SocketAddr,
+    target: &str,
+    labeled_path: Option<PathBuf>,
+    parent: Option<&str>,
+) -> Result<Option<(PathBuf, bool)>, BuildFailure> {
     // --- Step 1: Resolve makefile path ---
-    let mut path = match labeled_path.or_else(|| get_makefile_path(&pid).ok()) {
+    let mut path = match labeled_path.or_else(|| get_makefile_path(pid).ok()) {
         Some(p) => {
             info!("Resolved makefile path: {:?}", p);
             p
         }
-        None => warn_and_forward!("Failed to resolve the makefile path."),
+        None => {
+            return Err(BuildFailure::internal(
+                "Failed to resolve the makefile path.".into(),
+            ));
+        }
     };
 
     // --- Step 2: Fetching args ---
     let args = state
-        .read_args(&pid)
+        .read_args(pid)
         .await
         .ok()
         .flatten()
@@ -100,10 +214,11 @@ pub async fn handle_fetch<'a>(
     // --- Step 3: Execute make ---
     info!("Running make for target '{target}' at path {:?}", path);
     match execute_make(
-        &state,
+        state,
         pid.clone(),
         path.clone(),
-        Some(target.clone()),
+        Some(target.to_string()),
+        parent,
         &args,
     )
     .await
@@ -117,12 +232,12 @@ pub async fn handle_fetch<'a>(
             if !status.success() {
                 warn!("Fetcher: make exited with status {exit_code} for target '{target}'");
                 let inner = DaemonMessage::MakeError {
-                    guilty_node: daemon_sock.clone(),
+                    guilty_node: state.daemon_sock().clone(),
                     exit_code,
                 };
 
                 let msg = Message::new(inner, pid.clone());
-                if let Err(e) = send_message(msg, caller_sock.clone()).await {
+                if let Err(e) = state.send_message(msg, caller_sock.clone()).await {
                     warn!("Failed to send build failure to the caller: {e:?}");
                 }
             } else {
@@ -131,57 +246,257 @@ pub async fn handle_fetch<'a>(
         }
         Ok(None) => {
             info!("The make process has been aborted.");
-            return;
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(match e.downcast_ref::<DakeError>() {
+                Some(deadlock @ DakeError::DeadlockDetected { .. }) => BuildFailure {
+                    log: format!("Refusing to build {target}: {e:?}"),
+                    user: deadlock.to_string(),
+                },
+                _ => BuildFailure::internal(format!(
+                    "Failed to start make process for {target}: {e:?}"
+                )),
+            });
         }
-        Err(e) => warn_and_forward!("Failed to start make process for {target}: {e:?}"),
     }
 
     // --- Step 4: Validate resulting target path ---
-    path.push(target.clone());
+    path.push(target);
     info!("Checking resulting path {:?}", path);
 
     match path.metadata() {
-        Ok(meta) if meta.is_file() => info!("Verified target file exists: {:?}", path),
-        Ok(_) => warn_and_forward!(
-            "Resolved path {path:?} is not a file (possibly directory or special entry)",
-            format!(
-                "The target '{target}' did not produce a file (possibly a directory or other entry)."
-            )
-        ),
-        Err(e) => warn_and_forward!(
-            "Failed to access target path {path:?}: {e:?}",
-            format!("The target '{target}' does not seem to produce a file. Check your Makefile.")
-        ),
+        Ok(meta) if meta.is_file() => {
+            info!("Verified target file exists: {:?}", path);
+            Ok(Some((path, false)))
+        }
+        Ok(meta) if meta.is_dir() => {
+            info!("The target {path:?} is a directory, sending it as a tar archive");
+            Ok(Some((path, true)))
+        }
+        Ok(_) => Err(BuildFailure {
+            log: format!("Resolved path {path:?} is neither a file nor a directory"),
+            user: format!(
+                "The target '{target}' did not produce a file or a directory (possibly a special entry)."
+            ),
+        }),
+        Err(e) => Err(BuildFailure {
+            log: format!("Failed to access target path {path:?}: {e:?}"),
+            user: format!(
+                "The target '{target}' does not seem to produce a file. Check your Makefile."
+            ),
+        }),
     }
+}
 
-    // --- Step 5: Send artifact to client ---
-    let client = match stream.peer_addr() {
-        Ok(addr) => addr,
-        Err(e) => {
-            warn!("Failed to fetch fetcher client peer socket: {e}");
+/// Handles a "fetch" request.
+/// Log internal errors, sends stderr messages to the client,
+/// and reports build failures to the main daemon. It never panics.
+///
+/// If the built artifact hashes to `known_checksum`, only
+/// [`FetcherMessage::NotModified`] is sent back. Otherwise the artifact is sent from
+/// `offset`, followed by the [`FetcherMessage::Checksum`] of the whole artifact.
+///
+/// A directory target is sent as a tar archive of its content, built in memory.
+#[tracing::instrument(skip_all, fields(pid = %pid, target = %target))]
+pub async fn handle_fetch<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+    target: String,
+    labeled_path: Option<PathBuf>,
+    known_checksum: Option<[u8; 32]>,
+    offset: u64,
+    parent: Option<String>,
+) {
+    let caller_sock = match state.read_process_data(&pid).await {
+        Ok(Some(sock)) => sock,
+        _ => {
+            warn!("Failed to fetch the caller socket.");
             return;
         }
+    }
+    .caller_daemon;
+
+    // Macro to both log an internal warning and forward a user-facing error.
+    macro_rules! warn_and_forward {
+        ($msg:expr) => {{
+            warn!($msg);
+            forward_error(
+                stream,
+                &state,
+                &pid,
+                &caller_sock,
+                "Dake encountered an internal error.".into(),
+            )
+            .await;
+            return;
+        }};
+        ($msg:expr, $user:expr) => {{
+            warn!($msg);
+            forward_error(stream, &state, &pid, &caller_sock, $user.into()).await;
+            return;
+        }};
+    }
+
+    info!("Fetcher started for target '{target}' requested by the client");
+
+    let built = build_target(
+        &state,
+        &pid,
+        &caller_sock,
+        &target,
+        labeled_path,
+        parent.as_deref(),
+    )
+    .await;
+    let artifact = match built {
+        Ok(Some(artifact)) => artifact,
+        Ok(None) => return,
+        Err(BuildFailure { log, user }) => warn_and_forward!("{log}", user),
+    };
+
+    let request = FetchRequest {
+        target,
+        labeled_path: None,
+        known_checksum,
+        offset,
+    };
+    if let Err(BuildFailure { log, user }) =
+        send_artifact(stream, &state, &pid, artifact, &request).await
+    {
+        warn_and_forward!("{log}", user);
+    }
+
+    info!(
+        "Fetcher successfully completed for target '{}'",
+        request.target
+    );
+}
+
+/// Sends the artifact built for `request` to the fetcher, see [`handle_fetch`] for the
+/// messages. `artifact` is the path of the artifact and whether it is a directory.
+///
+/// # Errors
+/// Fails if the artifact cannot be read or sent, nothing is reported yet.
+async fn send_artifact(
+    stream: &mut Stream,
+    state: &State,
+    pid: &ProcessId,
+    (path, is_tar): (PathBuf, bool),
+    request: &FetchRequest,
+) -> Result<(), BuildFailure> {
+    let FetchRequest {
+        target,
+        known_checksum,
+        offset,
+        ..
+    } = request;
+    let offset = *offset;
+
+    // --- Step 5: Hash the artifact, skip the transfer if the fetcher is up to date ---
+    let (mut reader, checksum, total_bytes): (Box<dyn AsyncRead + Send + Unpin>, _, _) = if is_tar {
+        let (dir, name) = (path.clone(), target.clone());
+        let archive = match spawn_blocking(move || tar_directory(&dir, &name)).await {
+            Ok(Ok(archive)) => archive,
+            Ok(Err(e)) => {
+                return Err(BuildFailure::internal(format!(
+                    "Failed to archive {path:?}: {e:?}"
+                )));
+            }
+            Err(e) => {
+                return Err(BuildFailure::internal(format!(
+                    "The archiving task of {path:?} failed: {e:?}"
+                )));
+            }
+        };
+        let checksum = blake3::hash(&archive);
+        let total_bytes = Some(archive.len() as u64);
+        let mut archive = Cursor::new(archive);
+        archive.set_position(offset);
+        (Box::new(archive), checksum, total_bytes)
+    } else {
+        info!("Opening built artifact at {:?}", path);
+        let file = File::open(&path).await.map_err(|e| {
+            BuildFailure::internal(format!("Failed to open built artifact {path:?}: {e:?}"))
+        })?;
+
+        // Hashed through the opened file, the checksum describes exactly the streamed bytes.
+        let checksum = hash_file(&file)
+            .await
+            .map_err(|e| BuildFailure::internal(format!("Failed to hash {path:?}: {e:?}")))?;
+        // Streamed from the store, a make rewriting the build folder cannot alter the transfer.
+        let mut file = match open_stored(&file, checksum).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Failed to store {path:?}, streaming it from the build folder: {e:?}");
+                file
+            }
+        };
+
+        // Without a store, hashing moved the cursor of the file, shared with its clone.
+        file.seek(SeekFrom::Start(offset)).await.map_err(|e| {
+            BuildFailure::internal(format!("Failed to seek {path:?} to {offset}: {e:?}"))
+        })?;
+        let total_bytes = match file.metadata().await {
+            Ok(meta) => Some(meta.len()),
+            Err(e) => {
+                warn!("Failed to read the size of {path:?}: {e:?}");
+                None
+            }
+        };
+        (Box::new(BufReader::new(file)), checksum, total_bytes)
     };
 
-    info!("Opening built artifact at {:?}", path);
-    let file = match File::open(&path) {
-        Ok(f) => f,
-        Err(e) => warn_and_forward!("Failed to open built artifact {path:?}: {e:?}"),
+    if known_checksum.is_some_and(|known| known == *checksum.as_bytes()) {
+        info!("The fetcher already has '{target}', sending NotModified");
+        let message = Message::new(FetcherMessage::NotModified, pid.clone());
+        if let Err(e) = write_message(stream, message).await {
+            warn!("Failed to send NotModified to the fetcher: {e:?}");
+        }
+        return Ok(());
+    }
+    if offset > 0 {
+        info!("Resuming the transfer of '{target}' at byte {offset}");
+    }
+
+    // --- Step 6: Send artifact to client ---
+    let client = stream.peer_addr().map_err(|e| {
+        BuildFailure::internal(format!("Failed to fetch fetcher client peer socket: {e}"))
+    })?;
+    let forward_failure = |log: String| BuildFailure {
+        log,
+        user: format!(
+            "Failed to forward '{target}' from {} to {client}. \
+            The Dake daemon on {client} might be down.",
+            state.daemon_sock()
+        ),
     };
 
-    let mut reader = BufReader::new(file);
-    let err = format!(
-        "Failed to forward '{target}' from {daemon_sock} to {client}. \
-        The Dake daemon on {client} might be down."
+    // Large files periodically give the executor back to the other tasks.
+    let yield_periodically = total_bytes.is_none_or(|len| len > LARGE_FILE_THRESHOLD);
+
+    let message = Message::new(
+        FetcherMessage::Metadata {
+            is_tar,
+            total_bytes: total_bytes.unwrap_or(0),
+        },
+        pid.clone(),
     );
+    if let Err(e) = write_message(stream, message).await {
+        return Err(BuildFailure::internal(format!(
+            "Failed to send the size of '{target}' to {client}: {e:?}"
+        )));
+    }
 
+    let mut since_yield = 0;
     info!("Streaming file '{target}' to {client}");
     loop {
         let mut buf = vec![0; CHUNK_SIZE];
-        let n = match reader.read(&mut buf) {
-            Ok(n) => n,
-            Err(e) => warn_and_forward!("Failed to read {path:?}: {e:?}", err),
-        };
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| forward_failure(format!("Failed to read {path:?}: {e:?}")))?;
         if n == 0 {
             info!("End of file reached for '{target}'");
             break;
@@ -191,15 +506,114 @@ pub async fn handle_fetch<'a>(
         info!("Writing a new chunck of message, size = {n}");
         let message = Message::new(FetcherMessage::Object(buf), pid.clone());
         if let Err(e) = write_message(stream, message).await {
-            warn_and_forward!("Failed to send packet to {client}: {e:?}", err);
+            return Err(forward_failure(format!(
+                "Failed to send packet to {client}: {e:?}"
+            )));
+        }
+        state.metrics().add_artifact_bytes(n as u64);
+
+        since_yield += n as u64;
+        if yield_periodically && since_yield >= FETCH_YIELD_INTERVAL {
+            since_yield = 0;
+            yield_now().await;
         }
     }
 
+    let message = Message::new(
+        FetcherMessage::Checksum {
+            hex: checksum.to_hex().to_string(),
+        },
+        pid.clone(),
+    );
+    if let Err(e) = write_message(stream, message).await {
+        return Err(forward_failure(format!(
+            "Failed to send packet to {client}: {e:?}"
+        )));
+    }
+
     info!("Sending EOF message to signal that the object has been fully transmitted.");
     let message = Message::new(FetcherMessage::Eof, pid.clone());
     if let Err(e) = write_message(stream, message).await {
-        warn_and_forward!("Failed to send packet to {client}: {e:?}", err);
+        return Err(forward_failure(format!(
+            "Failed to send packet to {client}: {e:?}"
+        )));
     }
+    Ok(())
+}
 
-    info!("Fetcher successfully completed for target '{target}'");
+/// Handles a batch fetch request, building every target in its own task.
+///
+/// The artifacts are then sent in the order of the request, each one as
+/// [`handle_fetch`] would send it. A target that failed gets a [`FetcherMessage::Failed`]
+/// and the next targets are still sent, the fetcher is then kept waiting until the
+/// process is over, as for a single fetch.
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_fetch_batch<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+    targets: Vec<FetchRequest>,
+    parent: Option<String>,
+) {
+    let caller_sock = match state.read_process_data(&pid).await {
+        Ok(Some(datas)) => datas.caller_daemon,
+        _ => {
+            warn!("Failed to fetch the caller socket.");
+            return;
+        }
+    };
+
+    info!("Fetching {} targets in parallel", targets.len());
+    let tasks: Vec<_> = targets
+        .into_iter()
+        .map(|request| {
+            let (state, pid, caller_sock) = (state.clone(), pid.clone(), caller_sock.clone());
+            let (target, labeled_path) = (request.target.clone(), request.labeled_path.clone());
+            let parent = parent.clone();
+            let task = spawn(async move {
+                let parent = parent.as_deref();
+                build_target(&state, &pid, &caller_sock, &target, labeled_path, parent).await
+            });
+            (request, task)
+        })
+        .collect();
+
+    let mut failed = false;
+    for (request, task) in tasks {
+        let target = &request.target;
+        let built = task.await.unwrap_or_else(|e| {
+            Err(BuildFailure::internal(format!(
+                "The build task of '{target}' failed: {e:?}"
+            )))
+        });
+        let failure = match built {
+            Ok(Some(artifact)) => {
+                match send_artifact(stream, &state, &pid, artifact, &request).await {
+                    Ok(()) => {
+                        info!("Sent '{target}' to the fetcher");
+                        continue;
+                    }
+                    Err(failure) => failure,
+                }
+            }
+            Ok(None) => BuildFailure {
+                log: format!("The build of '{target}' was aborted."),
+                user: "The build was aborted.".into(),
+            },
+            Err(failure) => failure,
+        };
+
+        warn!("{}", failure.log);
+        failed = true;
+        let message = Message::new(FetcherMessage::Failed, pid.clone());
+        if let Err(e) = write_message(stream, message).await {
+            warn!("Failed to send the Failed message to the fetcher: {e:?}");
+        }
+        report_failure(&state, &pid, &caller_sock, failure.user).await;
+    }
+
+    if failed {
+        cancel_when_over(stream, &state, &pid).await;
+    }
+    info!("Batch fetch completed");
 }
@@ -6,27 +6,16 @@ use crate::{
     process_id::{ProcessId, ProjectId},
 };
 
-#[tracing::instrument(skip(state, stream), fields(project_id = %pid.project_id))]
+#[tracing::instrument(skip_all, fields(project_id = %pid.project_id))]
 pub async fn handle_fresh_request<'a>(
     MessageCtx {
-        pid,
-        stream,
-        mut state,
+        pid, stream, state, ..
     }: MessageCtx<'a>,
 ) {
     info!("Starting to handle fresh ID request");
 
-    let daemon_id = match state.config() {
-        Ok(config) => {
-            let id = config.id();
-            info!("Just fetched the daemon id: {id}");
-            id
-        }
-        Err(e) => {
-            warn!("Failed to fetch config: {e}");
-            return;
-        }
-    };
+    let daemon_id = state.daemon_id();
+    info!("Just fetched the daemon id: {daemon_id}");
     let project_id = ProjectId::new(daemon_id, pid.path().clone());
 
     info!("Fetching fresh ID for project: {:?}", project_id);
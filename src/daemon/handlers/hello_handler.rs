@@ -0,0 +1,24 @@
+use tracing::{info, warn};
+
+use crate::{
+    daemon::MessageCtx,
+    network::{CapabilityFlag, Message, NegotiatedVersion, hello, write_message},
+};
+
+/// Answers the hello opening a connection with the hello of this daemon, then keeps
+/// the negotiated version on the stream.
+///
+/// The answer itself is written before, the peer only knows the version once it read it.
+#[tracing::instrument(skip_all)]
+pub async fn handle_hello<'a>(
+    MessageCtx { pid, stream, .. }: MessageCtx<'a>,
+    version: u16,
+    capabilities: Vec<CapabilityFlag>,
+) {
+    let negotiated = NegotiatedVersion::negotiate(version, &capabilities);
+    info!("Peer of version {version} connected, negotiated {negotiated:?}");
+    if let Err(e) = write_message(stream, Message::new(hello(), pid)).await {
+        warn!("Failed to answer the hello: {e}");
+    }
+    stream.set_negotiated(negotiated);
+}
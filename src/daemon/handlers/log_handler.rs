@@ -1,25 +1,49 @@
 use std::time::Duration;
 
-use tracing::warn;
+use tracing::{info, warn};
 
 use crate::{
-    daemon::{MessageCtx, Notif},
+    daemon::{MessageCtx, Notif, State},
     lock,
+    network::OutputKind,
+    process_id::ProcessId,
 };
 
 #[derive(Debug)]
 pub enum OutputFile {
     Stdout,
     Stderr,
+    Progress { percent: u8, target: String },
 }
 
-#[tracing::instrument]
-pub async fn handle_log<'a>(
-    MessageCtx { pid, state, .. }: MessageCtx<'a>,
+impl From<OutputKind> for OutputFile {
+    fn from(kind: OutputKind) -> Self {
+        match kind {
+            OutputKind::Stdout => OutputFile::Stdout,
+            OutputKind::Stderr => OutputFile::Stderr,
+            OutputKind::Progress { percent, target } => OutputFile::Progress { percent, target },
+        }
+    }
+}
+
+/// Publishes a single output notification on the channel of `pid`.
+///
+/// Progress reports are published as [`Notif::BuildProgress`], regular output as [`Notif::Log`].
+async fn publish_output(
+    state: &State,
+    pid: &ProcessId,
     log: String,
     output: OutputFile,
+    timestamp_ms: u64,
 ) {
-    let notif = Notif::Log { log, output };
+    let notif = match output {
+        OutputFile::Progress { percent, target } => Notif::BuildProgress { percent, target },
+        output => Notif::Log {
+            log,
+            output,
+            timestamp_ms,
+        },
+    };
 
     let w = {
         let notifier_hub = state.notifier_hub().clone();
@@ -31,7 +55,7 @@ pub async fn handle_log<'a>(
             }
         };
 
-        match notifier_hub.arc_send(notif, &pid) {
+        match notifier_hub.arc_send(notif, pid) {
             Ok(w) => w,
             Err(e) => {
                 warn!("The channel for {pid:?} was not initialised: {e:?}");
@@ -44,3 +68,26 @@ pub async fn handle_log<'a>(
         warn!("Failed to wait for notif publication: {e:?}")
     }
 }
+
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_output<'a>(
+    MessageCtx { pid, state, .. }: MessageCtx<'a>,
+    log: String,
+    output: OutputKind,
+    timestamp_ms: u64,
+) {
+    publish_output(&state, &pid, log, output.into(), timestamp_ms).await
+}
+
+/// Splits a [`DaemonMessage::BatchLog`](crate::network::DaemonMessage::BatchLog)
+/// back into individual log notifications, preserving their order.
+#[tracing::instrument(skip_all, fields(pid = %pid))]
+pub async fn handle_batch_log<'a>(
+    MessageCtx { pid, state, .. }: MessageCtx<'a>,
+    logs: Vec<(OutputKind, String, u64)>,
+) {
+    info!("Forwarding a batch of {} logs for {pid:?}", logs.len());
+    for (kind, log, timestamp_ms) in logs {
+        publish_output(&state, &pid, log, kind.into(), timestamp_ms).await
+    }
+}
@@ -2,22 +2,55 @@ use tracing::{error, info, warn};
 
 use crate::{
     daemon::{MessageCtx, fs::push_makefile, process_datas::ProcessDatas},
+    lexer::{lex, parallel_jobs_of},
     makefile::RemoteMakefile,
     network::{AckMessage, Message, write_message},
 };
 
 /// Receives a remote makefile, writes it to disk, and replies with an acknowledgment.
-#[tracing::instrument(skip(stream, state, makefile))]
+///
+/// A `compressed` makefile is decompressed first, failing to do so is answered
+/// with [`AckMessage::Failure`]. The `parallel` directive of the makefile, if any,
+/// sets the job count of make on this host.
+#[tracing::instrument(skip_all, fields(pid = %pid, compressed = compressed))]
 pub async fn receiv_makefile<'a>(
-    MessageCtx { pid, stream, state }: MessageCtx<'a>,
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
     makefile: RemoteMakefile,
-    process_datas: ProcessDatas,
+    compressed: bool,
+    mut process_datas: ProcessDatas,
 ) {
-    info!("Handling incoming makefile: {}", makefile.to_string());
-
     // Closure to simplify message creation with same pid and client
     let message = |inner| Message::new(inner, pid.clone());
 
+    let makefile = if compressed {
+        match makefile.decompress() {
+            Ok(makefile) => makefile,
+            Err(e) => {
+                error!("Failed to decompress the makefile for pid {pid:?}: {e:?}");
+                if let Err(e) = write_message(stream, message(AckMessage::Failure)).await {
+                    warn!("Failed to send Fail message to distributor for pid {pid:?}: {e}");
+                }
+                return;
+            }
+        }
+    } else {
+        makefile
+    };
+    info!("Handling incoming makefile: {}", makefile.to_string());
+
+    process_datas.parallel_jobs = match lex(makefile.makefile().clone()) {
+        Ok(tokens) => parallel_jobs_of(&tokens),
+        Err(errors) => {
+            warn!(
+                "Failed to lex the makefile of {pid:?}: {} errors",
+                errors.len()
+            );
+            None
+        }
+    };
+
     // Registering the new makefile in the shared database
     state
         .set_process_datas(process_datas.pid.clone(), process_datas)
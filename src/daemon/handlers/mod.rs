@@ -1,18 +1,28 @@
+mod cancel_handler;
 mod done_handle;
+mod env_handler;
 mod error_handler;
 mod fetch_handler;
 mod fresh_request_handler;
+mod hello_handler;
 mod log_handler;
 mod makefile_handler;
+mod ping_handler;
+mod status_handler;
 
 mod new_process_handler;
 
 pub use self::{
+    cancel_handler::{handle_cancel_all, handle_cancel_process},
     done_handle::handle_done,
+    env_handler::handle_get_process_env,
     error_handler::handle_error,
-    fetch_handler::handle_fetch,
+    fetch_handler::{handle_fetch, handle_fetch_batch},
     fresh_request_handler::handle_fresh_request,
-    log_handler::{OutputFile, handle_log},
+    hello_handler::handle_hello,
+    log_handler::{OutputFile, handle_batch_log, handle_output},
     makefile_handler::receiv_makefile,
-    new_process_handler::new_process,
+    new_process_handler::{new_process, report_distribution_failure},
+    ping_handler::handle_ping,
+    status_handler::{handle_get_status, handle_query_status},
 };
@@ -3,27 +3,38 @@
 //! Handles incoming [`DaemonMessage::NewProcess`] messages.
 //!
 //! ## Responsibilities
+//! - Check that every involved host answers a ping before starting anything
 //! - Distribute remote makefiles to the necessary hosts via [`distribute`]
-//! - Register process metadata in the shared [`State`]
+//! - Register process metadata in the shared [`State`](crate::daemon::State)
 //! - Spawn and monitor a local `make` process
 //! - Stream logs and final results back to the caller
 //! - Forward termination or error notifications to all involved hosts
 //!
 //! ## Behavior
-//! - If distribution fails, error forwarding is **not yet implemented**
+//! - If a host is unreachable or the distribution fails, the error is reported to the caller through
+//!   [`report_distribution_failure`] and the process is removed from the state
 //! - The function runs until the local process completes or a `Notif::Error` is received
 
 use crate::{
+    constants::{EXIT_CODE_FAILURE, PING_TIMEOUT},
     daemon::{
-        MessageCtx, Notif, broadcast_done, distribute, execute_make, handlers::OutputFile,
-        process_datas::ProcessDatas,
+        MessageCtx, Notif, State, archive_completed_build, broadcast_done, distribute,
+        execute_make, handlers::OutputFile, process_datas::ProcessDatas,
     },
+    lexer::{guess_path_and_lex_in, parallel_jobs_of},
     lock,
     makefile::RemoteMakefile,
-    network::{Message, ProcessMessage, SocketAddr, write_message},
+    network::{
+        DaemonMessage, Message, MessageKind, Negotiated, ProcessMessage, SocketAddr, Stream, Timed,
+        broadcast_and_collect_responses, write_message,
+    },
+    process_id::ProcessId,
+    utils::now_ms,
 };
-use tokio::select;
-use tracing::{error, info, warn};
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use tokio::{io::AsyncWriteExt, select};
+use tracing::{Span, error, info, warn};
 
 fn remove_x_and_next<T: PartialEq + Clone>(v: &[T], x: T) -> Vec<T> {
     let mut skip_next = false;
@@ -44,6 +55,92 @@ fn remove_x_and_next<T: PartialEq + Clone>(v: &[T], x: T) -> Vec<T> {
         .collect()
 }
 
+/// Reports a failed distribution to the caller: the error is sent as a stderr log,
+/// followed by an [`ProcessMessage::End`] with a failure exit code.
+pub async fn report_distribution_failure<S: AsyncWriteExt + Negotiated + Timed + Unpin>(
+    stream: &mut S,
+    pid: &ProcessId,
+    error: &anyhow::Error,
+) -> Result<()> {
+    info!("Sending error message to the user.");
+    let msg = ProcessMessage::StderrLog {
+        log: format!("Dake failed to distribute makefile to remote hosts: {error}"),
+        timestamp_ms: now_ms(),
+    };
+    write_message(stream, Message::new(msg, pid.clone()))
+        .await
+        .context("Failed to forward distribute error to client")?;
+
+    info!("Sending end message to the user.");
+    let msg = ProcessMessage::End {
+        exit_code: EXIT_CODE_FAILURE,
+    };
+    write_message(stream, Message::new(msg, pid.clone()))
+        .await
+        .context("Failed to send end message to client")
+}
+
+/// Reports `error` to the caller, see [`report_distribution_failure`], and forgets the
+/// process `pid`, registered by its fresh id but never built.
+async fn abort_distribution(
+    state: &State,
+    stream: &mut Stream,
+    pid: &ProcessId,
+    error: &anyhow::Error,
+) {
+    if let Err(e) = report_distribution_failure(stream, pid, error).await {
+        warn!(?pid, error=?e, "Failed to report the distribution failure to client");
+    } else {
+        info!("Distribution failure reported to the client");
+    }
+
+    info!("End of the process, cleaning state database.");
+    if let Err(e) = state.remove_process(pid).await {
+        warn!("Failed to clean the state database: {e:?}");
+    } else {
+        info!("Successfully cleaned the database")
+    }
+}
+
+/// Pings every host in parallel and fails with the list of the hosts that did
+/// not answer within [`PING_TIMEOUT`].
+async fn check_hosts_reachable(pid: &ProcessId, hosts: &[SocketAddr]) -> Result<()> {
+    let ping = Message::new(
+        DaemonMessage::Ping,
+        ProcessId::process_less(pid.project_id.clone()),
+    );
+    let responses = broadcast_and_collect_responses::<_, Message<ProcessMessage>>(
+        hosts.to_vec(),
+        ping,
+        MessageKind::ProcessMessage,
+        PING_TIMEOUT,
+    )
+    .await;
+
+    let unreachable: Vec<String> = responses
+        .into_iter()
+        .filter_map(|(sock, res)| match res {
+            Ok(Message {
+                inner: ProcessMessage::Pong { .. },
+                ..
+            }) => None,
+            Ok(msg) => {
+                warn!("Unexpected answer to the ping of {sock}: {msg:?}");
+                Some(sock.to_string())
+            }
+            Err(e) => {
+                warn!("{sock} did not answer the ping: {e:?}");
+                Some(sock.to_string())
+            }
+        })
+        .collect();
+
+    if !unreachable.is_empty() {
+        bail!("Unreachable hosts: {}", unreachable.join(", "));
+    }
+    Ok(())
+}
+
 /// Handles the creation and supervision of a new distributed `make` process.
 ///
 /// # Workflow
@@ -52,11 +149,15 @@ fn remove_x_and_next<T: PartialEq + Clone>(v: &[T], x: T) -> Vec<T> {
 /// 3. Spawns and monitors the local `make` process.
 /// 4. Forwards logs and handles error/cancel notifications.
 /// 5. Sends a final [`ProcessMessage::End`] to the originating client.
-#[tracing::instrument(skip(state, pid, args, stream, makefiles))]
+#[tracing::instrument(skip_all, fields(pid = %pid, hosts))]
 pub async fn new_process<'a>(
-    MessageCtx { state, pid, stream }: MessageCtx<'a>,
+    MessageCtx {
+        state, pid, stream, ..
+    }: MessageCtx<'a>,
     makefiles: Vec<RemoteMakefile>,
     args: Vec<String>,
+    env: HashMap<String, String>,
+    env_overrides: HashMap<String, String>,
 ) {
     info!("Starting new process handler for pid = {pid} with args = {args:?}.");
 
@@ -69,51 +170,64 @@ pub async fn new_process<'a>(
         .map(|m| SocketAddr::from(*m.sock()))
         .collect();
 
-    info!("Distributing makefiles to involved hosts: {involved_hosts:?}");
-    let process_datas = ProcessDatas::new(
-        pid.clone(),
-        daemon_addr,
-        involved_hosts.clone(),
-        file_less_args,
-    );
-
-    match distribute(pid.clone(), makefiles, process_datas.clone()).await {
-        Ok(_) => info!(?pid, "Makefiles successfully distributed"),
-        Err(e) => {
-            warn!("Failed to distribute the makefiles: {e}");
-
-            info!("Sending error message to the user.");
-            let msg = ProcessMessage::StderrLog {
-                log: format!("Dake failed to distribute makefile to remote hosts: {e}"),
-            };
-
-            if let Err(e) = write_message(stream, Message::new(msg, pid.clone())).await {
-                warn!(?pid, error=?e, "Failed to forward distribute error to client");
-            } else {
-                info!("Message sent successfully");
-            }
+    Span::current().record("hosts", involved_hosts.len());
 
-            info!("Sending end message to the user.");
-            let msg = ProcessMessage::End { exit_code: 1 };
+    if let Err(e) = check_hosts_reachable(&pid, &involved_hosts).await {
+        warn!("Aborting the process before distribution: {e}");
+        abort_distribution(&state, stream, &pid, &e).await;
+        return;
+    }
 
-            if let Err(e) = write_message(stream, Message::new(msg, pid.clone())).await {
-                warn!(?pid, error=?e, "Failed to send end message to client");
-            } else {
-                info!("Message sent successfully");
-            }
+    info!("Distributing makefiles to involved hosts: {involved_hosts:?}");
+    let process_datas = ProcessDatas {
+        env_overrides,
+        ..ProcessDatas::new(
+            pid.clone(),
+            daemon_addr,
+            involved_hosts.clone(),
+            file_less_args,
+            env,
+        )
+    };
 
-            info!("End of the process, cleaning state database.");
-            if let Err(e) = state.remove_process(&pid).await {
-                warn!("Failed to clean the state database: {e:?}");
-            } else {
-                info!("Successfully cleaned the database")
-            }
+    let compression_threshold = match state.config() {
+        Ok(config) => config.makefile_compression_threshold(),
+        Err(e) => {
+            warn!("Failed to read the config, makefiles won't be compressed: {e:?}");
+            usize::MAX
+        }
+    };
 
+    match distribute(
+        pid.clone(),
+        makefiles,
+        process_datas.clone(),
+        compression_threshold,
+    )
+    .await
+    {
+        Ok(bytes) => {
+            info!(?pid, "Makefiles successfully distributed");
+            state.metrics().add_makefile_bytes(bytes);
+        }
+        Err(e) => {
+            warn!("Failed to distribute the makefiles: {e}");
+            abort_distribution(&state, stream, &pid, &e).await;
             return;
         }
     }
 
     // --- Step 2: Register process in shared state ---
+    // Only read now, the job count of this host must not reach the other ones.
+    let parallel_jobs = guess_path_and_lex_in(pid.path())
+        .map(|tokens| parallel_jobs_of(&tokens))
+        .inspect_err(|e| warn!("Failed to read the parallel directive: {e:?}"))
+        .ok()
+        .flatten();
+    let process_datas = ProcessDatas {
+        parallel_jobs,
+        ..process_datas
+    };
     state.set_process_datas(pid.clone(), process_datas).await;
     info!(?pid, "Wrote process datas of {pid} in shared database");
 
@@ -133,15 +247,21 @@ pub async fn new_process<'a>(
         notifier_hub.subscribe(&pid, 100)
     };
 
+    if let Err(e) = state.start_build(pid.clone()).await {
+        warn!("Failed to register {pid:?} as an active build: {e:?}");
+    }
+
     let mut make = Box::pin(execute_make(
         &state,
         pid.clone(),
         pid.path().clone(),
         None,
+        None,
         &args,
     ));
 
     // Exit code placeholder — filled either by make completion or notification
+    let mut guilty = None;
     let exit_code = loop {
         select! {
             // Handle local make process completion
@@ -190,18 +310,30 @@ pub async fn new_process<'a>(
                         if let Err(e) = broadcast_done(&state, pid.clone()).await {
                             warn!(?pid, error=?e, "Failed to broadcast Done message");
                         }
+                        guilty = Some(guilty_node.clone());
                         break *exit_code;
                     }
-                    Notif::Log { output, log } => {
+                    Notif::Log { output, log, timestamp_ms } => {
                         info!(?pid, output=?output, "Forwarding log to client");
                         let msg = match output {
-                            OutputFile::Stdout => ProcessMessage::StdoutLog { log: log.to_string() },
-                            OutputFile::Stderr => ProcessMessage::StderrLog { log: log.to_string() },
+                            OutputFile::Stdout => ProcessMessage::StdoutLog { log: log.to_string(), timestamp_ms: *timestamp_ms },
+                            OutputFile::Stderr => ProcessMessage::StderrLog { log: log.to_string(), timestamp_ms: *timestamp_ms },
+                            OutputFile::Progress { percent, target } => ProcessMessage::Progress {
+                                percent: *percent,
+                                target: target.clone(),
+                            },
                         };
                         if let Err(e) = write_message(stream, Message::new(msg, pid.clone())).await {
                             warn!(?pid, error=?e, "Failed to forward log to client");
                         }
                     }
+                    Notif::BuildProgress { percent, target } => {
+                        info!(?pid, %target, percent, "Forwarding progress to client");
+                        let msg = ProcessMessage::Progress { percent: *percent, target: target.clone() };
+                        if let Err(e) = write_message(stream, Message::new(msg, pid.clone())).await {
+                            warn!(?pid, error=?e, "Failed to forward progress to client");
+                        }
+                    }
                     _ => {
                         info!(?pid, notif=?notif, "Ignoring irrelevant notification");
                         continue;
@@ -211,13 +343,38 @@ pub async fn new_process<'a>(
         }
     };
 
-    // --- Step 4: Send final End message ---
+    // --- Step 4: Send final NodeDone and End messages ---
+    // Only the node ending the build is known, the make of this node or the failing one.
+    let node = guilty.unwrap_or_else(|| state.daemon_sock().clone());
+    let node_done = Message::new(ProcessMessage::NodeDone { node, exit_code }, pid.clone());
+    if let Err(e) = write_message(stream, node_done).await {
+        warn!("Failed to send NodeDone message: {e}");
+    }
+
     let end_message = Message::new(ProcessMessage::End { exit_code }, pid.clone());
 
     match write_message(stream, end_message).await {
         Ok(_) => info!("Sent End message to caller"),
         Err(e) => warn!("Failed to send End message: {e}"),
     }
+    if let Err(e) = state.end_build(&pid, exit_code).await {
+        warn!("Failed to unregister {pid:?} from the active builds: {e:?}");
+    }
+
+    match state.read_process_data(&pid).await {
+        Ok(Some(datas)) => {
+            if let Err(e) = state.record_build(&datas, exit_code).await {
+                warn!(?pid, error=?e, "Failed to record the build in the history");
+            }
+        }
+        Ok(None) => info!(
+            ?pid,
+            "Process already removed, not recording it in the history"
+        ),
+        Err(e) => warn!(?pid, error=?e, "Failed to read the process datas"),
+    }
+
+    archive_completed_build(&state, &pid);
 
     info!(?pid, "NewProcess handler completed");
 }
@@ -0,0 +1,35 @@
+use tracing::{info, warn};
+
+use crate::{
+    daemon::MessageCtx,
+    network::{Message, ProcessMessage, write_message},
+    read_lock,
+};
+
+/// Answers a liveness check with the uptime and the load of the daemon.
+///
+/// Awaited by the dispatch loop of the connection, so the pong goes back on the
+/// same stream as soon as the ping is read.
+#[tracing::instrument(skip_all)]
+pub async fn handle_ping<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+) {
+    let active_processes = match read_lock!(state.processes()).await {
+        Ok(processes) => processes.len(),
+        Err(e) => {
+            warn!("Failed to lock the processes: {e:?}");
+            0
+        }
+    };
+    let msg = ProcessMessage::Pong {
+        uptime_secs: state.uptime().as_secs(),
+        active_processes,
+    };
+
+    info!("Answering a ping");
+    if let Err(e) = write_message(stream, Message::new(msg, pid)).await {
+        warn!("Failed to answer the ping: {e}");
+    }
+}
@@ -0,0 +1,80 @@
+use tracing::{info, warn};
+
+use crate::{
+    daemon::MessageCtx,
+    network::{Message, ProcessMessage, ProcessSummary, write_message},
+    read_lock,
+};
+
+/// Responds with the amount of running processes and, if `verbose`, a dump of the state.
+#[tracing::instrument(skip_all, fields(verbose = verbose))]
+pub async fn handle_get_status<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+    verbose: bool,
+) {
+    let active_processes = match read_lock!(state.processes()).await {
+        Ok(processes) => processes.len(),
+        Err(e) => {
+            warn!("Failed to lock the processes: {e:?}");
+            0
+        }
+    };
+
+    let snapshot = if verbose {
+        match state.snapshot().await {
+            Ok(snapshot) => Some(snapshot.to_string()),
+            Err(e) => {
+                warn!("Failed to snapshot the state: {e:?}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    info!("Sending the status, {active_processes} active processes");
+
+    let msg = ProcessMessage::Status {
+        active_processes,
+        snapshot,
+    };
+    if let Err(e) = write_message(stream, Message::new(msg, pid)).await {
+        warn!("Failed to send the status: {e}");
+    }
+}
+
+/// Responds with a summary of every process registered on the daemon, oldest first,
+/// and the amount of builds waiting for a slot.
+#[tracing::instrument(skip_all)]
+pub async fn handle_query_status<'a>(
+    MessageCtx {
+        pid, stream, state, ..
+    }: MessageCtx<'a>,
+) {
+    let mut processes: Vec<ProcessSummary> = match read_lock!(state.processes()).await {
+        Ok(processes) => processes
+            .iter()
+            .map(|(pid, datas)| ProcessSummary {
+                pid: pid.clone(),
+                started_at: datas.start_time,
+                elapsed_secs: datas.start_time.elapsed().unwrap_or_default().as_secs(),
+                involved_hosts: datas.involved_hosts.clone(),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Failed to lock the processes: {e:?}");
+            Vec::new()
+        }
+    };
+    processes.sort_by_key(|p| p.started_at);
+    info!("Sending the status report, {} processes", processes.len());
+
+    let msg = ProcessMessage::StatusReport {
+        processes,
+        queued_builds: state.queued_builds(),
+    };
+    if let Err(e) = write_message(stream, Message::new(msg, pid)).await {
+        warn!("Failed to send the status report: {e}");
+    }
+}
@@ -6,42 +6,65 @@
 //! - Accepting incoming TCP/Unix connections on the daemon sockets.
 //! - Reading and deserializing [`DaemonMessage`]s sent by callers or distributors.
 //! - Dispatching requests to the appropriate handler
+//! - Serving its metrics, see [`serve_metrics`]
+//! - Advertising itself on the local network, see [`advertise`]
 //!
-//! The daemon runs indefinitely, spawning tasks to handle each connection
-//! asynchronously.
+//! The daemon runs until it receives SIGTERM or SIGINT, spawning tasks to handle
+//! each connection asynchronously. On a signal it drains the processes in progress
+//! (see [`State::drain`]), still serving their connections but refusing any new build,
+//! then stops accepting connections and removes its Unix socket.
 
-use std::{
-    fs::remove_file,
-    net::{IpAddr, Ipv4Addr},
-    path::Path,
-};
+use std::{fs::remove_file, path::Path};
 
 use anyhow::{Context, Result};
+use bytes::BytesMut;
 use tokio::{
     net::{TcpListener, UnixListener},
+    select,
+    signal::{
+        ctrl_c,
+        unix::{SignalKind, signal},
+    },
     sync::mpsc::channel,
     task::spawn,
-    try_join,
+    time::interval,
 };
-use tracing::{info, warn};
+use tracing::{Instrument, info, info_span, warn};
 
 use crate::{
+    constants::{
+        ARTIFACT_SWEEP_INTERVAL, DRAIN_TIMEOUT, MESSAGE_BUFFER_CAPACITY,
+        STALE_PROCESS_SWEEP_INTERVAL,
+    },
     daemon::{
-        State,
-        fs::init_fs,
+        DaemonConfig, State,
+        discovery::advertise,
+        fs::{artifact_ttl_prune, init_fs, remove_expired_archives},
         handlers::{
-            OutputFile, handle_done, handle_error, handle_fetch, handle_fresh_request, handle_log,
+            handle_batch_log, handle_cancel_all, handle_cancel_process, handle_done, handle_error,
+            handle_fetch, handle_fetch_batch, handle_fresh_request, handle_get_process_env,
+            handle_get_status, handle_hello, handle_output, handle_ping, handle_query_status,
             new_process, receiv_makefile,
         },
         message_ctx::MessageCtx,
+        serve_metrics,
     },
     dec,
     network::{
-        DAEMON_UNIX_SOCKET, DaemonMessage, Message, MessageKind, SocketAddr, Stream, get_daemon_ip,
-        get_daemon_port, read_next_message,
+        DAEMON_UNIX_SOCKET, DaemonMessage, Message, MessageKind, OutputKind, SocketAddr, Stream,
+        TlsConfig, get_daemon_bind_ip, get_daemon_ip, read_next_message_zero_copy,
     },
 };
 
+/// Waits for the processes in progress, see [`State::drain`], failing the builds of
+/// this daemon still running after [`DRAIN_TIMEOUT`].
+async fn drain(state: &State) {
+    if let Err(e) = state.drain(DRAIN_TIMEOUT).await {
+        warn!("{e}");
+        state.fail_active_builds().await;
+    }
+}
+
 /// Starts the daemon listener.
 /// For each incoming [`DaemonMessage`], a new task is spawned to run the
 /// corresponding handler.
@@ -51,23 +74,43 @@ pub async fn start() -> Result<()> {
     init_fs()?;
     info!("Daemon filesystem initialized");
 
+    let config = DaemonConfig::load_or_generate().context("Failed to load the daemon config.")?;
+    let allowed_peers = config.allowed_peers()?;
+
     // Bind the daemon TCP listener socket
     info!("Starting TCP listening...");
-    let ip = get_daemon_ip().unwrap_or(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
-    let port = get_daemon_port();
+    let ip = get_daemon_bind_ip();
+    let port = config.listen_port();
 
-    let tcp_listener = TcpListener::bind(format!("{ip}:{port}"))
+    let tcp_listener = TcpListener::bind((ip, port))
         .await
         .context("When starting the daemon.")?;
 
-    let daemon_tcp_sock = SocketAddr::from(
-        tcp_listener
-            .local_addr()
-            .context("Failed to fetch the daemon socket from the daemon TCP listener.")?,
-    );
+    let local_addr = tcp_listener
+        .local_addr()
+        .context("Failed to fetch the daemon socket from the daemon TCP listener.")?;
+
+    // When listening on all interfaces, advertise the address other hosts can reach us on.
+    let daemon_tcp_sock = if local_addr.ip().is_unspecified() {
+        SocketAddr::new_tcp(
+            get_daemon_ip().unwrap_or(local_addr.ip()),
+            local_addr.port(),
+        )?
+    } else {
+        SocketAddr::from(local_addr)
+    };
 
     info!("Daemon started and listening on {}", daemon_tcp_sock);
 
+    // Advertised on the local network for `dake nodes`, the daemon works without it
+    let advertisement = daemon_tcp_sock
+        .get_tcp()
+        .map(advertise)
+        .transpose()
+        .inspect_err(|e| warn!("The daemon will not be discoverable: {e:?}"))
+        .ok()
+        .flatten();
+
     // Bind the daemon Unix listener socker
     info!("Starting UNIX socket listening...");
     let path = Path::new(DAEMON_UNIX_SOCKET);
@@ -84,7 +127,75 @@ pub async fn start() -> Result<()> {
         .context("Failed to fetch local unix addr: {e:?}")?;
 
     // Initialising state
-    let state = State::new(daemon_tcp_sock).context("Failed to init state.")?;
+    let artifact_ttl = config.artifact_ttl();
+    let stale_process_ttl = config.stale_process_ttl();
+    let state = State::new(daemon_tcp_sock, config).context("Failed to init state.")?;
+
+    if let Some(ttl) = artifact_ttl {
+        let state = state.clone();
+        spawn(async move {
+            let mut ticks = interval(ARTIFACT_SWEEP_INTERVAL);
+            loop {
+                ticks.tick().await;
+                match remove_expired_archives(ttl) {
+                    Ok(removed) => info!("Removed {removed} archives older than {ttl:?}"),
+                    Err(e) => warn!("Failed to remove the expired archives: {e:?}"),
+                }
+                let registered = match state.registered_processes().await {
+                    Ok(registered) => registered,
+                    Err(e) => {
+                        warn!("Not pruning the build folders, the processes are unknown: {e:?}");
+                        continue;
+                    }
+                };
+                match artifact_ttl_prune(ttl, &registered) {
+                    Ok(freed) => {
+                        info!("Pruned the build folders older than {ttl:?}, {freed} bytes freed")
+                    }
+                    Err(e) => warn!("Failed to prune the expired build folders: {e:?}"),
+                }
+            }
+        });
+    }
+
+    if let Some(max_age) = stale_process_ttl {
+        let state = state.clone();
+        spawn(async move {
+            let mut ticks = interval(STALE_PROCESS_SWEEP_INTERVAL);
+            loop {
+                ticks.tick().await;
+                match state.reap_stale(max_age).await {
+                    Ok(0) => {}
+                    Ok(reaped) => info!("Reaped {reaped} stale processes"),
+                    Err(e) => warn!("Failed to reap the stale processes: {e:?}"),
+                }
+            }
+        });
+    }
+
+    // The metrics are optional, the daemon runs without them if the port is taken
+    let metrics = state.metrics().clone();
+    spawn(async move {
+        if let Err(e) = serve_metrics(metrics).await {
+            warn!("The metrics endpoint is disabled: {e:?}");
+        }
+    });
+
+    // Loaded before accepting anything, a broken TLS setup must not fall back to plaintext
+    let tls = TlsConfig::get().context("Failed to load the TLS configuration.")?;
+    match tls {
+        Some(_) => info!("TCP connections are encrypted with TLS"),
+        None => info!("TLS is not configured, TCP connections are plaintext"),
+    }
+
+    // Installed before accepting anything, so a signal never kills a build abruptly
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM.")?;
+    let shutdown = async move {
+        select! {
+            _ = terminate.recv() => "SIGTERM",
+            Ok(()) = ctrl_c() => "SIGINT",
+        }
+    };
 
     // Spawn two tasks, one per listener
     let (tx, mut rx) = channel(100);
@@ -92,11 +203,38 @@ pub async fn start() -> Result<()> {
     let tcp_tx = tx.clone();
     let tcp_task = spawn(async move {
         loop {
-            match tcp_listener.accept().await {
+            let accepted = tcp_listener.accept().await.inspect(|(stream, addr)| {
+                // Log lines are small, they must not wait for Nagle's algorithm
+                if let Err(e) = stream.set_nodelay(true) {
+                    warn!("Failed to set TCP_NODELAY on the connection from {addr}: {e}");
+                }
+            });
+            match accepted {
+                Ok((_, addr))
+                    if !allowed_peers.is_empty()
+                        && !allowed_peers.iter().any(|net| net.contains(&addr.ip())) =>
+                {
+                    warn!("Dropping connection {addr}, it is not an allowed peer");
+                }
+                Ok((stream, addr)) if let Some(tls) = tls => {
+                    info!("TCP connection from {}, starting the TLS handshake", addr);
+                    // The handshake runs apart so a slow peer does not block the listener
+                    let tcp_tx = tcp_tx.clone();
+                    spawn(async move {
+                        match tls.accept(stream).await {
+                            Ok(stream) => {
+                                let _ = tcp_tx
+                                    .send((Stream::from(stream), SocketAddr::from(addr)))
+                                    .await;
+                            }
+                            Err(e) => warn!("Dropping connection {addr}: {e:?}"),
+                        }
+                    });
+                }
                 Ok((stream, addr)) => {
                     info!("TCP connection from {}", addr);
                     if tcp_tx
-                        .send((Stream::Tcp(stream), SocketAddr::from(addr)))
+                        .send((Stream::from(stream), SocketAddr::from(addr)))
                         .await
                         .is_err()
                     {
@@ -118,7 +256,7 @@ pub async fn start() -> Result<()> {
                 Ok((stream, addr)) => {
                     info!("UNIX connection accepted on {unix_addr:?} form {addr:?}",);
                     if unix_tx
-                        .send((Stream::Unix(stream), SocketAddr::from(addr)))
+                        .send((Stream::from(stream), SocketAddr::from(addr)))
                         .await
                         .is_err()
                     {
@@ -133,16 +271,59 @@ pub async fn start() -> Result<()> {
         }
     });
 
-    // Main accept loop: handle new TCP connections
-    while let Some((mut stream, addr)) = rx.recv().await {
-        // Spawn a task for this connection
+    drop(tx);
+
+    // On a signal the connections are still accepted while draining, the builds in
+    // progress need them to fetch their targets and to send their logs and ends
+    let mut drained = Box::pin({
         let state = state.clone();
-        spawn(async move {
+        async move {
+            let signal = shutdown.await;
+            info!("Received {signal}, draining the processes in progress");
+            drain(&state).await;
+        }
+    });
+
+    // Main accept loop: handle new connections until the daemon is drained
+    loop {
+        let (mut stream, addr) = select! {
+            conn = rx.recv() => match conn {
+                Some(conn) => conn,
+                None => {
+                    warn!("Both listeners stopped");
+                    drain(&state).await;
+                    break;
+                }
+            },
+            _ = &mut drained => {
+                info!("Shutting down");
+                break;
+            }
+        };
+        // Spawn a task for this connection, everything it logs is attached to its span
+        let state = state.clone();
+        let span = info_span!("connection", %addr);
+        let task = async move {
             info!("Daemon spawned task to handle connection from {}", addr);
+            let mut idle_timeout = match state.config() {
+                Ok(config) => config.idle_connection_timeout(),
+                Err(e) => {
+                    warn!("Failed to read the config, dropping connection {addr}: {e:?}");
+                    return;
+                }
+            };
+            // Reused for every message of the connection, logs are read at a high rate.
+            let mut buffer = BytesMut::with_capacity(MESSAGE_BUFFER_CAPACITY);
 
             loop {
                 // Read next DaemonMessage from this TCP stream
-                let message = match read_next_message(&mut stream, MessageKind::DaemonMessage).await
+                let message = match read_next_message_zero_copy(
+                    &mut stream,
+                    MessageKind::DaemonMessage,
+                    &mut buffer,
+                    idle_timeout,
+                )
+                .await
                 {
                     Ok(Some(msg)) => {
                         info!("Daemon received raw DaemonMessage from {}", addr);
@@ -159,7 +340,7 @@ pub async fn start() -> Result<()> {
                 };
 
                 // Attempt to deserialize the DaemonMessage
-                let message: Message<DaemonMessage> = match dec!(message) {
+                let mut message: Message<DaemonMessage> = match dec!(message) {
                     Ok(msg) => {
                         info!("Successfully decoded DaemonMessage from {}", addr);
                         msg
@@ -170,6 +351,33 @@ pub async fn start() -> Result<()> {
                     }
                 };
 
+                if let Some(caller_path) = message.inner.take_caller_path() {
+                    info!("Using the caller path {caller_path:?} sent along the fetch");
+                    message.pid.project_id.path = caller_path;
+                }
+
+                // A new build would keep the draining daemon waiting.
+                if state.is_draining()
+                    && matches!(
+                        message.inner,
+                        DaemonMessage::FreshId
+                            | DaemonMessage::NewMakefile { .. }
+                            | DaemonMessage::NewProcess { .. }
+                    )
+                {
+                    warn!("The daemon is shutting down, refusing a new build from {addr}");
+                    // Registered by its fresh id, the process would never be removed.
+                    if matches!(message.inner, DaemonMessage::NewProcess { .. })
+                        && let Err(e) = state.remove_process(&message.pid).await
+                    {
+                        warn!(
+                            "Failed to forget the refused process {:?}: {e:?}",
+                            message.pid
+                        );
+                    }
+                    break;
+                }
+
                 if message.pid.is_process_less() {
                     info!("Received a process less message.");
                 } else {
@@ -191,39 +399,79 @@ pub async fn start() -> Result<()> {
                     }
                 }
 
+                // A log stream stays open as long as its make runs, even a quiet one.
+                if idle_timeout.is_some()
+                    && matches!(
+                        message.inner,
+                        DaemonMessage::StdoutLog { .. }
+                            | DaemonMessage::StderrLog { .. }
+                            | DaemonMessage::BatchLog { .. }
+                    )
+                {
+                    info!("Connection {addr} carries logs, it is never dropped as idle");
+                    idle_timeout = None;
+                }
+
                 // Spawn another task for handling the specific message
                 let pid = message.pid.clone();
                 let ctx = MessageCtx::new(&mut stream, state.clone(), pid.clone());
 
                 match message.inner {
-                    DaemonMessage::NewProcess { makefiles, args } => {
+                    DaemonMessage::NewProcess {
+                        makefiles,
+                        args,
+                        env,
+                        env_overrides,
+                    } => {
                         info!("Handling NewProcess request from pid {:?}", pid);
-                        new_process(ctx, makefiles, args).await
+                        // Queued while the daemon runs its maximum amount of builds
+                        let _slot = state.acquire_build_slot().await;
+                        new_process(ctx, makefiles, args, env, env_overrides).await
                     }
                     DaemonMessage::NewMakefile {
                         makefile,
+                        compressed,
                         process_datas,
                     } => {
                         info!("Handling Distribute request from pid {:?}", pid);
-                        receiv_makefile(ctx, makefile, process_datas).await
+                        receiv_makefile(ctx, makefile, compressed, process_datas).await
                     }
                     DaemonMessage::Fetch {
                         target,
                         labeled_path,
+                        known_checksum,
+                        offset,
+                        parent,
+                        ..
                     } => {
                         info!(
                             "Handling Fetch request for target '{}' from pid {:?}",
                             target, pid
                         );
-                        handle_fetch(ctx, target, labeled_path).await
+                        handle_fetch(ctx, target, labeled_path, known_checksum, offset, parent)
+                            .await
+                    }
+                    DaemonMessage::FetchBatch {
+                        targets, parent, ..
+                    } => {
+                        info!(
+                            "Handling FetchBatch request for {} targets from pid {:?}",
+                            targets.len(),
+                            pid
+                        );
+                        handle_fetch_batch(ctx, targets, parent).await
                     }
-                    DaemonMessage::StdoutLog { log } => {
+                    DaemonMessage::StdoutLog { log, timestamp_ms } => {
                         info!("Handling new log from pid {pid:?}");
-                        handle_log(ctx, log, OutputFile::Stdout).await
+                        handle_output(ctx, log, OutputKind::Stdout, timestamp_ms).await
                     }
-                    DaemonMessage::StderrLog { log } => {
+                    DaemonMessage::StderrLog { log, timestamp_ms } => {
                         info!("Handling new err from pid {pid:?}");
-                        handle_log(ctx, log, OutputFile::Stderr).await
+                        handle_output(ctx, log, OutputKind::Stderr, timestamp_ms).await
+                    }
+                    DaemonMessage::BatchLog { logs } => {
+                        info!("Handling a batch of logs from pid {pid:?}");
+                        handle_batch_log(ctx, logs).await
                     }
                     DaemonMessage::MakeError {
                         guilty_node,
@@ -236,12 +484,55 @@ pub async fn start() -> Result<()> {
                     }
                     DaemonMessage::Done => handle_done(ctx).await,
                     DaemonMessage::FreshId => handle_fresh_request(ctx).await,
+                    DaemonMessage::GetProcessEnv => {
+                        info!("Handling env request for {pid:?}");
+                        handle_get_process_env(ctx).await
+                    }
+                    DaemonMessage::CancelProcess { pid } => {
+                        info!("Handling cancellation of {pid:?}");
+                        handle_cancel_process(ctx, pid).await
+                    }
+                    DaemonMessage::CancelAll { reason } => {
+                        info!("Handling cancellation of every build: {reason}");
+                        handle_cancel_all(ctx, reason).await
+                    }
+                    DaemonMessage::Ping => handle_ping(ctx).await,
+                    DaemonMessage::GetStatus { verbose } => {
+                        info!("Handling status request");
+                        handle_get_status(ctx, verbose).await
+                    }
+                    DaemonMessage::QueryStatus => {
+                        info!("Handling query of the builds in progress");
+                        handle_query_status(ctx).await
+                    }
+                    DaemonMessage::Hello {
+                        version,
+                        capabilities,
+                    } => handle_hello(ctx, version, capabilities).await,
+                    DaemonMessage::Unknown => {
+                        warn!("Received unknown DaemonMessage variant, ignoring")
+                    }
                 }
             }
             info!("Daemon task for {} terminated", addr);
-        });
+        };
+        spawn(task.instrument(span));
+    }
+
+    tcp_task.abort();
+    unix_task.abort();
+    if let Err(e) = state.checkpoint().await {
+        warn!("Failed to checkpoint the state: {e:?}");
     }
 
-    try_join!(tcp_task, unix_task)?;
+    if let Some(mdns) = advertisement
+        && let Err(e) = mdns.shutdown()
+    {
+        warn!("Failed to stop the mDNS advertisement: {e}");
+    }
+    if let Err(e) = remove_file(DAEMON_UNIX_SOCKET) {
+        warn!("Failed to remove {DAEMON_UNIX_SOCKET}: {e}");
+    }
+    info!("Daemon stopped");
     Ok(())
 }
@@ -0,0 +1,59 @@
+//! # Build History
+//!
+//! Keeps a rolling record of the last completed builds so they can be inspected
+//! once the process has been removed from the processes database.
+//!
+//! The history is persisted as JSON in `<dake_path>/history.json` and reloaded
+//! when the daemon starts.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, read_to_string},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{daemon::fs::init_fs, network::SocketAddr, process_id::ProcessId};
+
+const HISTORY_NAME: &str = "history.json";
+
+/// Summary of a completed build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub pid: ProcessId,
+    pub start_time: SystemTime,
+    pub end_time: SystemTime,
+    pub exit_code: i32,
+    pub involved_hosts: Vec<SocketAddr>,
+}
+
+fn path() -> Result<PathBuf> {
+    let mut path = init_fs()?;
+    path.push(HISTORY_NAME);
+    Ok(path)
+}
+
+/// Loads the build history from disk, returns an empty history if none was saved yet.
+pub fn load_history() -> Result<VecDeque<BuildRecord>> {
+    let path = path()?;
+    if !path.exists() {
+        info!("No build history found at {path:?}");
+        return Ok(VecDeque::new());
+    }
+    let data =
+        read_to_string(&path).context(format!("Failed to read build history at {:?}", path))?;
+    serde_json::from_str(&data).context("Failed to parse the build history.")
+}
+
+/// Atomically replaces the build history stored on disk.
+pub fn save_history(history: &VecDeque<BuildRecord>) -> Result<()> {
+    let path = path()?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(history)?)
+        .context("Failed to write build history temp file")?;
+    fs::rename(tmp, path).context("Failed to atomically replace build history file")
+}
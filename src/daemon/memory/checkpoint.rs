@@ -0,0 +1,68 @@
+//! # State Checkpoint
+//!
+//! Persists the next process id of each project, so a restarted daemon never hands
+//! out an id it already gave before. The processes themselves are not persisted,
+//! they died with the previous daemon.
+//!
+//! The checkpoint is stored as JSON in `<dake_path>/state.json` and reloaded when
+//! the daemon starts.
+
+use std::{
+    collections::HashMap,
+    fs::{self, read_to_string},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{daemon::fs::init_fs, process_id::ProjectId};
+
+const CHECKPOINT_NAME: &str = "state.json";
+
+/// Next id of a project, a project id cannot be a JSON key.
+#[derive(Serialize, Deserialize)]
+struct IdEntry {
+    project_id: ProjectId,
+    next_id: u64,
+}
+
+fn path() -> Result<PathBuf> {
+    let mut path = init_fs()?;
+    path.push(CHECKPOINT_NAME);
+    Ok(path)
+}
+
+/// Loads the next id of each project, returns an empty map if no checkpoint was saved yet.
+pub fn load_checkpoint() -> Result<HashMap<ProjectId, u64>> {
+    let path = path()?;
+    if !path.exists() {
+        info!("No state checkpoint found at {path:?}");
+        return Ok(HashMap::new());
+    }
+    let data =
+        read_to_string(&path).context(format!("Failed to read the checkpoint at {path:?}"))?;
+    let entries: Vec<IdEntry> =
+        serde_json::from_str(&data).context("Failed to parse the state checkpoint.")?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.project_id, entry.next_id))
+        .collect())
+}
+
+/// Atomically replaces the checkpoint stored on disk.
+pub fn save_checkpoint(ids: &HashMap<ProjectId, u64>) -> Result<()> {
+    let entries: Vec<IdEntry> = ids
+        .iter()
+        .map(|(project_id, next_id)| IdEntry {
+            project_id: project_id.clone(),
+            next_id: *next_id,
+        })
+        .collect();
+    let path = path()?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&entries)?)
+        .context("Failed to write the checkpoint temp file")?;
+    fs::rename(tmp, path).context("Failed to atomically replace the checkpoint file")
+}
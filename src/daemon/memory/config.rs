@@ -1,49 +1,270 @@
+//! # Daemon Config
+//!
+//! Settings of the daemon, persisted as TOML in `<dake_path>/config.toml`.
+//!
+//! Every field has a default, so a config file written by an older version,
+//! or edited by hand, only needs to contain the keys it overrides.
+//!
+//! The file lives in the dake space rather than in the user config directory: it
+//! also holds the id and the pid of the daemon, and each daemon of a host has its
+//! own dake space. Environment variables override the values of the file, see
+//! [`DaemonConfig::load_or_generate`].
+
 use std::{
+    env::var,
+    fmt::Display,
     fs::{self, read_to_string},
     path::PathBuf,
+    str::FromStr,
+    time::Duration,
 };
 
 use anyhow::{Context, Result};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use sysinfo::{Pid, ProcessesToUpdate, System};
-use tracing::{info, warn};
+use tracing::{Level, info, warn};
+
+use crate::{daemon::fs::init_fs, env_variables::EnvVariable, network::DEFAULT_PORT};
+
+const CONFIG_NAME: &str = "config.toml";
+const DEFAULT_HISTORY_SIZE: usize = 100;
+const DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_MAKEFILE_COMPRESSION_THRESHOLD: usize = 1024;
+const DEFAULT_LOG_LEVEL: Level = Level::INFO;
+const DEFAULT_MAX_CONCURRENT_BUILDS: usize = 4;
+const DEFAULT_STALE_PROCESS_TTL_SECS: u64 = 2 * 60 * 60;
 
-use crate::daemon::{DaemonId, fs::init_fs};
+const DEFAULT_ENV_PASSTHROUGH: [&str; 9] = [
+    "CC",
+    "CXX",
+    "CFLAGS",
+    "CXXFLAGS",
+    "LDFLAGS",
+    "PATH",
+    "PKG_CONFIG_PATH",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+];
+
+fn default_env_passthrough() -> Vec<String> {
+    DEFAULT_ENV_PASSTHROUGH.map(String::from).to_vec()
+}
 
-const CONFIG_NAME: &str = "config.json";
+/// Replaces `value` by the content of `variable` if it is set and parses.
+fn env_override<T>(variable: EnvVariable, value: &mut T)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(content) = var(variable.to_string()) {
+        match content.parse() {
+            Ok(parsed) => *value = parsed,
+            Err(e) => {
+                warn!("Failed to parse the content of {variable}, keeping the config value: {e}")
+            }
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Clone, Copy, Default, Hash)]
+#[derive(Serialize, Deserialize, Clone, Hash)]
+#[serde(default)]
 pub struct DaemonConfig {
     os_pid: u32,
-    id: DaemonId,
+
+    /// Whether the build folders should be archived once their process is done.
+    archive_completed_builds: bool,
+
+    /// Amount of completed builds kept in the build history.
+    history_size: usize,
+
+    /// Environment variables of the caller forwarded to the make processes.
+    env_passthrough: Vec<String>,
+
+    /// How long the caller reuses the result of an unchanged build, 0 disables the cache.
+    build_cache_ttl_secs: u64,
+
+    /// How long an incoming connection may stay silent before the daemon drops it, 0 disables it.
+    ///
+    /// Connections forwarding logs are exempt, a make may stay quiet for a long time.
+    idle_connection_timeout_secs: u64,
+
+    /// Size in bytes above which the distributed makefiles are compressed.
+    makefile_compression_threshold: usize,
+
+    /// Amount of builds started by this daemon that may run at once, 0 for no limit.
+    /// The builds past the limit wait for a running one to end.
+    max_concurrent_builds: usize,
+
+    /// IPs or subnets (`192.168.1.0/24`) allowed to connect over TCP, everyone if empty.
+    allowed_peers: Vec<String>,
+
+    /// How long the build archives and the build folders are kept, 0 keeps them forever.
+    artifact_ttl_secs: u64,
+
+    /// How long a process may stay registered without a done, 0 keeps it until the done.
+    stale_process_ttl_secs: u64,
+
+    /// Port the daemon listens on.
+    listen_port: u16,
+
+    /// Most verbose level logged by the daemon, in debug builds.
+    log_level: String,
 }
 
-impl DaemonConfig {
-    fn fresh() -> Self {
+/// A fresh config, owned by the current process under a newly generated id.
+impl Default for DaemonConfig {
+    fn default() -> Self {
         Self {
             os_pid: std::process::id(),
-            id: DaemonId::generate(),
+            archive_completed_builds: false,
+            history_size: DEFAULT_HISTORY_SIZE,
+            env_passthrough: default_env_passthrough(),
+            build_cache_ttl_secs: 0,
+            idle_connection_timeout_secs: DEFAULT_IDLE_CONNECTION_TIMEOUT_SECS,
+            makefile_compression_threshold: DEFAULT_MAKEFILE_COMPRESSION_THRESHOLD,
+            max_concurrent_builds: DEFAULT_MAX_CONCURRENT_BUILDS,
+            allowed_peers: Vec::new(),
+            artifact_ttl_secs: 0,
+            stale_process_ttl_secs: DEFAULT_STALE_PROCESS_TTL_SECS,
+            listen_port: DEFAULT_PORT,
+            log_level: DEFAULT_LOG_LEVEL.to_string(),
         }
     }
+}
 
+impl DaemonConfig {
+    /// Returns whether the daemon owning the saved config is alive.
+    ///
+    /// The current process does not count, so the daemon can check for another
+    /// instance after generating its own config.
     pub fn is_running() -> bool {
         info!("Checking weather the daemon is running or no.");
         match Self::load() {
-            Ok(Some(config)) => {
+            Ok(config) if config.os_pid == std::process::id() => false,
+            Ok(config) => {
                 let mut sys = System::new_all();
                 sys.refresh_processes(ProcessesToUpdate::All, true);
                 sys.process(Pid::from(config.os_pid as usize)).is_some()
             }
-            Ok(None) => false,
             Err(e) => {
-                warn!("Failed to fetch config file: {e}");
+                info!("No usable config, the daemon is not running: {e:#}");
                 false
             }
         }
     }
 
-    pub fn id(&self) -> DaemonId {
-        self.id
+    pub fn archive_completed_builds(&self) -> bool {
+        self.archive_completed_builds
+    }
+
+    pub fn history_size(&self) -> usize {
+        self.history_size
+    }
+
+    pub fn env_passthrough(&self) -> &[String] {
+        &self.env_passthrough
+    }
+
+    /// Returns the variables to forward from the saved config, or the default list
+    /// if the config cannot be read. Used by the caller, which must not generate a config.
+    pub fn load_env_passthrough() -> Vec<String> {
+        match Self::load() {
+            Ok(config) => config.env_passthrough,
+            Err(e) => {
+                info!("Failed to load the config, using the default env passthrough: {e:#}");
+                default_env_passthrough()
+            }
+        }
+    }
+
+    pub fn build_cache_ttl_secs(&self) -> u64 {
+        self.build_cache_ttl_secs
+    }
+
+    pub fn makefile_compression_threshold(&self) -> usize {
+        self.makefile_compression_threshold
+    }
+
+    pub fn idle_connection_timeout(&self) -> Option<Duration> {
+        (self.idle_connection_timeout_secs > 0)
+            .then(|| Duration::from_secs(self.idle_connection_timeout_secs))
+    }
+
+    /// Returns the build limit, `None` if builds are not limited.
+    pub fn max_concurrent_builds(&self) -> Option<usize> {
+        (self.max_concurrent_builds > 0).then_some(self.max_concurrent_builds)
+    }
+
+    /// Returns the networks allowed to connect, an empty list allows everyone.
+    ///
+    /// # Errors
+    /// Fails if an entry is neither an IP nor a subnet.
+    pub fn allowed_peers(&self) -> Result<Vec<IpNet>> {
+        self.allowed_peers
+            .iter()
+            .map(|peer| {
+                peer.parse::<IpNet>()
+                    .or_else(|_| peer.parse::<std::net::IpAddr>().map(IpNet::from))
+                    .context(format!("Invalid allowed peer {peer}."))
+            })
+            .collect()
+    }
+
+    pub fn artifact_ttl(&self) -> Option<Duration> {
+        (self.artifact_ttl_secs > 0).then(|| Duration::from_secs(self.artifact_ttl_secs))
+    }
+
+    /// Returns the age past which a process is reaped, `None` if processes are never reaped.
+    pub fn stale_process_ttl(&self) -> Option<Duration> {
+        (self.stale_process_ttl_secs > 0).then(|| Duration::from_secs(self.stale_process_ttl_secs))
+    }
+
+    pub fn listen_port(&self) -> u16 {
+        self.listen_port
+    }
+
+    pub fn log_level(&self) -> Level {
+        self.log_level.parse().unwrap_or_else(|e| {
+            warn!(
+                "Invalid log level {}, using {DEFAULT_LOG_LEVEL}: {e}",
+                self.log_level
+            );
+            DEFAULT_LOG_LEVEL
+        })
+    }
+
+    /// Returns the port of the daemon from the saved config, or [`DEFAULT_PORT`] if the
+    /// config cannot be read. Used by the caller, which must not generate a config.
+    pub fn load_listen_port() -> u16 {
+        match Self::load() {
+            Ok(config) => config.listen_port,
+            Err(e) => {
+                info!("Failed to load the config, using the default port: {e:#}");
+                DEFAULT_PORT
+            }
+        }
+    }
+
+    /// Returns the log level of the daemon, from the saved config if any, with the
+    /// environment overrides. Used before the logs are initialized.
+    pub fn load_log_level() -> Level {
+        Self::load()
+            .unwrap_or_default()
+            .with_env_overrides()
+            .log_level()
+    }
+
+    /// Returns the build cache ttl from the saved config, or 0 (disabled) if the config
+    /// cannot be read. Used by the caller, which must not generate a config.
+    pub fn load_build_cache_ttl_secs() -> u64 {
+        match Self::load() {
+            Ok(config) => config.build_cache_ttl_secs,
+            Err(e) => {
+                info!("Failed to load the config, disabling the build cache: {e:#}");
+                0
+            }
+        }
     }
 
     fn path() -> Result<PathBuf> {
@@ -52,29 +273,59 @@ impl DaemonConfig {
         Ok(path)
     }
 
+    /// Applies the environment variables overriding the values of the file.
+    fn with_env_overrides(mut self) -> Self {
+        env_override(EnvVariable::DaemonPort, &mut self.listen_port);
+        env_override(EnvVariable::MaxJobs, &mut self.max_concurrent_builds);
+        env_override(EnvVariable::ArtifactTtl, &mut self.artifact_ttl_secs);
+        env_override(
+            EnvVariable::StaleProcessTtl,
+            &mut self.stale_process_ttl_secs,
+        );
+        env_override(EnvVariable::LogLevel, &mut self.log_level);
+        if let Ok(peers) = var(EnvVariable::AllowedPeers.to_string()) {
+            self.allowed_peers = peers
+                .split(',')
+                .map(str::trim)
+                .filter(|peer| !peer.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        self
+    }
+
+    /// Loads the saved config, or saves and returns a fresh one if it cannot be loaded.
+    ///
+    /// A config file that fails to parse is kept aside as `config.toml.bak`. The
+    /// environment overrides are applied to the returned config, not saved.
     pub fn load_or_generate() -> Result<Self> {
-        Self::load()?.map(Ok).unwrap_or_else(|| {
-            let config = Self::fresh();
+        let config = Self::load().or_else(|e| {
+            info!("Generating a fresh config: {e:#}");
+            let path = Self::path()?;
+            if path.exists() {
+                let backup = path.with_extension("toml.bak");
+                warn!("The config at {path:?} is invalid, moving it to {backup:?}");
+                fs::rename(&path, backup).context("Failed to back up the invalid config")?;
+            }
+            let config = Self::default();
             config.save()?;
-            Ok(config)
-        })
+            Ok::<_, anyhow::Error>(config)
+        })?;
+        Ok(config.with_env_overrides())
     }
 
-    fn load() -> Result<Option<Self>> {
+    pub fn load() -> Result<Self> {
         let path = Self::path()?;
-        if !path.exists() {
-            return Ok(None);
-        }
         let data =
-            read_to_string(&path).context(format!("Failed to read daemon state at {:?}", path))?;
-        Ok(Some(serde_json::from_str(&data)?))
+            read_to_string(&path).context(format!("Failed to read daemon config at {path:?}"))?;
+        toml::from_str(&data).context(format!("Failed to parse daemon config at {path:?}"))
     }
 
-    fn save(&self) -> Result<()> {
+    pub fn save(&self) -> Result<()> {
         let path = Self::path()?;
         let tmp = path.with_extension("tmp");
-        fs::write(&tmp, serde_json::to_string_pretty(self)?)
-            .context("Failed to write daemon state temp file")?;
-        fs::rename(tmp, path).context("Failed to atomically replace daemon state file")
+        fs::write(&tmp, toml::to_string_pretty(self)?)
+            .context("Failed to write daemon config temp file")?;
+        fs::rename(tmp, path).context("Failed to atomically replace daemon config file")
     }
 }
@@ -1,20 +1,69 @@
-use std::{fmt::Display, ops::Deref, str::FromStr};
+//! # Daemon Id
+//!
+//! Every daemon is identified by a random UUID, generated on its first start and
+//! stored in `<dake_path>/daemon_id`. The id does not depend on the address of the
+//! daemon, so the projects it embeds are still found after a change of port.
+
+use std::{
+    fmt::Display,
+    fs::{self, read_to_string},
+    ops::Deref,
+    path::PathBuf,
+    str::FromStr,
+};
 
 use anyhow::{Context, Error, Result};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 use uuid::Uuid;
 
+use crate::daemon::fs::init_fs;
+
+const DAEMON_ID_NAME: &str = "daemon_id";
+
 #[derive(Clone, Copy, Hash, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
-pub struct DaemonId(u128);
+pub struct DaemonId(Uuid);
 
 impl DaemonId {
     pub fn generate() -> Self {
-        Self(Uuid::new_v4().as_u128())
+        Self(Uuid::new_v4())
+    }
+
+    /// Loads the id of this host, generating and saving it on the first start.
+    ///
+    /// # Errors
+    /// Fails if the saved id cannot be read or parsed, or a new one cannot be saved.
+    pub fn load_or_generate() -> Result<Self> {
+        let path = Self::path()?;
+        if path.exists() {
+            let content = read_to_string(&path)
+                .context(format!("Failed to read the daemon id at {path:?}"))?;
+            return content.trim().parse();
+        }
+
+        let id = Self::generate();
+        info!("Generated the daemon id {id}");
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, id.to_string()).context("Failed to write the daemon id temp file")?;
+        fs::rename(tmp, &path).context("Failed to save the daemon id")?;
+        Ok(id)
+    }
+
+    fn path() -> Result<PathBuf> {
+        let mut path = init_fs()?;
+        path.push(DAEMON_ID_NAME);
+        Ok(path)
+    }
+
+    /// Id used by tests for the local daemon.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn local() -> Self {
+        Self::default()
     }
 }
 
 impl Deref for DaemonId {
-    type Target = u128;
+    type Target = Uuid;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -28,8 +77,9 @@ impl FromStr for DaemonId {
     }
 }
 
+/// Formats the id as a hyphenated UUID, `67e55044-10b1-426f-9247-bb680e5fe0c8`.
 impl Display for DaemonId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.0.hyphenated())
     }
 }
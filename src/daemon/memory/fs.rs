@@ -3,47 +3,47 @@
 //! This module handles filesystem interactions for Dake.  
 //!
 //! Responsibilities include:
-//! - Determining the persistent Dake working directory using `directories`.
-//! - Initializing the filesystem structure on demand.
+//! - Initializing the filesystem structure on demand, in the directory given by
+//!   [`get_dake_path`].
 //! - Hashing process identifiers into unique filenames for storing remote makefiles.
 //! - Writing remote makefiles received from other daemons.
+//! - Storing the built artifacts by content, so identical artifacts are stored once.
+//! - Archiving completed build directories as `tar.gz` files, and removing the expired ones.
+//! - Pruning the build folders left untouched for too long.
 //!
 //! The design ensures that each `ProcessId` gets a unique hashed path, avoiding
 //! collisions while remaining deterministic.
 
 use anyhow::{Context, Result, bail};
 use blake3::{self, Hash};
-use directories::ProjectDirs;
+use flate2::{Compression, write::GzEncoder};
 use std::{
-    env::var,
-    fs::{create_dir, create_dir_all, read_dir, remove_dir_all, remove_file, write},
-    path::PathBuf,
+    fs::{File, create_dir, create_dir_all, read_dir, remove_dir_all, remove_file, rename},
+    io::{Seek, SeekFrom, Write, copy},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
+use tempfile::NamedTempFile;
 use tracing::{error, info, warn};
 
-use crate::{env_variables::EnvVariable, makefile::RemoteMakefile, process_id::ProcessId};
+use crate::{
+    constants::ARTIFACT_PRUNE_MIN_AGE, makefile::RemoteMakefile, process_id::ProcessId,
+    utils::get_dake_path,
+};
 
-/// Returns the base path for Dake's working directory.
-///
-/// The directory is chosen using the [`directories`] crate and follows
-/// the convention:
-/// `~/.local/share/dake` on Linux, or the platform equivalent.
-///
-/// # Errors
-/// Fails if the project directory cannot be determined.
-fn get_dake_path() -> Result<PathBuf> {
-    var(EnvVariable::DakeSpacePath.to_string())
-        .map(PathBuf::from)
-        .or_else(|_| {
-            ProjectDirs::from("com", "zivo_martin", "dake")
-                .context("When fetching the project path.")
-                .map(|d| d.project_path().to_path_buf())
-        })
-        .context("Failed to fetch dake space path.")
-}
+/// Name of the folder, inside the Dake working directory, holding the build archives.
+const ARCHIVES_DIR: &str = "archives";
 
-/// Initializes the Dake filesystem structure if not already present.
+/// Name of the folder, inside the Dake working directory, holding the artifacts by content.
+const CAS_DIR: &str = "cas";
+
+/// Name of the folder, inside the Dake working directory, holding the caller caches.
+const CACHE_DIR: &str = "cache";
+
+/// Name of the makefile written in each build folder.
+const MAKEFILE_NAME: &str = "Makefile";
 
+/// Initializes the Dake filesystem structure if not already present.
 ///
 /// If the directory exists but is not a directory, this function fails.
 ///
@@ -67,6 +67,14 @@ pub fn init_fs() -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Returns the cache folder of the Dake working directory, creating it if needed.
+pub fn get_cache_dir() -> Result<PathBuf> {
+    let mut path = init_fs()?;
+    path.push(CACHE_DIR);
+    create_dir_all(&path).context("Failed to create the cache directory")?;
+    Ok(path)
+}
+
 /// Creates a hash from the [`ProcessId`], used to derive unique file paths.
 ///
 /// The hash includes:
@@ -118,9 +126,9 @@ pub fn push_makefile(makefile: &RemoteMakefile, pid: &ProcessId) -> Result<()> {
     }
     info!("Creation of the build directory for {pid:?} has been a success.");
 
-    path.push("Makefile");
+    path.push(MAKEFILE_NAME);
 
-    write(&path, makefile.makefile())
+    write_atomically(&path, makefile.makefile().as_bytes())
         .context("Failed to write the Makefile.")
         .map(|_| {
             info!(
@@ -130,12 +138,244 @@ pub fn push_makefile(makefile: &RemoteMakefile, pid: &ProcessId) -> Result<()> {
         })
 }
 
-/// Recursively deletes a file or directory and logs the total size removed.
-pub fn clean() -> Result<()> {
+/// Replaces the file at `path` with `content`, through a temporary file of the same
+/// directory renamed over it.
+///
+/// The file is always either its previous or its new complete version, even if the
+/// daemon dies while writing. The temporary file is removed if the write fails.
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .context(format!("{path:?} has no parent directory."))?;
+    let mut file =
+        NamedTempFile::new_in(dir).context(format!("Failed to create a file in {dir:?}"))?;
+    file.write_all(content)
+        .context("Failed to write the temporary file.")?;
+    file.persist(path)
+        .context(format!("Failed to move the temporary file to {path:?}"))?;
+    Ok(())
+}
+
+/// Stores a copy of the artifact `source`, whose content hashes to `hash`, in the
+/// content-addressed folder of the Dake working directory.
+///
+/// The copy is named after the first 32 hex characters of `hash` and is only written if
+/// absent, so identical artifacts of different builds are stored once. The artifact is
+/// copied from its start and never linked: the build folder stays untouched, and a later
+/// make rewriting it in place cannot change the stored copy. Reusing a copy refreshes its
+/// modification time, see [`artifact_ttl_prune`].
+///
+/// This is blocking, async callers should run it in `spawn_blocking`.
+///
+/// # Returns
+/// The path of the stored artifact.
+pub fn store_artifact(source: &mut File, hash: &Hash) -> Result<PathBuf> {
+    let mut dir = init_fs()?;
+    dir.push(CAS_DIR);
+    create_dir_all(&dir).context("Failed to create the content-addressed directory.")?;
+    let path = dir.join(&hash.to_hex()[..32]);
+
+    if path.is_file() {
+        info!("The artifact {path:?} is already stored");
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|stored| stored.set_modified(SystemTime::now()))
+            .context(format!("Failed to refresh the stored artifact {path:?}."))?;
+        return Ok(path);
+    }
+
+    let mut tmp =
+        NamedTempFile::new_in(&dir).context(format!("Failed to create a file in {dir:?}"))?;
+    source.seek(SeekFrom::Start(0))?;
+    copy(source, &mut tmp).context("Failed to copy the artifact.")?;
+    tmp.persist(&path)
+        .context(format!("Failed to move the stored artifact to {path:?}"))?;
+    info!("Stored a new artifact at {path:?}");
+    Ok(path)
+}
+
+/// Removes the stored artifacts of `dir` last used more than `max_age` ago.
+///
+/// # Returns
+/// The amount of bytes freed.
+fn prune_stored_artifacts(dir: &Path, max_age: Duration) -> Result<u64> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut freed = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let age = meta.modified()?.elapsed().unwrap_or_default();
+        if meta.is_file() && age > max_age {
+            remove_file(entry.path())
+                .context(format!("Failed to remove the artifact {:?}.", entry.path()))?;
+            freed += meta.len();
+        }
+    }
+    if freed > 0 {
+        info!("Pruned {freed} bytes of stored artifacts from {dir:?}");
+    }
+    Ok(freed)
+}
+
+/// Returns the path of the archive associated with a [`ProcessId`].
+///
+/// Unlike the build folder, the archive is unique per process and not per project,
+/// so the numeric id is part of the hash.
+pub fn get_archive_path(pid: &ProcessId) -> Result<PathBuf> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(hash_socket_path(pid).as_bytes());
+    hasher.update(&pid.id().to_le_bytes());
+    let hash = format!("{}", hasher.finalize());
+
+    let mut path = init_fs()?;
+    path.push(ARCHIVES_DIR);
+    path.push(format!("{}.tar.gz", &hash[..32]));
+    Ok(path)
+}
+
+/// Archives the build folder of a [`ProcessId`] as a `tar.gz` in the Dake working directory.
+///
+/// The archive is first written in a temporary file and then moved in place, so a
+/// half-written archive is never visible.
+///
+/// # Returns
+/// The path of the created archive.
+///
+/// # Errors
+/// Fails if the build folder does not exist or if writing the archive fails.
+pub fn archive_build(pid: &ProcessId) -> Result<PathBuf> {
+    let build_dir = get_makefile_path(pid)?;
+    if !build_dir.is_dir() {
+        bail!("No build directory {build_dir:?} to archive for {pid:?}.");
+    }
+
+    let path = get_archive_path(pid)?;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).context("Failed to create the archives directory.")?;
+    }
+
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp).context("Failed to create the archive file.")?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder
+        .append_dir_all(".", &build_dir)
+        .context(format!("Failed to archive {build_dir:?}."))?;
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .context("Failed to finish the archive.")?;
+    rename(&tmp, &path).context("Failed to move the archive in place.")?;
+
+    info!("Archived the build of {pid:?} in {path:?}");
+    Ok(path)
+}
+
+/// Removes the build archives last modified more than `ttl` ago.
+///
+/// # Returns
+/// The amount of removed archives.
+pub fn remove_expired_archives(ttl: Duration) -> Result<usize> {
+    let mut path = init_fs()?;
+    path.push(ARCHIVES_DIR);
+    if !path.is_dir() {
+        return Ok(0);
+    }
+
+    let mut removed = 0;
+    for entry in read_dir(&path)? {
+        let entry = entry?;
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > ttl {
+            remove_file(entry.path())
+                .context(format!("Failed to remove the archive {:?}.", entry.path()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the build folders of the Dake working directory last modified more than
+/// `max_age` ago.
+///
+/// The folders of the `registered` processes are kept: the modification time of a
+/// folder only changes with its direct entries, so a long build may look idle. The
+/// archives have their own expiration, see [`remove_expired_archives`], and the caller
+/// caches are left to the caller. The stored artifacts, shared by every build, are
+/// removed one by one once unused for `max_age`, see [`store_artifact`].
+/// Entries younger than [`ARTIFACT_PRUNE_MIN_AGE`] are always kept, whatever `max_age`.
+///
+/// # Returns
+/// The amount of bytes freed.
+pub fn artifact_ttl_prune(max_age: Duration, registered: &[ProcessId]) -> Result<u64> {
+    let max_age = max_age.max(ARTIFACT_PRUNE_MIN_AGE);
+    let path = init_fs()?;
+    let in_use = registered
+        .iter()
+        .map(get_makefile_path)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut freed = prune_stored_artifacts(&path.join(CAS_DIR), max_age)?;
+    for entry in read_dir(&path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if [ARCHIVES_DIR, CACHE_DIR, CAS_DIR]
+            .iter()
+            .any(|dir| name == *dir)
+            || !entry.file_type()?.is_dir()
+            || in_use.contains(&entry.path())
+        {
+            continue;
+        }
+
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > max_age {
+            let dir = entry.path();
+            let size = calculate_size(&dir)?;
+            remove_dir_all(&dir).context(format!("Failed to prune {dir:?}."))?;
+            info!("Pruned {dir:?}, untouched for {age:?} ({size} bytes)");
+            freed += size;
+        }
+    }
+    Ok(freed)
+}
+
+/// Recursively deletes the Dake working directory and logs the total size removed.
+///
+/// If `keep_archives` is set, the build archives are preserved. The stored artifacts
+/// are always removed.
+pub fn clean(keep_archives: bool) -> Result<()> {
     let path = get_dake_path()?;
-    let size = calculate_size(&path)?;
-    let _ = remove_dir_all(&path);
-    info!("Removed {:?} ({} bytes)", path, size);
+    if !keep_archives {
+        let size = calculate_size(&path)?;
+        let _ = remove_dir_all(&path);
+        info!("Removed {:?} ({} bytes)", path, size);
+        return Ok(());
+    }
+
+    if !path.is_dir() {
+        info!("Nothing to clean at {path:?}");
+        return Ok(());
+    }
+
+    let mut size = 0;
+    for entry in read_dir(&path)? {
+        let entry = entry?.path();
+        if entry.file_name().is_some_and(|name| name == ARCHIVES_DIR) {
+            info!("Keeping the archives at {entry:?}");
+            continue;
+        }
+        size += calculate_size(&entry)?;
+        let _ = if entry.is_dir() {
+            remove_dir_all(&entry)
+        } else {
+            remove_file(&entry)
+        };
+    }
+    info!("Removed the content of {:?} ({} bytes)", path, size);
     Ok(())
 }
 
@@ -154,3 +394,30 @@ fn calculate_size(path: &PathBuf) -> Result<u64> {
         Ok(0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::write_atomically;
+    use std::fs::{read_dir, read_to_string, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn atomic_writes_leave_no_partial_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("Makefile");
+        write(&path, "all:\n\techo previous\n").unwrap();
+
+        write_atomically(&path, b"all:\n\techo next\n").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "all:\n\techo next\n");
+
+        // A failed write leaves no temporary file behind.
+        let missing = dir.path().join("missing").join("Makefile");
+        assert!(write_atomically(&missing, b"all:\n").is_err());
+
+        let files: Vec<_> = read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["Makefile"]);
+    }
+}
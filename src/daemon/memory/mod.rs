@@ -1,7 +1,14 @@
 pub mod fs;
 
+mod build_history;
+mod checkpoint;
 mod config;
 mod daemon_id;
 mod state;
 
-pub use {daemon_id::DaemonId, state::State};
+pub use {
+    build_history::{BuildRecord, load_history},
+    config::DaemonConfig,
+    daemon_id::DaemonId,
+    state::State,
+};
@@ -1,35 +1,90 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
     fmt::{Debug, Formatter},
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Result, bail};
-use notifier_hub::notifier::NotifierHub;
-use tokio::sync::Mutex;
+use notifier_hub::notifier::{ChannelState, NotifierHub};
+use serde_json::{Value, json};
+use tokio::{
+    sync::{Mutex, OwnedSemaphorePermit, RwLock, Semaphore},
+    task::spawn_blocking,
+    time::{sleep, timeout},
+};
 use tracing::{info, warn};
 
 use crate::{
-    constants::{CHANNEL_SIZE, INITIAL_PROCESS_ID},
-    daemon::{Notif, memory::config::DaemonConfig, process_datas::ProcessDatas},
+    constants::{
+        CHANNEL_SIZE, DONE_NOTIFICATION_TIMEOUT, DRAIN_END_TIMEOUT, DRAIN_POLL_INTERVAL,
+        EXIT_CODE_FAILURE, INITIAL_PROCESS_ID, MUTEX_LOCK_TIMEOUT, POOL_MAX_PER_ADDR,
+    },
+    daemon::{
+        DaemonId, DakeError, DakeMetrics, Notif, broadcast_done,
+        memory::{
+            build_history::{BuildRecord, load_history, save_history},
+            checkpoint::{load_checkpoint, save_checkpoint},
+            config::DaemonConfig,
+        },
+        process_datas::ProcessDatas,
+    },
     lock,
-    network::SocketAddr,
+    network::{Message, MessageTrait, Pool, SocketAddr, send_message},
     process_id::{ProcessId, ProjectId},
+    read_lock, write_lock,
 };
 
+/// Substrings marking an environment variable as sensitive in [`State::snapshot`].
+const REDACTED_ENV_PATTERNS: [&str; 4] = ["KEY", "TOKEN", "SECRET", "PASSWORD"];
+const REDACTED_VALUE: &str = "<redacted>";
+
+/// Channel of the notifications about the whole daemon, no process has this id.
+fn daemon_channel() -> ProcessId {
+    ProcessId::default()
+}
+
 type Wrapped<T> = Arc<Mutex<T>>;
 type Hub = Wrapped<NotifierHub<Arc<Notif>, ProcessId>>;
 type IdDatabase = Wrapped<HashMap<ProjectId, u64>>;
-type ProcessesDatabase = Wrapped<HashMap<ProcessId, ProcessDatas>>;
-type TargetLocksSet = Wrapped<HashSet<(ProjectId, String)>>;
+/// Read far more often than written (every log, fetch and ack looks the process up),
+/// a `RwLock` lets the readers run concurrently.
+type ProcessesDatabase = Arc<RwLock<HashMap<ProcessId, ProcessDatas>>>;
+/// Processes currently holding each target, a process appears once per build it runs.
+type TargetLocksSet = Wrapped<HashMap<(ProjectId, String), Vec<ProcessId>>>;
+/// Targets waited for in [`State::lock_target`] by the build of a target, keyed by the
+/// process and the target it builds, see [`WaitNode`].
+type WaitGraph = Wrapped<HashMap<WaitNode, Vec<String>>>;
+/// The build of a target by a process, the target is held by the process while it runs.
+type WaitNode = (ProcessId, String);
+type BuildHistory = Arc<RwLock<VecDeque<BuildRecord>>>;
+/// Builds started by this daemon whose caller still waits for the end, with their start.
+type ActiveBuilds = Wrapped<HashMap<ProcessId, Instant>>;
 
 #[derive(Clone)]
 pub struct State {
     id_database: IdDatabase,
     target_locks: TargetLocksSet,
+    wait_graph: WaitGraph,
     notifier_hub: Hub,
     processes: ProcessesDatabase,
+    build_history: BuildHistory,
+    /// Held while the history is written on disk, so its snapshots land in order.
+    history_writer: Wrapped<()>,
+    active_builds: ActiveBuilds,
+    metrics: Arc<DakeMetrics>,
+    /// Limits the builds running at once, `None` if they are not limited.
+    build_slots: Option<Arc<Semaphore>>,
+    queued_builds: Arc<AtomicUsize>,
+    /// Set once [`State::drain`] started, the daemon refuses the new builds from then on.
+    draining: Arc<AtomicBool>,
+    pool: Pool,
     config: DaemonConfig,
+    started_at: Instant,
+    daemon_id: DaemonId,
     pub daemon_sock: SocketAddr,
 }
 
@@ -42,25 +97,106 @@ impl Debug for State {
 }
 
 impl State {
-    pub fn new(daemon_sock: SocketAddr) -> Result<Self> {
+    /// Builds the state of the daemon, failing if another daemon is already running.
+    pub fn new(daemon_sock: SocketAddr, config: DaemonConfig) -> Result<Self> {
         if DaemonConfig::is_running() {
             bail!("Daemon is already running.")
         }
+        Ok(Self::with_config(daemon_sock, config))
+    }
 
-        let config = DaemonConfig::load_or_generate().context("Failed to generate config.")?;
-
-        Ok(Self {
+    /// Builds a state around `config` without loading it from the disk.
+    ///
+    /// The daemon id, the build history and the id checkpoint are still loaded. Unlike [`State::new`],
+    /// it does not check whether another daemon is running, so several states can
+    /// live in the same test.
+    pub fn with_config(daemon_sock: SocketAddr, config: DaemonConfig) -> Self {
+        let history = load_history().unwrap_or_else(|e| {
+            warn!("Failed to load the build history, starting from scratch: {e:?}");
+            VecDeque::new()
+        });
+        let daemon_id = DaemonId::load_or_generate().unwrap_or_else(|e| {
+            warn!("Failed to load the daemon id, using a temporary one: {e:?}");
+            DaemonId::generate()
+        });
+        let ids = load_checkpoint().unwrap_or_else(|e| {
+            warn!("Failed to load the state checkpoint, ids start from scratch: {e:?}");
+            HashMap::new()
+        });
+
+        Self {
             daemon_sock,
-            config: config,
             target_locks: Wrapped::default(),
-            id_database: Wrapped::default(),
+            wait_graph: Wrapped::default(),
+            id_database: Arc::new(Mutex::new(ids)),
             notifier_hub: Wrapped::default(),
-            processes: Wrapped::default(),
-        })
+            processes: Arc::default(),
+            build_history: Arc::new(RwLock::new(history)),
+            history_writer: Wrapped::default(),
+            active_builds: Wrapped::default(),
+            metrics: Arc::default(),
+            build_slots: config
+                .max_concurrent_builds()
+                .map(|max| Arc::new(Semaphore::new(max))),
+            queued_builds: Arc::default(),
+            draining: Arc::default(),
+            config,
+            pool: Pool::new(POOL_MAX_PER_ADDR),
+            started_at: Instant::now(),
+            daemon_id,
+        }
     }
 
-    pub fn config(&mut self) -> Result<DaemonConfig> {
-        Ok(self.config)
+    /// Dumps the id database, the processes and the target locks as JSON, for debugging.
+    ///
+    /// The locks are taken one after the other, so the sections may be slightly out of
+    /// sync. Environment variables looking like credentials are redacted.
+    pub async fn snapshot(&self) -> Result<Value> {
+        let ids: Vec<Value> = lock!(self.id_database)
+            .await?
+            .iter()
+            .map(|(project_id, next_id)| json!({ "project_id": project_id, "next_id": next_id }))
+            .collect();
+
+        let processes = read_lock!(self.processes)
+            .await?
+            .values()
+            .cloned()
+            .map(|mut datas| {
+                for (key, value) in datas.env.iter_mut() {
+                    let upper = key.to_uppercase();
+                    if REDACTED_ENV_PATTERNS.iter().any(|p| upper.contains(p)) {
+                        *value = REDACTED_VALUE.to_string();
+                    }
+                }
+                serde_json::to_value(datas)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to serialize the processes.")?;
+
+        let target_locks: Vec<Value> = lock!(self.target_locks)
+            .await?
+            .iter()
+            .map(|((project_id, target), holders)| {
+                json!({ "project_id": project_id, "target": target, "holders": holders })
+            })
+            .collect();
+
+        Ok(json!({
+            "daemon_sock": self.daemon_sock,
+            "id_database": ids,
+            "processes": processes,
+            "target_locks": target_locks,
+        }))
+    }
+
+    pub fn config(&self) -> Result<DaemonConfig> {
+        Ok(self.config.clone())
+    }
+
+    /// Returns the id of this daemon, embedded in the projects it creates.
+    pub fn daemon_id(&self) -> DaemonId {
+        self.daemon_id
     }
 
     pub fn notifier_hub(&self) -> &Hub {
@@ -79,10 +215,368 @@ impl State {
         &self.daemon_sock
     }
 
+    /// Returns the time elapsed since the state was created, the uptime of the daemon.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Returns the metrics of the daemon, see [`serve_metrics`](crate::daemon::serve_metrics).
+    pub fn metrics(&self) -> &Arc<DakeMetrics> {
+        &self.metrics
+    }
+
+    /// Returns the pool of the connections opened to other daemons.
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Writes `msg` to `sock` through the connection pool, reconnecting once if the pooled
+    /// connection is broken.
+    pub async fn send_message<M: MessageTrait>(
+        &self,
+        msg: Message<M>,
+        sock: SocketAddr,
+    ) -> Result<()> {
+        send_message(msg, sock, Some(&self.pool)).await?;
+        Ok(())
+    }
+
+    /// Returns a copy of the recent build history, oldest build first.
+    pub async fn build_history(&self) -> Result<Vec<BuildRecord>> {
+        let history = timeout(MUTEX_LOCK_TIMEOUT, self.build_history.read())
+            .await
+            .context("Lock for build history timed out.")?;
+        Ok(history.iter().cloned().collect())
+    }
+
+    /// Pushes a completed build in the history and persists it on disk.
+    ///
+    /// A process is only recorded once, the first report of a build wins as it
+    /// carries the most meaningful exit code (an error is reported before the done).
+    ///
+    /// The history is written from a blocking task once its lock is released, the
+    /// readers of the history never wait for the disk.
+    pub async fn record_build(&self, datas: &ProcessDatas, exit_code: i32) -> Result<()> {
+        let (snapshot, _writer) = {
+            let mut history = timeout(MUTEX_LOCK_TIMEOUT, self.build_history.write())
+                .await
+                .context("Lock for build history timed out.")?;
+            if !Self::push_record(&mut history, datas, exit_code, self.config.history_size()) {
+                return Ok(());
+            }
+            // Taken before the history is released, the next snapshot is written after this one.
+            let writer = lock!(self.history_writer).await?;
+            (history.clone(), writer)
+        };
+
+        spawn_blocking(move || save_history(&snapshot))
+            .await
+            .context("The task writing the build history panicked.")?
+    }
+
+    /// Pushes the record of a completed build in `history`, keeping at most `size` of them.
+    ///
+    /// Returns false if the process is already in the history.
+    fn push_record(
+        history: &mut VecDeque<BuildRecord>,
+        datas: &ProcessDatas,
+        exit_code: i32,
+        size: usize,
+    ) -> bool {
+        if history.iter().any(|record| record.pid == datas.pid) {
+            info!("{:?} is already in the build history.", datas.pid);
+            return false;
+        }
+
+        history.push_back(BuildRecord {
+            pid: datas.pid.clone(),
+            start_time: datas.start_time,
+            end_time: SystemTime::now(),
+            exit_code,
+            involved_hosts: datas.involved_hosts.clone(),
+        });
+        while history.len() > size {
+            history.pop_front();
+        }
+        info!("Recorded the build of {:?} in the history.", datas.pid);
+        true
+    }
+
+    /// Removes `pid` from the state, publishing [`Notif::AllDone`] if it was the last process.
     pub async fn remove_process(&self, pid: &ProcessId) -> Result<Option<ProcessDatas>> {
-        let processes_ref = self.processes.clone();
-        let mut processes = lock!(processes_ref).await?;
-        Ok(processes.remove(pid))
+        let (datas, all_done) = {
+            let processes_ref = self.processes.clone();
+            let mut processes = write_lock!(processes_ref).await?;
+            let datas = processes.remove(pid);
+            (datas, processes.is_empty())
+        };
+        if all_done {
+            self.notify_all_done().await?;
+        }
+        Ok(datas)
+    }
+
+    /// Publishes [`Notif::AllDone`] on the daemon channel, for the callers of [`State::drain`].
+    async fn notify_all_done(&self) -> Result<()> {
+        let hub = lock!(self.notifier_hub).await?;
+        // Nobody is draining the daemon.
+        if !matches!(hub.channel_state(&daemon_channel()), ChannelState::Running) {
+            return Ok(());
+        }
+        info!("Every process is over, notifying the drain");
+        hub.arc_send(Notif::AllDone, &daemon_channel())
+            .context("Failed to publish the all done notification")?;
+        Ok(())
+    }
+
+    /// Removes the processes registered for more than `max_age` without a done, their
+    /// caller probably crashed. The targets they hold are released.
+    ///
+    /// The builds started by this daemon whose caller still waits are kept, as are the
+    /// processes whose make still runs on this host: it ends with a done of its own.
+    /// Returns the amount of reaped processes.
+    pub async fn reap_stale(&self, max_age: Duration) -> Result<usize> {
+        let active = self.active_builds().await?;
+        let old: Vec<ProcessId> = read_lock!(self.processes)
+            .await?
+            .iter()
+            .filter(|(pid, datas)| {
+                !active.contains(pid) && datas.start_time.elapsed().is_ok_and(|age| age > max_age)
+            })
+            .map(|(pid, _)| pid.clone())
+            .collect();
+        let old: Vec<ProcessId> = {
+            let hub = lock!(self.notifier_hub).await?;
+            old.into_iter()
+                .filter(|pid| match hub.channel_state(pid) {
+                    ChannelState::Running => {
+                        info!("Keeping {pid:?}, its make still runs");
+                        false
+                    }
+                    _ => true,
+                })
+                .collect()
+        };
+
+        let mut all_done = false;
+        let stale: Vec<ProcessId> = {
+            let mut processes = write_lock!(self.processes).await?;
+            let stale: Vec<ProcessId> = old
+                .into_iter()
+                .filter(|pid| processes.remove(pid).is_some())
+                .collect();
+            if !stale.is_empty() && processes.is_empty() {
+                all_done = true;
+            }
+            stale
+        };
+        if all_done {
+            self.notify_all_done().await?;
+        }
+
+        for pid in &stale {
+            info!("Reaped {pid:?}, it received no done within {max_age:?}");
+            if let Err(e) = self.unlock_all_targets_for_process(pid).await {
+                warn!("Failed to release the targets of {pid:?}: {e:?}");
+            }
+        }
+        Ok(stale.len())
+    }
+
+    /// Stops every process registered on the daemon, see [`State::kill_all_for_pid`].
+    ///
+    /// `reason` is only logged. Failures to stop a process are only logged too.
+    pub async fn kill_all(&self, reason: String) -> Result<()> {
+        let pids: Vec<ProcessId> = read_lock!(self.processes).await?.keys().cloned().collect();
+        warn!(
+            "Killing the {} processes of the daemon: {reason}",
+            pids.len()
+        );
+        for pid in pids {
+            self.kill_all_for_pid(pid).await;
+        }
+        Ok(())
+    }
+
+    /// Stops every make of `pid`, on this host and on the involved hosts.
+    ///
+    /// The involved hosts get a done broadcast and the process is removed from the
+    /// state, see [`broadcast_done`]. The local make is then killed by publishing
+    /// [`Notif::Done`] on the process channel. Failures are only logged.
+    pub async fn kill_all_for_pid(&self, pid: ProcessId) {
+        if let Err(e) = broadcast_done(self, pid.clone()).await {
+            warn!("Failed to broadcast Done to the involved hosts of {pid:?}: {e:?}");
+        }
+
+        let waiter = {
+            let hub = self.notifier_hub();
+            match lock!(hub).await {
+                Ok(notifier_hub) => match notifier_hub.channel_state(&pid) {
+                    ChannelState::Running => match notifier_hub.arc_send(Notif::Done, &pid) {
+                        Ok(w) => Some(w),
+                        Err(e) => {
+                            warn!(
+                                "Failed to publish the done notification over notifier_hub {e:?}"
+                            );
+                            None
+                        }
+                    },
+                    _ => {
+                        info!("No local task is listening on {pid:?} anymore.");
+                        None
+                    }
+                },
+                Err(_) => {
+                    warn!("Failed to lock notifier_hub");
+                    None
+                }
+            }
+        };
+        if let Some(w) = waiter
+            && let Err(e) = w.wait(Some(DONE_NOTIFICATION_TIMEOUT)).await
+        {
+            warn!("Failed to wait for done notif publication: {e:?}")
+        }
+    }
+
+    /// Waits until the build limit of the config allows one more build.
+    ///
+    /// The slot is held until the permit is dropped. Returns `None` if builds are
+    /// not limited.
+    pub async fn acquire_build_slot(&self) -> Option<OwnedSemaphorePermit> {
+        let slots = self.build_slots.clone()?;
+        self.queued_builds.fetch_add(1, Ordering::Relaxed);
+        let permit = slots
+            .acquire_owned()
+            .await
+            .inspect_err(|e| warn!("The build slots are closed, not limiting the build: {e}"))
+            .ok();
+        self.queued_builds.fetch_sub(1, Ordering::Relaxed);
+        permit
+    }
+
+    /// Returns the amount of builds waiting for a slot, see [`State::acquire_build_slot`].
+    pub fn queued_builds(&self) -> usize {
+        self.queued_builds.load(Ordering::Relaxed)
+    }
+
+    /// Marks `pid` as a build whose caller waits for the end, see [`State::drain`].
+    pub async fn start_build(&self, pid: ProcessId) -> Result<()> {
+        if lock!(self.active_builds)
+            .await?
+            .insert(pid, Instant::now())
+            .is_none()
+        {
+            self.metrics.build_started();
+        }
+        Ok(())
+    }
+
+    /// Marks the build `pid` as over with `exit_code`, its caller received the end.
+    pub async fn end_build(&self, pid: &ProcessId, exit_code: i32) -> Result<()> {
+        if let Some(started_at) = lock!(self.active_builds).await?.remove(pid) {
+            self.metrics.build_ended(exit_code, started_at.elapsed());
+        }
+        Ok(())
+    }
+
+    async fn active_builds(&self) -> Result<Vec<ProcessId>> {
+        Ok(lock!(self.active_builds).await?.keys().cloned().collect())
+    }
+
+    /// Returns whether the daemon is draining its processes, see [`State::drain`].
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Waits for every process of the daemon to be over, for at most `max_wait`.
+    ///
+    /// Wakes up on the [`Notif::AllDone`] published once the last process is removed.
+    /// From the first call on, [`State::is_draining`] is set so no new build starts.
+    ///
+    /// # Errors
+    /// Fails with [`DakeError::DrainTimeout`] listing the processes still running
+    /// once `max_wait` elapsed.
+    pub async fn drain(&self, max_wait: Duration) -> Result<()> {
+        self.draining.store(true, Ordering::Relaxed);
+        let mut subscriber = lock!(self.notifier_hub)
+            .await?
+            .subscribe(&daemon_channel(), CHANNEL_SIZE);
+
+        let wait_until_empty = async {
+            loop {
+                let running = read_lock!(self.processes).await?.len();
+                if running == 0 {
+                    break anyhow::Ok(());
+                }
+                info!("Waiting for {running} processes to finish");
+                match subscriber.recv().await {
+                    Some(notif) => notif.trace(),
+                    // The hub is gone, there is nothing left to wake us up.
+                    None => sleep(DRAIN_POLL_INTERVAL).await,
+                }
+            }
+        };
+        if let Ok(res) = timeout(max_wait, wait_until_empty).await {
+            res?;
+            info!("Every process is over.");
+            return Ok(());
+        }
+        let running = self.registered_processes().await?;
+        Err(DakeError::DrainTimeout { running }.into())
+    }
+
+    /// Fails the builds started by this daemon before a shutdown.
+    ///
+    /// The builds are failed with a [`Notif::Error`], so their handler sends the end to
+    /// the caller and tells the involved hosts to stop. Their handlers are given
+    /// [`DRAIN_END_TIMEOUT`] to do so.
+    pub async fn fail_active_builds(&self) {
+        let builds = match self.active_builds().await {
+            Ok(builds) => builds,
+            Err(e) => {
+                warn!("Failed to lock the active builds, abandoning them: {e:?}");
+                return;
+            }
+        };
+        warn!("Failing the {} builds still running", builds.len());
+        for pid in builds {
+            let notif = Notif::Error {
+                exit_code: EXIT_CODE_FAILURE,
+                guilty_node: self.daemon_sock.clone(),
+            };
+            let waiter = match lock!(self.notifier_hub).await {
+                Ok(notifier_hub) => notifier_hub.arc_send(notif, &pid),
+                Err(e) => {
+                    warn!("Failed to lock notifier_hub: {e}");
+                    continue;
+                }
+            };
+            match waiter {
+                Ok(w) => {
+                    if let Err(e) = w.wait(Some(DONE_NOTIFICATION_TIMEOUT)).await {
+                        warn!("Failed to wait for the error notif publication: {e:?}")
+                    }
+                }
+                Err(e) => warn!("Failed to fail {pid:?}: {e:?}"),
+            }
+        }
+
+        let wait_until_empty = async {
+            while !self.active_builds().await.is_ok_and(|b| b.is_empty()) {
+                sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        if timeout(DRAIN_END_TIMEOUT, wait_until_empty).await.is_err() {
+            warn!("Some callers did not receive the end of their build.");
+        }
+    }
+
+    /// Returns whether a process of `project_id` is still registered.
+    pub async fn has_active_process(&self, project_id: &ProjectId) -> Result<bool> {
+        let processes = self.processes.clone();
+        let processes = read_lock!(processes).await?;
+        Ok(processes.keys().any(|pid| &pid.project_id == project_id))
     }
 
     // Register the process in the database with a default ProcessData value
@@ -93,10 +587,15 @@ impl State {
         info!("{pid:?} has been registered.");
     }
 
+    /// Returns the processes registered on the daemon.
+    pub async fn registered_processes(&self) -> Result<Vec<ProcessId>> {
+        Ok(read_lock!(self.processes).await?.keys().cloned().collect())
+    }
+
     pub async fn set_process_datas(&self, pid: ProcessId, datas: ProcessDatas) {
         info!("Setting process datas {datas:?} for process {pid:?}.");
         let processes = self.processes.clone();
-        match lock!(processes).await {
+        match write_lock!(processes).await {
             Ok(mut processes) => {
                 processes.insert(pid.clone(), datas.clone());
                 info!("{datas:?} has been registered for the pid {pid:?}.");
@@ -108,7 +607,7 @@ impl State {
     pub async fn read_process_data(&self, pid: &ProcessId) -> Result<Option<ProcessDatas>> {
         info!("Trying to fetch the process datas for {pid:?}.");
         let processes = self.processes.clone();
-        let processes = lock!(processes).await?;
+        let processes = read_lock!(processes).await?;
         info!("Successfully locked the processes database for {pid:?}.");
         Ok(processes.get(pid).cloned())
     }
@@ -126,6 +625,21 @@ impl State {
         })
     }
 
+    /// Returns how long `pid` has been registered on this daemon.
+    ///
+    /// # Errors
+    /// Fails if the process is unknown, or if the clock went back since it started.
+    pub async fn process_duration(&self, pid: &ProcessId) -> Result<Duration> {
+        let datas = self
+            .read_process_data(pid)
+            .await?
+            .context(format!("The process {pid} is not registered."))?;
+        datas
+            .start_time
+            .elapsed()
+            .context("The clock went back since the process started.")
+    }
+
     pub async fn read_args(&self, pid: &ProcessId) -> Result<Option<Vec<String>>> {
         Ok(if let Some(datas) = self.read_process_data(pid).await? {
             Some(datas.args)
@@ -134,19 +648,34 @@ impl State {
         })
     }
 
+    /// Returns the next id of `project_id` and checkpoints the id database.
+    ///
+    /// A failed checkpoint is only logged, the id stays valid for this daemon.
     pub async fn get_fresh_id(&self, project_id: ProjectId) -> Result<u64> {
-        let id_database = self.id_database.clone();
-        let mut id_database = lock!(id_database).await?;
-        let entry = id_database
-            .entry(project_id)
-            .and_modify(|e| *e += 1)
-            .or_insert(INITIAL_PROCESS_ID + 1);
-        let id = *entry - 1;
+        let id = {
+            let id_database = self.id_database.clone();
+            let mut id_database = lock!(id_database).await?;
+            let entry = id_database
+                .entry(project_id)
+                .and_modify(|e| *e += 1)
+                .or_insert(INITIAL_PROCESS_ID + 1);
+            *entry - 1
+        };
+        if let Err(e) = self.checkpoint().await {
+            warn!("Failed to checkpoint the id database: {e:?}");
+        }
         Ok(id)
     }
 
-    #[tracing::instrument(skip(self), fields(%project_id, %target))]
-    pub async fn unlock_target(&self, project_id: ProjectId, target: String) -> Result<()> {
+    /// Saves the id database to the disk, so a restarted daemon continues the ids
+    /// from where this one stopped.
+    pub async fn checkpoint(&self) -> Result<()> {
+        let ids = lock!(self.id_database).await?;
+        save_checkpoint(&ids)
+    }
+
+    #[tracing::instrument(skip(self), fields(%pid, %target))]
+    pub async fn unlock_target(&self, pid: &ProcessId, target: String) -> Result<()> {
         info!("Attempting to unlock target...");
 
         {
@@ -154,58 +683,216 @@ impl State {
             let mut locks = lock!(locks).await?;
             info!("Acquired lock on target_locks");
 
-            if !locks.remove(&(project_id.clone(), target.clone())) {
+            let key = (pid.project_id.clone(), target.clone());
+            let Some(holders) = locks.get_mut(&key) else {
                 warn!("Target was already unlocked: {target}");
-            } else {
+                return Ok(());
+            };
+            match holders.iter().position(|holder| holder == pid) {
+                Some(index) => {
+                    holders.swap_remove(index);
+                    info!(
+                        "Released one of the {} locks of target: {target}",
+                        holders.len() + 1
+                    );
+                }
+                None => warn!("{pid:?} does not hold target: {target}"),
+            }
+            if holders.is_empty() {
+                locks.remove(&key);
                 info!("Successfully removed lock for target: {target}");
             }
         }
 
+        self.notify_target_unlock(&pid.project_id, target).await
+    }
+
+    /// Releases every lock held by `pid`, waking up the builds waiting on them.
+    ///
+    /// Used when a process fails, its builds may not have unlocked their targets.
+    #[tracing::instrument(skip(self), fields(%pid))]
+    pub async fn unlock_all_targets_for_process(&self, pid: &ProcessId) -> Result<()> {
+        // A build cancelled while waiting for a lock never removed its wait.
+        lock!(self.wait_graph)
+            .await?
+            .retain(|(waiting, _), _| waiting != pid);
+
+        let released: Vec<String> = {
+            let locks = self.target_locks.clone();
+            let mut locks = lock!(locks).await?;
+            let mut released = Vec::new();
+            locks.retain(|(_, target), holders| {
+                let held = holders.len();
+                holders.retain(|holder| holder != pid);
+                if holders.len() < held {
+                    released.push(target.clone());
+                }
+                !holders.is_empty()
+            });
+            released
+        };
+
+        if released.is_empty() {
+            info!("The process holds no target");
+            return Ok(());
+        }
+
+        warn!("Releasing the locks left on {released:?}");
+        for target in released {
+            self.notify_target_unlock(&pid.project_id, target).await?;
+        }
+        Ok(())
+    }
+
+    /// Releases every lock held on a target of `project_id`, waking up the builds waiting on them.
+    ///
+    /// Used once the last process of a project is done, so a lock leaked by a build
+    /// that did not unlock its target does not block the next builds forever.
+    #[tracing::instrument(skip(self), fields(%project_id))]
+    pub async fn unlock_all_targets_for_project(&self, project_id: &ProjectId) -> Result<()> {
+        lock!(self.wait_graph)
+            .await?
+            .retain(|(pid, _), _| &pid.project_id != project_id);
+
+        let released: Vec<String> = {
+            let locks = self.target_locks.clone();
+            let mut locks = lock!(locks).await?;
+            let released = locks
+                .keys()
+                .filter(|(project, _)| project == project_id)
+                .map(|(_, target)| target.clone())
+                .collect::<Vec<_>>();
+            locks.retain(|(project, _), _| project != project_id);
+            released
+        };
+
+        if released.is_empty() {
+            info!("No target of the project is locked");
+            return Ok(());
+        }
+
+        warn!("Releasing the locks left on {released:?}");
+        for target in released {
+            self.notify_target_unlock(project_id, target).await?;
+        }
+        Ok(())
+    }
+
+    /// Notifies the builds of `project_id` waiting on a lock that `target` was unlocked.
+    async fn notify_target_unlock(&self, project_id: &ProjectId, target: String) -> Result<()> {
         let hub = self.notifier_hub.clone();
         let hub = lock!(hub).await?;
         info!("Acquired lock on notifier_hub");
 
+        let channel = ProcessId {
+            id: 0,
+            project_id: project_id.clone(),
+        };
+
+        // Nobody ever waited on this project, there is no one to notify.
+        if !matches!(hub.channel_state(&channel), ChannelState::Running) {
+            info!("No process is waiting for a target of project {project_id}");
+            return Ok(());
+        }
+
         info!("Broadcasting unlock notification for target: {target}");
 
-        hub.arc_send(
-            Notif::TargetUnlock {
-                target: target.clone(),
-            },
-            &ProcessId {
-                id: 0,
-                project_id: project_id.clone(),
-            },
-        )
-        .context("Failed to broadcast the unlock notification")?;
+        hub.arc_send(Notif::TargetUnlock { target }, &channel)
+            .context("Failed to broadcast the unlock notification")?;
 
         info!("Unlock notification sent successfully for project {project_id}");
 
         Ok(())
     }
 
-    // This function does not take any duration because the only time we need to wait for something
-    // is when a build is running. However, this build might take up to 13 hours if the user wishes,
-    // so it is practically impossible to set a timeout for this lock.
-    #[tracing::instrument(skip(self), fields(%target))]
-    pub async fn lock_target(&self, project_id: ProjectId, target: String) -> Result<()> {
-        loop {
-            info!("Attempting to acquire lock for target");
+    /// Locks `target` if less than `max_jobs` builds currently hold it, without
+    /// waiting for it otherwise.
+    ///
+    /// Returns whether the lock was acquired.
+    #[tracing::instrument(skip(self), fields(%pid, %target))]
+    pub async fn try_lock_target(
+        &self,
+        pid: &ProcessId,
+        target: String,
+        max_jobs: usize,
+    ) -> Result<bool> {
+        let locks = self.target_locks.clone();
+        let mut locks = lock!(locks).await?;
+        let holders = locks.entry((pid.project_id.clone(), target)).or_default();
+        let was_free = holders.len() < max_jobs;
+        if was_free {
+            holders.push(pid.clone());
+        }
 
-            let free: bool = {
-                let locks = self.target_locks.clone();
-                let mut locks = lock!(locks).await?;
-                let was_free = locks.insert((project_id.clone(), target.clone()));
+        info!(?was_free, holders = holders.len(), "Lock table updated");
+        Ok(was_free)
+    }
 
-                info!(?was_free, "Lock table updated");
-                was_free
-            };
+    /// Records that the build of `parent` by `pid` waits for `target`, failing if the wait
+    /// closes a cycle.
+    ///
+    /// Nothing is recorded when a cycle is found, the caller gives up on the lock.
+    async fn add_wait_edge(&self, pid: &ProcessId, parent: &str, target: &str) -> Result<()> {
+        let locks = self.target_locks.clone();
+        let locks = lock!(locks).await?;
+        let graph = self.wait_graph.clone();
+        let mut graph = lock!(graph).await?;
+
+        let node = (pid.clone(), parent.to_string());
+        graph
+            .entry(node.clone())
+            .or_default()
+            .push(target.to_string());
+        if let Some(cycle) = detect_cycle(&graph, &locks) {
+            remove_edge(&mut graph, node, target);
+            warn!(?cycle, "Waiting for the target would deadlock");
+            return Err(DakeError::DeadlockDetected { cycle }.into());
+        }
+        Ok(())
+    }
 
-            if free {
+    /// Forgets one wait of the build of `parent` by `pid` for `target`, if any.
+    async fn remove_wait_edge(&self, pid: &ProcessId, parent: &str, target: &str) -> Result<()> {
+        let graph = self.wait_graph.clone();
+        let mut graph = lock!(graph).await?;
+        remove_edge(&mut graph, (pid.clone(), parent.to_string()), target);
+        Ok(())
+    }
+
+    /// Waits until `pid` may build `target`, at most `max_jobs` builds of the target
+    /// hold the lock at the same time.
+    ///
+    /// There is no timeout: the lock is only waited for while a build runs, and a build
+    /// may take hours.
+    ///
+    /// `parent` is the target whose build by `pid` asks for `target`, `None` for the
+    /// top-level build. Only the waits of a build hold a target while waiting, so only
+    /// they can deadlock: with a `parent`, fails with [`DakeError::DeadlockDetected`] if
+    /// the builds holding the target wait, directly or not, for `parent`.
+    #[tracing::instrument(skip(self), fields(%pid, %target))]
+    pub async fn lock_target(
+        &self,
+        pid: &ProcessId,
+        target: String,
+        max_jobs: usize,
+        parent: Option<&str>,
+    ) -> Result<()> {
+        loop {
+            info!("Attempting to acquire lock for target");
+
+            if self.try_lock_target(pid, target.clone(), max_jobs).await? {
                 info!("Lock acquired successfully for target");
+                if let Some(parent) = parent {
+                    self.remove_wait_edge(pid, parent, &target).await?;
+                }
                 break Ok(());
             } else {
                 info!("Target already locked, waiting for unlock notification");
 
+                if let Some(parent) = parent {
+                    self.add_wait_edge(pid, parent, &target).await?;
+                }
+
                 let mut subscriber = {
                     let hub = self.notifier_hub.clone();
                     let mut hub = lock!(hub).await?;
@@ -214,7 +901,7 @@ impl State {
                     let sub = hub.subscribe(
                         &ProcessId {
                             id: 0,
-                            project_id: project_id.clone(),
+                            project_id: pid.project_id.clone(),
                         },
                         CHANNEL_SIZE,
                     );
@@ -251,3 +938,66 @@ impl State {
         }
     }
 }
+
+/// Removes one wait of `node` for `target` from `graph`, if any.
+fn remove_edge(graph: &mut HashMap<WaitNode, Vec<String>>, node: WaitNode, target: &str) {
+    if let Entry::Occupied(mut entry) = graph.entry(node) {
+        let waited = entry.get_mut();
+        if let Some(index) = waited.iter().position(|waited| waited == target) {
+            waited.swap_remove(index);
+        }
+        if waited.is_empty() {
+            entry.remove();
+        }
+    }
+}
+
+/// Looks for a build waiting, through the holders of the targets, for its own target.
+///
+/// Each build of `wait_graph` waits for the builds of its targets by their holders in
+/// `locks`, a holder of a target being the build of this target by the holding process.
+/// Returns the processes of the first cycle found, in waiting order.
+fn detect_cycle(
+    wait_graph: &HashMap<WaitNode, Vec<String>>,
+    locks: &HashMap<(ProjectId, String), Vec<ProcessId>>,
+) -> Option<Vec<ProcessId>> {
+    let mut visited = HashSet::new();
+    for start in wait_graph.keys() {
+        let mut path = Vec::new();
+        if let Some(cycle) = visit(start, wait_graph, locks, &mut visited, &mut path) {
+            return Some(cycle.into_iter().map(|(pid, _)| pid).collect());
+        }
+    }
+    None
+}
+
+/// Depth first search of [`detect_cycle`], `path` holds the builds being visited.
+fn visit(
+    node: &WaitNode,
+    wait_graph: &HashMap<WaitNode, Vec<String>>,
+    locks: &HashMap<(ProjectId, String), Vec<ProcessId>>,
+    visited: &mut HashSet<WaitNode>,
+    path: &mut Vec<WaitNode>,
+) -> Option<Vec<WaitNode>> {
+    if let Some(start) = path.iter().position(|n| n == node) {
+        return Some(path[start..].to_vec());
+    }
+    if !visited.insert(node.clone()) {
+        return None;
+    }
+    let waited = wait_graph.get(node)?;
+    path.push(node.clone());
+    let (pid, _) = node;
+    for target in waited {
+        let key = (pid.project_id.clone(), target.clone());
+        let holders = locks.get(&key).map(Vec::as_slice).unwrap_or_default();
+        for holder in holders {
+            let next = (holder.clone(), target.clone());
+            if let Some(cycle) = visit(&next, wait_graph, locks, visited, path) {
+                return Some(cycle);
+            }
+        }
+    }
+    path.pop();
+    None
+}
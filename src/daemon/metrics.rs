@@ -0,0 +1,208 @@
+//! # Metrics
+//!
+//! Counters describing the activity of the daemon, served in the Prometheus text
+//! format on `/metrics` by [`serve_metrics`].
+//!
+//! The endpoint listens on every interface, on the port `DAKE_METRICS_PORT` or
+//! [`DEFAULT_METRICS_PORT`]. It is optional: if the port cannot be bound the daemon
+//! keeps running without it.
+
+use std::{
+    env::var,
+    fmt::Write,
+    net::Ipv4Addr,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    Router, extract::State, http::header::CONTENT_TYPE, response::IntoResponse, routing::get,
+};
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::{constants::DEFAULT_METRICS_PORT, env_variables::EnvVariable, network::check_port};
+
+/// Upper bounds of the buckets of `dake_build_duration_seconds`, in seconds.
+const BUILD_DURATION_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// Content type of the Prometheus text format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Histogram of the build durations, each bucket counting the builds of its range only.
+#[derive(Default)]
+struct DurationHistogram {
+    buckets: [AtomicU64; BUILD_DURATION_BUCKETS.len()],
+    sum_millis: AtomicU64,
+    count: AtomicU64,
+}
+
+impl DurationHistogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = BUILD_DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+        {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_millis
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Writes the cumulative buckets, the sum and the count of the histogram `name`.
+    fn render(&self, out: &mut String, name: &str) -> std::fmt::Result {
+        let mut cumulative = 0;
+        for (bound, bucket) in BUILD_DURATION_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}")?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}")?;
+        let sum = self.sum_millis.load(Ordering::Relaxed) as f64 / 1000.0;
+        writeln!(out, "{name}_sum {sum}")?;
+        writeln!(out, "{name}_count {count}")
+    }
+}
+
+/// Metrics of the daemon, shared by every handler through the [`State`](crate::daemon::State).
+#[derive(Default)]
+pub struct DakeMetrics {
+    active_builds: AtomicI64,
+    completed_builds: AtomicU64,
+    failed_builds: AtomicU64,
+    makefile_bytes_distributed: AtomicU64,
+    artifact_bytes_transferred: AtomicU64,
+    build_duration: DurationHistogram,
+}
+
+impl DakeMetrics {
+    /// Counts a build started by this daemon.
+    pub fn build_started(&self) {
+        self.active_builds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the end of a build started by this daemon, failed if `exit_code` is not 0.
+    pub fn build_ended(&self, exit_code: i32, duration: Duration) {
+        self.active_builds.fetch_sub(1, Ordering::Relaxed);
+        if exit_code == 0 {
+            self.completed_builds.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed_builds.fetch_add(1, Ordering::Relaxed);
+        }
+        self.build_duration.observe(duration);
+    }
+
+    /// Counts the bytes of the makefiles sent to the other hosts.
+    pub fn add_makefile_bytes(&self, bytes: u64) {
+        self.makefile_bytes_distributed
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts the bytes of the artifacts sent to the fetchers.
+    pub fn add_artifact_bytes(&self, bytes: u64) {
+        self.artifact_bytes_transferred
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // Writing in a string cannot fail.
+        let _ = self.write_metrics(&mut out);
+        out
+    }
+
+    fn write_metrics(&self, out: &mut String) -> std::fmt::Result {
+        let gauges = [(
+            "dake_active_builds",
+            "Builds started by this daemon and still running.",
+            self.active_builds.load(Ordering::Relaxed),
+        )];
+        for (name, help, value) in gauges {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} gauge")?;
+            writeln!(out, "{name} {value}")?;
+        }
+
+        let counters = [
+            (
+                "dake_completed_builds_total",
+                "Builds started by this daemon that succeeded.",
+                &self.completed_builds,
+            ),
+            (
+                "dake_failed_builds_total",
+                "Builds started by this daemon that failed.",
+                &self.failed_builds,
+            ),
+            (
+                "dake_makefile_bytes_distributed_total",
+                "Bytes of makefiles sent to the other hosts.",
+                &self.makefile_bytes_distributed,
+            ),
+            (
+                "dake_artifact_bytes_transferred_total",
+                "Bytes of artifacts sent to the fetchers.",
+                &self.artifact_bytes_transferred,
+            ),
+        ];
+        for (name, help, value) in counters {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} counter")?;
+            writeln!(out, "{name} {}", value.load(Ordering::Relaxed))?;
+        }
+
+        let name = "dake_build_duration_seconds";
+        writeln!(
+            out,
+            "# HELP {name} Duration of the builds started by this daemon."
+        )?;
+        writeln!(out, "# TYPE {name} histogram")?;
+        self.build_duration.render(out, name)
+    }
+}
+
+/// Returns the port of the metrics endpoint, `DAKE_METRICS_PORT` or [`DEFAULT_METRICS_PORT`].
+fn metrics_port() -> Result<u16> {
+    let variable = EnvVariable::MetricsPort;
+    let Ok(content) = var(variable.to_string()) else {
+        return Ok(DEFAULT_METRICS_PORT);
+    };
+    let port = content
+        .parse()
+        .context(format!("{variable} is not a valid port: {content}"))?;
+    check_port(port)
+}
+
+async fn get_metrics(State(metrics): State<Arc<DakeMetrics>>) -> impl IntoResponse {
+    ([(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], metrics.render())
+}
+
+/// Serves `metrics` on `/metrics`, until the daemon stops.
+///
+/// # Errors
+/// Fails if the port is invalid or cannot be bound.
+pub async fn serve_metrics(metrics: Arc<DakeMetrics>) -> Result<()> {
+    let port = metrics_port()?;
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .await
+        .context(format!(
+            "Failed to bind the metrics endpoint on port {port}."
+        ))?;
+    info!("Serving the metrics on port {port}");
+
+    let app = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(metrics);
+    axum::serve(listener, app)
+        .await
+        .context("The metrics endpoint failed.")
+}
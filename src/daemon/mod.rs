@@ -1,16 +1,23 @@
+pub mod discovery;
+
+mod error;
 mod handlers;
 mod listen;
 mod memory;
 mod message_ctx;
+mod metrics;
 mod notif;
 mod operations;
 mod process_datas;
 
 pub use {
+    error::DakeError,
+    handlers::report_distribution_failure,
     listen::start,
-    memory::{DaemonId, State, fs},
+    memory::{BuildRecord, DaemonConfig, DaemonId, State, fs, load_history},
     message_ctx::MessageCtx,
+    metrics::{DakeMetrics, serve_metrics},
     notif::Notif,
-    operations::{broadcast_done, distribute, execute_make},
+    operations::{archive_completed_build, broadcast_done, distribute, execute_make},
     process_datas::ProcessDatas,
 };
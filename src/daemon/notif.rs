@@ -7,8 +7,12 @@ pub enum Notif {
     /// Task successfully completed.
     Done,
 
-    /// Log message produced during execution.
-    Log { output: OutputFile, log: String },
+    /// Log message produced during execution, at `timestamp_ms` on its host.
+    Log {
+        output: OutputFile,
+        log: String,
+        timestamp_ms: u64,
+    },
 
     /// Fatal error with exit code and the node responsible.
     Error {
@@ -18,6 +22,12 @@ pub enum Notif {
 
     /// The target is unlock
     TargetUnlock { target: String },
+
+    /// Progress report of the build of a target.
+    BuildProgress { percent: u8, target: String },
+
+    /// The last process of the daemon is over.
+    AllDone,
 }
 
 impl Notif {
@@ -36,6 +46,10 @@ impl Notif {
                 )
             }
             Notif::TargetUnlock { target } => info!("New target just unlocked: {target}"),
+            Notif::BuildProgress { percent, target } => {
+                info!("Notification: {target} is {percent}% built")
+            }
+            Notif::AllDone => info!("Notification: every process is over"),
         }
     }
 }
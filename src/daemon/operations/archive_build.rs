@@ -0,0 +1,25 @@
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+
+use crate::{
+    daemon::{State, fs::archive_build},
+    process_id::ProcessId,
+};
+
+/// Archives the build folder of `pid` if the daemon is configured to do so.
+///
+/// The archive is written by a blocking task, the caller does not wait for it.
+/// Failures are only logged, archiving is never allowed to fail a build.
+pub fn archive_completed_build(state: &State, pid: &ProcessId) {
+    match state.config() {
+        Ok(config) if config.archive_completed_builds() => {
+            let pid = pid.clone();
+            spawn_blocking(move || match archive_build(&pid) {
+                Ok(path) => info!("Build of {pid:?} archived at {path:?}"),
+                Err(e) => warn!("Failed to archive the build of {pid:?}: {e:?}"),
+            });
+        }
+        Ok(_) => info!("Build archival is disabled, skipping {pid:?}"),
+        Err(e) => warn!("Failed to read the daemon config: {e:?}"),
+    }
+}
@@ -1,20 +1,37 @@
-use anyhow::{Result, bail};
+use anyhow::Result;
+use tracing::{info, warn};
 
 use crate::{
-    daemon::{State, operations::wait_acks},
-    network::{DaemonMessage, Message, broadcast_message},
+    daemon::State,
+    network::{DaemonMessage, Message, broadcast_messages},
     process_id::ProcessId,
 };
 
+/// Notifies every involved host of `pid` that the process is over, then forgets it.
+///
+/// The acks are not awaited: the hosts clean up on their own and a host that cannot be
+/// reached only gets a warning. A process that is not registered anymore is skipped.
 pub async fn broadcast_done(state: &State, pid: ProcessId) -> Result<()> {
-    let involved_processes = match state.read_involved_hosts(&pid).await? {
-        Some(involved_processes) => involved_processes,
-        None => bail!("The process {pid:?} is not registered."),
+    let Some(involved_hosts) = state.read_involved_hosts(&pid).await? else {
+        warn!("The process {pid:?} is not registered anymore, no Done to broadcast.");
+        return Ok(());
     };
 
-    let message = Message::new(DaemonMessage::Done, pid);
+    let messages = involved_hosts
+        .iter()
+        .map(|_| Message::new(DaemonMessage::Done, pid.clone()))
+        .collect();
 
-    let mut streams = broadcast_message(involved_processes, message).await?;
-    let streams = streams.iter_mut().collect();
-    wait_acks(streams, None).await
+    let results = broadcast_messages(involved_hosts.clone(), messages).await?;
+    for (sock, result) in involved_hosts.iter().zip(results) {
+        match result {
+            Ok(_) => info!("Done sent to {sock}"),
+            Err((_, e)) => warn!("Failed to send Done to {sock}: {e:#}"),
+        }
+    }
+
+    if state.remove_process(&pid).await?.is_some() {
+        info!("{pid:?} removed from the processes database.");
+    }
+    Ok(())
 }
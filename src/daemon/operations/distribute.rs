@@ -5,26 +5,68 @@
 //!
 //! The distribute workflow is as follows:
 //! 1. Bind a temporary listener socket for acknowledgments.
-//! 2. Send each host a `DaemonMessage::Distribute` containing its `RemoteMakefile`.
-//! . Wait for acknowledgments (`DistributerMessage::Ack`) or failures
+//! 2. Send each host a `DaemonMessage::Distribute` containing its `RemoteMakefile`,
+//!    all the hosts being contacted in parallel. Makefiles larger than the
+//!    compression threshold are sent zstd-compressed.
+//! 3. Wait for acknowledgments (`DistributerMessage::Ack`) or failures
 //!    (`DistributerMessage::Failed`) from all hosts.
-//! 4. Return success only if all hosts acknowledged within the timeout.
+//! 4. Resend the full makefile to the hosts answering `NeedFullMakefile`, at
+//!    most [`MAX_RESEND_DEPTH`] times.
+//! 5. Return success only if all hosts acknowledged within the timeout.
 //!
-//! If acknowledgments are missing after a timeout, or if any host sends a
-//! `Failed` message, the distributor aborts with an error.
+//! If a host cannot be reached, if acknowledgments are missing after a timeout, or
+//! if any host sends a `Failed` message, the distributor aborts with an error. The
+//! error lists every unreachable host, it ends up in the stderr of the caller.
 
-use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+use anyhow::{Context, Result, bail};
 
 use tracing::info;
 
 use crate::{
     daemon::{operations::wait_acks::wait_acks, process_datas::ProcessDatas},
     makefile::RemoteMakefile,
-    network::{DaemonMessage, Message, SocketAddr, broadcast_messages},
+    network::{
+        BroadcastResult, DaemonMessage, Message, SocketAddr, TimedStream, broadcast_messages,
+    },
     process_id::ProcessId,
 };
 
+/// Maximum amount of times the full makefile is resent to a host asking for it.
+const MAX_RESEND_DEPTH: usize = 2;
+
+/// Pairs each stream with its host, failing with the list of the hosts that could not be reached.
+fn all_sent(
+    socks: &[SocketAddr],
+    results: Vec<BroadcastResult<TimedStream>>,
+) -> Result<Vec<(SocketAddr, TimedStream)>> {
+    let mut streams = Vec::with_capacity(results.len());
+    let mut failures = Vec::new();
+    for (sock, result) in socks.iter().zip(results) {
+        match result {
+            Ok(stream) => streams.push((sock.clone(), stream)),
+            Err((sock, e)) => failures.push(format!("{sock} ({e:#})")),
+        }
+    }
+    if !failures.is_empty() {
+        bail!(
+            "Failed to reach {} of the {} hosts: {}",
+            failures.len(),
+            socks.len(),
+            failures.join(", ")
+        );
+    }
+    Ok(streams)
+}
+
+fn makefile_len(makefile: &RemoteMakefile) -> u64 {
+    makefile.makefile().len() as u64
+}
+
 /// Distributes a list of makefiles to remote hosts and waits for acknowledgments.
+/// Returns the amount of makefile bytes sent, resends included.
+///
 /// Returns an error if:
 /// - Binding or accepting sockets fails.
 /// - Sending messages to remote hosts fails.
@@ -34,46 +76,114 @@ pub async fn distribute(
     pid: ProcessId,
     makefiles: Vec<RemoteMakefile>,
     process_datas: ProcessDatas,
-) -> Result<()> {
+    compression_threshold: usize,
+) -> Result<u64> {
     let host_amount = makefiles.len();
     info!("Preparing to distribute {} makefiles", host_amount);
 
     // Nothing to distribute
     if host_amount == 0 {
         info!("Distributer: No makefiles to distribute, returning immediately");
-        return Ok(());
+        return Ok(0);
     }
 
     let socks = makefiles
         .iter()
-        .map(|makefile| SocketAddr::from(makefile.sock().clone()))
+        .map(|makefile| SocketAddr::from(*makefile.sock()))
         .collect::<Vec<_>>();
 
     info!("Involved sockets: {socks:?}");
 
+    let new_makefile_message = |makefile: RemoteMakefile| -> Result<_> {
+        let compressed = makefile.makefile().len() > compression_threshold;
+        let makefile = if compressed {
+            info!("Compressing the makefile of {}", makefile.sock());
+            makefile.compress()?
+        } else {
+            makefile
+        };
+        Ok(Message::new(
+            DaemonMessage::NewMakefile {
+                makefile,
+                compressed,
+                process_datas: process_datas.clone(),
+            },
+            ProcessId::process_less(pid.project_id.clone()),
+        ))
+    };
+
+    let mut sent_bytes = makefiles.iter().map(makefile_len).sum::<u64>();
     let messages = makefiles
         .iter()
         .cloned()
-        .map(|makefile| {
-            Message::new(
-                DaemonMessage::NewMakefile {
-                    makefile,
-                    process_datas: process_datas.clone(),
-                },
-                ProcessId::process_less(pid.project_id.clone()),
-            )
-        })
-        .collect::<Vec<_>>();
+        .map(new_makefile_message)
+        .collect::<Result<Vec<_>>>()?;
 
+    // Every host is contacted concurrently, see `broadcast_messages`.
     info!("Broadcasting the messages..");
-    let mut streams = broadcast_messages(socks, messages).await?;
+    let results = broadcast_messages(socks.clone(), messages).await?;
+    let mut streams = all_sent(&socks, results)?;
     info!("Broadcasting done !");
-    let streams = streams.iter_mut().collect();
+
+    // The streams follow the order of the makefiles, keep track of which makefile each host got.
+    let makefile_of_peer = streams
+        .iter()
+        .map(|(sock, _)| sock.clone())
+        .zip(makefiles)
+        .collect::<HashMap<_, _>>();
+
+    let streams = streams
+        .iter_mut()
+        .map(|(sock, stream)| (sock.clone(), stream))
+        .collect();
     info!("Waiting for acks...");
-    wait_acks(streams, None)
+    let mut report = wait_acks(streams, None, None)
         .await
         .context("Failed to wait for acknowledgments from hosts.")?;
+
+    let mut depth = 0;
+    while !report.need_full_makefile.is_empty() {
+        if depth == MAX_RESEND_DEPTH {
+            bail!(
+                "Hosts {:?} still need the full makefile after {MAX_RESEND_DEPTH} resends.",
+                report.need_full_makefile
+            );
+        }
+        depth += 1;
+
+        info!(
+            "Resending the full makefile to {:?} (attempt {depth})",
+            report.need_full_makefile
+        );
+        let messages = report
+            .need_full_makefile
+            .iter()
+            .map(|sock| {
+                makefile_of_peer
+                    .get(sock)
+                    .cloned()
+                    .context(format!("{sock} asked for a makefile it was never sent."))
+                    .and_then(new_makefile_message)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        sent_bytes += report
+            .need_full_makefile
+            .iter()
+            .filter_map(|sock| makefile_of_peer.get(sock))
+            .map(makefile_len)
+            .sum::<u64>();
+
+        let results = broadcast_messages(report.need_full_makefile.clone(), messages).await?;
+        let mut streams = all_sent(&report.need_full_makefile, results)?;
+        let streams = streams
+            .iter_mut()
+            .map(|(sock, stream)| (sock.clone(), stream))
+            .collect();
+        report = wait_acks(streams, None, None)
+            .await
+            .context("Failed to wait for acknowledgments after resending the makefiles.")?;
+    }
     info!("Successfully received all the acks.");
 
-    Ok(())
+    Ok(sent_bytes)
 }
@@ -1,9 +1,10 @@
+mod archive_build;
 mod broadcast_done;
 mod distribute;
 mod process_make;
 mod wait_acks;
 
 pub use self::{
-    broadcast_done::broadcast_done, distribute::distribute, process_make::execute_make,
-    wait_acks::wait_acks,
+    archive_build::archive_completed_build, broadcast_done::broadcast_done, distribute::distribute,
+    process_make::execute_make,
 };
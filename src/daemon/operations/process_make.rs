@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use std::{
-    fs::read_to_string,
+    env::var,
+    mem::take,
     path::PathBuf,
     process::{ExitStatus, Stdio},
 };
@@ -8,27 +9,84 @@ use tokio::{
     io::{AsyncReadExt, BufReader},
     process::Command,
     select, spawn,
+    sync::mpsc::{Receiver, Sender, channel},
     task::JoinHandle,
+    time::interval,
 };
-use tracing::{error, info, warn};
+use tracing::{Instrument, error, info, warn};
 
 use crate::{
+    constants::{CHANNEL_SIZE, LOG_BATCH_INTERVAL},
     daemon::{Notif, State},
+    env_variables::EnvVariable,
+    lexer::{guess_path_and_lex_in, max_jobs_of},
     lock,
     makefile::RemoteMakefile,
-    network::{DaemonMessage, Message, SocketAddr, connect, write_message},
+    network::{DaemonMessage, Message, OutputKind, SocketAddr, write_message},
     process_id::ProcessId,
+    utils::{is_jobserver_flag, now_ms},
 };
 
+const MAKEFLAGS: &str = "MAKEFLAGS";
+
+/// Removes the jobserver flags of a `MAKEFLAGS` value, with the job count they
+/// come with, and forces `-j1` instead.
+///
+/// The file descriptors of the jobserver are not inherited by the processes spawned
+/// by the daemon, so keeping them only confuses make. Returns `None` if `flags` does
+/// not use a jobserver.
+fn strip_jobserver_flags(flags: &str) -> Option<String> {
+    let words: Vec<&str> = flags.split_whitespace().collect();
+    if !words.iter().any(|word| is_jobserver_flag(word)) {
+        return None;
+    }
+
+    // Variables overrides come after a lone `--` and are kept as is.
+    let split = words
+        .iter()
+        .position(|word| *word == "--")
+        .unwrap_or(words.len());
+    let (options, overrides) = words.split_at(split);
+
+    let mut cleaned = Vec::new();
+    let mut options = options.iter().copied().peekable();
+    while let Some(word) = options.next() {
+        if word == "-j" || word == "--jobs" {
+            // The job count is optional, it may come as the next word.
+            options.next_if(|count| count.parse::<usize>().is_ok());
+        } else if !is_jobserver_flag(word)
+            && !word.starts_with("-j")
+            && !word.starts_with("--jobs=")
+        {
+            cleaned.push(word);
+        }
+    }
+    cleaned.push("-j1");
+    cleaned.extend(overrides);
+    Some(cleaned.join(" "))
+}
+
 /// Executes a `make` command asynchronously, forwarding logs to the daemon and
 /// reacting to cancellation messages.
 ///
 /// # Behavior
 /// 1. Spawns a `make` process in the given working directory.
-/// 2. Forwards its `stdout` and `stderr` lines asynchronously to the daemon.
+/// 2. Forwards its `stdout` and `stderr` output asynchronously to the daemon,
+///    batched every [`LOG_BATCH_INTERVAL`].
 /// 3. Waits for process completion or external `Notif::Done` signal.
 /// 4. Returns the process exit status (or `None` if killed early).
 ///
+/// The target is locked for the whole make, see [`State::lock_target`], `parent` being
+/// the target whose build asked for it. Its name is given to the fetches of the recipes
+/// through [`EnvVariable::BuiltTarget`].
+///
+/// The environment overrides of the caller win over the forwarded environment, see
+/// [`ProcessDatas::env_overrides`](crate::daemon::ProcessDatas).
+///
+/// Jobserver flags inherited through `MAKEFLAGS` or the arguments are replaced
+/// by `-j1`, see [`strip_jobserver_flags`]. The job count of a `parallel` directive,
+/// see [`ProcessDatas::parallel_jobs`](crate::daemon::ProcessDatas), wins over both.
+///
 /// # Returns
 /// - `Ok(Some(exit_status))` when the process completes normally.  
 /// - `Ok(None)` if it was killed due to a `Notif::Done`.  
@@ -42,6 +100,7 @@ pub async fn execute_make(
     pid: ProcessId,
     current_dir: PathBuf,
     target: Option<String>,
+    parent: Option<&str>,
     args: &[String],
 ) -> Result<Option<ExitStatus>> {
     info!(
@@ -49,28 +108,29 @@ pub async fn execute_make(
         pid, current_dir
     );
 
+    let path = RemoteMakefile::guess_path(current_dir.clone())
+        .context(format!("There is no makefile at {}", current_dir.display()))?;
+    info!("Full path to the makefile: {}", path.display());
+
     if let Some(target) = &target {
+        let max_jobs = guess_path_and_lex_in(&current_dir)
+            .map(|tokens| max_jobs_of(&tokens, target))
+            .inspect_err(|e| warn!("Failed to read the max-jobs of {target}: {e:?}"))
+            .ok()
+            .flatten()
+            .unwrap_or(1);
         state
-            .lock_target(pid.project_id.clone(), target.clone())
+            .lock_target(&pid, target.clone(), max_jobs, parent)
             .await
             .context("Failed to lock the target before executing make")?
     }
 
-    let path = RemoteMakefile::guess_path(current_dir.clone())
-        .context(format!("There is no makefile at {}", current_dir.display()))?;
-    info!("Full path to the makefile: {}", path.display());
-    let content = read_to_string(path.clone()).context(format!(
-        "Failed to read the content of the makefile at {}",
-        path.display(),
-    ))?;
-    info!("Content of the makefile:\n{content}");
-
-    let caller_sock = state
+    let process_datas = state
         .read_process_data(&pid)
         .await
         .context("Failed to fetch the caller sock.")?
-        .context("Failed to fetch the caller sock, process is over.")?
-        .caller_daemon;
+        .context("Failed to fetch the caller sock, process is over.")?;
+    let caller_sock = process_datas.caller_daemon;
 
     info!("Just fetched caller_sock: {caller_sock}");
 
@@ -79,13 +139,37 @@ pub async fn execute_make(
 
     let mut cmd = Command::new("make");
 
-    if let Some(target) = &target {
-        if !target.is_empty() {
-            cmd.arg(target);
-        }
+    if let Some(target) = &target
+        && !target.is_empty()
+    {
+        cmd.arg(target)
+            .env(EnvVariable::BuiltTarget.to_string(), target);
     }
 
-    cmd.args(args)
+    let mut env = process_datas.env.clone();
+    env.extend(process_datas.env_overrides.clone());
+    let makeflags = env.get(MAKEFLAGS).cloned().or_else(|| var(MAKEFLAGS).ok());
+    if let Some(flags) = makeflags.as_deref().and_then(strip_jobserver_flags) {
+        info!("Jobserver detected in {MAKEFLAGS}, running with {flags:?}");
+        env.insert(MAKEFLAGS.to_string(), flags);
+    }
+
+    let mut args = args.to_vec();
+    if args.iter().any(|arg| is_jobserver_flag(arg)) {
+        info!("Jobserver detected in the arguments, running with -j1");
+        args.retain(|arg| !is_jobserver_flag(arg) && !arg.starts_with("-j"));
+        args.push("-j1".to_string());
+    }
+    if let Some(jobs) = process_datas.parallel_jobs {
+        info!("Running make with {jobs} jobs, as asked by the parallel directive");
+        args.retain(|arg| !arg.starts_with("-j"));
+        args.push(format!("-j{jobs}"));
+    }
+
+    cmd.args(&args)
+        .envs(&env)
+        // Read back by `dake fetch`, the pid of its command line may have mangled the path.
+        .env(EnvVariable::CallerPath.to_string(), pid.path())
         .current_dir(&current_dir)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
@@ -97,57 +181,103 @@ pub async fn execute_make(
     info!("Spawned make process (pid={:?})", process.id());
 
     // --- Step 2: Log forwarding helpers ---
-    fn spawn_log_forwarder<R, F>(
+    fn spawn_pipe_reader<R>(
         pid: ProcessId,
         mut pipe: R,
-        make_msg: F,
-        caller_sock: SocketAddr,
+        kind: OutputKind,
+        sender: Sender<(OutputKind, String, u64)>,
     ) -> JoinHandle<()>
     where
         R: AsyncReadExt + Unpin + Send + 'static,
-        F: Fn(String) -> DaemonMessage + Send + Sync + 'static,
     {
+        spawn(
+            async move {
+                let mut buf = [0u8; 4096];
+                loop {
+                    match pipe.read(&mut buf).await {
+                        Ok(0) => break, // EOF
+                        Ok(n) => {
+                            let text = String::from_utf8_lossy(&buf[..n]).to_string();
+                            if sender.send((kind.clone(), text, now_ms())).await.is_err() {
+                                warn!("Log batcher of {:?} is gone, stop reading {kind:?}", pid);
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Error reading process output for {:?}: {e:?}", pid);
+                            break;
+                        }
+                    }
+                }
+
+                info!("{kind:?} reader terminated for {:?}", pid);
+            }
+            .in_current_span(),
+        )
+    }
+
+    /// Accumulates the logs of both pipes and sends them to the caller daemon
+    /// as a single [`DaemonMessage::BatchLog`] every [`LOG_BATCH_INTERVAL`].
+    fn spawn_log_batcher(
+        state: State,
+        pid: ProcessId,
+        mut receiver: Receiver<(OutputKind, String, u64)>,
+        caller_sock: SocketAddr,
+    ) -> JoinHandle<()> {
         spawn(async move {
-            let mut buf = [0u8; 4096];
-            let mut stream = match connect(caller_sock.clone()).await {
+            // Held for the whole process, then it goes back to the pool for the next one.
+            let mut stream = match state.pool().get(caller_sock.clone()).await {
                 Ok(stream) => stream,
                 Err(e) => {
                     warn!("Failed to connect with the daemon: {e}");
                     return;
                 }
             };
+
+            let mut ticker = interval(LOG_BATCH_INTERVAL);
+            let mut logs = Vec::new();
             loop {
-                match pipe.read(&mut buf).await {
-                    Ok(0) => break, // EOF
-                    Ok(n) => {
-                        let text = String::from_utf8_lossy(&buf[..n]);
-                        let msg = Message::new(make_msg(text.to_string()), pid.clone());
+                select! {
+                    log = receiver.recv() => match log {
+                        Some(log) => logs.push(log),
+                        None => break, // Both pipes are closed
+                    },
+                    _ = ticker.tick() => {
+                        if logs.is_empty() {
+                            continue;
+                        }
+                        let msg = Message::new(DaemonMessage::BatchLog { logs: take(&mut logs) }, pid.clone());
                         if let Err(e) = write_message(&mut stream, msg).await {
-                            warn!("Failed to forward process log to the caller: {e:?}");
-                            break;
+                            warn!("Failed to forward process logs to the caller: {e:?}");
+                            return;
                         }
                     }
-                    Err(e) => {
-                        warn!("Error reading process output for {:?}: {e:?}", pid);
-                        break;
-                    }
+                }
+            }
+
+            if !logs.is_empty() {
+                let msg = Message::new(DaemonMessage::BatchLog { logs }, pid.clone());
+                if let Err(e) = write_message(&mut stream, msg).await {
+                    warn!("Failed to forward the last process logs to the caller: {e:?}");
                 }
             }
 
             info!("Log forwarder terminated for {:?}", pid);
-        })
+        }
+        .in_current_span())
     }
 
     // --- Step 3: Attach log handlers ---
     let mut handlers = Vec::new();
+    let (sender, receiver) = channel(CHANNEL_SIZE);
 
     if let Some(stdout) = process.stdout.take().map(BufReader::new) {
         info!("Attaching stdout log handler for {:?}", pid);
-        handlers.push(spawn_log_forwarder(
+        handlers.push(spawn_pipe_reader(
             pid.clone(),
             stdout,
-            |log| DaemonMessage::StdoutLog { log },
-            caller_sock.clone(),
+            OutputKind::Stdout,
+            sender.clone(),
         ));
     } else {
         warn!("Failed to attach stdout for process {:?}", pid);
@@ -155,16 +285,23 @@ pub async fn execute_make(
 
     if let Some(stderr) = process.stderr.take().map(BufReader::new) {
         info!("Attaching stderr log handler for {:?}", pid);
-        handlers.push(spawn_log_forwarder(
+        handlers.push(spawn_pipe_reader(
             pid.clone(),
             stderr,
-            |log| DaemonMessage::StderrLog { log },
-            caller_sock,
+            OutputKind::Stderr,
+            sender,
         ));
     } else {
         warn!("Failed to attach stderr for process {:?}", pid);
     }
 
+    handlers.push(spawn_log_batcher(
+        state.clone(),
+        pid.clone(),
+        receiver,
+        caller_sock,
+    ));
+
     // --- Step 4: Subscribe to notifier hub for process cancellation ---
     info!("Subscribing to notifier hub for PID {:?}", pid);
     let subscriber = {
@@ -232,10 +369,45 @@ pub async fn execute_make(
 
     if let Some(target) = target {
         state
-            .unlock_target(pid.project_id, target)
+            .unlock_target(&pid, target)
             .await
             .context("Failed to unlock the target after executing make")?
     }
 
     Ok(Some(exit_status))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::strip_jobserver_flags;
+
+    #[test]
+    fn flags_without_a_jobserver_are_kept() {
+        assert_eq!(strip_jobserver_flags("-k -j4"), None);
+    }
+
+    #[test]
+    fn job_counts_are_stripped_in_every_form() {
+        for flags in [
+            "-k -j4 --jobserver-auth=3,4",
+            "-k -j 4 --jobserver-auth=3,4",
+            "-k --jobs=4 --jobserver-auth=3,4",
+            "-k --jobs 4 --jobserver-fds=3,4",
+            "-k -j --jobserver-auth=fifo:/tmp/jobs",
+        ] {
+            assert_eq!(
+                strip_jobserver_flags(flags).as_deref(),
+                Some("-k -j1"),
+                "{flags}"
+            );
+        }
+    }
+
+    #[test]
+    fn overrides_are_kept_as_is() {
+        assert_eq!(
+            strip_jobserver_flags("-j 4 --jobserver-auth=3,4 -- JOBS=4 -j8").as_deref(),
+            Some("-j1 -- JOBS=4 -j8")
+        );
+    }
+}
@@ -1,110 +1,143 @@
 use crate::{
     dec,
-    network::{AckMessage, Message, MessageKind, Stream, read_next_message},
+    network::{AckMessage, Message, MessageKind, SocketAddr, TimedStream, read_next_message},
 };
 use anyhow::{Result, bail};
-use futures::future::join_all;
+use futures::{StreamExt, stream::FuturesUnordered};
 use std::time::Duration;
-use tokio::{select, sync::mpsc::channel, time::sleep};
+use tokio::{select, time::sleep};
 use tracing::{error, info, warn};
 
-#[tracing::instrument(skip(streams, timeout))]
-pub async fn wait_acks<'a>(streams: Vec<&'a mut Stream>, timeout: Option<Duration>) -> Result<()> {
-    let host_amount = streams.len();
+/// Default overall timeout of [`wait_acks`].
+const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(30);
 
-    info!("Waiting for {host_amount} acks");
+/// Per-host result of [`wait_acks`].
+#[derive(Debug, Default, Clone)]
+pub struct WaitAcksReport {
+    /// Hosts that acknowledged with [`AckMessage::Ok`].
+    pub succeeded: Vec<SocketAddr>,
+    /// Hosts that answered with a failure, an invalid message, or closed the stream.
+    pub failed: Vec<SocketAddr>,
+    /// Hosts that did not answer yet when the quorum was reached.
+    pub pending: Vec<SocketAddr>,
+    /// Hosts that answered with [`AckMessage::NeedFullMakefile`].
+    pub need_full_makefile: Vec<SocketAddr>,
+}
 
-    // Channel to collect acknowledgments
-    let (sender, mut receiver) = channel(streams.len());
+/// Reads and decodes the next ack of `stream`, `None` if the host did not send a valid ack.
+async fn read_ack(stream: &mut TimedStream, sock: &SocketAddr) -> Option<AckMessage> {
+    info!("Awaiting an acknowledgment from {}", sock);
 
-    let tasks = streams
-        .into_iter()
-        .map(|stream| {
-            let sender = sender.clone();
-            async move {
-                let sock = match stream.peer_addr() {
-                    Ok(sock) => sock,
-                    Err(e) => {
-                        error!("Failed to fetch peer address on stream: {e}");
-                        return;
-                    }
-                };
+    // Read acknowledgment message
+    let message: Vec<u8> = match read_next_message(stream, MessageKind::AckMessage, None).await {
+        Ok(Some(message)) => message,
+        Ok(None) => {
+            warn!("Buffer EOF while waiting for ack from {}", sock);
+            return None;
+        }
+        Err(e) => {
+            warn!(
+                "Failed to read ack message from {}: {}",
+                sock,
+                e.root_cause()
+            );
+            return None;
+        }
+    };
+    info!("Received a new ack from {sock}");
+    dec!(message)
+        .inspect_err(|e| warn!("Failed to decode the ack of {sock}: {e}"))
+        .inspect(|_| info!("Successfully decoded the ack of {sock}"))
+        .ok()
+        .map(|msg: Message<AckMessage>| msg.inner)
+}
 
-                info!("Awaiting an acknowledgment from {}", sock);
+/// Waits for the acknowledgments of `streams`, each paired with the host it is connected to.
+///
+/// Returns as soon as `min_acks` hosts acknowledged (all of them by default), the
+/// remaining hosts are reported as pending. Hosts asking for the full makefile are
+/// reported apart and count towards the quorum, the caller is expected to resend it.
+/// Fails if the quorum cannot be reached anymore or if `timeout` (30s by default)
+/// elapses first.
+#[tracing::instrument(skip(streams, timeout))]
+pub async fn wait_acks(
+    streams: Vec<(SocketAddr, &mut TimedStream)>,
+    timeout: Option<Duration>,
+    min_acks: Option<usize>,
+) -> Result<WaitAcksReport> {
+    let host_amount = streams.len();
+    let min_acks = min_acks.unwrap_or(host_amount);
+    if min_acks > host_amount {
+        bail!("Cannot wait for {min_acks} acks from only {host_amount} hosts.");
+    }
 
-                // Read acknowledgment message
-                let message: Vec<u8> =
-                    match read_next_message(stream, MessageKind::AckMessage).await {
-                        Ok(Some(message)) => message,
-                        Ok(None) => {
-                            warn!("Buffer EOF while waiting for ack from {}", sock);
-                            return;
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Failed to read ack message from {}: {}",
-                                sock,
-                                e.root_cause()
-                            );
-                            return;
-                        }
-                    };
-                info!("Received a new ack from {sock}");
-                let msg: Option<Message<AckMessage>> = dec!(message)
-                    .inspect_err(|e| warn!("Failed to decode the ack of {sock}: {e}"))
-                    .inspect(|_| info!("Successfully decoded the ack of {sock}"))
-                    .ok();
+    info!("Waiting for {min_acks}/{host_amount} acks");
 
-                // Forward acknowledgment to channel
-                if let Err(e) = sender.send((msg.map(|msg| msg.inner), sock.clone())).await {
-                    error!("Failed to forward message from {} via channel: {e}", sock);
-                } else {
-                    info!(
-                        "Ack/Fail/Invalid message from {} forwarded to main loop",
-                        sock
-                    );
-                }
-            }
-        })
-        .collect::<Vec<_>>();
+    let mut report = WaitAcksReport::default();
+    let mut acks = FuturesUnordered::new();
+    for (sock, stream) in streams {
+        report.pending.push(sock.clone());
+        acks.push(async move {
+            let ack = read_ack(stream, &sock).await;
+            (sock, ack)
+        });
+    }
 
     // Timeout for all acknowledgments
-    let all_tasks = join_all(tasks);
-    let mut sleep_fut = Box::pin(sleep(timeout.unwrap_or(Duration::from_secs(30))));
+    let timeout = timeout.unwrap_or(DEFAULT_ACK_TIMEOUT);
+    let sleep_fut = sleep(timeout);
+    tokio::pin!(sleep_fut);
 
-    select! {
-        // Timeout branch
-        _ = &mut sleep_fut => {
-            error!("Timed out after 10s while waiting for acks");
-            bail!("Timed out when waiting for acks.");
-        },
+    while report.succeeded.len() + report.need_full_makefile.len() < min_acks {
+        select! {
+            // Timeout branch
+            _ = &mut sleep_fut => {
+                error!("Timed out after {timeout:?} while waiting for acks");
+                bail!("Timed out when waiting for acks.");
+            },
 
-        _ = all_tasks => {
-            let mut ack_count = 0;
+            ack = acks.next() => {
+                let Some((sock, ack)) = ack else {
+                    break;
+                };
+                report.pending.retain(|s| s != &sock);
 
-            // Process messages received via channel
-            while let Ok(message) = receiver.try_recv() {
-                match message {
-                    (Some(AckMessage::Ok), sock) => {
-                        ack_count += 1;
-                        info!("Received Ack from {} (ack_count={}/{})", sock, ack_count, host_amount);
-                    },
-                    (Some(AckMessage::Failure), sock) => {
-                        bail!("Received a failed message from: {sock}");
+                match ack {
+                    Some(AckMessage::Ok) => {
+                        report.succeeded.push(sock.clone());
+                        info!("Received Ack from {} (ack_count={}/{})", sock, report.succeeded.len(), min_acks);
+                    }
+                    Some(AckMessage::NeedFullMakefile) => {
+                        info!("{sock} needs the full makefile");
+                        report.need_full_makefile.push(sock);
+                    }
+                    Some(AckMessage::Failure) => {
+                        warn!("Received a failed message from: {sock}");
+                        report.failed.push(sock);
                     }
-                    (None, sock) => {
-                        bail!("Received an invalid message from {sock}");
+                    None => {
+                        warn!("Received an invalid message from {sock}");
+                        report.failed.push(sock);
                     }
                 }
+
+                if report.succeeded.len() + report.need_full_makefile.len() + report.pending.len()
+                    < min_acks
+                {
+                    bail!("Too many hosts failed to acknowledge: {:?}", report.failed);
+                }
             }
-            if ack_count == host_amount {
-                info!("All {} acknowledgments received successfully", host_amount);
-            } else {
-                bail!("Failed to receiv all the acknowledgments.")
-            }
+        }
+    }
 
-        },
+    if report.succeeded.len() + report.need_full_makefile.len() < min_acks {
+        bail!("Failed to receiv all the acknowledgments.")
     }
 
-    Ok(())
+    info!(
+        "{} acknowledgments received successfully, {} still pending",
+        report.succeeded.len(),
+        report.pending.len()
+    );
+    Ok(report)
 }
@@ -1,13 +1,41 @@
+use std::{collections::HashMap, time::SystemTime};
+
 use serde::{Deserialize, Serialize};
 
 use crate::{network::SocketAddr, process_id::ProcessId};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessDatas {
     pub caller_daemon: SocketAddr,
     pub involved_hosts: Vec<SocketAddr>,
     pub args: Vec<String>,
     pub pid: ProcessId,
+    pub start_time: SystemTime,
+    /// Environment of the caller forwarded to make.
+    pub env: HashMap<String, String>,
+    /// Variables set for make whatever the passthrough list, such as the `MAKEFLAGS`
+    /// of the caller. They win over `env`.
+    #[serde(default)]
+    pub env_overrides: HashMap<String, String>,
+    /// Amount of jobs of make on this host, from a `parallel` directive. Each host sets
+    /// it from its own makefile, it is never distributed.
+    #[serde(default)]
+    pub parallel_jobs: Option<usize>,
+}
+
+impl Default for ProcessDatas {
+    fn default() -> Self {
+        Self {
+            caller_daemon: SocketAddr::default(),
+            involved_hosts: Vec::new(),
+            args: Vec::new(),
+            pid: ProcessId::default(),
+            start_time: SystemTime::now(),
+            env: HashMap::new(),
+            env_overrides: HashMap::new(),
+            parallel_jobs: None,
+        }
+    }
 }
 
 impl ProcessDatas {
@@ -16,12 +44,17 @@ impl ProcessDatas {
         caller_daemon: SocketAddr,
         involved_hosts: Vec<SocketAddr>,
         args: Vec<String>,
+        env: HashMap<String, String>,
     ) -> Self {
         Self {
             involved_hosts,
             caller_daemon,
             args,
             pid,
+            start_time: SystemTime::now(),
+            env,
+            env_overrides: HashMap::new(),
+            parallel_jobs: None,
         }
     }
 }
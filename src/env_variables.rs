@@ -8,19 +8,83 @@ pub enum EnvVariable {
     DaemonPort,
     /// IP address of the daemon
     DaemonIp,
+    /// Full TCP socket address of the daemon, takes precedence over the ip and port
+    DaemonSocket,
     /// Path to the binary executable of dake
     BinaryPath,
     /// Path to the DAKE workspace and data directory
     DakeSpacePath,
+    /// Set to `0` to run local-only builds through the daemon instead of in-process
+    NoDaemon,
+    /// Set to `1` to print the build logs sorted by the time they were produced at
+    SortLogs,
+    /// Working directory of the caller, set by the daemon for the make processes
+    CallerPath,
+    /// Target built by a make process, set by the daemon for the fetches of its recipes
+    BuiltTarget,
+    /// Milliseconds to wait for a freshly spawned daemon to accept connections
+    DaemonConnectTimeout,
+    /// Milliseconds a read may wait on the peer before failing
+    ReadTimeout,
+    /// Milliseconds a write may wait on the peer before failing
+    WriteTimeout,
+    /// Payload size from which messages are sent zstd-compressed
+    CompressThreshold,
+    /// Certificate presented to the peers, enables TLS with the key and the authority
+    TlsCert,
+    /// Private key of the TLS certificate
+    TlsKey,
+    /// Authority the certificates of the peers must be signed by
+    TlsCa,
+    /// Seconds between two liveness checks of the daemon by the caller
+    HeartbeatInterval,
+    /// Set to `1` to accept daemon addresses with a port below 1024
+    AllowPrivilegedPorts,
+    /// Amount of builds the daemon may run at once, overrides the config
+    MaxJobs,
+    /// Comma separated IPs or subnets allowed to connect, overrides the config
+    AllowedPeers,
+    /// Seconds the build archives are kept, overrides the config
+    ArtifactTtl,
+    /// Most verbose level logged by the daemon, overrides the config
+    LogLevel,
+    /// Port of the Prometheus metrics endpoint of the daemon
+    MetricsPort,
+    /// Seconds a process may stay registered without a done, overrides the config
+    StaleProcessTtl,
 }
 
 impl Display for EnvVariable {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            EnvVariable::DaemonPort => "DAKE_PORT",
-            EnvVariable::DaemonIp => "DAKE_IP",
-            EnvVariable::BinaryPath => "DAKE_PATH",
-            EnvVariable::DakeSpacePath => "DAKE_SPACE_PATH",
-        })
+        write!(
+            f,
+            "{}",
+            match self {
+                EnvVariable::DaemonPort => "DAKE_PORT",
+                EnvVariable::DaemonIp => "DAKE_IP",
+                EnvVariable::DaemonSocket => "DAKE_SOCKET",
+                EnvVariable::BinaryPath => "DAKE_PATH",
+                EnvVariable::DakeSpacePath => "DAKE_SPACE_PATH",
+                EnvVariable::NoDaemon => "DAKE_NO_DAEMON",
+                EnvVariable::SortLogs => "DAKE_SORT_LOGS",
+                EnvVariable::CallerPath => "DAKE_CALLER_PATH",
+                EnvVariable::BuiltTarget => "DAKE_BUILT_TARGET",
+                EnvVariable::DaemonConnectTimeout => "DAKE_DAEMON_CONNECT_TIMEOUT_MS",
+                EnvVariable::ReadTimeout => "DAKE_READ_TIMEOUT_MS",
+                EnvVariable::WriteTimeout => "DAKE_WRITE_TIMEOUT_MS",
+                EnvVariable::CompressThreshold => "DAKE_COMPRESS_THRESHOLD",
+                EnvVariable::TlsCert => "DAKE_TLS_CERT",
+                EnvVariable::TlsKey => "DAKE_TLS_KEY",
+                EnvVariable::TlsCa => "DAKE_TLS_CA",
+                EnvVariable::HeartbeatInterval => "DAKE_HEARTBEAT_INTERVAL_SECS",
+                EnvVariable::AllowPrivilegedPorts => "DAKE_ALLOW_PRIVILEGED_PORTS",
+                EnvVariable::MaxJobs => "DAKE_MAX_JOBS",
+                EnvVariable::AllowedPeers => "DAKE_ALLOWED_PEERS",
+                EnvVariable::ArtifactTtl => "DAKE_ARTIFACT_TTL_SECS",
+                EnvVariable::LogLevel => "DAKE_LOG_LEVEL",
+                EnvVariable::MetricsPort => "DAKE_METRICS_PORT",
+                EnvVariable::StaleProcessTtl => "DAKE_STALE_PROCESS_TTL_SECS",
+            }
+        )
     }
 }
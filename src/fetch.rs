@@ -1,23 +1,174 @@
 use std::{
-    fs::OpenOptions,
-    io::{BufWriter, Write},
-    path::PathBuf,
+    env::{var, var_os},
+    io::{IsTerminal, stderr},
+    path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
-use tokio::time::sleep;
+use anyhow::{Context, Result, bail};
+use blake3::Hasher;
+use indicatif::{ProgressBar, ProgressStyle};
+use tar::Archive;
+use tokio::{
+    fs::{File, OpenOptions, remove_file, rename},
+    io::{AsyncWriteExt, BufWriter},
+    task::spawn_blocking,
+};
 use tracing::{error, info, warn};
 
 use crate::{
-    constants::FETCH_FAILURE_DELAY,
+    constants::PARTIAL_FETCH_SUFFIX,
     dec,
+    env_variables::EnvVariable,
     network::{
-        DaemonMessage, FetcherMessage, Message, MessageKind, SocketAddr, connect,
-        read_next_message, write_message,
+        DaemonMessage, FetchRequest, FetcherMessage, Message, MessageKind, Negotiated, SocketAddr,
+        TimedStream, connect, read_next_message, write_message,
     },
     process_id::ProcessId,
+    utils::{file_checksum, file_hasher},
 };
 
+/// Result of the transfer of a target.
+#[derive(Debug, PartialEq, Eq)]
+enum Attempt {
+    /// The target is complete or up to date, or the transfer stopped early and can be resumed.
+    Finished,
+    /// The resumed target does not hash to the checksum of the daemon.
+    Corrupted,
+    /// The daemon could not send the target, see [`FetcherMessage::Failed`].
+    Failed,
+}
+
+/// What the fetcher already has of a target, sent along its request so the daemon
+/// can skip or resume the transfer.
+struct LocalCopy {
+    /// Blake3 hash of the complete local copy, if any.
+    known_checksum: Option<[u8; 32]>,
+    /// Length of the partial file left by an interrupted fetch, 0 if none.
+    offset: u64,
+    /// Fed with the `offset` bytes already received.
+    hasher: Hasher,
+}
+
+impl LocalCopy {
+    /// Inspects the local copy and the partial file of `target`.
+    async fn of(target: &str) -> Result<Self> {
+        let file_path = Path::new(target);
+        let known_checksum = if file_path.is_file() {
+            match checksum_of(file_path).await {
+                Ok(checksum) => Some(checksum),
+                Err(e) => {
+                    warn!("Failed to hash the local copy of '{target}': {e:?}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let partial_path = partial_path(target);
+        let offset = match partial_path.metadata() {
+            Ok(meta) if meta.is_file() => meta.len(),
+            _ => 0,
+        };
+        // Every byte of the artifact goes through the hasher, the kept ones first.
+        let hasher = if offset > 0 {
+            info!("Resuming the fetch of '{target}' after {offset} bytes");
+            hasher_of(&partial_path).await?
+        } else {
+            Hasher::new()
+        };
+
+        Ok(Self {
+            known_checksum,
+            offset,
+            hasher,
+        })
+    }
+}
+
+/// Returns the path the target is written to until it is complete.
+fn partial_path(target: &str) -> PathBuf {
+    PathBuf::from(format!("{target}{PARTIAL_FETCH_SUFFIX}"))
+}
+
+/// Opens the output file the first time data arrives, appending to it if `append`,
+/// truncating the previous copy otherwise.
+async fn open_output<'a>(
+    writer: &'a mut Option<BufWriter<File>>,
+    file_path: &PathBuf,
+    append: bool,
+) -> Result<&'a mut BufWriter<File>> {
+    Ok(match writer {
+        Some(writer) => writer,
+        None => {
+            info!("Opening output file at {:?}", file_path);
+            let mut options = OpenOptions::new();
+            options.create(true);
+            if append {
+                options.append(true);
+            } else {
+                options.write(true).truncate(true);
+            }
+            let file = options
+                .open(file_path)
+                .await
+                .with_context(|| format!("Failed to open output file {file_path:?}"))?;
+            writer.insert(BufWriter::new(file))
+        }
+    })
+}
+
+/// Shows the progress of the transfer of `target` on stderr, if it is a terminal.
+///
+/// A spinner is shown when the size of the artifact is unknown.
+fn progress_bar(target: &str, total_bytes: u64, offset: u64) -> ProgressBar {
+    if !stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let (bar, template) = if total_bytes == 0 {
+        (
+            ProgressBar::new_spinner(),
+            "{spinner} {msg} {bytes} ({bytes_per_sec})",
+        )
+    } else {
+        (
+            ProgressBar::new(total_bytes),
+            "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {percent}%)",
+        )
+    };
+    match ProgressStyle::with_template(template) {
+        Ok(style) => bar.set_style(style),
+        Err(e) => warn!("Invalid progress bar template: {e}"),
+    }
+    bar.set_message(target.to_string());
+    bar.set_position(offset);
+    bar
+}
+
+/// Hashes `path` on the blocking pool.
+async fn checksum_of(path: &Path) -> Result<[u8; 32]> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || file_checksum(&path)).await?
+}
+
+/// Returns a hasher fed with the content of `path`, on the blocking pool.
+async fn hasher_of(path: &Path) -> Result<Hasher> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || file_hasher(&path)).await?
+}
+
+/// Unpacks the tar archive at `path` in the current directory, on the blocking pool.
+async fn unpack_archive(path: &Path) -> Result<()> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        let file = std::fs::File::open(&path).context(format!("Failed to open {path:?}"))?;
+        Archive::new(file)
+            .unpack(".")
+            .context(format!("Failed to unpack {path:?}"))
+    })
+    .await?
+}
+
 /// Handles the client-side of a fetch operation.
 ///
 /// This function:
@@ -27,9 +178,25 @@ use crate::{
 /// 4. Receives and writes `FetcherMessage::Object` data into a target file.
 /// It is the *mirror* of the daemon’s `handle_fetch()` operation.
 ///
+/// If a local copy of the target exists, its checksum is sent along with the
+/// request and the copy is kept as is when the daemon answers `NotModified`.
+///
+/// The data is written next to the target, with the [`PARTIAL_FETCH_SUFFIX`], and
+/// moved over the target once complete. If such a partial file is left by an
+/// interrupted fetch, the transfer resumes after its bytes. A directory target is
+/// received as a tar archive, unpacked in the current directory once complete.
+///
+/// The whole artifact is checked against the checksum sent by the daemon. A resumed
+/// artifact that does not match is fetched again from the start.
+///
+/// # Errors
+/// Fails if the artifact fetched from the start does not match its checksum, the
+/// corrupted file is removed. Fails once cancelled after a daemon-side error.
+///
 /// # Notes
-/// This function does not stop on daemon-side errors immediately — it may wait
-/// after a `FetcherMessage::Failed` to allow parent synchronization.
+/// This function does not stop on daemon-side errors immediately: after a
+/// `FetcherMessage::Failed` it waits for the daemon to send `FetcherMessage::Cancel`,
+/// once the whole process is over.
 #[tracing::instrument(skip(labeled_path, pid))]
 pub async fn fetch(
     target: String,
@@ -39,18 +206,61 @@ pub async fn fetch(
 ) -> Result<()> {
     info!("Fetcher started for target '{}' with PID {:?}", target, pid);
 
+    let attempt = fetch_once(&target, labeled_path.clone(), &pid, &sock).await?;
+    if attempt == Attempt::Corrupted {
+        refetch(&target, labeled_path, &pid, &sock).await?;
+    }
+
+    info!("Fetcher finished successfully for PID {:?}", pid);
+    Ok(())
+}
+
+/// Fetches `target` again from the start, the resumed copy being corrupted.
+async fn refetch(
+    target: &str,
+    labeled_path: Option<PathBuf>,
+    pid: &ProcessId,
+    sock: &SocketAddr,
+) -> Result<()> {
+    warn!("The resumed copy of '{target}' is corrupted, fetching it from the start.");
+    remove_file(partial_path(target))
+        .await
+        .context("Failed to remove the corrupted partial file")?;
+    // Without a partial file the transfer starts at 0, nothing is checked again.
+    fetch_once(target, labeled_path, pid, sock).await?;
+    Ok(())
+}
+
+/// Sends a single fetch request, resuming from the partial file of the target if any.
+async fn fetch_once(
+    target: &str,
+    labeled_path: Option<PathBuf>,
+    pid: &ProcessId,
+    sock: &SocketAddr,
+) -> Result<Attempt> {
     // --- Step 1: Connect to remote daemon ---
     info!("Connecting with the daemon...");
-    let mut stream = connect(sock.clone())
+    let mut stream = connect(sock.clone(), None)
         .await
         .context("Failed to connect with the daemon.")?;
-    info!("Connected successfully.");
+    info!(
+        "Connected successfully with protocol version {}.",
+        stream.negotiated().version
+    );
 
     // --- Step 2: Send Fetch request to remote daemon ---
+    let local = LocalCopy::of(target).await?;
+
+    // Set by the daemon running make, more reliable than the path parsed from the pid.
+    let caller_path = var_os(EnvVariable::CallerPath.to_string()).map(PathBuf::from);
     let fetch_message = Message::new(
         DaemonMessage::Fetch {
-            target: target.clone(),
+            target: target.to_string(),
             labeled_path,
+            known_checksum: local.known_checksum,
+            offset: local.offset,
+            parent: var(EnvVariable::BuiltTarget.to_string()).ok(),
+            caller_path,
         },
         pid.clone(),
     );
@@ -65,21 +275,64 @@ pub async fn fetch(
     );
 
     // --- Step 3: Receive all messages and write object to file ---
-    let file_path = PathBuf::from(&target);
-    info!("Opening output file at {:?}", file_path);
+    match receive_artifact(&mut stream, target, sock, local).await? {
+        Attempt::Failed => {
+            wait_for_cancel(&mut stream, sock).await?;
+            bail!("The fetch of '{target}' failed.")
+        }
+        attempt => Ok(attempt),
+    }
+}
+
+/// Waits for the [`FetcherMessage::Cancel`] the daemon sends once the process of a
+/// failed fetch is over.
+///
+/// Exiting before would fail the parent make before the build is stopped, adding
+/// spurious errors.
+async fn wait_for_cancel(stream: &mut TimedStream, sock: &SocketAddr) -> Result<()> {
+    info!("Waiting for the process to be over before exit...");
+    loop {
+        let msg = match read_next_message(stream, MessageKind::FetcherMessage, None).await {
+            Ok(Some(msg)) => msg,
+            Ok(None) => bail!("The daemon {sock} closed the connection before the cancel."),
+            Err(e) => {
+                warn!("Failed to read FetcherMessage from {}: {e:?}", sock);
+                continue;
+            }
+        };
+        match dec!(msg, Message<FetcherMessage>)?.inner {
+            FetcherMessage::Cancel => return Ok(()),
+            msg => warn!("Unexpected message while waiting for the cancel: {msg:?}"),
+        }
+    }
+}
 
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&file_path)
-        .with_context(|| format!("Failed to open output file for target '{target}'"))?;
-    let mut writer = BufWriter::new(file);
+/// Receives the artifact of `target` and writes it in the current directory, see
+/// [`fetch`]. The transfer continues the partial file described by `local`.
+///
+/// Returns [`Attempt::Failed`] as soon as the daemon reports a failure.
+async fn receive_artifact(
+    stream: &mut TimedStream,
+    target: &str,
+    sock: &SocketAddr,
+    local: LocalCopy,
+) -> Result<Attempt> {
+    let LocalCopy {
+        offset, mut hasher, ..
+    } = local;
+    let file_path = PathBuf::from(target);
+    let partial_path = partial_path(target);
+    // Opened lazily so a `NotModified` answer leaves the local copy untouched.
+    let mut writer = None;
+    let mut expected_checksum = None;
+    let mut unpack = false;
+    // Replaced once the daemon sent the size of the artifact.
+    let mut progress = ProgressBar::hidden();
 
     info!("Waiting for object data from daemon {}", sock);
 
     loop {
-        let msg = match read_next_message(&mut stream, MessageKind::FetcherMessage).await {
+        let msg = match read_next_message(stream, MessageKind::FetcherMessage, None).await {
             Ok(Some(raw_msg)) => {
                 info!("Received raw FetcherMessage from {}", sock);
                 raw_msg
@@ -97,35 +350,162 @@ pub async fn fetch(
             }
         };
 
-        let msg: FetcherMessage = dec!(msg)?;
+        let Message { inner: msg, .. }: Message<FetcherMessage> = dec!(msg)?;
         match msg {
+            FetcherMessage::Metadata {
+                is_tar,
+                total_bytes,
+            } => {
+                info!("'{target}' is {total_bytes} bytes long, tar archive: {is_tar}");
+                unpack = is_tar;
+                progress = progress_bar(target, total_bytes, offset);
+            }
             FetcherMessage::Object(obj) => {
+                progress.inc(obj.len() as u64);
+                hasher.update(&obj);
                 info!("Writing {} bytes from object chunk to file", obj.len());
-                writer
+                open_output(&mut writer, &partial_path, offset > 0)
+                    .await?
                     .write_all(&obj)
+                    .await
                     .with_context(|| format!("Failed writing object data for target '{target}'"))?;
             }
+            FetcherMessage::Checksum { hex } => expected_checksum = Some(hex),
             FetcherMessage::Eof => {
                 info!("Received EOF message, end of fetching stage.");
+                progress.finish();
+                // An empty artifact is only made of the EOF message.
+                open_output(&mut writer, &partial_path, offset > 0).await?;
+                if let Some(mut writer) = writer.take() {
+                    writer
+                        .flush()
+                        .await
+                        .context("Failed to flush file buffer after receiving all data")?;
+                }
+                let checksum = hasher.finalize().to_hex();
+                if expected_checksum.as_deref() != Some(checksum.as_str()) {
+                    warn!("'{target}' hashes to {checksum}, the daemon sent {expected_checksum:?}");
+                    if offset > 0 {
+                        return Ok(Attempt::Corrupted);
+                    }
+                    remove_file(&partial_path)
+                        .await
+                        .context("Failed to remove the corrupted artifact")?;
+                    bail!("artifact checksum mismatch for '{target}'");
+                }
+                if unpack {
+                    unpack_archive(&partial_path).await?;
+                    remove_file(&partial_path)
+                        .await
+                        .context("Failed to remove the unpacked archive")?;
+                } else {
+                    rename(&partial_path, &file_path).await.with_context(|| {
+                        format!("Failed to move {partial_path:?} to {file_path:?}")
+                    })?;
+                }
+                break;
+            }
+            FetcherMessage::NotModified => {
+                info!("The local copy of '{target}' is up to date.");
+                if offset > 0 {
+                    remove_file(&partial_path)
+                        .await
+                        .context("Failed to remove the partial file of an up to date target")?;
+                }
                 break;
             }
             FetcherMessage::Failed => {
-                error!(
-                    "Fetcher: Daemon reported a fetch failure for target '{}'. \
-                     Waiting for parent synchronization before exit...",
-                    target
-                );
-                // Wait for parent to send Done notification before terminating
-                // This prevents premature process cleanup that could cause spurious errors
-                sleep(FETCH_FAILURE_DELAY).await;
+                error!("Fetcher: Daemon reported a fetch failure for target '{target}'.");
+                progress.abandon();
+                return Ok(Attempt::Failed);
             }
+            FetcherMessage::Cancel => bail!("The fetch of '{target}' failed."),
+        }
+    }
+
+    if !progress.is_finished() {
+        progress.abandon();
+    }
+    // Kept for the next fetch to resume if the transfer was interrupted.
+    if let Some(mut writer) = writer {
+        writer
+            .flush()
+            .await
+            .context("Failed to flush file buffer after receiving all data")?;
+    }
+    Ok(Attempt::Finished)
+}
+
+/// Fetches several targets at once from the daemon, which builds them in parallel.
+///
+/// The artifacts come one after the other on the same connection, each one like
+/// [`fetch`] receives it: the local copies and partial files are sent along the
+/// request, so up to date targets are skipped and interrupted ones resumed.
+///
+/// # Errors
+/// Fails if a target could not be fetched, once the daemon cancelled the fetch. The
+/// other targets are still written.
+#[tracing::instrument(skip(pid))]
+pub async fn fetch_batch(
+    targets: Vec<(String, Option<PathBuf>)>,
+    pid: ProcessId,
+    sock: SocketAddr,
+) -> Result<()> {
+    info!("Batch fetcher started for {} targets", targets.len());
+    let mut stream = connect(sock.clone(), None)
+        .await
+        .context("Failed to connect with the daemon.")?;
+
+    let mut requests = Vec::with_capacity(targets.len());
+    let mut locals = Vec::with_capacity(targets.len());
+    for (target, labeled_path) in targets {
+        let local = LocalCopy::of(&target).await?;
+        requests.push(FetchRequest {
+            target,
+            labeled_path,
+            known_checksum: local.known_checksum,
+            offset: local.offset,
+        });
+        locals.push(local);
+    }
+
+    let caller_path = var_os(EnvVariable::CallerPath.to_string()).map(PathBuf::from);
+    let parent = var(EnvVariable::BuiltTarget.to_string()).ok();
+    let message = Message::new(
+        DaemonMessage::FetchBatch {
+            targets: requests.clone(),
+            parent,
+            caller_path,
+        },
+        pid.clone(),
+    );
+    write_message(&mut stream, message)
+        .await
+        .with_context(|| format!("Failed to send the FetchBatch request to {sock}"))?;
+
+    let mut failures = Vec::new();
+    let mut corrupted = Vec::new();
+    for (request, local) in requests.into_iter().zip(locals) {
+        match receive_artifact(&mut stream, &request.target, &sock, local).await? {
+            Attempt::Finished => info!("Fetched '{}'", request.target),
+            Attempt::Corrupted => corrupted.push(request),
+            Attempt::Failed => failures.push(request.target),
         }
     }
 
-    writer
-        .flush()
-        .context("Failed to flush file buffer after receiving all data")?;
+    if !failures.is_empty() {
+        wait_for_cancel(&mut stream, &sock).await?;
+        bail!("Failed to fetch {}.", failures.join(", "))
+    }
+    for FetchRequest {
+        target,
+        labeled_path,
+        ..
+    } in corrupted
+    {
+        refetch(&target, labeled_path, &pid, &sock).await?;
+    }
 
-    info!("Fetcher finished successfully for PID {:?}", pid);
+    info!("Batch fetcher finished successfully for PID {:?}", pid);
     Ok(())
 }
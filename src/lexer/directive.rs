@@ -6,7 +6,23 @@ pub const DIRECTIVE_PREFIX: &str = "#!";
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Directive {
-    RootDef { ip: IpAddr, path: PathBuf },
+    RootDef {
+        ip: IpAddr,
+        path: PathBuf,
+    },
+    /// Limits the amount of concurrent builds of the next target on a host.
+    MaxJobs {
+        count: usize,
+    },
+    /// Amount of jobs make may run at once on the host `ip`, as with `make -j <jobs>`.
+    Parallel {
+        ip: IpAddr,
+        jobs: usize,
+    },
+    /// Includes another makefile, its path is relative to the including makefile.
+    Include {
+        path: PathBuf,
+    },
 }
 
 impl FromStr for Directive {
@@ -22,6 +38,20 @@ impl FromStr for Directive {
                 ip: ip.parse()?,
                 path: path.parse()?,
             },
+            ["max-jobs", count] => match count.parse()? {
+                0 => bail!("max-jobs must be at least 1: {}", s),
+                count => Directive::MaxJobs { count },
+            },
+            ["parallel", ip, jobs] => match jobs.parse()? {
+                0 => bail!("parallel must allow at least 1 job: {}", s),
+                jobs => Directive::Parallel {
+                    ip: ip.parse()?,
+                    jobs,
+                },
+            },
+            ["include", path] => Directive::Include {
+                path: path.parse()?,
+            },
             _ => bail!("Invalid Dake directive: {}", s),
         })
     }
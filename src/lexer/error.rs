@@ -0,0 +1,65 @@
+//! # Lexing Errors
+//!
+//! Structured diagnostics returned by [`lex`](crate::lexer::lexer::lex), each one
+//! pointing at the line and column of the makefile that could not be lexed.
+
+use std::fmt::{self, Display, Formatter};
+
+/// What went wrong on a line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LexErrorKind {
+    /// A `[` opening a target label is never closed.
+    UnmatchedBracket,
+    /// A `#!` directive, or an `include` line, could not be parsed.
+    BadDirective(String),
+    /// The label of a target could not be parsed.
+    BadLabel(String),
+    /// An `else` or `endif` without its conditional, or a conditional without `endif`.
+    UnbalancedConditional(String),
+}
+
+impl Display for LexErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnmatchedBracket => write!(f, "unmatched `[` in the target"),
+            Self::BadDirective(e) => write!(f, "invalid directive: {e}"),
+            Self::BadLabel(e) => write!(f, "invalid target label: {e}"),
+            Self::UnbalancedConditional(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// An error found while lexing, `line` and `column` start at 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LexError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LexErrorKind,
+}
+
+impl LexError {
+    pub fn new(line: usize, column: usize, kind: LexErrorKind) -> Self {
+        Self { line, column, kind }
+    }
+
+    /// Joins `errors` into a single message, one error per line.
+    pub fn join(errors: &[LexError]) -> String {
+        errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for LexError {}
@@ -1,16 +1,20 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Error, Result, bail};
+use ipnet::IpNet;
 use std::{
     net::{IpAddr, SocketAddr, ToSocketAddrs},
     str::FromStr,
 };
 
-use crate::network::DEFAULT_PORT;
+use crate::network::{DEFAULT_PORT, check_port};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum HostId {
     Socket(SocketAddr),
     Ip(IpAddr),
-    Name(String),
+    /// A host name and a port, such as `builder-01.local:1808`, resolved through DNS.
+    Name(String, u16),
+    /// Any daemon of the subnet listening on the port, such as `192.168.1.0/24:1808`.
+    Subnet(IpNet, u16),
 }
 
 impl HostId {
@@ -18,24 +22,87 @@ impl HostId {
         Ok(match self {
             HostId::Ip(ip) => SocketAddr::new(ip, DEFAULT_PORT),
             HostId::Socket(sock) => sock,
-            HostId::Name(name) => (name, DEFAULT_PORT)
-                .to_socket_addrs()?
-                .next()
-                .context("Failed to resolve DNS name {name}.")?,
+            HostId::Name(name, port) => (name.as_str(), port)
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .context(format!(
+                    "Failed to resolve the host name {name}, check the DNS configuration of this host."
+                ))?,
+            HostId::Subnet(net, port) => {
+                bail!("The subnet {net}:{port} only resolves against known daemons.")
+            }
         })
     }
+
+    /// Returns whether `sock` is a daemon this id designates.
+    pub fn matches(&self, sock: &SocketAddr) -> bool {
+        match self {
+            HostId::Socket(s) => s == sock,
+            HostId::Ip(ip) => *ip == sock.ip() && sock.port() == DEFAULT_PORT,
+            HostId::Subnet(net, port) => net.contains(&sock.ip()) && *port == sock.port(),
+            HostId::Name(..) => false,
+        }
+    }
 }
 
 impl FromStr for HostId {
     type Err = Error;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // `NET/PREFIX` or `NET/PREFIX:PORT`
+        if let Some((addr, rest)) = s.rsplit_once('/') {
+            let (prefix, port) = match rest.split_once(':') {
+                Some((prefix, port)) => (prefix, check_port(port.parse()?)?),
+                None => (rest, DEFAULT_PORT),
+            };
+            let net: IpNet = format!("{addr}/{prefix}")
+                .parse()
+                .context(format!("Invalid subnet {s}."))?;
+            return Ok(Self::Subnet(net.trunc(), port));
+        }
+
         Ok(match s.parse::<SocketAddr>() {
-            Ok(sock) => Self::Socket(sock),
+            Ok(sock) => {
+                check_port(sock.port()).context(format!("Invalid label {s}."))?;
+                Self::Socket(sock)
+            }
             Err(_) => match s.parse::<IpAddr>() {
                 Ok(ip) => Self::Ip(ip),
-                Err(_) => HostId::Name(s.to_string()),
+                Err(_) => match s.rsplit_once(':') {
+                    Some((name, port)) if let Ok(port) = port.parse() => {
+                        check_port(port).context(format!("Invalid label {s}."))?;
+                        HostId::Name(name.to_string(), port)
+                    }
+                    _ => HostId::Name(s.to_string(), DEFAULT_PORT),
+                },
             },
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HostId;
+    use crate::network::DEFAULT_PORT;
+
+    #[test]
+    fn host_names_keep_their_port() {
+        assert_eq!(
+            "builder-01.local:1900".parse::<HostId>().unwrap(),
+            HostId::Name("builder-01.local".to_string(), 1900)
+        );
+        assert_eq!(
+            "builder-01.local".parse::<HostId>().unwrap(),
+            HostId::Name("builder-01.local".to_string(), DEFAULT_PORT)
+        );
+
+        let sock = "localhost:1900"
+            .parse::<HostId>()
+            .unwrap()
+            .resolve()
+            .unwrap();
+        assert!(sock.ip().is_loopback(), "localhost resolved to {sock}");
+        assert_eq!(sock.port(), 1900);
+    }
+}
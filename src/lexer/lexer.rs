@@ -5,7 +5,8 @@
 //!
 //! Responsibilities:
 //! - Find and read a Makefile from disk (default candidates: `Makefile`, `makefile`, `GNUMakefile`).
-//! - Process Makefile content into lines (`Line`), handling directives, raw text, and target definitions.
+//! - Process Makefile content into lines (`Line`), handling directives, raw text, variable
+//!   assignments and target definitions.
 //! - Convert lines into tokens (`Token`), associating optional target labels.
 //! - Detect common issues such as unmatched brackets or unexpected raw lines.
 //!
@@ -13,19 +14,120 @@
 
 use crate::{
     lexer::{
-        directive::DIRECTIVE_PREFIX,
+        directive::{DIRECTIVE_PREFIX, Directive},
+        error::{LexError, LexErrorKind},
         target_label::TargetLabel,
-        tokens::{Line, Token},
+        tokens::{
+            AssignOp, ConditionalKind, ELSE_KEYWORD, ENDIF_KEYWORD, EXPORT_KEYWORD,
+            INCLUDE_KEYWORD, Line, PHONY_TARGET, Span, Token, UNEXPORT_KEYWORD,
+        },
     },
     makefile::RemoteMakefile,
 };
-use anyhow::{Context, Result};
-use std::{env::current_dir, fs::File, io::Read, path::PathBuf};
+use anyhow::{Context, Result, anyhow};
+use std::{
+    env::current_dir,
+    fs::File,
+    io::Read,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    vec::IntoIter,
+};
 use tracing::{info, warn};
 
 /// Error message if no Makefile was found.
 const NO_MAKEFILE_FOUND: &str = "dake: *** No targets specified and no makefile found.  Stop.";
 
+/// Where a backslash continuation occurs, which decides how the next line is joined.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ContinuationContext {
+    /// Outside of a recipe (dependency list, variable...): the backslash, newline and
+    /// surrounding whitespace become a single space, as in GNU make.
+    DependencyList,
+    /// Inside a tab-indented recipe line: the continuation is kept verbatim.
+    Recipe,
+}
+
+impl ContinuationContext {
+    /// A tab-indented line is part of a recipe, anything else is not.
+    fn of(line: &str) -> Self {
+        if line.starts_with('\t') {
+            Self::Recipe
+        } else {
+            Self::DependencyList
+        }
+    }
+}
+
+/// Returns whether a recipe line of `command` has the `+` prefix, which makes
+/// make run it even with `-n`, `-t` or `-q`, as for recursive make invocations.
+fn is_recursive_recipe(command: &str) -> bool {
+    command.lines().any(|line| {
+        line.strip_prefix('\t').is_some_and(|line| {
+            line.chars()
+                .take_while(|c| matches!(c, '@' | '-' | '+') || c.is_whitespace())
+                .any(|c| c == '+')
+        })
+    })
+}
+
+/// Splits an `export` or `unexport` line into its [`Line`]s, one per variable.
+///
+/// An assignment on an `export` line gives the [`Line::Assignment`] followed by the
+/// export of the variable, whose value is only kept if the assignment replaces it.
+/// Returns `None` for any other line, and for a bare `export` exporting everything.
+fn export_lines(line: &str) -> Option<Vec<Line>> {
+    let keyword_end = |keyword: &str| {
+        line.strip_prefix(keyword)
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .map(str::trim)
+    };
+
+    if let Some(names) = keyword_end(UNEXPORT_KEYWORD) {
+        let lines: Vec<_> = names
+            .split_whitespace()
+            .map(|name| Line::Unexport(name.to_string()))
+            .collect();
+        return (!lines.is_empty()).then_some(lines);
+    }
+
+    let rest = keyword_end(EXPORT_KEYWORD)?;
+    if let Some((name, op, value)) = AssignOp::split(rest) {
+        // `+=` and `?=` depend on the current value, only the assignment can tell.
+        let replaces = matches!(op, AssignOp::Simple | AssignOp::Recursive);
+        return Some(vec![
+            Line::Assignment {
+                name: name.to_string(),
+                op,
+                value: value.to_string(),
+            },
+            Line::Export {
+                name: name.to_string(),
+                value: replaces.then(|| value.to_string()),
+            },
+        ]);
+    }
+    let lines: Vec<_> = rest
+        .split_whitespace()
+        .map(|name| Line::Export {
+            name: name.to_string(),
+            value: None,
+        })
+        .collect();
+    (!lines.is_empty()).then_some(lines)
+}
+
+/// What ended a block of lines, see [`lex`].
+enum BlockEnd {
+    /// An `else`, possibly chaining another conditional.
+    Else(Span, Option<(ConditionalKind, String)>),
+    Endif(Span),
+    Eof,
+}
+
+/// Lines along with the lines of the makefile they come from.
+type NumberedLines = Peekable<IntoIter<(Span, Line)>>;
+
 /// The result of lexing: a list of tokens.
 pub type LexingOutput = Vec<Token>;
 
@@ -34,26 +136,39 @@ pub type LexingOutput = Vec<Token>;
 /// # Behavior
 /// - Splits into [`Line`]s (directives, raw lines, colon rules).
 /// - Groups consecutive raw lines into `RawText`.
-/// - Converts colon rules into `Target` tokens, possibly with labels.
-/// - Parses directives into `Directive` tokens.
+/// - Converts colon rules into `Target` tokens, possibly with labels. The recipe is
+///   kept verbatim, including the `@`, `-` and `+` prefixes of its lines.
+/// - Parses directives into `Directive` tokens, includes become `Include` tokens.
+/// - Converts `.PHONY` rules into `PhonyDecl` tokens.
+/// - Converts variable assignments outside of recipes into `Variable` tokens.
+/// - Converts `export` and `unexport` lines into one `Export` or `Unexport` token per
+///   variable, an assignment on an `export` line also gives a `Variable` token.
+/// - Nests the lines of `ifdef`/`ifndef`/`ifeq`/`ifneq` blocks into `Conditional` tokens.
 ///
 /// # Errors
-/// Returns an error if directive parsing or target label parsing fails.
-pub fn lex(s: String) -> Result<LexingOutput> {
+/// Lexing goes on after an error, every [`LexError`] found is returned: unmatched
+/// brackets, bad labels, bad directives and unbalanced conditional blocks.
+pub fn lex(s: String) -> Result<LexingOutput, Vec<LexError>> {
     const FORBIDDEN_RIGHT_PREFIX: [&str; 1] = ["="];
 
     /// Splits the raw string into [`Line`]s, handling:
     /// - Directives (prefixed with `DIRECTIVE_PREFIX`)
     /// - Comments (ignored)
-    /// - Continuations (`\` at end of line)
-    fn generate_lines(s: &str) -> Vec<Line> {
+    /// - Continuations (`\` at end of line), see [`ContinuationContext`]
+    /// - Variable assignments (`=`, `:=`, `::=`, `+=`, `?=`), before the colon rules
+    /// - `include` lines, turned into one include directive per file
+    /// - `export` and `unexport` lines, see [`export_lines`]
+    /// - Conditional lines (`ifeq`, `else`, `endif`...)
+    fn generate_lines(s: &str) -> Vec<(Span, Line)> {
         let mut lines = Vec::new();
-        let mut lines_iter = s.lines();
+        let mut lines_iter = s.lines().enumerate();
+
+        while let Some((index, line)) = lines_iter.next() {
+            let mut span = Span::line(index + 1);
 
-        while let Some(line) = lines_iter.next() {
             // Handle directives
             if line.starts_with(DIRECTIVE_PREFIX) {
-                lines.push(Line::Directive(line[2..].to_string()));
+                lines.push((span, Line::Directive(line[2..].to_string())));
                 continue;
             }
 
@@ -63,11 +178,68 @@ pub fn lex(s: String) -> Result<LexingOutput> {
                 .map(|(l, _)| (l.to_string(), ()))
                 .unwrap_or((line.to_string(), ()));
 
-            /// Pushes a line into the `lines` vector as either a raw or colon line.
-            fn push_line(lines: &mut Vec<Line>, line: &str) {
+            /// Pushes a line into the `lines` vector as either an assignment, a raw or a colon line.
+            fn push_line(lines: &mut Vec<(Span, Line)>, span: Span, line: &str) {
                 if line.is_empty() {
                     return;
                 }
+                if !line.starts_with('\t') {
+                    let trimmed = line.trim();
+                    if let Some((kind, condition)) = ConditionalKind::split(trimmed) {
+                        lines.push((
+                            span,
+                            Line::CondStart {
+                                kind,
+                                condition: condition.to_string(),
+                            },
+                        ));
+                        return;
+                    }
+                    if trimmed == ENDIF_KEYWORD {
+                        lines.push((span, Line::CondEnd));
+                        return;
+                    }
+                    if let Some(rest) = trimmed.strip_prefix(ELSE_KEYWORD)
+                        && (rest.is_empty() || rest.starts_with(char::is_whitespace))
+                    {
+                        let chained = ConditionalKind::split(rest.trim())
+                            .map(|(kind, condition)| (kind, condition.to_string()));
+                        if rest.trim().is_empty() || chained.is_some() {
+                            lines.push((span, Line::CondElse(chained)));
+                            return;
+                        }
+                    }
+                }
+                if let Some(files) = line.strip_prefix(INCLUDE_KEYWORD)
+                    && files.starts_with(char::is_whitespace)
+                {
+                    lines.extend(
+                        files.split_whitespace().map(|file| {
+                            (span, Line::Directive(format!("{INCLUDE_KEYWORD} {file}")))
+                        }),
+                    );
+                    return;
+                }
+                if !line.starts_with('\t')
+                    && let Some(exports) = export_lines(line.trim())
+                {
+                    lines.extend(exports.into_iter().map(|line| (span, line)));
+                    return;
+                }
+                // A tab-indented line belongs to a recipe, `VAR=value cmd` is a command there.
+                if !line.starts_with('\t')
+                    && let Some((name, op, value)) = AssignOp::split(line)
+                {
+                    lines.push((
+                        span,
+                        Line::Assignment {
+                            name: name.to_string(),
+                            op,
+                            value: value.to_string(),
+                        },
+                    ));
+                    return;
+                }
                 let line = match line.rsplit_once(':') {
                     Some((left, right)) => {
                         if FORBIDDEN_RIGHT_PREFIX.iter().any(|s| right.starts_with(s)) {
@@ -78,101 +250,323 @@ pub fn lex(s: String) -> Result<LexingOutput> {
                     }
                     None => Line::RawLine(format!("{line}\n")),
                 };
-                lines.push(line);
+                lines.push((span, line));
             }
 
             // Handle continuations with "\"
+            let context = ContinuationContext::of(&line);
             while line.ends_with('\\') {
-                if let Some(next_line) = lines_iter.next() {
+                if let Some((index, next_line)) = lines_iter.next() {
+                    span.end_line = index + 1;
                     line.pop(); // remove the backslash
-                    line.push_str(next_line);
+                    match context {
+                        ContinuationContext::DependencyList => {
+                            line.truncate(line.trim_end().len());
+                            line.push(' ');
+                            line.push_str(next_line.trim_start());
+                        }
+                        ContinuationContext::Recipe => line.push_str(next_line),
+                    }
                 } else {
-                    push_line(&mut lines, &line);
+                    push_line(&mut lines, span, &line);
                     break;
                 }
             }
 
-            push_line(&mut lines, &line);
+            push_line(&mut lines, span, &line);
         }
         lines
     }
 
-    /// Converts a sequence of [`Line`]s into [`Token`]s.
-    fn lines_to_tokens(lines: Vec<Line>) -> Result<Vec<Token>> {
+    /// Lexes the branches of a conditional opened at `span`, whose opening line was
+    /// just consumed, up to and including its `endif`.
+    fn conditional(
+        kind: ConditionalKind,
+        condition: String,
+        span: Span,
+        lines_iter: &mut NumberedLines,
+        errors: &mut Vec<LexError>,
+    ) -> Token {
+        let missing_endif = || {
+            LexError::new(
+                span.start_line,
+                1,
+                LexErrorKind::UnbalancedConditional(format!(
+                    "missing `{ENDIF_KEYWORD}` for `{kind} {condition}`"
+                )),
+            )
+        };
+
+        let (then_tokens, end) = lines_to_tokens(lines_iter, errors);
+        let (else_tokens, end_span) = match end {
+            BlockEnd::Endif(end) => (Vec::new(), end),
+            BlockEnd::Else(_, None) => match lines_to_tokens(lines_iter, errors) {
+                (else_tokens, BlockEnd::Endif(end)) => (else_tokens, end),
+                (else_tokens, BlockEnd::Else(end, _)) => {
+                    errors.push(LexError::new(
+                        end.start_line,
+                        1,
+                        LexErrorKind::UnbalancedConditional(format!(
+                            "extraneous `{ELSE_KEYWORD}` for `{kind} {condition}`"
+                        )),
+                    ));
+                    (else_tokens, end)
+                }
+                (else_tokens, BlockEnd::Eof) => {
+                    errors.push(missing_endif());
+                    (else_tokens, span)
+                }
+            },
+            // `else ifeq ...` shares the `endif` of the outer conditional.
+            BlockEnd::Else(else_span, Some((kind, condition))) => {
+                let chained = conditional(kind, condition, else_span, lines_iter, errors);
+                let end = chained.span();
+                (vec![chained], end)
+            }
+            BlockEnd::Eof => {
+                errors.push(missing_endif());
+                (Vec::new(), span)
+            }
+        };
+        Token::Conditional {
+            kind,
+            condition,
+            then_tokens,
+            else_tokens,
+            span: span.to(end_span),
+        }
+    }
+
+    /// Parses the `[label]` of a target rule at line `number`, returning the target
+    /// and its label if it has one.
+    fn parse_label(
+        left: &str,
+        number: usize,
+        errors: &mut Vec<LexError>,
+    ) -> Option<(String, TargetLabel)> {
+        let mut rest = left.trim();
+        // Offset of `rest` in the line, to report columns.
+        let mut offset = left.len() - left.trim_start().len();
+        let mut left_parsed = None;
+
+        // Parse labels inside [brackets]
+        while let Some(open) = rest.find('[') {
+            let column = offset + open + 1;
+            if let Some(close) = rest[open..].find(']') {
+                let prefix = rest[..open].trim();
+                let inside = &rest[open + 1..open + close].trim();
+
+                match inside.parse::<TargetLabel>() {
+                    Ok(label) => left_parsed = Some((prefix.to_string(), label)),
+                    Err(e) => errors.push(LexError::new(
+                        number,
+                        column,
+                        LexErrorKind::BadLabel(format!("{inside}: {e:#}")),
+                    )),
+                }
+
+                rest = &rest[open + close + 1..];
+                offset += open + close + 1;
+            } else {
+                warn!("Lexer: Unmatched bracket in target: {}", left);
+                errors.push(LexError::new(
+                    number,
+                    column,
+                    LexErrorKind::UnmatchedBracket,
+                ));
+                break;
+            }
+        }
+        left_parsed
+    }
+
+    /// Converts a sequence of [`Line`]s into [`Token`]s, up to the end of the
+    /// current conditional branch.
+    fn lines_to_tokens(
+        lines_iter: &mut NumberedLines,
+        errors: &mut Vec<LexError>,
+    ) -> (Vec<Token>, BlockEnd) {
         let mut tokens = Vec::new();
-        let mut lines_iter = lines.into_iter().peekable();
+        // Limit of a `max-jobs` directive, waiting for the target it applies to.
+        let mut max_jobs = None;
 
         loop {
             // Gather consecutive RawLines as one RawText
             let mut dummy_text = String::new();
-            while let Some(Line::RawLine(line)) = lines_iter.peek() {
+            let mut text_span: Option<Span> = None;
+            while let Some((span, Line::RawLine(line))) = lines_iter.peek() {
                 dummy_text.push_str(line);
+                text_span = Some(text_span.map_or(*span, |text_span| text_span.to(*span)));
                 lines_iter.next();
             }
-            if !dummy_text.is_empty() {
-                tokens.push(Token::RawText(dummy_text));
+            if let Some(span) = text_span {
+                if let Some(count) = max_jobs.take() {
+                    warn!("Lexer: max-jobs {count} is not followed by a target, ignoring it");
+                }
+                tokens.push(Token::RawText {
+                    text: dummy_text,
+                    span,
+                });
             }
 
             match lines_iter.next() {
-                Some(Line::ColonLine(left, mut right)) => {
+                Some((span, Line::ColonLine(left, right))) if left.trim() == PHONY_TARGET => {
+                    let targets: Vec<String> = right.split_whitespace().map(String::from).collect();
+                    info!("Lexer: Phony targets declared: {:?}", targets);
+                    tokens.push(Token::PhonyDecl { targets, span });
+                }
+                Some((mut span, Line::ColonLine(left, mut right))) => {
                     // Peek next line for inline continuation
-                    if let Some(Line::RawLine(extra)) = lines_iter.peek() {
+                    if let Some((extra_span, Line::RawLine(extra))) = lines_iter.peek() {
                         right.push_str(extra);
+                        span = span.to(*extra_span);
                         lines_iter.next();
                     }
-
-                    let mut rest = left.trim();
-                    let mut left_parsed = None;
-
-                    // Parse labels inside [brackets]
-                    while let Some(open) = rest.find('[') {
-                        if let Some(close) = rest[open..].find(']') {
-                            let prefix = rest[..open].trim();
-                            let inside = &rest[open + 1..open + close].trim();
-
-                            left_parsed =
-                                Some((prefix.to_string(), inside.parse::<TargetLabel>()?));
-
-                            rest = &rest[open + close + 1..];
-                        } else {
-                            warn!("Lexer: Unmatched bracket in target: {}", left);
-                            break;
-                        }
+                    // The following tab-indented lines are the rest of the recipe
+                    while let Some((extra_span, Line::RawLine(extra))) = lines_iter.peek()
+                        && extra.starts_with('\t')
+                    {
+                        right.push_str(extra);
+                        span = span.to(*extra_span);
+                        lines_iter.next();
                     }
+                    let recursive = is_recursive_recipe(&right);
 
-                    let token = match left_parsed {
+                    let token = match parse_label(&left, span.start_line, errors) {
                         Some((target, label)) => Token::Target {
                             target,
                             label: Some(label),
                             command: right,
+                            max_jobs: max_jobs.take(),
+                            recursive,
+                            span,
                         },
                         None => Token::Target {
                             target: left,
                             label: None,
                             command: right,
+                            max_jobs: max_jobs.take(),
+                            recursive,
+                            span,
                         },
                     };
                     tokens.push(token);
                 }
-                Some(Line::RawLine(line)) => {
+                Some((_, Line::RawLine(line))) => {
                     warn!("Lexer: Unexpected RawLine after processing: {}", line);
                 }
-                Some(Line::Directive(dir)) => tokens.push(Token::Directive(dir.parse()?)),
-                None => break,
+                Some((span, Line::Assignment { name, op, value })) => {
+                    info!("Lexer: Variable {name} {op} {value}");
+                    tokens.push(Token::Variable {
+                        name,
+                        op,
+                        value,
+                        span,
+                    });
+                }
+                Some((span, Line::Export { name, value })) => {
+                    info!("Lexer: Export of {name}");
+                    tokens.push(Token::Export { name, value, span });
+                }
+                Some((span, Line::Unexport(name))) => {
+                    info!("Lexer: Unexport of {name}");
+                    tokens.push(Token::Unexport { name, span });
+                }
+                Some((span, Line::Directive(dir))) => match dir.parse::<Directive>() {
+                    Ok(Directive::Include { path }) => {
+                        info!("Lexer: Include of {}", path.display());
+                        tokens.push(Token::Include { path, span })
+                    }
+                    Ok(directive) => {
+                        if let Directive::MaxJobs { count } = directive {
+                            max_jobs = Some(count);
+                        }
+                        tokens.push(Token::Directive { directive, span })
+                    }
+                    Err(e) => errors.push(LexError::new(
+                        span.start_line,
+                        1,
+                        LexErrorKind::BadDirective(format!("{e:#}")),
+                    )),
+                },
+                Some((span, Line::CondStart { kind, condition })) => {
+                    info!("Lexer: Conditional {kind} {condition}");
+                    tokens.push(conditional(kind, condition, span, lines_iter, errors))
+                }
+                Some((span, Line::CondElse(chained))) => {
+                    return (tokens, BlockEnd::Else(span, chained));
+                }
+                Some((span, Line::CondEnd)) => return (tokens, BlockEnd::Endif(span)),
+                None => return (tokens, BlockEnd::Eof),
             }
         }
-        Ok(tokens)
     }
 
     let lines = generate_lines(&s);
     info!("Lexer: Generated {} lines", lines.len());
 
-    let tokens = lines_to_tokens(lines.clone())?;
+    let mut errors = Vec::new();
+    let mut lines_iter = lines.into_iter().peekable();
+    let mut tokens = Vec::new();
+    // A stray `else` or `endif` is reported, the following lines are still lexed.
+    loop {
+        let (block, end) = lines_to_tokens(&mut lines_iter, &mut errors);
+        tokens.extend(block);
+        let (span, keyword) = match end {
+            BlockEnd::Eof => break,
+            BlockEnd::Else(span, _) => (span, ELSE_KEYWORD),
+            BlockEnd::Endif(span) => (span, ENDIF_KEYWORD),
+        };
+        errors.push(LexError::new(
+            span.start_line,
+            1,
+            LexErrorKind::UnbalancedConditional(format!(
+                "`{keyword}` without a matching conditional"
+            )),
+        ));
+    }
+
+    if !errors.is_empty() {
+        warn!("Lexer: Found {} errors", errors.len());
+        return Err(errors);
+    }
     info!("Lexer: Produced {} tokens", tokens.len());
 
     Ok(tokens)
 }
 
+/// Returns the `max-jobs` limit of the rule building `target` in `tokens`, if any.
+///
+/// The rules of both branches of a conditional are looked at.
+pub fn max_jobs_of(tokens: &[Token], target: &str) -> Option<usize> {
+    tokens.iter().find_map(|token| match token {
+        Token::Target {
+            target: targets,
+            max_jobs,
+            ..
+        } if targets.split_whitespace().any(|t| t == target) => *max_jobs,
+        Token::Conditional {
+            then_tokens,
+            else_tokens,
+            ..
+        } => max_jobs_of(then_tokens, target).or_else(|| max_jobs_of(else_tokens, target)),
+        _ => None,
+    })
+}
+
+/// Returns the job count of the first `parallel` directive of `tokens`, if any.
+///
+/// The generator only writes the directive of a host in the makefile of that host.
+pub fn parallel_jobs_of(tokens: &[Token]) -> Option<usize> {
+    tokens.iter().find_map(|token| match token {
+        Token::Directive {
+            directive: Directive::Parallel { jobs, .. },
+            ..
+        } => Some(*jobs),
+        _ => None,
+    })
+}
+
 /// Reads a file from the given path and lexes its contents.
 ///
 /// # Errors
@@ -185,6 +579,17 @@ pub fn lex_from_path(path: PathBuf) -> Result<LexingOutput> {
     info!("Lexer: Successfully read file {}", path.display());
 
     lex(content)
+        .map_err(|errors| anyhow!(LexError::join(&errors)))
+        .context(format!("When lexing the file {}.", path.display()))
+}
+
+/// Attempts to guess the Makefile path from default candidates in `root` and lex it.
+///
+/// # Errors
+/// Fails if no Makefile is found in `root`.
+pub fn guess_path_and_lex_in(root: &Path) -> Result<LexingOutput> {
+    let path = RemoteMakefile::guess_path(root.to_path_buf()).context(NO_MAKEFILE_FOUND)?;
+    lex_from_path(path)
 }
 
 /// Attempts to guess the Makefile path from default candidates and lex it.
@@ -192,7 +597,264 @@ pub fn lex_from_path(path: PathBuf) -> Result<LexingOutput> {
 /// # Errors
 /// Fails if no Makefile is found in the current directory.
 pub fn guess_path_and_lex() -> Result<LexingOutput> {
-    let current_dir = current_dir().context("Failed to fetch current dir.")?;
-    let path = RemoteMakefile::guess_path(current_dir).context(NO_MAKEFILE_FOUND)?;
-    lex_from_path(path)
+    guess_path_and_lex_in(&current_dir().context("Failed to fetch current dir.")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lex;
+    use crate::{
+        lexer::{
+            ConditionalKind, Token,
+            error::LexErrorKind,
+            tokens::{AssignOp, Span},
+        },
+        makefile::RemoteMakefileSet,
+        process_id::ProcessId,
+    };
+
+    const MAKEFILE: &str = "CC = gcc
+.PHONY: all
+all: build
+\t@echo building
+\t-rm -f stale.o
+\t+$(MAKE) -C sub
+build:
+\t@-$(CC) -o app main.c
+";
+
+    #[test]
+    fn recipe_prefixes_round_trip() {
+        let tokens = lex(MAKEFILE.to_string()).unwrap();
+
+        let recursive: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Target {
+                    target, recursive, ..
+                } => Some((target.as_str(), *recursive)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(recursive, [("all", true), ("build", false)]);
+
+        let set = RemoteMakefileSet::generate(
+            tokens,
+            "127.0.0.1:1808".parse().unwrap(),
+            ProcessId::test_local(1),
+        )
+        .unwrap();
+        assert_eq!(set.my_makefile(), MAKEFILE);
+    }
+
+    #[test]
+    fn pattern_rules_are_kept_on_every_host() {
+        const PATTERN_RULE: &str = "%.o: %.c\n\t$(CC) -c $< -o $@ -MF $*.d\n";
+        let makefile = format!("{PATTERN_RULE}app[10.0.0.2]: main.o\n\t$(CC) -o $@ $^\n");
+
+        let set = RemoteMakefileSet::generate(
+            lex(makefile).unwrap(),
+            "127.0.0.1:1808".parse().unwrap(),
+            ProcessId::test_local(1),
+        )
+        .unwrap();
+
+        assert!(set.my_makefile().starts_with(PATTERN_RULE));
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        assert_eq!(
+            remote.makefile(),
+            &format!("{PATTERN_RULE}app: main.o\n\t$(CC) -o $@ $^\n")
+        );
+    }
+
+    /// Returns the prerequisites and recipe of the rule of `target`.
+    fn rule_of<'a>(tokens: &'a [Token], target: &str) -> &'a str {
+        tokens
+            .iter()
+            .find_map(|token| match token {
+                Token::Target {
+                    target: name,
+                    command,
+                    ..
+                } if name == target => Some(command.as_str()),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("No rule for {target} in {tokens:?}"))
+    }
+
+    #[test]
+    fn dependency_continuations_become_a_single_space() {
+        let tokens = lex("a: b\\\n\tc\nd: e \\\n  f \\\n\t\tg\n".to_string()).unwrap();
+        assert_eq!(rule_of(&tokens, "a"), " b c\n");
+        assert_eq!(rule_of(&tokens, "d"), " e f g\n");
+    }
+
+    #[test]
+    fn variable_continuations_become_a_single_space() {
+        let tokens = lex("SRCS = a.c\\\n\tb.c\n".to_string()).unwrap();
+        let [Token::Variable { value, .. }] = tokens.as_slice() else {
+            panic!("Expected a single variable, got {tokens:?}");
+        };
+        assert_eq!(value, "a.c b.c");
+    }
+
+    #[test]
+    fn recipe_continuations_keep_their_tabs() {
+        let tokens = lex("all:\n\techo a \\\n\t\tb\n".to_string()).unwrap();
+        assert_eq!(rule_of(&tokens, "all"), "\n\techo a \t\tb\n");
+    }
+
+    #[test]
+    fn assignments_are_lexed_with_their_operator() {
+        let makefile =
+            "A = 1\nB := $(A)\nC ::= 3\nD += -O2\nE ?= gcc\napp[10.0.0.2]:\n\techo $(E)\n";
+        let tokens = lex(makefile.to_string()).unwrap();
+
+        let variables: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Variable {
+                    name, op, value, ..
+                } => Some((name.as_str(), *op, value.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            variables,
+            [
+                ("A", AssignOp::Recursive, "1"),
+                ("B", AssignOp::Simple, "$(A)"),
+                ("C", AssignOp::Simple, "3"),
+                ("D", AssignOp::Append, "-O2"),
+                ("E", AssignOp::Conditional, "gcc"),
+            ]
+        );
+
+        // The variables are defined on the remote host too.
+        let set = RemoteMakefileSet::generate(
+            tokens,
+            "127.0.0.1:1808".parse().unwrap(),
+            ProcessId::test_local(1),
+        )
+        .unwrap();
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        for makefile in [remote.makefile(), set.my_makefile()] {
+            assert!(
+                makefile.starts_with("A = 1\nB := $(A)\nC := 3\nD += -O2\nE ?= gcc\n"),
+                "{makefile}"
+            );
+        }
+    }
+
+    #[test]
+    fn conditional_blocks_are_nested() {
+        let tokens = lex(
+            "ifeq ($(OS),Windows_NT)\nEXT = .exe\nelse ifdef DEBUG\nEXT = .dbg\nelse\nEXT =\nendif\n"
+                .to_string(),
+        )
+        .unwrap();
+
+        let [
+            Token::Conditional {
+                kind: ConditionalKind::Ifeq,
+                condition,
+                then_tokens,
+                else_tokens,
+                span,
+            },
+        ] = tokens.as_slice()
+        else {
+            panic!("Expected a single conditional, got {tokens:?}");
+        };
+        assert_eq!(condition, "($(OS),Windows_NT)");
+        assert_eq!(*span, Span::new(1, 7));
+        assert!(
+            matches!(then_tokens.as_slice(), [Token::Variable { value, .. }] if value == ".exe")
+        );
+
+        // `else ifdef` chains a conditional nested in the else branch.
+        let [
+            Token::Conditional {
+                kind: ConditionalKind::Ifdef,
+                condition,
+                else_tokens,
+                ..
+            },
+        ] = else_tokens.as_slice()
+        else {
+            panic!("Expected a chained conditional, got {else_tokens:?}");
+        };
+        assert_eq!(condition, "DEBUG");
+        assert!(
+            matches!(else_tokens.as_slice(), [Token::Variable { value, .. }] if value.is_empty())
+        );
+    }
+
+    #[test]
+    fn unbalanced_conditionals_are_reported() {
+        let errors =
+            lex("all:\n\techo all\nendif\nifdef DEBUG\nCFLAGS = -g\n".to_string()).unwrap_err();
+
+        let lines: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                assert!(
+                    matches!(error.kind, LexErrorKind::UnbalancedConditional(_)),
+                    "{error}"
+                );
+                error.line
+            })
+            .collect();
+        assert_eq!(lines, [3, 4]);
+    }
+
+    #[test]
+    fn errors_point_at_their_line_and_column() {
+        let errors = lex(
+            "all: app\napp[10.0.0.0/33]:\n\techo app\n#! bogus\nx lib[10.0.0.3:\n\techo lib\n"
+                .to_string(),
+        )
+        .unwrap_err();
+
+        let positions: Vec<_> = errors
+            .iter()
+            .map(|error| (error.line, error.column))
+            .collect();
+        assert_eq!(positions, [(2, 4), (4, 1), (5, 6)], "{errors:?}");
+        assert!(
+            matches!(&errors[0].kind, LexErrorKind::BadLabel(label) if label.starts_with("10.0.0.0/33"))
+        );
+        assert!(matches!(errors[1].kind, LexErrorKind::BadDirective(_)));
+        assert_eq!(errors[2].kind, LexErrorKind::UnmatchedBracket);
+        assert_eq!(
+            errors[2].to_string(),
+            "line 5, column 6: unmatched `[` in the target"
+        );
+    }
+
+    #[test]
+    fn tokens_span_the_lines_they_come_from() {
+        let tokens = lex(
+            "# objects\nOBJS = a.o \\\n\tb.o\n\n.PHONY: all\nall: $(OBJS)\n\techo a\n\n\techo b\n#! max-jobs 2\n"
+                .to_string(),
+        )
+        .unwrap();
+
+        let spans: Vec<_> = tokens.iter().map(Token::span).collect();
+        assert_eq!(
+            spans,
+            [
+                Span::new(2, 3),
+                Span::line(5),
+                Span::new(6, 9),
+                Span::line(10)
+            ],
+            "{tokens:?}"
+        );
+        assert_eq!(Span::new(6, 9).to_string(), "lines 6-9");
+    }
 }
@@ -1,11 +1,18 @@
 mod directive;
+mod error;
 mod host_id;
+#[allow(clippy::module_inception)]
 mod lexer;
 mod target_label;
 mod tokens;
 
-pub use directive::Directive;
+pub use directive::{DIRECTIVE_PREFIX, Directive};
 pub use host_id::HostId;
-pub use lexer::guess_path_and_lex;
+pub use lexer::{
+    guess_path_and_lex, guess_path_and_lex_in, lex, lex_from_path, max_jobs_of, parallel_jobs_of,
+};
 pub use target_label::TargetLabel;
-pub use tokens::Token;
+pub use tokens::{
+    ConditionalKind, EXPORT_KEYWORD, INCLUDE_KEYWORD, PATTERN_WILDCARD, PHONY_TARGET, Token,
+    UNEXPORT_KEYWORD,
+};
@@ -22,6 +22,8 @@ use crate::lexer::HostId;
 /// Example formats:
 /// - `"127.0.0.1:8080"` → `sock=127.0.0.1:8080, path=None`
 /// - `"127.0.0.1|/tmp/build"` → `sock=127.0.0.1:DEFAULT_PORT, path=/tmp/build`
+/// - `"192.168.1.0/24:1808"` → any daemon of `192.168.1.0/24` listening on 1808
+/// - `"builder-01.local:1808"` → the daemon of the host `builder-01.local`
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct TargetLabel {
     /// The socket (IP + port) of the remote daemon.
@@ -47,6 +49,10 @@ impl FromStr for TargetLabel {
     /// - `"IP"` -> defaults to [`DEFAULT_PORT`]
     /// - `"IP:PORT|PATH"` -> with optional build directory path and port
     /// - `"IP|PATH"` -> with optional build directory path
+    /// - `"HOST:PORT"` or `"HOST"` -> a host name, resolved through DNS when the
+    ///   makefiles are generated, also accepting a build directory path
+    /// - `"NET/PREFIX:PORT"` or `"NET/PREFIX"` -> any known daemon of the subnet,
+    ///   also accepting a build directory path
     fn from_str(s: &str) -> Result<Self> {
         Ok(match s.rsplit_once('|') {
             Some((sock, path)) => {
@@ -1,19 +1,269 @@
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+};
+
 use crate::lexer::{directive::Directive, target_label::TargetLabel};
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Line {
     RawLine(String),
     ColonLine(String, String),
     Directive(String),
+    Assignment {
+        name: String,
+        op: AssignOp,
+        value: String,
+    },
+    /// Opening line of a conditional block, such as `ifeq ($(OS),Windows_NT)`.
+    CondStart {
+        kind: ConditionalKind,
+        condition: String,
+    },
+    /// `else`, possibly chaining another conditional (`else ifdef DEBUG`).
+    CondElse(Option<(ConditionalKind, String)>),
+    /// `endif`
+    CondEnd,
+    /// A variable exported to the environment of the recipes, with its value if the
+    /// export line sets it, such as `export CC := clang`.
+    Export {
+        name: String,
+        value: Option<String>,
+    },
+    /// A variable removed from the environment of the recipes.
+    Unexport(String),
+}
+
+/// Keyword closing a conditional block.
+pub const ENDIF_KEYWORD: &str = "endif";
+/// Keyword starting the second branch of a conditional block.
+pub const ELSE_KEYWORD: &str = "else";
+
+/// Kind of a GNU Make conditional.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConditionalKind {
+    Ifdef,
+    Ifndef,
+    Ifeq,
+    Ifneq,
+}
+
+impl ConditionalKind {
+    const ALL: [Self; 4] = [Self::Ifdef, Self::Ifndef, Self::Ifeq, Self::Ifneq];
+
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Self::Ifdef => "ifdef",
+            Self::Ifndef => "ifndef",
+            Self::Ifeq => "ifeq",
+            Self::Ifneq => "ifneq",
+        }
+    }
+
+    /// Splits a conditional line into its kind and its condition.
+    ///
+    /// Returns `None` if `line` does not start with a conditional keyword.
+    pub fn split(line: &str) -> Option<(Self, &str)> {
+        Self::ALL.into_iter().find_map(|kind| {
+            let condition = line.strip_prefix(kind.keyword())?;
+            (condition.starts_with(char::is_whitespace) || condition.starts_with('('))
+                .then_some((kind, condition.trim()))
+        })
+    }
+}
+
+impl Display for ConditionalKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
+
+/// Operator of a variable assignment.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AssignOp {
+    /// `:=` or `::=`, the value is expanded once.
+    Simple,
+    /// `=`, the value is expanded on each use.
+    Recursive,
+    /// `+=`, the value is appended to the current one.
+    Append,
+    /// `?=`, the value is only set if the variable is undefined.
+    Conditional,
+}
+
+impl AssignOp {
+    /// Splits `line` around the first assignment operator, returning the variable
+    /// name, the operator and the value.
+    ///
+    /// Returns `None` if the left side is not a plain variable name.
+    pub fn split(line: &str) -> Option<(&str, Self, &str)> {
+        let eq = line.find('=')?;
+        let (left, value) = (&line[..eq], &line[eq + 1..]);
+        let (name, op) = if let Some(name) = left.strip_suffix("::") {
+            (name, Self::Simple)
+        } else if let Some(name) = left.strip_suffix(':') {
+            (name, Self::Simple)
+        } else if let Some(name) = left.strip_suffix('+') {
+            (name, Self::Append)
+        } else if let Some(name) = left.strip_suffix('?') {
+            (name, Self::Conditional)
+        } else {
+            (left, Self::Recursive)
+        };
+
+        let name = name.trim();
+        let is_name = !name.is_empty()
+            && !name
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, ':' | '!' | '#' | '='));
+        is_name.then_some((name, op, value.trim_start()))
+    }
+}
+
+impl Display for AssignOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                AssignOp::Simple => ":=",
+                AssignOp::Recursive => "=",
+                AssignOp::Append => "+=",
+                AssignOp::Conditional => "?=",
+            }
+        )
+    }
+}
+
+/// Keyword of the GNU Make `include` directive.
+pub const INCLUDE_KEYWORD: &str = "include";
+
+/// Keyword exporting variables to the environment of the recipes.
+pub const EXPORT_KEYWORD: &str = "export";
+
+/// Keyword removing variables from the environment of the recipes.
+pub const UNEXPORT_KEYWORD: &str = "unexport";
+
+/// Wildcard of the pattern rules, such as `%.o: %.c`.
+pub const PATTERN_WILDCARD: char = '%';
+
+/// Left side of the rule declaring phony targets.
+pub const PHONY_TARGET: &str = ".PHONY";
+
+/// Lines of the makefile a token comes from, both ends included and starting at 1.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+impl Span {
+    pub fn new(start_line: usize, end_line: usize) -> Self {
+        Self {
+            start_line,
+            end_line,
+        }
+    }
+
+    /// A span covering a single line.
+    pub fn line(line: usize) -> Self {
+        Self::new(line, line)
+    }
+
+    /// Returns the span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self::new(
+            self.start_line.min(other.start_line),
+            self.end_line.max(other.end_line),
+        )
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.start_line == self.end_line {
+            write!(f, "line {}", self.start_line)
+        } else {
+            write!(f, "lines {}-{}", self.start_line, self.end_line)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Token {
-    RawText(String),
+    RawText {
+        text: String,
+        span: Span,
+    },
     Target {
         target: String,
         label: Option<TargetLabel>,
         command: String,
+        /// Set by a preceding `max-jobs` directive.
+        max_jobs: Option<usize>,
+        /// A recipe line has the `+` prefix, typically a recursive `$(MAKE)`.
+        recursive: bool,
+        span: Span,
+    },
+    Directive {
+        directive: Directive,
+        span: Span,
+    },
+    /// Targets declared by a `.PHONY` rule.
+    PhonyDecl {
+        targets: Vec<String>,
+        span: Span,
+    },
+    /// A variable assignment, such as `CFLAGS += -O2`.
+    Variable {
+        name: String,
+        op: AssignOp,
+        value: String,
+        span: Span,
+    },
+    /// An exported variable, such as `export CC := clang`. The value is only kept for
+    /// the assignments replacing the variable (`=`, `:=`), the assignment itself is
+    /// also lexed as a preceding [`Token::Variable`].
+    Export {
+        name: String,
+        value: Option<String>,
+        span: Span,
     },
-    Directive(Directive),
+    /// A variable removed from the environment of the recipes by `unexport`.
+    Unexport {
+        name: String,
+        span: Span,
+    },
+    /// An included makefile, spliced in place by the generator.
+    Include {
+        path: PathBuf,
+        span: Span,
+    },
+    /// A conditional block, the generator keeps only the matching branch when it can
+    /// evaluate the condition for a host.
+    Conditional {
+        kind: ConditionalKind,
+        condition: String,
+        then_tokens: Vec<Token>,
+        else_tokens: Vec<Token>,
+        span: Span,
+    },
+}
+
+impl Token {
+    /// Returns the lines of the makefile the token comes from.
+    pub fn span(&self) -> Span {
+        match self {
+            Token::RawText { span, .. }
+            | Token::Target { span, .. }
+            | Token::Directive { span, .. }
+            | Token::PhonyDecl { span, .. }
+            | Token::Variable { span, .. }
+            | Token::Export { span, .. }
+            | Token::Unexport { span, .. }
+            | Token::Include { span, .. }
+            | Token::Conditional { span, .. } => *span,
+        }
+    }
 }
@@ -6,12 +6,12 @@
 
 pub mod caller;
 pub mod daemon;
+pub mod env_variables;
 pub mod fetch;
 pub mod network;
 pub mod process_id;
 
 mod constants;
-mod env_variables;
 mod lexer;
 mod macros;
 mod makefile;
@@ -1,3 +1,8 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::time::timeout;
+
 #[macro_export]
 macro_rules! dec {
     ($bytes:expr) => {
@@ -22,24 +27,51 @@ macro_rules! wrap {
     };
 }
 
+/// Waits for the `guard` of a lock, failing once `duration` elapsed.
+///
+/// Shared by [`lock!`], [`read_lock!`] and [`write_lock!`], `lock` names the kind of
+/// lock in the error.
+pub(crate) async fn timed_guard<G>(
+    guard: impl Future<Output = G>,
+    duration: Duration,
+    lock: &str,
+) -> Result<G> {
+    timeout(duration, guard)
+        .await
+        .map_err(|_| anyhow!("{lock} timed out after {duration:?}."))
+}
+
+/// Locks a [`tokio::sync::Mutex`], failing after [`MUTEX_LOCK_TIMEOUT`] or the given duration.
+///
+/// [`MUTEX_LOCK_TIMEOUT`]: crate::constants::MUTEX_LOCK_TIMEOUT
 #[macro_export]
 macro_rules! lock {
     ($mutex:expr) => {
-        lock!($mutex, crate::constants::MUTEX_LOCK_TIMEOUT)
-    };
-    ($mutex:expr, $dur:expr) => {{
-        async {
-            use anyhow::bail;
-            use tokio::time::sleep;
-
-            tokio::select! {
-                _ = sleep($dur) => {
-                    bail!("Lock for mutex timed out.");
-                }
-                guard = $mutex.lock() => {
-                    Ok(guard)
-                }
-            }
-        }
-    }};
+        lock!($mutex, $crate::constants::MUTEX_LOCK_TIMEOUT)
+    };
+    ($mutex:expr, $dur:expr) => {
+        $crate::macros::timed_guard($mutex.lock(), $dur, "Lock for mutex")
+    };
+}
+
+/// Like [`lock!`], for the shared access of a [`tokio::sync::RwLock`].
+#[macro_export]
+macro_rules! read_lock {
+    ($rwlock:expr) => {
+        read_lock!($rwlock, $crate::constants::MUTEX_LOCK_TIMEOUT)
+    };
+    ($rwlock:expr, $dur:expr) => {
+        $crate::macros::timed_guard($rwlock.read(), $dur, "Read lock for rwlock")
+    };
+}
+
+/// Like [`lock!`], for the exclusive access of a [`tokio::sync::RwLock`].
+#[macro_export]
+macro_rules! write_lock {
+    ($rwlock:expr) => {
+        write_lock!($rwlock, $crate::constants::MUTEX_LOCK_TIMEOUT)
+    };
+    ($rwlock:expr, $dur:expr) => {
+        $crate::macros::timed_guard($rwlock.write(), $dur, "Write lock for rwlock")
+    };
 }
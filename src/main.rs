@@ -8,23 +8,43 @@
 //! - **Daemon**: start the local build daemon that listens for requests.
 //! - **Caller**: intercept and run a `make` process, potentially distributed.
 //! - **Clean**: clean the dake workspace
+//! - **Status**: inspect the daemon, its builds in progress and its recent build history
+//! - **FetchArchive**: retrieve the archive of a completed build
+//! - **Nodes**: list the daemons advertised on the local network
+//! - **Kill**: cancel a build in progress, or all of them
 //!
 //! The CLI also ensures logging is initialized and provides help output if no
 //! command is supplied.
 
+use anyhow::{Context, bail};
 use std::{
+    env::{current_dir, set_var},
+    fs::copy,
+    net::IpAddr,
     path::PathBuf,
+    pin::pin,
     process::{ExitCode, exit},
+    time::Duration,
 };
 
 use clap::{Parser, Subcommand};
 use dake::{
     caller,
-    daemon::{self, fs},
+    daemon::{
+        self, BuildRecord, DaemonConfig,
+        discovery::{DISCOVERY_WINDOW, DiscoveredNode, browse},
+        fs, load_history,
+    },
+    env_variables::EnvVariable,
     fetch,
-    network::SocketAddr,
+    network::{
+        DaemonMessage, Message, MessageKind, ProcessMessage, ProcessSummary, SocketAddr,
+        get_daemon_unix_sock, send_message_and_await_response,
+    },
     process_id::ProcessId,
 };
+use futures::StreamExt;
+use tokio::{select, time::sleep};
 use tracing::info;
 
 /// CLI root structure used by `clap` for parsing arguments.
@@ -43,6 +63,22 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// Always run the build, even if an unchanged build has a cached result
+    #[arg(long = "no-cache")]
+    no_cache: bool,
+
+    /// Run make directly without the daemon, every target must be local
+    #[arg(long = "no-daemon")]
+    no_daemon: bool,
+
+    /// Print the makefile each host would receive and exit without building
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Write the events of the build as JSON lines to this file descriptor
+    #[arg(long = "events-fd", value_name = "N")]
+    events_fd: Option<u32>,
+
     /// Fallback arguments passed directly to the caller
     #[arg(trailing_var_arg = true)]
     args: Vec<String>,
@@ -63,56 +99,363 @@ enum Commands {
         #[arg(long = "labeled-path")]
         labeled_path: Option<PathBuf>,
 
-        /// The build target to fetch
-        target: String,
+        /// Build and fetch up to N targets at once on the remote daemon
+        #[arg(long, value_name = "N")]
+        parallel: Option<usize>,
+
+        /// The build targets to fetch
+        #[arg(required = true)]
+        targets: Vec<String>,
     },
 
     /// Clean up Dake cache and workspace
-    Clean,
+    Clean {
+        /// Preserve the archives of completed builds
+        #[arg(long = "keep-archives")]
+        keep_archives: bool,
+    },
+
+    /// Retrieve the archive of a completed build in the current directory
+    FetchArchive {
+        /// Pid of the archived process
+        pid: ProcessId,
+    },
 
     /// Start the Dake daemon
-    Daemon,
+    Daemon {
+        /// Port to listen on, overrides `DAKE_PORT`
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// IP to bind the daemon on, overrides `DAKE_IP` (all interfaces by default)
+        #[arg(long = "bind-ip")]
+        bind_ip: Option<IpAddr>,
+    },
+
+    /// Show the daemon status and the builds in progress
+    Status {
+        /// Print the recent build history
+        #[arg(long)]
+        history: bool,
+
+        /// Print the environment forwarded to the make processes of a build
+        #[arg(long = "show-env")]
+        show_env: Option<ProcessId>,
+
+        /// Print a JSON dump of the daemon state, for debugging
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// List the daemons advertised on the local network
+    Nodes,
+
+    /// Cancel a build in progress on the daemon
+    Kill {
+        /// Pid of the build to cancel
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        pid: Option<ProcessId>,
+
+        /// Cancel every build in progress
+        #[arg(long)]
+        all: bool,
+    },
 
     /// Show Dake version information
     Version,
 }
 
+/// Prints the build history, one build per line.
+fn print_history(history: &[BuildRecord]) {
+    if history.is_empty() {
+        println!("No build recorded yet.");
+        return;
+    }
+    println!("{:<40} {:>6} {:>10}  HOSTS", "PID", "EXIT", "DURATION");
+    for record in history {
+        let duration = record
+            .end_time
+            .duration_since(record.start_time)
+            .unwrap_or_default();
+        let hosts: Vec<String> = record
+            .involved_hosts
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        println!(
+            "{:<40} {:>6} {:>9.1}s  {}",
+            record.pid.to_string(),
+            record.exit_code,
+            duration.as_secs_f64(),
+            hosts.join(", ")
+        );
+    }
+}
+
+/// Asks the daemon for the environment forwarded to `pid` and prints it.
+async fn print_env(pid: ProcessId) -> anyhow::Result<()> {
+    let msg: Message<ProcessMessage> = send_message_and_await_response(
+        Message::new(DaemonMessage::GetProcessEnv, pid.clone()),
+        get_daemon_unix_sock()?,
+        MessageKind::ProcessMessage,
+        Duration::from_secs(5),
+    )
+    .await?;
+
+    match msg.inner {
+        ProcessMessage::Env { env: Some(env) } => {
+            let mut env: Vec<_> = env.into_iter().collect();
+            env.sort();
+            for (key, value) in env {
+                println!("{key}={value}");
+            }
+            Ok(())
+        }
+        ProcessMessage::Env { env: None } => bail!("The daemon does not know the process {pid}."),
+        msg => bail!("Unexpected response from the daemon: {msg:?}"),
+    }
+}
+
+/// Asks the daemon for the builds in progress and the amount of queued builds.
+async fn query_status() -> anyhow::Result<(Vec<ProcessSummary>, usize)> {
+    let msg: Message<ProcessMessage> = send_message_and_await_response(
+        Message::new(DaemonMessage::QueryStatus, ProcessId::default()),
+        get_daemon_unix_sock()?,
+        MessageKind::ProcessMessage,
+        Duration::from_secs(5),
+    )
+    .await?;
+
+    match msg.inner {
+        ProcessMessage::StatusReport {
+            processes,
+            queued_builds,
+        } => Ok((processes, queued_builds)),
+        msg => bail!("Unexpected response from the daemon: {msg:?}"),
+    }
+}
+
+/// Asks the daemon for the builds in progress and prints them, one build per line.
+async fn print_processes() -> anyhow::Result<()> {
+    let (processes, queued_builds) = query_status().await?;
+    if queued_builds > 0 {
+        println!("{queued_builds} builds waiting for a slot.");
+    }
+    if processes.is_empty() {
+        println!("No build in progress.");
+        return Ok(());
+    }
+    println!("{:<40} {:>10}  HOSTS", "PID", "RUNNING");
+    for process in processes {
+        // Measured by the daemon, the clocks of both sides may differ.
+        let running = Duration::from_secs(process.elapsed_secs);
+        let hosts: Vec<String> = process
+            .involved_hosts
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        println!(
+            "{:<40} {:>9.1}s  {}",
+            process.pid.to_string(),
+            running.as_secs_f64(),
+            hosts.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Listens for the daemons of the local network during [`DISCOVERY_WINDOW`] and prints them.
+async fn print_nodes() -> anyhow::Result<()> {
+    let mut nodes = pin!(browse()?);
+    let mut window = pin!(sleep(DISCOVERY_WINDOW));
+    let mut found: Vec<DiscoveredNode> = Vec::new();
+    loop {
+        select! {
+            _ = &mut window => break,
+            node = nodes.next() => match node {
+                Some(node) if !found.contains(&node) => found.push(node),
+                Some(_) => {}
+                None => break,
+            },
+        }
+    }
+
+    if found.is_empty() {
+        println!("No daemon found on the local network.");
+        return Ok(());
+    }
+    println!("{:<40} {:<24} VERSION", "NAME", "ADDRESS");
+    for node in found {
+        println!(
+            "{:<40} {:<24} {}",
+            node.name,
+            node.sock.to_display_string(),
+            node.version.as_deref().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
+
+/// Asks the daemon for a dump of its state and prints it.
+async fn print_snapshot() -> anyhow::Result<()> {
+    let msg: Message<ProcessMessage> = send_message_and_await_response(
+        Message::new(
+            DaemonMessage::GetStatus { verbose: true },
+            ProcessId::default(),
+        ),
+        get_daemon_unix_sock()?,
+        MessageKind::ProcessMessage,
+        Duration::from_secs(5),
+    )
+    .await?;
+
+    match msg.inner {
+        ProcessMessage::Status {
+            active_processes,
+            snapshot,
+        } => {
+            println!("Active processes: {active_processes}");
+            match snapshot {
+                Some(snapshot) => {
+                    let snapshot: serde_json::Value = serde_json::from_str(&snapshot)
+                        .context("The daemon sent an invalid snapshot.")?;
+                    println!("{}", serde_json::to_string_pretty(&snapshot)?);
+                }
+                None => println!("The daemon failed to snapshot its state."),
+            }
+            Ok(())
+        }
+        msg => bail!("Unexpected response from the daemon: {msg:?}"),
+    }
+}
+
 /// Entry point of the application.
 ///
 /// Parses CLI arguments, and dispatches execution
 /// to the relevant Dake subsystem (`fetch`, `daemon`, or `caller`).
 #[tokio::main]
 async fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+
     #[cfg(debug_assertions)]
-    tracing_subscriber::fmt::init();
+    match cli.command {
+        Some(Commands::Daemon { .. }) => tracing_subscriber::fmt()
+            .with_max_level(DaemonConfig::load_log_level())
+            .init(),
+        _ => tracing_subscriber::fmt::init(),
+    }
 
-    let cli = Cli::parse();
     info!("Parsed CLI arguments: {:?}", cli);
 
     let exit_code = match cli.command {
         Some(Commands::Fetch {
-            target,
+            targets,
             pid,
             labeled_path,
+            parallel,
             sock,
         }) => {
-            info!("Executing Fetch command for target '{target}' with socket {sock}");
-            fetch::fetch(target, labeled_path, pid, sock).await?;
+            info!("Executing Fetch command for targets {targets:?} with socket {sock}");
+            match parallel {
+                Some(parallel) if parallel > 1 && targets.len() > 1 => {
+                    for batch in targets.chunks(parallel) {
+                        let batch = batch
+                            .iter()
+                            .map(|target| (target.clone(), labeled_path.clone()))
+                            .collect();
+                        fetch::fetch_batch(batch, pid.clone(), sock.clone()).await?;
+                    }
+                }
+                _ => {
+                    for target in targets {
+                        fetch::fetch(target, labeled_path.clone(), pid.clone(), sock.clone())
+                            .await?;
+                    }
+                }
+            }
             0
         }
 
-        Some(Commands::Daemon) => {
+        Some(Commands::Daemon { port, bind_ip }) => {
+            // SAFETY: no other thread reads the environment before the daemon starts.
+            unsafe {
+                if let Some(port) = port {
+                    set_var(EnvVariable::DaemonPort.to_string(), port.to_string());
+                }
+                if let Some(ip) = bind_ip {
+                    set_var(EnvVariable::DaemonIp.to_string(), ip.to_string());
+                }
+            }
             info!("Starting daemon...");
             daemon::start().await?;
             0
         }
 
-        Some(Commands::Clean) => {
+        Some(Commands::Clean { keep_archives }) => {
             info!("Cleaning dake space..");
-            fs::clean()?;
+            fs::clean(keep_archives)?;
+            0
+        }
+
+        Some(Commands::FetchArchive { pid }) => {
+            info!("Retrieving the archive of {pid}");
+            let archive = fs::get_archive_path(&pid)?;
+            if !archive.is_file() {
+                bail!("No archive found for {pid}, was the build archived ?");
+            }
+            let dest = current_dir()?.join(archive.file_name().context("Invalid archive path.")?);
+            copy(&archive, &dest).context("Failed to copy the archive.")?;
+            println!("{}", dest.display());
             0
         }
 
+        Some(Commands::Status {
+            history,
+            show_env,
+            verbose,
+        }) => {
+            if DaemonConfig::is_running() {
+                println!("Daemon is running.");
+                print_processes().await?;
+            } else {
+                println!("Daemon is not running.");
+            }
+            if history {
+                print_history(&Vec::from(load_history()?));
+            }
+            if let Some(pid) = show_env {
+                print_env(pid).await?;
+            }
+            if verbose {
+                print_snapshot().await?;
+            }
+            0
+        }
+
+        Some(Commands::Nodes) => {
+            info!("Looking for the daemons of the local network");
+            print_nodes().await?;
+            0
+        }
+
+        Some(Commands::Kill { pid, all }) => {
+            if !DaemonConfig::is_running() {
+                bail!("Daemon is not running.");
+            }
+            if all {
+                info!("Cancelling every build in progress");
+                caller::cancel_all("dake kill --all".to_string()).await?;
+                println!("Cancelled every build in progress");
+                0
+            } else {
+                let pid = pid.context("No build to cancel.")?;
+                info!("Cancelling {pid}");
+                caller::cancel_process(pid.clone()).await?;
+                println!("Cancelled {pid}");
+                0
+            }
+        }
+
         Some(Commands::Version) => {
             // ★ Added: explicit subcommand for version display
             println!("Dake {}", env!("CARGO_PKG_VERSION"));
@@ -121,8 +464,14 @@ async fn main() -> anyhow::Result<ExitCode> {
 
         None => {
             info!("Executing default Caller mode with args: {:?}", cli.args);
-            let exit_code = caller::make(cli.args).await?;
-            exit_code
+            caller::make(
+                cli.args,
+                cli.no_cache,
+                cli.no_daemon,
+                cli.dry_run,
+                cli.events_fd,
+            )
+            .await?
         }
     };
 
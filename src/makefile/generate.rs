@@ -7,111 +7,335 @@
 //! makefiles are stored separately.
 
 use crate::{
-    lexer::{Directive, HostId, TargetLabel, Token},
-    makefile::{RemoteMakefile, RemoteMakefileSet},
-    network::DEFAULT_PORT,
+    lexer::{
+        DIRECTIVE_PREFIX, Directive, EXPORT_KEYWORD, HostId, INCLUDE_KEYWORD, PATTERN_WILDCARD,
+        PHONY_TARGET, TargetLabel, Token, UNEXPORT_KEYWORD, lex_from_path,
+    },
+    makefile::{PLATFORM_VARIABLES, Platform, RemoteMakefile, RemoteMakefileSet},
+    network::{self, DEFAULT_PORT},
     process_id::ProcessId,
 };
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use std::{
     collections::{HashMap, HashSet},
-    net::SocketAddr,
-    path::PathBuf,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
 };
 use tracing::{info, warn};
 
-impl RemoteMakefileSet {
-    /// Generates a new [`RemoteMakefileSet`] from a set of tokens.
-    ///
-    /// # Behavior
-    /// - Raw text (`Token::RawText`) is appended to all makefiles.
-    /// - Target rules (`Token::Target`) are rewritten into:
-    ///   - A local target rule in the appropriate makefile.
-    ///   - A "fetch" rule in other makefiles, instructing them to fetch the
-    ///     target from the correct host.
-    /// - Directives (`Token::Directive`) register root paths for resolving
-    ///   labels.
-    ///
-    /// # Returns
-    /// A new [`RemoteMakefileSet`] containing the distributed makefiles.
-    pub fn generate(tokens: Vec<Token>, sock: SocketAddr, pid: ProcessId) -> Result<Self> {
-        info!(
-            "RemoteMakefileSet: Starting generation with {} tokens",
-            tokens.len()
-        );
+/// Replaces each [`Token::Include`] by the tokens of the included makefile, recursively.
+///
+/// Include paths are resolved from `dir`, the directory of the makefile the tokens come
+/// from. `stack` holds the makefiles being inlined, to detect include cycles.
+///
+/// Only literal paths to existing files are inlined. The others, such as `$(DEPS)`,
+/// `*.mk` or a file generated by the build, are kept as an `include` line for make to
+/// resolve, see [`is_literal_include`].
+///
+/// # Errors
+/// Fails if an included makefile cannot be lexed, or includes itself.
+fn inline_includes(tokens: Vec<Token>, dir: &Path, stack: &mut Vec<PathBuf>) -> Result<Vec<Token>> {
+    let mut inlined = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let (path, span) = match token {
+            Token::Include { path, span } => (path, span),
+            Token::Conditional {
+                kind,
+                condition,
+                then_tokens,
+                else_tokens,
+                span,
+            } => {
+                inlined.push(Token::Conditional {
+                    kind,
+                    condition,
+                    then_tokens: inline_includes(then_tokens, dir, stack)?,
+                    else_tokens: inline_includes(else_tokens, dir, stack)?,
+                    span,
+                });
+                continue;
+            }
+            token => {
+                inlined.push(token);
+                continue;
+            }
+        };
 
-        let path = pid.path().clone();
-        let mut full_fetch_makefile = String::new();
-        let mut saw_ips = HashSet::from([sock]);
-        let mut makefiles = vec![RemoteMakefile::new(String::new(), sock)];
-        let mut root_path_set = HashMap::from([(sock, path.clone())]);
-
-        // Utility closure to construct fetch commands
-        let get_fetch_command = |root_path_set: &HashMap<SocketAddr, PathBuf>,
-                                 TargetLabel { id, path }: TargetLabel,
-                                 target: String|
-         -> Result<String> {
-            let sock = id.resolve()?;
-            let label_path = match path {
+        let full_path = dir.join(&path);
+        let full_path = match full_path.canonicalize() {
+            Ok(full_path) if is_literal_include(&path) && full_path.is_file() => full_path,
+            _ => {
+                info!(
+                    "RemoteMakefileSet: Keeping the include of {} at {span} for make",
+                    path.display()
+                );
+                inlined.push(Token::RawText {
+                    text: format!("{INCLUDE_KEYWORD} {}\n", path.display()),
+                    span,
+                });
+                continue;
+            }
+        };
+        if stack.contains(&full_path) {
+            bail!("The makefile {} includes itself.", full_path.display());
+        }
+
+        info!("RemoteMakefileSet: Inlining {}", full_path.display());
+        let included = lex_from_path(full_path.clone())?;
+        let included_dir = full_path.parent().unwrap_or(dir).to_path_buf();
+        stack.push(full_path);
+        inlined.extend(inline_includes(included, &included_dir, stack)?);
+        stack.pop();
+    }
+    Ok(inlined)
+}
+
+/// Returns true if `path` names a file as is, without variables or wildcards for make
+/// to expand.
+fn is_literal_include(path: &Path) -> bool {
+    !path.to_string_lossy().contains(['$', '*', '?', '['])
+}
+
+/// Calls `f` on every token, including the ones nested in conditionals.
+fn visit_tokens<'a>(tokens: &'a [Token], f: &mut impl FnMut(&'a Token)) {
+    for token in tokens {
+        f(token);
+        if let Token::Conditional {
+            then_tokens,
+            else_tokens,
+            ..
+        } = token
+        {
+            visit_tokens(then_tokens, f);
+            visit_tokens(else_tokens, f);
+        }
+    }
+}
+
+/// Returns the line exporting or unexporting the variable of an [`Token::Export`] or
+/// [`Token::Unexport`], with its value if `with_value` is set and the token has one.
+fn export_line(token: &Token, with_value: bool) -> Option<String> {
+    match token {
+        Token::Export {
+            name,
+            value: Some(value),
+            ..
+        } if with_value => Some(format!("{EXPORT_KEYWORD} {name}={value}\n")),
+        Token::Export { name, .. } => Some(format!("{EXPORT_KEYWORD} {name}\n")),
+        Token::Unexport { name, .. } => Some(format!("{UNEXPORT_KEYWORD} {name}\n")),
+        _ => None,
+    }
+}
+
+/// Hosts the unlabeled targets are assigned to, by target name.
+///
+/// Typically filled from the daemons found by
+/// [`discovery::browse`](crate::daemon::discovery::browse), see
+/// [`RemoteMakefileSet::generate_with_hints`].
+pub type DiscoveryHints = HashMap<String, SocketAddr>;
+
+/// State of a generation, see [`RemoteMakefileSet::generate`].
+struct Generator {
+    pid: ProcessId,
+    /// Address of the caller host, owner of the first makefile.
+    sock: SocketAddr,
+    /// Fetch rules and variables seen so far, the starting content of a new host.
+    full_fetch_makefile: String,
+    saw_ips: HashSet<SocketAddr>,
+    makefiles: Vec<RemoteMakefile>,
+    root_path_set: HashMap<SocketAddr, PathBuf>,
+    phony_set: HashSet<String>,
+    /// Platform of the caller host, the platform of the other hosts is unknown.
+    platform: Platform,
+    hints: DiscoveryHints,
+    /// Job counts of the `parallel` directives, by host.
+    parallel: HashMap<IpAddr, usize>,
+}
+
+impl Generator {
+    /// Returns a new makefile for the host `sock`, starting with its `parallel`
+    /// directive if it has one, then `content`.
+    fn new_makefile(
+        parallel: &HashMap<IpAddr, usize>,
+        content: &str,
+        sock: SocketAddr,
+    ) -> RemoteMakefile {
+        let directive = parallel
+            .get(&sock.ip())
+            .map(|jobs| format!("{DIRECTIVE_PREFIX} parallel {} {jobs}\n", sock.ip()))
+            .unwrap_or_default();
+        RemoteMakefile::new(directive + content, sock)
+    }
+
+    /// Turns a subnet label into the first known daemon of the subnet, or the
+    /// primary node if none matches. Other labels are returned unchanged.
+    fn resolve_subnet(&self, label: TargetLabel) -> TargetLabel {
+        let HostId::Subnet(net, port) = &label.id else {
+            return label;
+        };
+        let sock = self
+            .makefiles
+            .iter()
+            .map(|m| *m.sock())
+            .find(|sock| label.id.matches(sock))
+            .unwrap_or_else(|| {
+                info!("RemoteMakefileSet: No known daemon in {net}:{port}, using the primary node");
+                self.sock
+            });
+        TargetLabel::new(HostId::Socket(sock), label.path)
+    }
+
+    /// Builds the `dake fetch` command fetching `target` from the host of its label.
+    fn get_fetch_command(
+        &self,
+        TargetLabel { id, path }: TargetLabel,
+        target: String,
+    ) -> Result<String> {
+        let sock = id.resolve()?;
+        let label_path = match path {
+            Some(label_path) => format!("--labeled-path {}", label_path.display()),
+            None => match self.root_path_set.get(&sock) {
                 Some(label_path) => format!("--labeled-path {}", label_path.display()),
-                None => match root_path_set.get(&sock) {
-                    Some(label_path) => format!("--labeled-path {}", label_path.display()),
-                    None => String::new(),
-                },
-            };
-            Ok(format!(
-                "dake fetch {process_id} {label_sock} {label_path} \"{target}\"\n",
-                process_id = pid,
-                label_sock = sock.ip()
-            ))
+                None => String::new(),
+            },
         };
+        Ok(format!(
+            "dake fetch \"{process_id}\" {label_sock} {label_path} \"{target}\"\n",
+            process_id = self.pid,
+            label_sock = network::SocketAddr::from(sock).to_display_string()
+        ))
+    }
 
-        // Process tokens
+    /// Appends `content` to every makefile, except the ones of the `skipped` hosts.
+    fn push_all(&mut self, content: &str, skipped: &HashSet<SocketAddr>) {
+        self.makefiles
+            .iter_mut()
+            .filter(|m| !skipped.contains(m.sock()))
+            .for_each(|m| m.push_content(content))
+    }
+
+    /// Processes `tokens`, the makefiles of the `skipped` hosts are left untouched.
+    fn process(&mut self, tokens: Vec<Token>, skipped: &HashSet<SocketAddr>) -> Result<()> {
         for token in tokens.into_iter() {
             match token {
-                Token::RawText(text) => {
+                Token::RawText { text, .. } => {
                     info!(
                         "RemoteMakefileSet: Appending raw text of length {}",
                         text.len()
                     );
-                    makefiles
-                        .iter_mut()
-                        .for_each(|m: &mut RemoteMakefile| m.push_content(&text))
+                    self.push_all(&text, skipped)
                 }
+                // A pattern rule is not a target, every host needs it as is to build its files.
                 Token::Target {
                     target,
                     label,
                     command,
+                    max_jobs,
+                    ..
+                } if target.contains(PATTERN_WILDCARD) => {
+                    if let Some(label) = label {
+                        warn!(
+                            "RemoteMakefileSet: Pattern rule '{}' is kept on every host, ignoring its label {:?}",
+                            target, label
+                        );
+                    }
+                    info!("RemoteMakefileSet: Copying pattern rule '{target}' to every makefile");
+
+                    let max_jobs = max_jobs
+                        .map(|count| format!("{DIRECTIVE_PREFIX} max-jobs {count}\n"))
+                        .unwrap_or_default();
+                    let rule = format!("{max_jobs}{target}:{command}");
+                    self.full_fetch_makefile += &rule;
+                    self.push_all(&rule, skipped)
+                }
+                Token::Target {
+                    target,
+                    label,
+                    command,
+                    max_jobs,
+                    recursive,
+                    span,
                 } => {
+                    let resolve_context =
+                        || format!("Failed to resolve the label of '{target}' at {span}.");
+                    let label = label.or_else(|| {
+                        let sock = *self.hints.get(&target)?;
+                        info!("RemoteMakefileSet: Assigning the unlabeled '{target}' to {sock}");
+                        Some(TargetLabel::new(HostId::Socket(sock), None))
+                    });
+                    let label = label.map(|label| self.resolve_subnet(label));
+                    // A recursive make needs the whole project, it stays on the primary node.
+                    let label = match label {
+                        Some(label)
+                            if recursive
+                                && label
+                                    .id
+                                    .clone()
+                                    .resolve()
+                                    .with_context(resolve_context)?
+                                    .ip()
+                                    != self.sock.ip() =>
+                        {
+                            warn!(
+                                "RemoteMakefileSet: '{}' has a `+` recipe, building it on the primary node instead of {:?}",
+                                target, label
+                            );
+                            None
+                        }
+                        label => label,
+                    };
                     let label =
-                        label.unwrap_or_else(|| TargetLabel::new(HostId::Socket(sock), None));
+                        label.unwrap_or_else(|| TargetLabel::new(HostId::Socket(self.sock), None));
                     info!(
                         "RemoteMakefileSet: Processing target '{}' for label {:?}",
                         target, label
                     );
 
-                    let sock = label.id.clone().resolve()?;
+                    let sock = label.id.clone().resolve().with_context(resolve_context)?;
 
                     // Add a new makefile for this IP if not already seen
-                    if saw_ips.insert(sock) {
+                    if self.saw_ips.insert(sock) {
                         info!(
                             "RemoteMakefileSet: Adding new RemoteMakefile for sock {}",
                             sock
                         );
-                        makefiles.push(RemoteMakefile::new(full_fetch_makefile.clone(), sock))
+                        self.makefiles.push(Self::new_makefile(
+                            &self.parallel,
+                            &self.full_fetch_makefile,
+                            sock,
+                        ))
                     }
 
                     // Build fetch and default rules
-                    let fetch_command =
-                        get_fetch_command(&root_path_set, label.clone(), target.clone())?;
+                    let fetch_command = self
+                        .get_fetch_command(label.clone(), target.clone())
+                        .with_context(resolve_context)?;
+
+                    let phony: Vec<&str> = target
+                        .split_whitespace()
+                        .filter(|t| self.phony_set.contains(*t))
+                        .collect();
+                    let phony = if phony.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{PHONY_TARGET}: {}\n", phony.join(" "))
+                    };
+
+                    let max_jobs = max_jobs
+                        .map(|count| format!("{DIRECTIVE_PREFIX} max-jobs {count}\n"))
+                        .unwrap_or_default();
 
-                    let fetch = format!("{target}:\n\t{fetch_command}\n");
-                    let default = format!("{target}:{command}");
+                    let fetch = format!("{phony}{target}:\n\t{fetch_command}\n");
+                    let default = format!("{phony}{max_jobs}{target}:{command}");
 
-                    full_fetch_makefile += &fetch;
+                    self.full_fetch_makefile += &fetch;
 
                     // Distribute rules across makefiles
-                    for m in makefiles.iter_mut() {
+                    for m in self
+                        .makefiles
+                        .iter_mut()
+                        .filter(|m| !skipped.contains(m.sock()))
+                    {
                         m.push_content(if m.ip() == sock.ip() {
                             &default
                         } else {
@@ -119,20 +343,206 @@ impl RemoteMakefileSet {
                         })
                     }
                 }
-                Token::Directive(dir) => match dir {
+                Token::Variable {
+                    name, op, value, ..
+                } => {
+                    info!("RemoteMakefileSet: Assigning variable {name}");
+                    let assignment = format!("{name} {op} {value}\n");
+                    self.full_fetch_makefile += &assignment;
+                    self.push_all(&assignment, skipped)
+                }
+                Token::Conditional {
+                    kind,
+                    condition,
+                    then_tokens,
+                    else_tokens,
+                    ..
+                } => {
+                    let local = self.platform.evaluate(kind, &condition);
+                    info!("RemoteMakefileSet: `{kind} {condition}` is {local:?} on this host");
+
+                    // Hosts that cannot evaluate the condition keep the whole block.
+                    let mut block_skipped = skipped.clone();
+                    let mut then_skipped = skipped.clone();
+                    let mut else_skipped = skipped.clone();
+                    match local {
+                        Some(true) => {
+                            block_skipped.insert(self.sock);
+                            else_skipped.insert(self.sock);
+                        }
+                        Some(false) => {
+                            block_skipped.insert(self.sock);
+                            then_skipped.insert(self.sock);
+                        }
+                        None => {}
+                    }
+
+                    let header = format!("{kind} {condition}\n");
+                    self.full_fetch_makefile += &header;
+                    self.push_all(&header, &block_skipped);
+                    self.process(then_tokens, &then_skipped)?;
+
+                    if !else_tokens.is_empty() {
+                        self.full_fetch_makefile += "else\n";
+                        self.push_all("else\n", &block_skipped);
+                        self.process(else_tokens, &else_skipped)?;
+                    }
+
+                    self.full_fetch_makefile += "endif\n";
+                    self.push_all("endif\n", &block_skipped);
+                }
+                // The unconditional exports are hoisted before processing, these ones
+                // depend on a conditional and stay in place.
+                token @ (Token::Export { .. } | Token::Unexport { .. }) => {
+                    if let Some(line) = export_line(&token, false) {
+                        info!("RemoteMakefileSet: Conditional {}", line.trim_end());
+                        self.full_fetch_makefile += &line;
+                        self.push_all(&line, skipped)
+                    }
+                }
+                // Includes are inlined before processing.
+                Token::Include { path, .. } => {
+                    warn!(
+                        "RemoteMakefileSet: Include of {} was not inlined",
+                        path.display()
+                    )
+                }
+                Token::PhonyDecl { targets, .. } => {
+                    info!("RemoteMakefileSet: Phony declaration of {:?}", targets)
+                }
+                Token::Directive { directive, .. } => match directive {
                     Directive::RootDef { ip, path: dir_path } => {
                         info!(
                             "RemoteMakefileSet: Registered RootDef ip={}, path={:?}",
                             ip, dir_path
                         );
-                        root_path_set.insert(SocketAddr::new(ip, DEFAULT_PORT), dir_path);
+                        self.root_path_set
+                            .insert(SocketAddr::new(ip, DEFAULT_PORT), dir_path);
+                    }
+                    Directive::MaxJobs { count } => {
+                        info!("RemoteMakefileSet: Next target limited to {count} jobs")
+                    }
+                    Directive::Parallel { ip, jobs } => {
+                        info!("RemoteMakefileSet: {ip} runs make with {jobs} jobs")
+                    }
+                    Directive::Include { path } => {
+                        warn!(
+                            "RemoteMakefileSet: Include of {} was not inlined",
+                            path.display()
+                        )
                     }
                 },
             }
         }
+        Ok(())
+    }
+}
+
+impl RemoteMakefileSet {
+    /// Generates a new [`RemoteMakefileSet`] from a set of tokens.
+    ///
+    /// # Behavior
+    /// - Raw text (`Token::RawText`) is appended to all makefiles.
+    /// - Variable assignments (`Token::Variable`) are appended to all makefiles,
+    ///   including the ones of hosts met later on.
+    /// - Exports (`Token::Export`, `Token::Unexport`) outside of conditionals are
+    ///   written at the top of every makefile, as `export NAME=VALUE`, so every host
+    ///   passes the same variables to its recipes. The ones inside a conditional stay
+    ///   in place, as their variable assignment does.
+    /// - Target rules (`Token::Target`) are rewritten into:
+    ///   - A local target rule in the appropriate makefile.
+    ///   - A "fetch" rule in other makefiles, instructing them to fetch the
+    ///     target from the correct host.
+    ///
+    ///   A subnet label (`192.168.1.0/24:1808`) picks the first known daemon of the
+    ///   subnet, in the order they were met, or the primary node if none matches.
+    ///   A target with a `+` recipe line is always built by the primary node, a
+    ///   pattern rule (`%.o: %.c`) is copied unchanged to every makefile.
+    /// - Directives (`Token::Directive`) register root paths for resolving
+    ///   labels. A `max-jobs` limit is written back before the local rule of its
+    ///   target, so the host building it can read it. A `parallel` directive is
+    ///   only written at the top of the makefile of its host, which runs make with
+    ///   that many jobs.
+    /// - Phony declarations (`Token::PhonyDecl`) are collected beforehand, each
+    ///   phony target gets a `.PHONY` line before its rule in every makefile.
+    /// - Includes (`Token::Include`) are read from disk, relative to the project
+    ///   directory, and their tokens are spliced in place before anything else.
+    /// - Conditionals (`Token::Conditional`) only depending on the platform
+    ///   variables (`$(OS)`, `$(ARCH)`) are evaluated for the caller host, whose
+    ///   makefile only gets the matching branch. The other hosts, and conditions
+    ///   on any other variable, keep the whole block for make to evaluate.
+    ///
+    /// # Errors
+    /// Fails if an included makefile cannot be read, or if a label cannot be resolved.
+    ///
+    /// # Returns
+    /// A new [`RemoteMakefileSet`] containing the distributed makefiles.
+    pub fn generate(tokens: Vec<Token>, sock: SocketAddr, pid: ProcessId) -> Result<Self> {
+        Self::generate_with_hints(tokens, sock, pid, DiscoveryHints::new())
+    }
+
+    /// Same as [`RemoteMakefileSet::generate`], the unlabeled targets found in
+    /// `hints` are built by their host instead of the caller host.
+    pub fn generate_with_hints(
+        tokens: Vec<Token>,
+        sock: SocketAddr,
+        pid: ProcessId,
+        hints: DiscoveryHints,
+    ) -> Result<Self> {
+        info!(
+            "RemoteMakefileSet: Starting generation with {} tokens",
+            tokens.len()
+        );
+
+        let path = pid.path().clone();
+        let tokens = inline_includes(tokens, &path, &mut Vec::new())?;
+
+        // `.PHONY` may be declared after the rule, so the phony targets are collected first.
+        let mut phony_set = HashSet::new();
+        // A platform variable assigned by the makefile cannot be evaluated beforehand.
+        let mut platform = Platform::local();
+        let mut parallel = HashMap::new();
+        visit_tokens(&tokens, &mut |token| match token {
+            Token::PhonyDecl { targets, .. } => phony_set.extend(targets.iter().cloned()),
+            Token::Directive {
+                directive: Directive::Parallel { ip, jobs },
+                ..
+            } => {
+                parallel.insert(*ip, *jobs);
+            }
+            Token::Variable { name, .. } if PLATFORM_VARIABLES.contains(&name.as_str()) => {
+                platform.forget(name)
+            }
+            _ => {}
+        });
+        info!("RemoteMakefileSet: Phony targets: {:?}", phony_set);
+
+        // Written before any rule, the assignments stay in place to keep their order.
+        let (exports, tokens): (Vec<_>, Vec<_>) = tokens
+            .into_iter()
+            .partition(|token| matches!(token, Token::Export { .. } | Token::Unexport { .. }));
+        let exports: String = exports
+            .iter()
+            .filter_map(|token| export_line(token, true))
+            .collect();
+        info!("RemoteMakefileSet: Exports: {:?}", exports);
+
+        let mut generator = Generator {
+            pid,
+            sock,
+            full_fetch_makefile: exports.clone(),
+            saw_ips: HashSet::from([sock]),
+            makefiles: vec![Generator::new_makefile(&parallel, &exports, sock)],
+            root_path_set: HashMap::from([(sock, path)]),
+            phony_set,
+            platform,
+            hints,
+            parallel,
+        };
+        generator.process(tokens, &HashSet::new())?;
 
         // Build RemoteMakefileSet from results
-        let mut iter = makefiles.into_iter();
+        let mut iter = generator.makefiles.into_iter();
         Ok(match iter.next() {
             Some(first) => {
                 let rest: Vec<_> = iter.collect();
@@ -149,3 +559,200 @@ impl RemoteMakefileSet {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DiscoveryHints;
+    use crate::{
+        daemon::DaemonId,
+        lexer::{Directive, HostId, lex, lex_from_path, parallel_jobs_of},
+        makefile::RemoteMakefileSet,
+        process_id::ProcessId,
+    };
+    use std::{fs::write, path::Path};
+    use tempfile::tempdir;
+
+    /// Generates the makefiles of the project in `dir` from its `Makefile`.
+    fn generate_in(dir: &Path, hints: DiscoveryHints) -> RemoteMakefileSet {
+        let pid = ProcessId::new(1, DaemonId::default(), dir.to_path_buf());
+        RemoteMakefileSet::generate_with_hints(
+            lex_from_path(dir.join("Makefile")).unwrap(),
+            "127.0.0.1:1808".parse().unwrap(),
+            pid,
+            hints,
+        )
+        .unwrap()
+    }
+
+    /// Generates the makefiles of a project whose only file is the `Makefile` `src`.
+    fn generate_from(src: &str) -> RemoteMakefileSet {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("Makefile"), src).unwrap();
+        generate_in(dir.path(), DiscoveryHints::new())
+    }
+
+    #[test]
+    fn hints_assign_unlabeled_targets() {
+        let dir = tempdir().unwrap();
+        write(
+            dir.path().join("Makefile"),
+            "a:\n\techo a\nb:\n\techo b\nc[10.0.0.3]:\n\techo c\n",
+        )
+        .unwrap();
+
+        let hints = DiscoveryHints::from([
+            ("a".to_string(), "10.0.0.2:1808".parse().unwrap()),
+            ("c".to_string(), "10.0.0.2:1808".parse().unwrap()),
+        ]);
+        let set = generate_in(dir.path(), hints);
+
+        // The label of `c` wins over the hints, `b` has no hint and stays local.
+        let [hinted, labeled] = set.remote_makefiles().as_slice() else {
+            panic!("Expected two remote makefiles");
+        };
+        assert_eq!(hinted.sock().to_string(), "10.0.0.2:1808");
+        assert!(hinted.makefile().contains("a:\n\techo a\n"));
+        assert_eq!(labeled.sock().to_string(), "10.0.0.3:1808");
+        assert!(set.my_makefile().contains("b:\n\techo b\n"));
+        assert!(!set.my_makefile().contains("echo a"));
+    }
+
+    #[test]
+    fn subnet_labels_route_to_known_daemons() {
+        assert!(matches!(
+            "192.168.1.0/24:1900".parse::<HostId>().unwrap(),
+            HostId::Subnet(net, 1900) if net.to_string() == "192.168.1.0/24"
+        ));
+
+        let set = generate_from(
+            "a[10.0.0.2]:\n\techo a\nb[10.0.0.0/24:1808]:\n\techo b\nc[192.168.0.0/16]:\n\techo c\n",
+        );
+
+        // `b` joins the daemon of `a`, no daemon is known in the subnet of `c`.
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        assert_eq!(remote.sock().to_string(), "10.0.0.2:1808");
+        assert!(remote.makefile().contains("b:\n\techo b\n"));
+        assert!(set.my_makefile().contains("c:\n\techo c\n"));
+        assert!(!set.my_makefile().contains("echo b"));
+    }
+
+    #[test]
+    fn exports_reach_every_host() {
+        let set = generate_from(
+            "a[10.0.0.2]:\n\techo a\nexport CC := clang\nexport CFLAGS += -O2\nunexport MAKEFLAGS\n\
+             ifeq ($(DEBUG),1)\nexport DEBUG_FLAGS\nendif\nb[10.0.0.3]:\n\t$(CC) -o b b.c\n",
+        );
+
+        // Both hosts get the exports before any rule, even the one met after them.
+        const HEADER: &str = "export CC=clang\nexport CFLAGS\nunexport MAKEFLAGS\n";
+        let [first, second] = set.remote_makefiles().as_slice() else {
+            panic!("Expected two remote makefiles");
+        };
+        for makefile in [first.makefile(), second.makefile(), set.my_makefile()] {
+            assert!(makefile.starts_with(HEADER), "{makefile}");
+            assert!(makefile.contains("CC := clang\nCFLAGS += -O2\n"));
+            assert!(makefile.contains("ifeq ($(DEBUG),1)\nexport DEBUG_FLAGS\nendif\n"));
+        }
+    }
+
+    #[test]
+    fn phony_targets_are_declared_in_fetch_stubs() {
+        let set = generate_from("app[10.0.0.2]:\n\techo app\nclean:\n\trm -f app\n.PHONY: clean\n");
+
+        // `clean` is a fetch stub on the remote host, `app` is not phony anywhere.
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        assert!(
+            remote
+                .makefile()
+                .contains(".PHONY: clean\nclean:\n\tdake fetch")
+        );
+        assert!(
+            set.my_makefile()
+                .contains(".PHONY: clean\nclean:\n\trm -f app\n")
+        );
+        for makefile in [remote.makefile(), set.my_makefile()] {
+            assert_eq!(makefile.matches(".PHONY").count(), 1, "{makefile}");
+        }
+    }
+
+    #[test]
+    fn parallel_directives_reach_their_host_only() {
+        let set = generate_from(
+            "#! parallel 10.0.0.2 32\napp[10.0.0.2]:\n\techo app\nlib[10.0.0.3]:\n\techo lib\n",
+        );
+
+        let [parallel, other] = set.remote_makefiles().as_slice() else {
+            panic!("Expected two remote makefiles");
+        };
+        assert!(parallel.makefile().starts_with("#! parallel 10.0.0.2 32\n"));
+        let tokens = lex(parallel.makefile().clone()).unwrap();
+        assert_eq!(parallel_jobs_of(&tokens), Some(32));
+        for makefile in [other.makefile(), set.my_makefile()] {
+            assert!(!makefile.contains("parallel"), "{makefile}");
+        }
+        assert!("parallel 10.0.0.2 0".parse::<Directive>().is_err());
+    }
+
+    #[test]
+    fn platform_conditionals_are_resolved_on_the_caller_only() {
+        let set = generate_from(
+            "ifneq ($(ARCH),dake-test-arch)\nEXT = .bin\nelse\nEXT = .test\nendif\n\
+             ifdef DEBUG\nCFLAGS = -g\nendif\napp[10.0.0.2]:\n\techo app$(EXT)\n",
+        );
+
+        // Only the caller knows its platform, the remote host lets make decide.
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        assert!(
+            remote.makefile().starts_with(
+                "ifneq ($(ARCH),dake-test-arch)\nEXT = .bin\nelse\nEXT = .test\nendif\n"
+            )
+        );
+        assert!(
+            set.my_makefile()
+                .starts_with("EXT = .bin\nifdef DEBUG\nCFLAGS = -g\nendif\n"),
+            "{}",
+            set.my_makefile()
+        );
+    }
+
+    #[test]
+    fn existing_includes_are_inlined() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("rules.mk"), "lib[10.0.0.2]:\n\techo lib\n").unwrap();
+        write(
+            dir.path().join("Makefile"),
+            "include rules.mk\nall: lib\n\techo all\n",
+        )
+        .unwrap();
+
+        let set = generate_in(dir.path(), DiscoveryHints::new());
+
+        let [remote] = set.remote_makefiles().as_slice() else {
+            panic!("Expected a single remote makefile");
+        };
+        assert!(remote.makefile().contains("lib:\n\techo lib\n"));
+        assert!(!set.my_makefile().contains("include"));
+    }
+
+    #[test]
+    fn unresolved_includes_are_left_to_make() {
+        let dir = tempdir().unwrap();
+        write(
+            dir.path().join("Makefile"),
+            "include missing.mk $(DEPS) *.d\nall:\n\techo all\n",
+        )
+        .unwrap();
+
+        let set = generate_in(dir.path(), DiscoveryHints::new());
+
+        for include in ["include missing.mk\n", "include $(DEPS)\n", "include *.d\n"] {
+            assert!(set.my_makefile().contains(include), "{}", set.my_makefile());
+        }
+    }
+}
@@ -4,6 +4,8 @@ use std::{
     path::PathBuf,
 };
 
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
 use derive_getters::Getters;
 use serde::{Deserialize, Serialize};
 
@@ -36,6 +38,25 @@ impl RemoteMakefile {
         self.makefile
     }
 
+    /// Replaces the content by its zstd compression, base64 encoded so it stays a valid string.
+    pub fn compress(self) -> Result<Self> {
+        let compressed = zstd::encode_all(self.makefile.as_bytes(), 0)
+            .context("Failed to compress the makefile.")?;
+        Ok(Self::new(STANDARD.encode(compressed), self.sock))
+    }
+
+    /// Reverts [`RemoteMakefile::compress`].
+    pub fn decompress(self) -> Result<Self> {
+        let compressed = STANDARD
+            .decode(self.makefile)
+            .context("The compressed makefile is not valid base64.")?;
+        let makefile = zstd::decode_all(compressed.as_slice())
+            .context("Failed to decompress the makefile.")?;
+        let makefile =
+            String::from_utf8(makefile).context("The decompressed makefile is not valid utf-8.")?;
+        Ok(Self::new(makefile, self.sock))
+    }
+
     pub fn ip(&self) -> IpAddr {
         self.sock.ip()
     }
@@ -48,6 +69,5 @@ impl RemoteMakefile {
             .iter()
             .map(|path| pos.join(path))
             .find(|path| path.try_exists().unwrap_or(false))
-            .map(PathBuf::from)
     }
 }
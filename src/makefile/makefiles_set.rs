@@ -16,6 +16,11 @@ impl RemoteMakefileSet {
         }
     }
 
+    /// Returns whether every target is built by the caller host, so no daemon is needed.
+    pub fn is_local_only(&self) -> bool {
+        self.remote_makefiles.is_empty()
+    }
+
     pub fn drop_makefiles(self) -> Vec<RemoteMakefile> {
         self.remote_makefiles
     }
@@ -1,6 +1,9 @@
 mod generate;
+#[allow(clippy::module_inception)]
 mod makefile;
 mod makefiles_set;
+mod platform;
 
 pub use makefile::RemoteMakefile;
 pub use makefiles_set::RemoteMakefileSet;
+pub use platform::{PLATFORM_VARIABLES, Platform};
@@ -0,0 +1,145 @@
+//! # Platform
+//!
+//! Evaluation of the makefile conditionals that only depend on the platform of a
+//! host, such as `ifeq ($(OS),Windows_NT)`.
+//!
+//! The platform variables are read from the environment, like GNU Make does, so a
+//! branch picked by Dake is the one make would have picked on the same host.
+
+use std::{collections::HashMap, env::var};
+
+use crate::lexer::ConditionalKind;
+
+/// Variables describing the platform of a host.
+pub const PLATFORM_VARIABLES: [&str; 2] = ["OS", "ARCH"];
+
+/// Values of the platform variables on a host, an empty value means undefined.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Platform {
+    variables: HashMap<String, String>,
+}
+
+impl Platform {
+    /// Returns the platform of the current host.
+    pub fn local() -> Self {
+        Self {
+            variables: PLATFORM_VARIABLES
+                .iter()
+                .map(|name| (name.to_string(), var(name).unwrap_or_default()))
+                .collect(),
+        }
+    }
+
+    /// Stops evaluating `name`, used when the makefile assigns it itself.
+    pub fn forget(&mut self, name: &str) {
+        self.variables.remove(name);
+    }
+
+    /// Expands the known variables of `s`, returns `None` if a reference remains.
+    fn expand(&self, s: &str) -> Option<String> {
+        let expanded = self
+            .variables
+            .iter()
+            .fold(s.to_string(), |s, (name, value)| {
+                s.replace(&format!("$({name})"), value)
+                    .replace(&format!("${{{name}}}"), value)
+            });
+        (!expanded.contains('$')).then_some(expanded)
+    }
+
+    /// Splits the condition of `ifeq`/`ifneq` into its two arguments, either
+    /// `(a,b)` or quoted (`"a" "b"`, `'a' 'b'`).
+    fn arguments(condition: &str) -> Option<(String, String)> {
+        if let Some(inner) = condition
+            .strip_prefix('(')
+            .and_then(|c| c.strip_suffix(')'))
+        {
+            let (left, right) = inner.split_once(',')?;
+            return Some((left.trim().to_string(), right.trim().to_string()));
+        }
+
+        let mut rest = condition;
+        let mut args = Vec::new();
+        while let Some(quote) = rest.chars().next().filter(|c| matches!(c, '"' | '\'')) {
+            let (arg, tail) = rest[1..].split_once(quote)?;
+            args.push(arg.to_string());
+            rest = tail.trim_start();
+        }
+        match (args.as_slice(), rest.is_empty()) {
+            ([left, right], true) => Some((left.clone(), right.clone())),
+            _ => None,
+        }
+    }
+
+    /// Evaluates a conditional on this platform.
+    ///
+    /// Returns `None` if the condition refers to a variable that is not a known
+    /// platform variable, make has to evaluate it on the host.
+    pub fn evaluate(&self, kind: ConditionalKind, condition: &str) -> Option<bool> {
+        match kind {
+            ConditionalKind::Ifdef | ConditionalKind::Ifndef => {
+                let defined = !self.variables.get(condition.trim())?.is_empty();
+                Some(defined == (kind == ConditionalKind::Ifdef))
+            }
+            ConditionalKind::Ifeq | ConditionalKind::Ifneq => {
+                let (left, right) = Self::arguments(&self.expand(condition)?)?;
+                Some((left == right) == (kind == ConditionalKind::Ifeq))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Platform;
+    use crate::lexer::ConditionalKind;
+
+    fn windows() -> Platform {
+        Platform {
+            variables: [("OS", "Windows_NT"), ("ARCH", "")]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn platform_conditions_are_evaluated() {
+        let platform = windows();
+        let evaluate = |kind, condition| platform.evaluate(kind, condition);
+
+        assert_eq!(
+            evaluate(ConditionalKind::Ifeq, "($(OS),Windows_NT)"),
+            Some(true)
+        );
+        assert_eq!(
+            evaluate(ConditionalKind::Ifeq, "\"${OS}\" 'Linux'"),
+            Some(false)
+        );
+        assert_eq!(
+            evaluate(ConditionalKind::Ifneq, "($(OS), Linux)"),
+            Some(true)
+        );
+        assert_eq!(evaluate(ConditionalKind::Ifdef, "OS"), Some(true));
+        assert_eq!(evaluate(ConditionalKind::Ifdef, "ARCH"), Some(false));
+        assert_eq!(evaluate(ConditionalKind::Ifndef, "ARCH"), Some(true));
+    }
+
+    #[test]
+    fn other_conditions_are_left_to_make() {
+        let mut platform = windows();
+
+        assert_eq!(platform.evaluate(ConditionalKind::Ifdef, "DEBUG"), None);
+        assert_eq!(
+            platform.evaluate(ConditionalKind::Ifeq, "($(CC),gcc)"),
+            None
+        );
+        assert_eq!(platform.evaluate(ConditionalKind::Ifeq, "($(OS))"), None);
+
+        platform.forget("OS");
+        assert_eq!(
+            platform.evaluate(ConditionalKind::Ifeq, "($(OS),Windows_NT)"),
+            None
+        );
+    }
+}
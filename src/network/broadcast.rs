@@ -1,62 +1,190 @@
-use anyhow::{Context, Result};
+//! # Broadcast
+//!
+//! Sends messages to several hosts in parallel, both the connections and the writes.
+//!
+//! Results always follow the order of the given hosts.
+//! [`broadcast_messages`] reports a [`BroadcastResult`] for each host, so one
+//! unreachable host does not hide the others, and [`broadcast_message`] reports
+//! the outcome of each host so callers can tell which hosts received the message
+//! and which need a retry.
+//! [`broadcast_and_collect_responses`] also waits for the response of each host.
+
+use std::{fmt, time::Duration};
+
+use anyhow::{Context, Result, bail};
 use futures::future::join_all;
-use tokio::spawn;
-use tracing::info;
+use serde::de::DeserializeOwned;
+use tracing::{info, warn};
+
+use crate::network::{
+    Message, MessageKind, MessageTrait, SocketAddr, TimedStream, connect,
+    send_message_and_await_response, write_message,
+};
+
+/// What happened when sending the message to a single host.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The message was written, the stream is kept open for the response.
+    Sent(TimedStream),
+    /// Connecting or writing to the host failed.
+    Failed(anyhow::Error),
+}
+
+/// Outcome of a broadcast for a single host.
+#[derive(Debug)]
+pub struct HostOutcome {
+    pub sock: SocketAddr,
+    pub outcome: Outcome,
+}
+
+/// Result of [`broadcast_messages`] for a single host, failures carry the host they come from.
+///
+/// Callers needing every host to succeed can collect the results into a
+/// `Result<Vec<_>, _>`.
+pub type BroadcastResult<T> = Result<T, (SocketAddr, anyhow::Error)>;
+
+/// Returned by [`broadcast_message`] when not a single host could be reached.
+#[derive(Debug)]
+pub struct BroadcastError {
+    pub failures: Vec<(SocketAddr, anyhow::Error)>,
+}
 
-use crate::network::{Message, MessageTrait, SocketAddr, Stream, connect, write_message};
+impl fmt::Display for BroadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Broadcast failed for every host:")?;
+        for (sock, e) in &self.failures {
+            write!(f, " [{sock}: {e:#}]")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BroadcastError {}
 
+/// Sends `message` to every host of `network`.
+///
+/// Partial failures are reported host by host in the returned results, an error
+/// is only returned if every host failed.
 pub async fn broadcast_message<M>(
     network: Vec<SocketAddr>,
     message: Message<M>,
-) -> Result<Vec<Stream>>
+) -> Result<Vec<HostOutcome>, BroadcastError>
 where
     M: MessageTrait,
 {
     let n = network.len();
-    broadcast_messages(network, vec![message; n]).await
+    let results = send_to_each(network, vec![message; n]).await;
+
+    if n == 0
+        || results
+            .iter()
+            .any(|r| matches!(r.outcome, Outcome::Sent(_)))
+    {
+        return Ok(results);
+    }
+
+    let failures = results
+        .into_iter()
+        .filter_map(|r| match r.outcome {
+            Outcome::Failed(e) => Some((r.sock, e)),
+            Outcome::Sent(_) => None,
+        })
+        .collect();
+    Err(BroadcastError { failures })
+}
+
+/// Sends `message` to every host of `network` in parallel and reads the response of each of them.
+///
+/// Each exchange, connection included, is bounded by `timeout`. The results are in
+/// the order of `network`.
+pub async fn broadcast_and_collect_responses<M, Resp>(
+    network: Vec<SocketAddr>,
+    message: Message<M>,
+    response_kind: MessageKind,
+    timeout: Duration,
+) -> Vec<(SocketAddr, Result<Resp>)>
+where
+    M: MessageTrait,
+    Resp: DeserializeOwned,
+{
+    let exchanges = network.into_iter().map(|sock| {
+        let message = message.clone();
+        async move {
+            let res =
+                send_message_and_await_response(message, sock.clone(), response_kind, timeout)
+                    .await;
+            (sock, res)
+        }
+    });
+    join_all(exchanges).await
 }
 
+/// Sends each message to its host, reporting the result of each host in the order of `network`.
+///
+/// A host that cannot be reached does not prevent the others from receiving their message.
+///
+/// # Errors
+/// Fails if there is not exactly one message per host.
 #[tracing::instrument(skip(messages, network))]
 pub async fn broadcast_messages<M>(
     network: Vec<SocketAddr>,
     messages: Vec<Message<M>>,
-) -> Result<Vec<Stream>>
+) -> Result<Vec<BroadcastResult<TimedStream>>>
 where
     M: MessageTrait,
 {
-    info!("Broadcasting {} messages to {network:?}", messages.len());
-
-    info!("Connecting to each hosts..");
-    let connect_tasks = network
-        .iter()
-        .cloned()
-        .map(|sock| {
-            spawn(async move {
-                connect(sock.clone())
-                    .await
-                    .context(format!("Failed to connect to the host {sock}"))
-            })
+    if network.len() != messages.len() {
+        bail!(
+            "Cannot broadcast {} messages to {} hosts.",
+            messages.len(),
+            network.len()
+        );
+    }
+    Ok(send_to_each(network, messages)
+        .await
+        .into_iter()
+        .map(|r| match r.outcome {
+            Outcome::Sent(stream) => Ok(stream),
+            Outcome::Failed(e) => Err((r.sock, e)),
         })
-        .collect::<Vec<_>>();
+        .collect())
+}
 
-    // Wait for all tasks to finish
-    let connect_results = join_all(connect_tasks).await;
+/// Connects and writes each message to its host in parallel, recording the outcome of each host.
+///
+/// The futures are polled concurrently on the current task, so the total latency is
+/// the one of the slowest host instead of the sum of all of them.
+async fn send_to_each<M>(network: Vec<SocketAddr>, messages: Vec<Message<M>>) -> Vec<HostOutcome>
+where
+    M: MessageTrait,
+{
+    info!("Broadcasting {} messages to {network:?}", messages.len());
 
-    // Handle both spawn errors and connection errors
-    let mut streams: Vec<Stream> = connect_results
+    let sends = network
         .into_iter()
-        .zip(network)
-        .map(|(join_res, sock)| {
-            join_res.context(format!("Failed to connect to the host {sock}"))?
-        })
-        .collect::<Result<Vec<_>, _>>()?;
-
-    info!("Connected successfully.");
+        .zip(messages)
+        .map(|(sock, message)| async move {
+            let res = async {
+                let mut stream = connect(sock.clone(), None)
+                    .await
+                    .context(format!("Failed to connect to the host {sock}"))?;
+                info!("Sending broadcast message to host {sock}");
+                write_message(&mut stream, message)
+                    .await
+                    .context(format!("Failed to send the message to the host {sock}"))?;
+                Ok::<_, anyhow::Error>(stream)
+            }
+            .await;
 
-    for (stream, message) in streams.iter_mut().zip(messages.into_iter()) {
-        info!("Sending broadcast message to host {}", stream.peer_addr()?);
-        write_message(stream, message).await?;
-    }
+            let outcome = match res {
+                Ok(stream) => Outcome::Sent(stream),
+                Err(e) => {
+                    warn!("Broadcast to {sock} failed: {e:#}");
+                    Outcome::Failed(e)
+                }
+            };
+            HostOutcome { sock, outcome }
+        });
 
-    Ok(streams)
+    join_all(sends).await
 }
@@ -6,14 +6,18 @@
 //! Messages are serialized with `postcard` and transmitted across TCP sockets
 //! between the daemon, caller, distributor, and fetcher components.
 
-use std::{fmt::Debug, path::PathBuf};
+use std::{collections::HashMap, fmt::Debug, path::PathBuf, time::SystemTime};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    daemon::ProcessDatas, enc, makefile::RemoteMakefile, network::SocketAddr, process_id::ProcessId,
+    daemon::ProcessDatas,
+    enc,
+    makefile::RemoteMakefile,
+    network::{CapabilityFlag, SocketAddr},
+    process_id::ProcessId,
 };
 
 /// A trait implemented by all message types.
@@ -22,6 +26,11 @@ use crate::{
 pub trait MessageTrait: Clone + Serialize + Send + Debug {
     /// Returns the [`MessageKind`] associated with this message.
     fn get_kind(&self) -> MessageKind;
+
+    /// Returns false if the message is not worth compressing, whatever its size.
+    fn is_compressible(&self) -> bool {
+        true
+    }
 }
 
 /// Header prepended to every serialized message.
@@ -29,6 +38,7 @@ pub trait MessageTrait: Clone + Serialize + Send + Debug {
 /// Contains:
 /// - `size`: Length of the serialized payload (in bytes)
 /// - `kind`: The [`MessageKind`] of the message
+/// - `flags`: How the payload is encoded, see [`MessageHeader::COMPRESSED`]
 #[derive(Default, Debug)]
 pub struct MessageHeader {
     /// Size of the message payload in bytes.
@@ -36,6 +46,9 @@ pub struct MessageHeader {
 
     /// The kind of the message (daemon, process, etc.).
     pub kind: MessageKind,
+
+    /// Encoding flags of the payload.
+    pub flags: u8,
 }
 
 impl Serialize for MessageHeader {
@@ -46,6 +59,7 @@ impl Serialize for MessageHeader {
         let mut buf = [0u8; MessageHeader::SIZE];
         buf[..8].copy_from_slice(&self.size.to_le_bytes());
         buf[8] = self.kind as u8;
+        buf[9] = self.flags;
         serializer.serialize_bytes(&buf)
     }
 }
@@ -69,15 +83,13 @@ impl<'de> Deserialize<'de> for MessageHeader {
                 .try_into()
                 .map_err(|_| serde::de::Error::custom("Failed to cast integer in bytes."))?,
         );
-        let kind = match bytes[8] {
-            0 => MessageKind::DaemonMessage,
-            1 => MessageKind::ProcessMessage,
-            2 => MessageKind::AckMessage,
-            3 => MessageKind::FetcherMessage,
-            other => return Err(serde::de::Error::custom(format!("invalid kind: {}", other))),
-        };
+        let kind = MessageKind::try_from(bytes[8]).map_err(serde::de::Error::custom)?;
 
-        Ok(Self { size, kind })
+        Ok(Self {
+            size,
+            kind,
+            flags: bytes[9],
+        })
     }
 }
 
@@ -99,21 +111,44 @@ pub enum MessageKind {
     FetcherMessage,
 }
 
+impl TryFrom<u8> for MessageKind {
+    type Error = anyhow::Error;
+
+    /// Decodes the kind tag of a header, failing on a kind added by a newer peer.
+    fn try_from(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => MessageKind::DaemonMessage,
+            1 => MessageKind::ProcessMessage,
+            2 => MessageKind::AckMessage,
+            3 => MessageKind::FetcherMessage,
+            other => bail!("Unknown message kind {other}."),
+        })
+    }
+}
+
 static HEADER_LENGTH: OnceCell<usize> = OnceCell::new();
 
 impl MessageHeader {
-    const SIZE: usize = 8 /* u64 size */ + 1 /* kind tag */;
+    const SIZE: usize = 8 /* u64 size */ + 1 /* kind tag */ + 1 /* flags */;
+
+    /// Flag set when the payload is zstd-compressed.
+    pub const COMPRESSED: u8 = 1;
 
     /// Creates a new [`MessageHeader`].
-    pub fn new(size: u64, kind: MessageKind) -> Self {
-        Self { size, kind }
+    pub fn new(size: u64, kind: MessageKind, flags: u8) -> Self {
+        Self { size, kind, flags }
+    }
+
+    /// Returns true if the payload is zstd-compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.flags & Self::COMPRESSED != 0
     }
 
     /// Returns the serialized length of a default message header.
     pub fn get_header_length() -> Result<usize> {
         HEADER_LENGTH
             .get_or_try_init(|| Ok(enc!(MessageHeader::default())?.len()))
-            .map(|len| *len)
+            .copied()
     }
 
     /// Prepends a serialized header to a message payload.
@@ -121,8 +156,9 @@ impl MessageHeader {
     /// # Arguments
     /// * `msg` - The serialized payload.
     /// * `kind` - The message kind for this payload.
-    pub fn wrap(mut msg: Vec<u8>, kind: MessageKind) -> Result<Vec<u8>> {
-        let header = MessageHeader::new(msg.len() as u64, kind);
+    /// * `flags` - How the payload is encoded.
+    pub fn wrap(mut msg: Vec<u8>, kind: MessageKind, flags: u8) -> Result<Vec<u8>> {
+        let header = MessageHeader::new(msg.len() as u64, kind, flags);
         let mut header = enc!(header)?;
         header.append(&mut msg);
         Ok(header)
@@ -135,19 +171,22 @@ impl MessageHeader {
 /// - `inner`: The actual message payload (implements [`MessageTrait`])
 /// - `pid`: The [`ProcessId`] of the sender
 /// - `client`: The socket of the sender, if none then reuse the same stream
+///
+/// `inner` is serialized last: if its variant is unknown to the receiver, the
+/// variant fields are trailing bytes that postcard ignores, see [`DaemonMessage::Unknown`].
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Message<M: MessageTrait> {
-    /// The actual message payload.
-    pub inner: M,
-
     /// The process identifier of the sender.
     pub pid: ProcessId,
+
+    /// The actual message payload.
+    pub inner: M,
 }
 
 impl<M: MessageTrait> Message<M> {
     /// Constructs a new [`Message`] with the given payload, process, and client.
     pub fn new(inner: M, pid: ProcessId) -> Self {
-        Self { inner, pid }
+        Self { pid, inner }
     }
 
     /// Returns the [`MessageKind`] of the contained payload.
@@ -170,6 +209,13 @@ pub enum DaemonMessage {
 
         /// Arguments to forward to `make`.
         args: Vec<String>,
+
+        /// Environment of the caller forwarded to the make processes.
+        env: HashMap<String, String>,
+
+        /// Variables of the caller forced on the make processes, see
+        /// [`ProcessDatas::env_overrides`].
+        env_overrides: HashMap<String, String>,
     },
 
     /// Request to distribute a single makefile to a remote host.
@@ -177,6 +223,9 @@ pub enum DaemonMessage {
         /// The remote makefile.
         makefile: RemoteMakefile,
 
+        /// Whether the content of `makefile` is compressed, see [`RemoteMakefile::compress`].
+        compressed: bool,
+
         /// The process datas for the build process of the makefile.
         process_datas: ProcessDatas,
     },
@@ -188,13 +237,47 @@ pub enum DaemonMessage {
 
         /// An optional labeled path for fetching.
         labeled_path: Option<PathBuf>,
+
+        /// Blake3 hash of the copy the fetcher already has, if any.
+        known_checksum: Option<[u8; 32]>,
+
+        /// Bytes of the artifact the fetcher already received, the transfer starts after them.
+        offset: u64,
+
+        /// Target whose make sent the fetch, `None` for the top-level build.
+        parent: Option<String>,
+
+        /// The working directory of the caller, taking precedence over the project path of
+        /// the message pid, which may be mangled by the command line of `dake fetch`.
+        caller_path: Option<PathBuf>,
+    },
+
+    /// Request to build several targets in parallel and fetch them on the same stream.
+    ///
+    /// Each target is answered in order, as a [`DaemonMessage::Fetch`] of its own would be.
+    FetchBatch {
+        /// The targets to fetch.
+        targets: Vec<FetchRequest>,
+
+        /// Target whose make sent the fetch, `None` for the top-level build.
+        parent: Option<String>,
+
+        /// See the `caller_path` of [`DaemonMessage::Fetch`].
+        caller_path: Option<PathBuf>,
     },
 
     /// Submit a new log to forward to the caller on stdout
-    StdoutLog { log: String },
+    StdoutLog { log: String, timestamp_ms: u64 },
 
     /// Submit a new log to forward to the caller on stderr
-    StderrLog { log: String },
+    StderrLog { log: String, timestamp_ms: u64 },
+
+    /// Submit a batch of logs to forward to the caller, in emission order.
+    ///
+    /// Each log comes with the time it was produced at, in milliseconds since the Unix epoch.
+    BatchLog {
+        logs: Vec<(OutputKind, String, u64)>,
+    },
 
     /// Indicates that one of the make failed.
     MakeError {
@@ -204,6 +287,59 @@ pub enum DaemonMessage {
 
     /// Indicate that the process is done
     Done,
+
+    /// Sent by the caller when the user interrupts the build.
+    CancelProcess { pid: ProcessId },
+
+    /// Request the environment forwarded to the process of the message pid.
+    GetProcessEnv,
+
+    /// Liveness check, answered with a [`ProcessMessage::Pong`].
+    Ping,
+
+    /// Request the status of the daemon, answered with a [`ProcessMessage::Status`].
+    GetStatus {
+        /// Whether to include a JSON dump of the daemon state.
+        verbose: bool,
+    },
+
+    /// Request the builds in progress, answered with a [`ProcessMessage::StatusReport`].
+    QueryStatus,
+
+    /// First message of every connection, answered with the hello of the daemon,
+    /// see [`handshake`](crate::network::handshake).
+    Hello {
+        /// Protocol version of the sender.
+        version: u16,
+
+        /// Optional features supported by the sender.
+        capabilities: Vec<CapabilityFlag>,
+    },
+
+    /// Sent by `dake kill --all` to stop every build of the daemon, acknowledged once
+    /// they are all stopped.
+    CancelAll {
+        /// Why the builds are stopped, only logged.
+        reason: String,
+    },
+
+    /// Any variant this daemon does not know, sent by a newer peer.
+    ///
+    /// Never sent on the wire, so it must stay the last variant: new variants are
+    /// added before it.
+    #[serde(other)]
+    Unknown,
+}
+
+impl DaemonMessage {
+    /// Takes the working directory of the caller sent along a fetch request, if any.
+    pub fn take_caller_path(&mut self) -> Option<PathBuf> {
+        match self {
+            DaemonMessage::Fetch { caller_path, .. }
+            | DaemonMessage::FetchBatch { caller_path, .. } => caller_path.take(),
+            _ => None,
+        }
+    }
 }
 
 impl MessageTrait for DaemonMessage {
@@ -212,17 +348,69 @@ impl MessageTrait for DaemonMessage {
     }
 }
 
+/// Output stream a forwarded log was produced on.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub enum OutputKind {
+    Stdout,
+    Stderr,
+    /// Progress of the build of `target`, the attached log is ignored.
+    Progress {
+        percent: u8,
+        target: String,
+    },
+}
+
 /// Messages related to process lifecycle.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ProcessMessage {
     /// Response of the daemon, the pid of the message is the fresh pid.
     FreshId,
     /// Log form the remote make processes on stdout.
-    StdoutLog { log: String },
-    /// Log form the remote make processes on stderr.
-    StderrLog { log: String },
+    ///
+    /// `timestamp_ms` is the time the log was produced on its host, in milliseconds
+    /// since the Unix epoch.
+    StdoutLog { log: String, timestamp_ms: u64 },
+    /// Log form the remote make processes on stderr, see [`ProcessMessage::StdoutLog`].
+    StderrLog { log: String, timestamp_ms: u64 },
+    /// Progress of a target built by one of the make processes.
+    Progress { percent: u8, target: String },
+    /// A host is done with the build, sent before [`ProcessMessage::End`].
+    NodeDone { node: SocketAddr, exit_code: i32 },
     /// Indicates that the process has finished execution.
     End { exit_code: i32 },
+    /// Response to [`DaemonMessage::GetProcessEnv`], `None` if the process is unknown.
+    Env {
+        env: Option<HashMap<String, String>>,
+    },
+    /// Response to [`DaemonMessage::Ping`].
+    Pong {
+        /// Seconds since the daemon started.
+        uptime_secs: u64,
+        /// Amount of processes registered on the daemon.
+        active_processes: usize,
+    },
+    /// Response to [`DaemonMessage::GetStatus`].
+    Status {
+        active_processes: usize,
+        /// JSON dump of the daemon state, only for verbose requests.
+        snapshot: Option<String>,
+    },
+    /// Response to [`DaemonMessage::QueryStatus`].
+    StatusReport {
+        processes: Vec<ProcessSummary>,
+        /// Builds waiting for the daemon to run less builds than its limit.
+        queued_builds: usize,
+    },
+}
+
+/// A build in progress on a daemon, see [`ProcessMessage::StatusReport`].
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ProcessSummary {
+    pub pid: ProcessId,
+    pub started_at: SystemTime,
+    /// Time the process has been running for, measured by the daemon.
+    pub elapsed_secs: u64,
+    pub involved_hosts: Vec<SocketAddr>,
 }
 
 impl MessageTrait for ProcessMessage {
@@ -239,6 +427,10 @@ pub enum AckMessage {
 
     /// The makefile distribution failed.
     Failure,
+
+    /// The host does not know the makefile it was asked to confirm, the full
+    /// makefile has to be sent again.
+    NeedFullMakefile,
 }
 
 impl MessageTrait for AckMessage {
@@ -250,16 +442,51 @@ impl MessageTrait for AckMessage {
 /// Messages used by the fetcher to transfer objects or build artifacts.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum FetcherMessage {
+    /// First message of a transfer, the size of the whole artifact, 0 if unknown.
+    ///
+    /// A directory target is sent as a tar archive to unpack, flagged by `is_tar`.
+    Metadata { is_tar: bool, total_bytes: u64 },
     /// Encapsulates a build object (binary data).
-    Object(Vec<u8>),
+    /// Encoded as raw bytes, which postcard writes identically to a `u8`
+    /// sequence but without the per-element overhead.
+    Object(#[serde(with = "serde_bytes")] Vec<u8>),
     /// Indicates the object has been fully transmitted.
     Eof,
     /// Indicated that the fetch failed
     Failed,
+    /// The process of a failed fetch is over, the fetcher can exit.
+    Cancel,
+    /// The artifact matches the `known_checksum` of the request, nothing is transmitted.
+    NotModified,
+    /// Hex encoded blake3 hash of the whole artifact, sent after the data and before
+    /// [`FetcherMessage::Eof`], so the fetcher can check what it wrote.
+    Checksum { hex: String },
+}
+
+/// A target of a [`DaemonMessage::FetchBatch`], with the same fields as a
+/// [`DaemonMessage::Fetch`].
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FetchRequest {
+    /// The build target to fetch.
+    pub target: String,
+
+    /// An optional labeled path for fetching.
+    pub labeled_path: Option<PathBuf>,
+
+    /// Blake3 hash of the copy the fetcher already has, if any.
+    pub known_checksum: Option<[u8; 32]>,
+
+    /// Bytes of the artifact the fetcher already received, the transfer starts after them.
+    pub offset: u64,
 }
 
 impl MessageTrait for FetcherMessage {
     fn get_kind(&self) -> MessageKind {
         MessageKind::FetcherMessage
     }
+
+    /// Objects are chunks of build artifacts, mostly binaries that barely compress.
+    fn is_compressible(&self) -> bool {
+        !matches!(self, FetcherMessage::Object(_))
+    }
 }
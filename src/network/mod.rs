@@ -1,22 +1,41 @@
 mod broadcast;
 mod messages;
+mod pool;
+mod protocol;
 mod socket;
 mod stream;
+mod timed;
+mod tls;
 mod utils;
 
 pub use self::{
-    broadcast::{broadcast_message, broadcast_messages},
+    broadcast::{
+        BroadcastError, BroadcastResult, HostOutcome, Outcome, broadcast_and_collect_responses,
+        broadcast_message, broadcast_messages,
+    },
     messages::{
-        AckMessage, DaemonMessage, FetcherMessage, Message, MessageHeader, MessageKind,
-        MessageTrait, ProcessMessage,
+        AckMessage, DaemonMessage, FetchRequest, FetcherMessage, Message, MessageHeader,
+        MessageKind, MessageTrait, OutputKind, ProcessMessage, ProcessSummary,
+    },
+    pool::{Pool, PooledStream},
+    protocol::{
+        CapabilityFlag, Negotiated, NegotiatedVersion, PROTOCOL_VERSION, handshake, hello,
+        local_capabilities,
     },
-    socket::SocketAddr,
-    stream::Stream,
+    socket::{SocketAddr, check_port},
+    stream::{Stream, StreamConfig, StreamReader, StreamWriter},
+    timed::{StreamTimeouts, Timed, TimedStream},
+    tls::TlsConfig,
     utils::{
-        connect, connect_with_daemon_or_start_it, get_daemon_ip, get_daemon_port,
-        get_daemon_tcp_sock, get_daemon_unix_sock, read_next_message, send_message, write_message,
+        connect, connect_with_daemon_or_start_it, get_daemon_bind_ip, get_daemon_ip,
+        get_daemon_port, get_daemon_tcp_sock, get_daemon_unix_sock, read_next_message,
+        read_next_message_with_timeout, read_next_message_zero_copy, send_message,
+        send_message_and_await_response, write_message,
     },
 };
 
+#[cfg(feature = "stats")]
+pub use stream::StreamStats;
+
 pub const DEFAULT_PORT: u16 = 1808;
 pub const DAEMON_UNIX_SOCKET: &str = "/tmp/dake_daemon.sock";
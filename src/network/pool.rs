@@ -0,0 +1,209 @@
+//! # Connection Pool
+//!
+//! Keeps the connections opened to other daemons once they are no longer used, so
+//! the next message to the same daemon skips the connection and the handshake.
+//!
+//! A [`PooledStream`] is checked out by a single user at a time and goes back to
+//! the pool when dropped, unless a read or a write failed on it.
+
+use std::{
+    collections::HashMap,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::Instant,
+};
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{info, warn};
+
+use crate::{
+    constants::POOL_IDLE_TIMEOUT,
+    network::{
+        Negotiated, NegotiatedVersion, SocketAddr, StreamTimeouts, Timed, TimedStream, connect,
+    },
+};
+
+/// A connection waiting in the pool, with the time it was returned at.
+type IdleStream = (TimedStream, Instant);
+
+/// Pool of idle connections, keyed by the address they are connected to.
+///
+/// Cloning the pool shares the same connections. The idle connections are behind a
+/// std mutex, [`PooledStream`] puts itself back in its `Drop`, which cannot await.
+#[derive(Clone)]
+pub struct Pool {
+    max_per_addr: usize,
+    idle: Arc<Mutex<HashMap<SocketAddr, Vec<IdleStream>>>>,
+}
+
+impl Pool {
+    /// Creates an empty pool keeping at most `max_per_addr` idle connections per address.
+    pub fn new(max_per_addr: usize) -> Self {
+        Self {
+            max_per_addr,
+            idle: Arc::default(),
+        }
+    }
+
+    /// Returns a connection to `addr`, reusing an idle one if any.
+    ///
+    /// Connections idle for more than [`POOL_IDLE_TIMEOUT`] are dropped instead of
+    /// reused, the peer may have closed them in the meantime.
+    pub async fn get(&self, addr: SocketAddr) -> Result<PooledStream> {
+        if let Some(stream) = self.take_idle(&addr) {
+            info!("Reusing a pooled connection to {addr}");
+            return Ok(PooledStream::new(stream, addr, Some(self.clone())));
+        }
+        self.open(addr).await
+    }
+
+    /// Returns a new connection to `addr`, that goes back to the pool once dropped.
+    pub async fn open(&self, addr: SocketAddr) -> Result<PooledStream> {
+        let stream = connect(addr.clone(), None).await?;
+        info!("Opened a new pooled connection to {addr}");
+        Ok(PooledStream::new(stream, addr, Some(self.clone())))
+    }
+
+    fn take_idle(&self, addr: &SocketAddr) -> Option<TimedStream> {
+        let mut idle = match self.idle.lock() {
+            Ok(idle) => idle,
+            Err(e) => {
+                warn!("The connection pool is poisoned: {e}");
+                return None;
+            }
+        };
+        let streams = idle.get_mut(addr)?;
+        streams.retain(|(_, since)| since.elapsed() < POOL_IDLE_TIMEOUT);
+        streams.pop().map(|(stream, _)| stream)
+    }
+
+    fn put_back(&self, addr: SocketAddr, stream: TimedStream) {
+        let mut idle = match self.idle.lock() {
+            Ok(idle) => idle,
+            Err(e) => {
+                warn!("The connection pool is poisoned, dropping the connection: {e}");
+                return;
+            }
+        };
+        let streams = idle.entry(addr).or_default();
+        if streams.len() < self.max_per_addr {
+            streams.push((stream, Instant::now()));
+        }
+    }
+}
+
+/// A connection checked out of a [`Pool`], or a standalone one if it has no pool.
+///
+/// Goes back to its pool on drop, unless it is broken: a read or a write failed,
+/// or the peer closed the connection.
+pub struct PooledStream {
+    stream: Option<TimedStream>,
+    version: NegotiatedVersion,
+    addr: SocketAddr,
+    pool: Option<Pool>,
+    broken: bool,
+}
+
+impl PooledStream {
+    fn new(stream: TimedStream, addr: SocketAddr, pool: Option<Pool>) -> Self {
+        Self {
+            version: stream.negotiated().clone(),
+            stream: Some(stream),
+            addr,
+            pool,
+            broken: false,
+        }
+    }
+
+    /// Opens a connection to `addr` that is closed on drop instead of pooled.
+    pub async fn standalone(addr: SocketAddr) -> Result<Self> {
+        let stream = connect(addr.clone(), None).await?;
+        Ok(Self::new(stream, addr, None))
+    }
+
+    /// Returns the inner stream, marking the connection broken on failure.
+    fn poll_stream<T>(
+        &mut self,
+        poll: impl FnOnce(Pin<&mut TimedStream>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+        };
+        let res = poll(Pin::new(stream));
+        if let Poll::Ready(Err(_)) = res {
+            self.broken = true;
+        }
+        res
+    }
+}
+
+impl Drop for PooledStream {
+    fn drop(&mut self) {
+        if self.broken {
+            info!("Dropping the broken connection to {}", self.addr);
+            return;
+        }
+        if let (Some(pool), Some(stream)) = (self.pool.take(), self.stream.take()) {
+            pool.put_back(self.addr.clone(), stream);
+        }
+    }
+}
+
+impl Negotiated for PooledStream {
+    fn negotiated(&self) -> &NegotiatedVersion {
+        &self.version
+    }
+}
+
+impl Timed for PooledStream {
+    fn timeouts(&self) -> StreamTimeouts {
+        self.stream
+            .as_ref()
+            .map(Timed::timeouts)
+            .unwrap_or_default()
+    }
+}
+
+impl AsyncRead for PooledStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let res = this.poll_stream(|s| s.poll_read(cx, buf));
+        // Nothing read on a non-empty buffer means the peer closed the connection.
+        if let Poll::Ready(Ok(())) = res
+            && buf.filled().len() == filled
+            && buf.remaining() > 0
+        {
+            this.broken = true;
+        }
+        res
+    }
+}
+
+impl AsyncWrite for PooledStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_stream(|s| s.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_stream(|s| s.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // A shut down connection cannot be reused.
+        this.broken = true;
+        this.poll_stream(|s| s.poll_shutdown(cx))
+    }
+}
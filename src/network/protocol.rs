@@ -0,0 +1,142 @@
+//! # Protocol Version
+//!
+//! Every connection starts with an exchange of [`DaemonMessage::Hello`]: the connector
+//! advertises its protocol version and capabilities, the daemon answers with its own.
+//! Both sides then restrict themselves to the lowest version and to the capabilities
+//! they share, so daemons of different versions can work together.
+//!
+//! The outcome is kept on the stream, see [`Negotiated`]: until the handshake is done,
+//! a stream assumes its peer is [`NegotiatedVersion::legacy`].
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use tracing::{info, warn};
+
+use crate::{
+    constants::HANDSHAKE_TIMEOUT,
+    dec,
+    network::{DaemonMessage, Message, MessageKind, Timed, read_next_message, write_message},
+    process_id::ProcessId,
+};
+
+/// Version of the protocol spoken by this build.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Optional features a host may support.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub enum CapabilityFlag {
+    /// Large payloads are sent zstd-compressed, see
+    /// [`MessageHeader::COMPRESSED`](crate::network::MessageHeader::COMPRESSED).
+    Compression,
+
+    /// Any capability this host does not know, advertised by a newer peer.
+    ///
+    /// Must stay the last variant: new variants are added before it.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Returns the capabilities of this build.
+pub fn local_capabilities() -> Vec<CapabilityFlag> {
+    vec![CapabilityFlag::Compression]
+}
+
+/// Returns the [`DaemonMessage::Hello`] advertising this build.
+pub fn hello() -> DaemonMessage {
+    DaemonMessage::Hello {
+        version: PROTOCOL_VERSION,
+        capabilities: local_capabilities(),
+    }
+}
+
+/// Protocol version and capabilities agreed on with a peer.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NegotiatedVersion {
+    pub version: u16,
+    pub capabilities: Vec<CapabilityFlag>,
+}
+
+impl NegotiatedVersion {
+    /// Agrees on the lowest version and on the capabilities known by both sides.
+    pub fn negotiate(remote_version: u16, remote_capabilities: &[CapabilityFlag]) -> Self {
+        Self {
+            version: PROTOCOL_VERSION.min(remote_version),
+            capabilities: local_capabilities()
+                .into_iter()
+                .filter(|c| remote_capabilities.contains(c))
+                .collect(),
+        }
+    }
+
+    /// Version of a peer predating the handshake, it supports no capability.
+    pub fn legacy() -> Self {
+        Self {
+            version: 0,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// Returns true if both sides support `capability`.
+    pub fn supports(&self, capability: CapabilityFlag) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// A stream knowing what its peer supports.
+pub trait Negotiated {
+    /// Returns the protocol version negotiated on this stream.
+    fn negotiated(&self) -> &NegotiatedVersion;
+}
+
+impl<T: Negotiated + ?Sized> Negotiated for &mut T {
+    fn negotiated(&self) -> &NegotiatedVersion {
+        (**self).negotiated()
+    }
+}
+
+/// Sends a [`DaemonMessage::Hello`] on a freshly opened stream and waits for the one of the peer.
+///
+/// A peer predating the handshake ignores the hello, so if no answer comes within
+/// [`HANDSHAKE_TIMEOUT`] it is assumed to be [`NegotiatedVersion::legacy`].
+///
+/// # Errors
+/// Fails if the stream breaks, or if the peer answers something else than a hello.
+pub async fn handshake<S: AsyncReadExt + AsyncWriteExt + Negotiated + Timed + Unpin>(
+    stream: &mut S,
+) -> Result<NegotiatedVersion> {
+    write_message(stream, Message::new(hello(), ProcessId::default()))
+        .await
+        .context("Failed to send the hello.")?;
+
+    let response = match timeout(
+        HANDSHAKE_TIMEOUT,
+        read_next_message(stream, MessageKind::DaemonMessage, None),
+    )
+    .await
+    {
+        Ok(response) => response?.context("The peer closed the stream during the handshake.")?,
+        Err(_) => {
+            warn!("The peer did not answer the hello, assuming it predates the handshake.");
+            return Ok(NegotiatedVersion::legacy());
+        }
+    };
+
+    match dec!(response, Message<DaemonMessage>)
+        .context("Failed to decode the hello of the peer.")?
+        .inner
+    {
+        DaemonMessage::Hello {
+            version,
+            capabilities,
+        } => {
+            let negotiated = NegotiatedVersion::negotiate(version, &capabilities);
+            info!("Negotiated {negotiated:?} with a peer of version {version}");
+            Ok(negotiated)
+        }
+        other => bail!("Expected a hello from the peer, received {other:?}."),
+    }
+}
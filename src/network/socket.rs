@@ -1,16 +1,37 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 use std::{
+    env::var,
     fmt::{Display, Formatter},
     hash::Hash,
     net::{IpAddr, SocketAddr as TcpSocketAddr},
     path::{Path, PathBuf},
     str::FromStr,
 };
-use tokio::net::unix::SocketAddr as UnixSocketAddr;
+use tokio::net::{lookup_host, unix::SocketAddr as UnixSocketAddr};
+
+use crate::{
+    constants::MIN_UNPRIVILEGED_PORT, env_variables::EnvVariable, network::utils::parse_env_var,
+};
 
 const UNNAMED_UNIX: &str = "unix:unnamed";
 
+/// Fails if `port` is privileged, such as `80` or `443`, most likely a typo in an
+/// address since daemons listen on higher ports.
+///
+/// The port `0`, picked by the OS on bind, is always accepted. Setting
+/// [`EnvVariable::AllowPrivilegedPorts`] to `1` disables the check.
+pub fn check_port(port: u16) -> Result<u16> {
+    if port == 0 || port >= MIN_UNPRIVILEGED_PORT {
+        return Ok(port);
+    }
+    let allow = EnvVariable::AllowPrivilegedPorts;
+    if var(allow.to_string()).is_ok_and(|v| v == "1") {
+        return Ok(port);
+    }
+    bail!("The port {port} is privileged, set {allow}=1 if a daemon really listens on it.")
+}
+
 /// Unified socket address abstraction supporting both TCP and Unix sockets.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum SocketAddr {
@@ -19,8 +40,9 @@ pub enum SocketAddr {
 }
 
 impl SocketAddr {
-    pub fn new_tcp(ip: IpAddr, port: u16) -> Self {
-        Self::Tcp(TcpSocketAddr::new(ip, port))
+    /// Creates a TCP address, failing if `port` is privileged, see [`check_port`].
+    pub fn new_tcp(ip: IpAddr, port: u16) -> Result<Self> {
+        Ok(Self::Tcp(TcpSocketAddr::new(ip, check_port(port)?)))
     }
 
     pub fn new_unix(path: PathBuf) -> Result<Self> {
@@ -31,6 +53,27 @@ impl SocketAddr {
         Ok(Self::Unix(None))
     }
 
+    /// Resolves `host` through DNS and returns its first address with `port`.
+    ///
+    /// Unlike [`FromStr`], which only accepts literal addresses, `host` may be a
+    /// host name such as `builder-01.local`.
+    pub async fn from_hostname(host: &str, port: u16) -> Result<Self> {
+        let mut addrs = lookup_host((host, port)).await.context(format!(
+            "Failed to resolve the host name {host}, check the DNS configuration of this host."
+        ))?;
+        match addrs.next() {
+            Some(addr) => Ok(Self::Tcp(addr)),
+            None => bail!(
+                "The host name {host} resolved to no address, check the DNS configuration of this host."
+            ),
+        }
+    }
+
+    /// Reads and parses the socket address stored in the environment variable `var_name`.
+    pub fn from_env(var_name: &str) -> Result<Self> {
+        parse_env_var(var_name)
+    }
+
     pub fn ip(&self) -> Option<IpAddr> {
         self.get_tcp().map(|sock| sock.ip())
     }
@@ -56,6 +99,16 @@ impl SocketAddr {
     pub fn is_tcp(&self) -> bool {
         matches!(self, Self::Tcp(_))
     }
+
+    /// Formats the address so it can be parsed back with [`FromStr`], IPv6 addresses
+    /// are wrapped in brackets (`[::1]:1808`). Used when writing addresses in makefiles.
+    pub fn to_display_string(&self) -> String {
+        match self {
+            Self::Tcp(TcpSocketAddr::V6(addr)) => format!("[{}]:{}", addr.ip(), addr.port()),
+            Self::Tcp(TcpSocketAddr::V4(addr)) => format!("{}:{}", addr.ip(), addr.port()),
+            Self::Unix(_) => self.to_string(),
+        }
+    }
 }
 
 impl Default for SocketAddr {
@@ -67,9 +120,14 @@ impl Default for SocketAddr {
 impl FromStr for SocketAddr {
     type Err = anyhow::Error;
 
+    /// Parses a TCP address, failing on privileged ports (see [`check_port`]),
+    /// or falls back to a Unix socket path.
     fn from_str(s: &str) -> Result<Self> {
         Ok(match s.parse::<TcpSocketAddr>() {
-            Ok(addr) => Self::Tcp(addr),
+            Ok(addr) => {
+                check_port(addr.port()).context(format!("Invalid address {s}."))?;
+                Self::Tcp(addr)
+            }
             Err(_) => {
                 if s == UNNAMED_UNIX {
                     Self::Unix(None)
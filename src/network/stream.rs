@@ -1,17 +1,100 @@
 use anyhow::{Context, Result};
+#[cfg(feature = "stats")]
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
 use std::{pin::Pin, task::Poll};
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
-    net::{TcpStream, UnixStream},
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf, split},
+    net::{TcpSocket, TcpStream, UnixStream, tcp, unix},
 };
+use tokio_rustls::TlsStream;
+
+use crate::network::{Negotiated, NegotiatedVersion, SocketAddr, StreamTimeouts, Timed, TlsConfig};
+
+/// Options applied to the TCP connections opened by [`Stream::connect_with_config`],
+/// Unix connections ignore them.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamConfig {
+    /// Disables Nagle's algorithm, so small messages such as log lines are sent at once.
+    pub nodelay: bool,
+    /// Size of the kernel receive buffer, the system default if `None`.
+    pub read_buf_size: Option<usize>,
+    /// Size of the kernel send buffer, the system default if `None`.
+    pub write_buf_size: Option<usize>,
+}
 
-use crate::network::SocketAddr;
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            read_buf_size: None,
+            write_buf_size: None,
+        }
+    }
+}
 
 /// Unified stream abstraction for both TCP and Unix sockets.
 #[derive(Debug)]
-pub enum Stream {
+pub struct Stream {
+    kind: StreamKind,
+    negotiated: NegotiatedVersion,
+    #[cfg(feature = "stats")]
+    stats: StatsCounters,
+}
+
+#[derive(Debug)]
+enum StreamKind {
     Tcp(TcpStream),
     Unix(UnixStream),
+    /// A TCP connection upgraded to TLS, either the client or the server side.
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl From<StreamKind> for Stream {
+    fn from(kind: StreamKind) -> Self {
+        Self {
+            kind,
+            negotiated: NegotiatedVersion::legacy(),
+            #[cfg(feature = "stats")]
+            stats: StatsCounters::default(),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        StreamKind::Tcp(stream).into()
+    }
+}
+
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        StreamKind::Unix(stream).into()
+    }
+}
+
+impl From<TlsStream<TcpStream>> for Stream {
+    fn from(stream: TlsStream<TcpStream>) -> Self {
+        StreamKind::Tls(Box::new(stream)).into()
+    }
+}
+
+/// Number of bytes that went through a [`Stream`], see [`Stream::stats`].
+#[cfg(feature = "stats")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Counters behind [`StreamStats`], shared so they can be read while the stream is in use.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, Default)]
+struct StatsCounters {
+    bytes_read: Arc<AtomicU64>,
+    bytes_written: Arc<AtomicU64>,
 }
 
 impl AsyncRead for Stream {
@@ -20,10 +103,19 @@ impl AsyncRead for Stream {
         cx: &mut std::task::Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        match &mut *self {
-            Stream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
-            Stream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        #[cfg(feature = "stats")]
+        let filled = buf.filled().len();
+        let res = match &mut self.kind {
+            StreamKind::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            StreamKind::Unix(s) => Pin::new(s).poll_read(cx, buf),
+            StreamKind::Tls(s) => Pin::new(s.as_mut()).poll_read(cx, buf),
+        };
+        #[cfg(feature = "stats")]
+        if let Poll::Ready(Ok(())) = res {
+            let read = (buf.filled().len() - filled) as u64;
+            self.stats.bytes_read.fetch_add(read, Ordering::Relaxed);
         }
+        res
     }
 }
 
@@ -34,19 +126,28 @@ impl AsyncWrite for Stream {
         cx: &mut std::task::Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        match &mut *self {
-            Stream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
-            Stream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        let res = match &mut self.kind {
+            StreamKind::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            StreamKind::Unix(s) => Pin::new(s).poll_write(cx, buf),
+            StreamKind::Tls(s) => Pin::new(s.as_mut()).poll_write(cx, buf),
+        };
+        #[cfg(feature = "stats")]
+        if let Poll::Ready(Ok(written)) = res {
+            self.stats
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
         }
+        res
     }
 
     fn poll_flush(
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<std::io::Result<()>> {
-        match &mut *self {
-            Stream::Tcp(s) => Pin::new(s).poll_flush(cx),
-            Stream::Unix(s) => Pin::new(s).poll_flush(cx),
+        match &mut self.kind {
+            StreamKind::Tcp(s) => Pin::new(s).poll_flush(cx),
+            StreamKind::Unix(s) => Pin::new(s).poll_flush(cx),
+            StreamKind::Tls(s) => Pin::new(s.as_mut()).poll_flush(cx),
         }
     }
 
@@ -54,56 +155,299 @@ impl AsyncWrite for Stream {
         mut self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<std::io::Result<()>> {
-        match &mut *self {
-            Stream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
-            Stream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        match &mut self.kind {
+            StreamKind::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            StreamKind::Unix(s) => Pin::new(s).poll_shutdown(cx),
+            StreamKind::Tls(s) => Pin::new(s.as_mut()).poll_shutdown(cx),
         }
     }
 }
 
 impl Stream {
+    /// Connects to `sock` with the default [`StreamConfig`].
     pub async fn connect(sock: SocketAddr) -> Result<Stream> {
+        Self::connect_with_config(sock, StreamConfig::default()).await
+    }
+
+    /// Connects to `sock`, TCP connections are upgraded to TLS when it is configured,
+    /// see [`TlsConfig`].
+    pub async fn connect_with_config(sock: SocketAddr, config: StreamConfig) -> Result<Stream> {
         Ok(match sock {
-            SocketAddr::Tcp(addr) => Self::Tcp(
-                TcpStream::connect(addr)
+            SocketAddr::Tcp(addr) => {
+                let socket = if addr.is_ipv4() {
+                    TcpSocket::new_v4()
+                } else {
+                    TcpSocket::new_v6()
+                }
+                .context("Failed to create a TCP socket")?;
+                if let Some(size) = config.read_buf_size {
+                    let size =
+                        u32::try_from(size).context("The receive buffer size is too large")?;
+                    socket
+                        .set_recv_buffer_size(size)
+                        .context("Failed to set the receive buffer size")?;
+                }
+                if let Some(size) = config.write_buf_size {
+                    let size = u32::try_from(size).context("The send buffer size is too large")?;
+                    socket
+                        .set_send_buffer_size(size)
+                        .context("Failed to set the send buffer size")?;
+                }
+                let stream = socket
+                    .connect(addr)
                     .await
-                    .context("Failed to connect over TCP")?,
-            ),
-            SocketAddr::Unix(addr) => Self::Unix(
+                    .context("Failed to connect over TCP")?;
+                stream
+                    .set_nodelay(config.nodelay)
+                    .context("Failed to set TCP_NODELAY")?;
+                match TlsConfig::get()? {
+                    Some(tls) => tls.connect(addr.ip(), stream).await?.into(),
+                    None => stream.into(),
+                }
+            }
+            SocketAddr::Unix(addr) => {
                 UnixStream::connect(addr.context("Can't connect to an unnamed socket.")?)
                     .await
-                    .context("Failed to connect over Unix")?,
-            ),
+                    .context("Failed to connect over Unix")?
+                    .into()
+            }
         })
     }
 
+    /// Records the protocol version agreed on with the peer, see [`handshake`](crate::network::handshake).
+    pub fn set_negotiated(&mut self, negotiated: NegotiatedVersion) {
+        self.negotiated = negotiated;
+    }
+
     pub fn peer_addr(&self) -> Result<SocketAddr> {
-        Ok(match self {
-            Stream::Tcp(stream) => SocketAddr::Tcp(
+        Ok(match &self.kind {
+            StreamKind::Tcp(stream) => SocketAddr::Tcp(
                 stream
                     .peer_addr()
                     .context("Failed to fetch peer address from TCP.")?,
             ),
-            Stream::Unix(stream) => SocketAddr::from(
+            StreamKind::Unix(stream) => SocketAddr::from(
                 stream
                     .peer_addr()
                     .context("Failed to fetch peer address from Unix.")?,
             ),
+            StreamKind::Tls(stream) => SocketAddr::Tcp(
+                stream
+                    .get_ref()
+                    .0
+                    .peer_addr()
+                    .context("Failed to fetch peer address from TLS.")?,
+            ),
         })
     }
 
     pub fn local_addr(&self) -> Result<SocketAddr> {
-        Ok(match self {
-            Stream::Tcp(stream) => SocketAddr::Tcp(
+        Ok(match &self.kind {
+            StreamKind::Tcp(stream) => SocketAddr::Tcp(
                 stream
                     .local_addr()
                     .context("Failed to fetch local address from TCP.")?,
             ),
-            Stream::Unix(stream) => SocketAddr::from(
+            StreamKind::Unix(stream) => SocketAddr::from(
                 stream
                     .local_addr()
                     .context("Failed to fetch local address from Unix.")?,
             ),
+            StreamKind::Tls(stream) => SocketAddr::Tcp(
+                stream
+                    .get_ref()
+                    .0
+                    .local_addr()
+                    .context("Failed to fetch local address from TLS.")?,
+            ),
         })
     }
+
+    /// Returns the number of bytes read from and written to the stream so far.
+    ///
+    /// For TLS connections these are the decrypted bytes, not the ones on the wire.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            bytes_read: self.stats.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.stats.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Splits the stream into owned halves, so reading and writing can happen in
+    /// independent tasks.
+    ///
+    /// The halves are not counted in the statistics of the stream.
+    pub fn split(self) -> Result<(StreamReader, StreamWriter)> {
+        Ok(match self.kind {
+            StreamKind::Tcp(stream) => {
+                let (reader, writer) = stream.into_split();
+                (StreamReader::Tcp(reader), StreamWriter::Tcp(writer))
+            }
+            StreamKind::Unix(stream) => {
+                let (reader, writer) = stream.into_split();
+                (StreamReader::Unix(reader), StreamWriter::Unix(writer))
+            }
+            StreamKind::Tls(stream) => {
+                // The halves of a TLS stream do not give access to the socket anymore
+                let peer = stream
+                    .get_ref()
+                    .0
+                    .peer_addr()
+                    .context("Failed to fetch peer address from TLS.")?;
+                let (reader, writer) = split(*stream);
+                (StreamReader::Tls(reader), StreamWriter::Tls(writer, peer))
+            }
+        })
+    }
+}
+
+impl Negotiated for Stream {
+    fn negotiated(&self) -> &NegotiatedVersion {
+        &self.negotiated
+    }
+}
+
+/// A plain stream waits forever, see [`TimedStream`](crate::network::TimedStream).
+impl Timed for Stream {
+    fn timeouts(&self) -> StreamTimeouts {
+        StreamTimeouts::default()
+    }
+}
+
+/// Reading half of a [`Stream`], see [`Stream::split`].
+#[derive(Debug)]
+pub enum StreamReader {
+    Tcp(tcp::OwnedReadHalf),
+    Unix(unix::OwnedReadHalf),
+    Tls(ReadHalf<TlsStream<TcpStream>>),
+}
+
+/// Writing half of a [`Stream`], see [`Stream::split`].
+#[derive(Debug)]
+pub enum StreamWriter {
+    Tcp(tcp::OwnedWriteHalf),
+    Unix(unix::OwnedWriteHalf),
+    /// The TLS half, with the address of the peer.
+    Tls(WriteHalf<TlsStream<TcpStream>>, std::net::SocketAddr),
+}
+
+impl AsyncRead for StreamReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match &mut *self {
+            StreamReader::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            StreamReader::Unix(s) => Pin::new(s).poll_read(cx, buf),
+            StreamReader::Tls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for StreamWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        match &mut *self {
+            StreamWriter::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            StreamWriter::Unix(s) => Pin::new(s).poll_write(cx, buf),
+            StreamWriter::Tls(s, _) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match &mut *self {
+            StreamWriter::Tcp(s) => Pin::new(s).poll_flush(cx),
+            StreamWriter::Unix(s) => Pin::new(s).poll_flush(cx),
+            StreamWriter::Tls(s, _) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match &mut *self {
+            StreamWriter::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            StreamWriter::Unix(s) => Pin::new(s).poll_shutdown(cx),
+            StreamWriter::Tls(s, _) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl StreamWriter {
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(match self {
+            StreamWriter::Tcp(half) => SocketAddr::Tcp(
+                half.peer_addr()
+                    .context("Failed to fetch peer address from TCP.")?,
+            ),
+            StreamWriter::Unix(half) => SocketAddr::from(
+                half.peer_addr()
+                    .context("Failed to fetch peer address from Unix.")?,
+            ),
+            StreamWriter::Tls(_, peer) => SocketAddr::Tcp(*peer),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::{Stream, StreamConfig, StreamKind};
+    use crate::network::SocketAddr;
+
+    async fn listen() -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sock = SocketAddr::from(listener.local_addr().unwrap());
+        (listener, sock)
+    }
+
+    fn nodelay(stream: &Stream) -> bool {
+        let StreamKind::Tcp(stream) = &stream.kind else {
+            panic!("Expected a plain TCP stream, got {stream:?}");
+        };
+        stream.nodelay().unwrap()
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_disable_nagle() {
+        let (_listener, sock) = listen().await;
+        let stream = Stream::connect(sock).await.unwrap();
+        assert!(nodelay(&stream));
+    }
+
+    #[tokio::test]
+    async fn the_config_is_applied() {
+        let (_listener, sock) = listen().await;
+
+        let config = StreamConfig {
+            nodelay: false,
+            read_buf_size: Some(64 * 1024),
+            write_buf_size: Some(64 * 1024),
+        };
+        let stream = Stream::connect_with_config(sock.clone(), config)
+            .await
+            .unwrap();
+        assert!(!nodelay(&stream));
+
+        let config = StreamConfig {
+            read_buf_size: Some(usize::MAX),
+            ..StreamConfig::default()
+        };
+        let res = Stream::connect_with_config(sock, config).await;
+        assert!(
+            res.as_ref()
+                .is_err_and(|e| e.to_string().contains("too large")),
+            "An oversized receive buffer should be refused, got {res:?}"
+        );
+    }
 }
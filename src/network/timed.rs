@@ -0,0 +1,150 @@
+//! # Timed Stream
+//!
+//! A [`Stream`] whose message reads and writes fail when they take too long, so an
+//! unresponsive peer cannot block a task forever.
+
+use std::{
+    env::var,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::warn;
+
+#[cfg(feature = "stats")]
+use crate::network::StreamStats;
+use crate::{
+    env_variables::EnvVariable,
+    network::{Negotiated, NegotiatedVersion, SocketAddr, Stream},
+};
+
+/// Read and write timeouts of a [`TimedStream`], `None` waits forever.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StreamTimeouts {
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
+impl StreamTimeouts {
+    /// Reads the timeouts from [`EnvVariable::ReadTimeout`] and
+    /// [`EnvVariable::WriteTimeout`], in milliseconds.
+    pub fn from_env() -> Self {
+        Self {
+            read: Self::parse(EnvVariable::ReadTimeout),
+            write: Self::parse(EnvVariable::WriteTimeout),
+        }
+    }
+
+    fn parse(variable: EnvVariable) -> Option<Duration> {
+        let content = var(variable.to_string()).ok()?;
+        content
+            .parse()
+            .map(Duration::from_millis)
+            .inspect_err(|e| warn!("Failed to parse the content of {variable}: {e}"))
+            .ok()
+    }
+}
+
+/// A [`Stream`] with read and write timeouts.
+///
+/// The timeouts bound each message read and write made through the helpers of this
+/// module, see [`Timed`]. Every operation gets the whole timeout, so one cancelled
+/// midway does not shorten the next.
+#[derive(Debug)]
+pub struct TimedStream {
+    stream: Stream,
+    timeouts: StreamTimeouts,
+}
+
+impl TimedStream {
+    pub fn new(stream: Stream, timeouts: StreamTimeouts) -> Self {
+        Self { stream, timeouts }
+    }
+
+    pub fn set_read_timeout(&mut self, d: Duration) {
+        self.timeouts.read = Some(d);
+    }
+
+    pub fn set_write_timeout(&mut self, d: Duration) {
+        self.timeouts.write = Some(d);
+    }
+
+    /// See [`Stream::set_negotiated`].
+    pub fn set_negotiated(&mut self, negotiated: NegotiatedVersion) {
+        self.stream.set_negotiated(negotiated);
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    /// See [`Stream::stats`].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> StreamStats {
+        self.stream.stats()
+    }
+}
+
+/// A stream whose message reads and writes are bounded in time.
+///
+/// [`read_next_message`](crate::network::read_next_message) and
+/// [`write_message`](crate::network::write_message) wrap each of their reads and writes
+/// in a [`timeout`](tokio::time::timeout) of the matching duration.
+pub trait Timed {
+    /// Returns the timeouts applied to the operations on this stream.
+    fn timeouts(&self) -> StreamTimeouts;
+}
+
+impl<T: Timed + ?Sized> Timed for &mut T {
+    fn timeouts(&self) -> StreamTimeouts {
+        (**self).timeouts()
+    }
+}
+
+impl Timed for TimedStream {
+    fn timeouts(&self) -> StreamTimeouts {
+        self.timeouts
+    }
+}
+
+impl Negotiated for TimedStream {
+    fn negotiated(&self) -> &NegotiatedVersion {
+        self.stream.negotiated()
+    }
+}
+
+impl AsyncRead for TimedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TimedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,137 @@
+//! # TLS
+//!
+//! Mutual TLS over the TCP connections, enabled when `DAKE_TLS_CERT`,
+//! `DAKE_TLS_KEY` and `DAKE_TLS_CA` are set. Without them, connections stay plaintext.
+//!
+//! Every host presents the certificate `DAKE_TLS_CERT` and only accepts peers whose
+//! certificate is signed by the authority `DAKE_TLS_CA`. Daemons are reached by IP,
+//! so their certificate must hold their IP address as a subject alternative name.
+
+use std::{env::var, net::IpAddr, path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use once_cell::sync::OnceCell;
+use tokio::{net::TcpStream, time::timeout};
+use tokio_rustls::{
+    TlsAcceptor, TlsConnector, TlsStream,
+    rustls::{
+        ClientConfig, RootCertStore, ServerConfig,
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+};
+use tracing::info;
+
+use crate::{constants::HANDSHAKE_TIMEOUT, env_variables::EnvVariable};
+
+static TLS_CONFIG: OnceCell<Option<TlsConfig>> = OnceCell::new();
+
+/// TLS settings shared by every connection of the process.
+pub struct TlsConfig {
+    connector: TlsConnector,
+    acceptor: TlsAcceptor,
+}
+
+impl TlsConfig {
+    /// Returns the TLS settings of the process, read from the environment on first use.
+    ///
+    /// Returns `None` if no TLS variable is set.
+    ///
+    /// # Errors
+    /// Fails if only some of the variables are set, or if a file cannot be loaded.
+    pub fn get() -> Result<Option<&'static TlsConfig>> {
+        TLS_CONFIG
+            .get_or_try_init(Self::from_env)
+            .map(Option::as_ref)
+    }
+
+    fn from_env() -> Result<Option<Self>> {
+        let paths = [
+            EnvVariable::TlsCert,
+            EnvVariable::TlsKey,
+            EnvVariable::TlsCa,
+        ]
+        .map(|v| var(v.to_string()).ok().map(PathBuf::from));
+        let [cert, key, ca] = match paths {
+            [None, None, None] => return Ok(None),
+            [Some(cert), Some(key), Some(ca)] => [cert, key, ca],
+            _ => bail!(
+                "{}, {} and {} must be set together.",
+                EnvVariable::TlsCert,
+                EnvVariable::TlsKey,
+                EnvVariable::TlsCa
+            ),
+        };
+
+        let certs = CertificateDer::pem_file_iter(&cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .context(format!(
+                "Failed to load the certificate {}.",
+                cert.display()
+            ))?;
+        let key = PrivateKeyDer::from_pem_file(&key)
+            .context(format!("Failed to load the private key {}.", key.display()))?;
+
+        let mut roots = RootCertStore::empty();
+        for ca_cert in CertificateDer::pem_file_iter(&ca)
+            .context(format!("Failed to load the authority {}.", ca.display()))?
+        {
+            roots
+                .add(ca_cert.context(format!("Invalid authority {}.", ca.display()))?)
+                .context(format!("Invalid authority {}.", ca.display()))?;
+        }
+        let roots = Arc::new(roots);
+        let provider = Arc::new(default_provider());
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+            .build()
+            .context("Failed to build the client certificate verifier.")?;
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs.clone(), key.clone_key())
+            .context("Invalid server certificate or key.")?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .context("Invalid client certificate or key.")?;
+
+        info!("TLS enabled with the certificate {}", cert.display());
+        Ok(Some(Self {
+            connector: TlsConnector::from(Arc::new(client)),
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+        }))
+    }
+
+    /// Runs the client side of the handshake with the daemon at `ip`.
+    ///
+    /// Fails if the handshake takes longer than [`HANDSHAKE_TIMEOUT`].
+    pub async fn connect(&self, ip: IpAddr, stream: TcpStream) -> Result<TlsStream<TcpStream>> {
+        let handshake = self
+            .connector
+            .connect(ServerName::IpAddress(ip.into()), stream);
+        Ok(timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .context(format!(
+                "TLS handshake with {ip} timed out after {HANDSHAKE_TIMEOUT:?}."
+            ))?
+            .context(format!("TLS handshake with {ip} failed."))?
+            .into())
+    }
+
+    /// Runs the server side of the handshake on an accepted connection.
+    ///
+    /// Fails if the handshake takes longer than [`HANDSHAKE_TIMEOUT`], so a peer that
+    /// never speaks does not hold its connection forever.
+    pub async fn accept(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>> {
+        Ok(timeout(HANDSHAKE_TIMEOUT, self.acceptor.accept(stream))
+            .await
+            .context(format!(
+                "TLS handshake with an incoming connection timed out after {HANDSHAKE_TIMEOUT:?}."
+            ))?
+            .context("TLS handshake with an incoming connection failed.")?
+            .into())
+    }
+}
@@ -5,121 +5,250 @@
 
 use std::{
     env::var,
-    io::ErrorKind,
-    net::{IpAddr, UdpSocket},
+    fmt::Display,
+    io::{ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, UdpSocket},
     path::PathBuf,
     process::Command,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    spawn,
     time::{sleep, timeout},
 };
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    constants::{DAEMON_RETRY_INTERVAL, DAEMON_STARTUP_TIMEOUT},
+    constants::{
+        DAEMON_RETRY_INTERVAL, DAEMON_STARTUP_TIMEOUT, DEFAULT_COMPRESS_THRESHOLD, MAX_MESSAGE_SIZE,
+    },
+    daemon::DaemonConfig,
     dec, enc,
     env_variables::EnvVariable,
     network::{
-        DAEMON_UNIX_SOCKET, DEFAULT_PORT, Message, MessageHeader, MessageKind, MessageTrait,
-        SocketAddr, Stream,
+        CapabilityFlag, DAEMON_UNIX_SOCKET, Message, MessageHeader, MessageKind, MessageTrait,
+        Negotiated, Pool, PooledStream, SocketAddr, Stream, StreamTimeouts, Timed, TimedStream,
+        handshake,
     },
-    utils::get_dake_path,
+    utils::get_dake_binary_path,
 };
 
 /// Write a message on a given stream.
-pub async fn write_message<M: MessageTrait, S: AsyncWriteExt + Unpin>(
+///
+/// Payloads larger than [`get_compress_threshold`] are sent zstd-compressed. The write
+/// fails if it takes longer than the write timeout of the stream, see [`Timed`].
+pub async fn write_message<M: MessageTrait, S: AsyncWriteExt + Negotiated + Timed + Unpin>(
     stream: &mut S,
     msg: Message<M>,
 ) -> Result<()> {
     info!("Writing a new message : {:?}", msg.get_kind());
 
-    let enc_msg = MessageHeader::wrap(enc!(msg)?, msg.get_kind())
+    let mut payload = enc!(msg)?;
+    let mut flags = 0;
+    if stream.negotiated().supports(CapabilityFlag::Compression)
+        && msg.inner.is_compressible()
+        && payload.len() > get_compress_threshold()
+    {
+        let compressed =
+            zstd::encode_all(payload.as_slice(), 0).context("Failed to compress the message.")?;
+        info!(
+            "Compressed the message from {} to {} bytes",
+            payload.len(),
+            compressed.len()
+        );
+        payload = compressed;
+        flags |= MessageHeader::COMPRESSED;
+    }
+
+    let enc_msg = MessageHeader::wrap(payload, msg.get_kind(), flags)
         .context("Failed to compute the message header.")?;
-    stream
-        .write_all(&enc_msg)
-        .await
-        .context("When writing on the stream")?;
-    stream
-        .flush()
-        .await
-        .context("Failed to flush on the stream")
+    let write_timeout = stream.timeouts().write;
+    let write = async {
+        stream
+            .write_all(&enc_msg)
+            .await
+            .context("When writing on the stream")?;
+        stream
+            .flush()
+            .await
+            .context("Failed to flush on the stream")
+    };
+    match write_timeout {
+        Some(duration) => timeout(duration, write)
+            .await
+            .map_err(|_| anyhow!("write_message timed out after {duration:?}"))?,
+        None => write.await,
+    }
 }
 
-/// Connect with tcp on the given socket.
-pub async fn connect(sock: SocketAddr) -> Result<Stream> {
+/// Connects to the daemon listening on `sock` and negotiates the protocol version with it,
+/// see [`handshake`]. The negotiated version is kept on the returned stream.
+///
+/// Without `timeouts`, the ones of the environment are used, see [`StreamTimeouts::from_env`].
+pub async fn connect(sock: SocketAddr, timeouts: Option<StreamTimeouts>) -> Result<TimedStream> {
     info!("Attempting to connect to socket {}.", sock);
     let stream = Stream::connect(sock.clone())
         .await
         .context("When connecting on the stream.")?;
+    let mut stream = TimedStream::new(stream, timeouts.unwrap_or_else(StreamTimeouts::from_env));
+    let version = handshake(&mut stream)
+        .await
+        .context(format!("The handshake with {sock} failed."))?;
 
-    info!("Connected to {}", sock);
+    info!(
+        "Connected to {} with protocol version {}",
+        sock, version.version
+    );
+    stream.set_negotiated(version);
     Ok(stream)
 }
 
 /// Sends a serialized message to the given socket.
 /// Returns the stream used to send the message.
+///
+/// With a `pool`, the connection is taken from it and goes back to it once the returned
+/// stream is dropped. If the pooled connection turns out to be broken, the message is
+/// sent once more on a fresh one. Without a pool, the connection is closed on drop.
+///
 /// Returns an error if connection or writing fails.
-pub async fn send_message<M: MessageTrait>(msg: Message<M>, sock: SocketAddr) -> Result<Stream> {
+pub async fn send_message<M: MessageTrait>(
+    msg: Message<M>,
+    sock: SocketAddr,
+    pool: Option<&Pool>,
+) -> Result<PooledStream> {
     info!("Attempting to send a message to socket {}", sock);
-    let mut stream = connect(sock.clone()).await?;
-    write_message(&mut stream, msg).await?;
+    let stream = match pool {
+        Some(pool) => {
+            let mut stream = pool.get(sock.clone()).await?;
+            if let Err(e) = write_message(&mut stream, msg.clone()).await {
+                warn!("Pooled connection to {sock} is broken, reconnecting: {e:?}");
+                stream = pool.open(sock.clone()).await?;
+                write_message(&mut stream, msg).await?;
+            }
+            stream
+        }
+        None => {
+            let mut stream = PooledStream::standalone(sock.clone()).await?;
+            write_message(&mut stream, msg).await?;
+            stream
+        }
+    };
     info!("Successfully sent message to {}", sock);
     Ok(stream)
 }
 
+/// Sends a message to the given socket and reads the single response of kind `response_kind`.
+///
+/// The whole exchange, connection included, is bounded by `timeout`.
+///
+/// # Errors
+/// Returns an error on timeout, if the connection closes before the response, or if
+/// the response cannot be decoded as `Resp`.
+pub async fn send_message_and_await_response<Req: MessageTrait, Resp: DeserializeOwned>(
+    msg: Message<Req>,
+    sock: SocketAddr,
+    response_kind: MessageKind,
+    timeout: Duration,
+) -> Result<Resp> {
+    let exchange = async {
+        let mut stream = send_message(msg, sock.clone(), None).await?;
+        let response = read_next_message(&mut stream, response_kind, None)
+            .await?
+            .context(format!("{sock} closed the stream before responding."))?;
+        dec!(response, Resp).context(format!("Failed to decode the response of {sock}."))
+    };
+
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .context(format!("{sock} did not respond within {timeout:?}."))?
+}
+
+/// Reads and parses the environment variable `variable`, logging a warning if it is
+/// set but cannot be parsed.
+pub(crate) fn parse_env_var<T>(variable: impl Display) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let content = var(variable.to_string())
+        .context(format!("The environment variable {variable} is not set."))?;
+    content.parse::<T>().map_err(|e| {
+        warn!("Failed to parse the content of {variable}: {e}");
+        anyhow!("Failed to parse the content of {variable}: {e}")
+    })
+}
+
+static COMPRESS_THRESHOLD: OnceCell<usize> = OnceCell::new();
+
+/// Returns the payload size from which messages are compressed, the content of
+/// [`EnvVariable::CompressThreshold`] if set, [`DEFAULT_COMPRESS_THRESHOLD`] otherwise.
+///
+/// The variable is read by the first call only.
+fn get_compress_threshold() -> usize {
+    *COMPRESS_THRESHOLD.get_or_init(|| {
+        parse_env_var(EnvVariable::CompressThreshold).unwrap_or(DEFAULT_COMPRESS_THRESHOLD)
+    })
+}
+
+/// Returns how long to wait for a freshly spawned daemon, the content of
+/// [`EnvVariable::DaemonConnectTimeout`] in milliseconds if set,
+/// [`DAEMON_STARTUP_TIMEOUT`] otherwise.
+fn get_daemon_connect_timeout() -> Duration {
+    parse_env_var(EnvVariable::DaemonConnectTimeout)
+        .map(Duration::from_millis)
+        .unwrap_or(DAEMON_STARTUP_TIMEOUT)
+}
+
+/// Returns the port of the daemon: the content of [`EnvVariable::DaemonPort`] if set,
+/// the port of the daemon config otherwise.
 pub fn get_daemon_port() -> u16 {
-    var(EnvVariable::DaemonPort.to_string())
-        .context("Failed to get the daemon port with environment variable.")
-        .and_then(|port| {
-            let err = format!(
-                "Failed to parse the content of {} as an integer.",
-                EnvVariable::DaemonPort
-            );
-            let res = port.parse::<u16>().context(err.clone());
-            if let Err(e) = &res {
-                warn!("{err} {e}");
-            }
-            res
-        })
-        .unwrap_or(DEFAULT_PORT)
+    parse_env_var(EnvVariable::DaemonPort).unwrap_or_else(|_| DaemonConfig::load_listen_port())
 }
 
 pub fn get_daemon_ip() -> Result<IpAddr> {
-    var(EnvVariable::DaemonIp.to_string())
-        .context("Failed to get the ip with environment variable.")
-        .and_then(|ip| {
-            let err = format!(
-                "Failed to parse the content of {} as an ip.",
-                EnvVariable::DaemonIp
-            );
-            let res = ip.parse::<IpAddr>().context(err.clone());
-            if let Err(e) = &res {
-                warn!("{err} {e}");
-            }
-            res
-        })
-        .or_else(|_| {
-            let socket = UdpSocket::bind("0.0.0.0:0")
-                .context("Failed to bind on udp to get the default daemon address.")?;
-            socket.connect("8.8.8.8:80")?;
-            Ok(socket
-                .local_addr()
-                .context("Failed to fetch local address on the UDP socket.")?
-                .ip())
-        })
+    parse_env_var(EnvVariable::DaemonIp).or_else(|_| {
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .context("Failed to bind on udp to get the default daemon address.")?;
+        socket.connect("8.8.8.8:80")?;
+        Ok(socket
+            .local_addr()
+            .context("Failed to fetch local address on the UDP socket.")?
+            .ip())
+    })
+}
+
+/// Returns the IP the daemon listener binds on: the content of
+/// [`EnvVariable::DaemonIp`] if set, all interfaces otherwise.
+pub fn get_daemon_bind_ip() -> IpAddr {
+    parse_env_var(EnvVariable::DaemonIp).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
 }
 
-/// Returns the daemon's TCP socket address based on environment variables
-/// or defaults. If IP is missing, returns an error.
-/// If port is missing, uses DEFAULT_PORT.
+/// Returns the daemon's TCP socket address.
+///
+/// [`EnvVariable::DaemonSocket`] is used if set, otherwise the address is built from
+/// the daemon ip and port. If the IP cannot be determined, returns an error.
+/// The port comes from [`get_daemon_port`].
+///
+/// The Unix socket of the daemon is fixed, see [`get_daemon_unix_sock`], so
+/// [`EnvVariable::DaemonSocket`] must hold a TCP address.
 pub fn get_daemon_tcp_sock() -> Result<SocketAddr> {
+    let socket_var = EnvVariable::DaemonSocket.to_string();
+    if var(&socket_var).is_ok() {
+        let sock = SocketAddr::from_env(&socket_var)?;
+        if !sock.is_tcp() {
+            bail!("{socket_var} must hold a TCP address, got {sock}.");
+        }
+        return Ok(sock);
+    }
     let ip = get_daemon_ip()?;
     let port: u16 = get_daemon_port();
-    Ok(SocketAddr::new_tcp(ip, port))
+    SocketAddr::new_tcp(ip, port)
 }
 
 /// Returns the daemon socket address using the DAEMON_UNIX_SOCKET constant.
@@ -127,19 +256,22 @@ pub fn get_daemon_unix_sock() -> Result<SocketAddr> {
     SocketAddr::new_unix(PathBuf::from(DAEMON_UNIX_SOCKET))
 }
 
-/// Connect to the daemon, starting it if not already running.
+/// Connect to the daemon, starting it if not already running, and negotiates the
+/// protocol version with it.
 ///
 /// If the daemon is not active (connection refused), this function:
 /// - Spawns the daemon (`dake daemon`)
-/// - Waits up to 1s for it to start
-/// - Retries connection
+/// - Retries connection with an exponential backoff, see [`connect_with_backoff`]
 ///
 /// # Errors
 /// Returns an error if the daemon cannot be started or contacted.
 #[tracing::instrument]
-pub async fn connect_with_daemon_or_start_it(daemon_addr: SocketAddr) -> Result<Stream> {
-    match connect(daemon_addr.clone()).await {
-        Ok(stream) => Ok(stream),
+pub async fn connect_with_daemon_or_start_it(
+    daemon_addr: SocketAddr,
+    timeouts: Option<StreamTimeouts>,
+) -> Result<TimedStream> {
+    match connect(daemon_addr.clone(), timeouts).await {
+        Ok(connection) => Ok(connection),
         Err(e) => {
             for cause in e.chain() {
                 if let Some(e) = cause.downcast_ref::<tokio::io::Error>() {
@@ -147,7 +279,7 @@ pub async fn connect_with_daemon_or_start_it(daemon_addr: SocketAddr) -> Result<
                         info!("Daemon not running, attempting to spawn it...");
 
                         Command::new(
-                            get_dake_path()
+                            get_dake_binary_path()
                                 .context("Failed to fetch dake path when starting daemon.")?,
                         )
                         .arg("daemon")
@@ -156,34 +288,12 @@ pub async fn connect_with_daemon_or_start_it(daemon_addr: SocketAddr) -> Result<
 
                         info!("Daemon process spawned, waiting for availability...");
 
-                        let thread_daemon_addr = daemon_addr.clone();
-                        let connections = spawn(async move {
-                            loop {
-                                if let Ok(stream) = connect(thread_daemon_addr.clone()).await {
-                                    break stream;
-                                }
-                                sleep(DAEMON_RETRY_INTERVAL).await;
-                            }
-                        });
-
-                        return match timeout(DAEMON_STARTUP_TIMEOUT, connections).await {
-                            Ok(Ok(stream)) => {
-                                info!("Daemon is responsive, connected successfully");
-                                Ok(stream)
-                            }
-                            _ => match connect(daemon_addr).await {
-                                Ok(stream) => {
-                                    info!("Retried and successfully connected to the daemon");
-                                    Ok(stream)
-                                }
-                                Err(e) => {
-                                    error!(
-                                        "Failed to connect to the daemon after starting it: {e}"
-                                    );
-                                    bail!("Failed to connect to the daemon after starting it: {e}")
-                                }
-                            },
-                        };
+                        return connect_with_backoff(
+                            daemon_addr,
+                            timeouts,
+                            get_daemon_connect_timeout(),
+                        )
+                        .await;
                     } else {
                         warn!("Failed to connect to daemon for a non expected reason: {e:?}");
                     }
@@ -194,34 +304,149 @@ pub async fn connect_with_daemon_or_start_it(daemon_addr: SocketAddr) -> Result<
     }
 }
 
+/// Connects to a daemon that was just spawned, retrying until it listens.
+///
+/// The delay between two attempts starts at [`DAEMON_RETRY_INTERVAL`] and doubles after
+/// each failure, the attempts stop once `budget` is spent.
+async fn connect_with_backoff(
+    daemon_addr: SocketAddr,
+    timeouts: Option<StreamTimeouts>,
+    budget: Duration,
+) -> Result<TimedStream> {
+    let start = Instant::now();
+    let mut delay = DAEMON_RETRY_INTERVAL;
+    let mut attempt = 1;
+    loop {
+        match connect(daemon_addr.clone(), timeouts).await {
+            Ok(connection) => {
+                info!("Daemon is responsive, connected after {attempt} attempts");
+                return Ok(connection);
+            }
+            Err(e) => debug!("Connection attempt {attempt} to the daemon failed: {e}"),
+        }
+
+        let remaining = budget.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            error!(
+                "Failed to connect to the daemon after starting it, {attempt} attempts in {budget:?}"
+            );
+            bail!(
+                "Failed to connect to the daemon after starting it, it did not answer within {budget:?}."
+            )
+        }
+        sleep(delay.min(remaining)).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
 /// Reads the next message from a TCP stream.
 ///
 /// This function:
 /// 1. Reads a [`MessageHeader`] from the stream.
 /// 2. Reads the payload based on the size in the header.
 /// 3. Verifies that the expected [`MessageKind`] matches the header.
+/// 4. Decompresses the payload if the header flags it as compressed.
 ///
 /// # Arguments
 /// * `tcp_stream` - The TCP stream to read from.
 /// * `kind` - The expected message kind.
+/// * `timeout` - If set, bounds the header read and the payload read separately.
+///   Defaults to the read timeout of the stream, see [`Timed`].
 ///
 /// # Returns
 /// Returns `Ok(Some(Vec<u8>))` with the raw message payload, or `Ok(None)` if
 /// the stream was closed.
 ///
 /// # Errors
-/// Returns an error if deserialization fails, if the message size is invalid,
-/// or if the message kind does not match.
-pub async fn read_next_message<S: AsyncReadExt + Unpin>(
+/// Returns an error if deserialization fails, if the message size is invalid or above
+/// [`MAX_MESSAGE_SIZE`], if the message kind does not match, or if a read timed out.
+pub async fn read_next_message<S: AsyncReadExt + Timed + Unpin>(
     stream: &mut S,
     kind: MessageKind,
+    timeout: Option<Duration>,
 ) -> Result<Option<Vec<u8>>> {
+    let Some(header) = read_header(stream, timeout).await? else {
+        return Ok(None);
+    };
+
+    // Read message payload
+    let mut message = vec![0; header.size as usize];
+    if let Err(e) = read_exact_within(stream, &mut message, timeout).await? {
+        return Err(payload_error(e));
+    }
+
+    check_kind(&header, kind)?;
+    if header.is_compressed() {
+        return decompress(&message).map(Some);
+    }
+    Ok(Some(message))
+}
+
+/// Same as [`read_next_message`], but reads the payload in `buffer` instead of a fresh
+/// allocation.
+///
+/// The returned [`Bytes`] is a view on `buffer`, so once it is dropped the next call
+/// reuses the same memory. Callers keep one buffer per connection and call `.to_vec()` if they need an owned payload.
+pub async fn read_next_message_zero_copy<S: AsyncReadExt + Timed + Unpin>(
+    stream: &mut S,
+    kind: MessageKind,
+    buffer: &mut BytesMut,
+    timeout: Option<Duration>,
+) -> Result<Option<Bytes>> {
+    let Some(header) = read_header(stream, timeout).await? else {
+        return Ok(None);
+    };
+
+    // Read message payload
+    buffer.clear();
+    buffer.resize(header.size as usize, 0);
+    if let Err(e) = read_exact_within(stream, &mut buffer[..], timeout).await? {
+        return Err(payload_error(e));
+    }
+
+    check_kind(&header, kind)?;
+    if header.is_compressed() {
+        // The decompressed payload cannot live in `buffer`, it is the only copy made.
+        return decompress(&buffer[..]).map(|payload| Some(Bytes::from(payload)));
+    }
+    Ok(Some(buffer.split().freeze()))
+}
+
+/// Decompresses a payload flagged with [`MessageHeader::COMPRESSED`].
+///
+/// Like the payload itself, the decompressed message may not exceed [`MAX_MESSAGE_SIZE`].
+fn decompress(payload: &[u8]) -> Result<Vec<u8>> {
+    let decoder = zstd::Decoder::new(payload).context("Failed to start the decompression.")?;
+    let mut decompressed = Vec::new();
+    decoder
+        .take(MAX_MESSAGE_SIZE + 1)
+        .read_to_end(&mut decompressed)
+        .context("Failed to decompress the message.")?;
+    if decompressed.len() as u64 > MAX_MESSAGE_SIZE {
+        bail!("The decompressed message exceeds the maximum of {MAX_MESSAGE_SIZE} bytes.");
+    }
+    info!(
+        "Decompressed the message from {} to {} bytes",
+        payload.len(),
+        decompressed.len()
+    );
+    Ok(decompressed)
+}
+
+/// Reads and decodes the next [`MessageHeader`], returns `None` if the stream was closed.
+async fn read_header<S: AsyncReadExt + Timed + Unpin>(
+    stream: &mut S,
+    timeout: Option<Duration>,
+) -> Result<Option<MessageHeader>> {
     let header_length =
         MessageHeader::get_header_length().context("Failed to compute header length.")?;
     let mut header = vec![0; header_length];
 
-    // Read message header
-    if stream.read_exact(&mut header).await.is_err() {
+    if read_exact_within(stream, &mut header, timeout)
+        .await?
+        .is_err()
+    {
         info!("Connection closed while trying to read header");
         return Ok(None);
     }
@@ -230,31 +455,121 @@ pub async fn read_next_message<S: AsyncReadExt + Unpin>(
     let header: MessageHeader = dec!(header).context("Failed to decode the MessageHeader.")?;
 
     info!(
-        "Received message header with size={} and kind={:?}",
-        header.size, header.kind
+        "Received message header with size={}, kind={:?} and flags={:#04b}",
+        header.size, header.kind, header.flags
     );
+    // The payload buffer is sized from the header, a bogus size must not allocate it.
+    if header.size > MAX_MESSAGE_SIZE {
+        bail!(
+            "The message size {} exceeds the maximum of {MAX_MESSAGE_SIZE} bytes.",
+            header.size
+        );
+    }
+    Ok(Some(header))
+}
 
-    // Read message payload
-    let mut message = vec![0; header.size as usize];
-    if let Err(e) = stream.read_exact(&mut message).await {
-        if matches!(e.kind(), ErrorKind::UnexpectedEof) {
-            error!("Header size did not match actual message size");
-            bail!("The message size and the header annotated size doesn't match.");
-        } else {
-            error!("Error when reading message: {}", e);
-            bail!("Error when reading a message.");
-        }
+fn payload_error(e: std::io::Error) -> anyhow::Error {
+    if matches!(e.kind(), ErrorKind::UnexpectedEof) {
+        error!("Header size did not match actual message size");
+        anyhow!("The message size and the header annotated size doesn't match.")
+    } else {
+        error!("Error when reading message: {}", e);
+        anyhow!("Error when reading a message.")
     }
+}
 
-    // Check message kind
+fn check_kind(header: &MessageHeader, kind: MessageKind) -> Result<()> {
     if kind != header.kind {
         error!(
             "Expected message kind {:?}, but received {:?}",
             kind, header.kind
         );
         bail!("The asked and received kind doesn't match.");
-    } else {
-        info!("Successfully read message of kind {:?}", kind);
-        Ok(Some(message))
+    }
+    info!("Successfully read message of kind {:?}", kind);
+    Ok(())
+}
+
+/// Shorthand for [`read_next_message`] bounded by `duration`.
+pub async fn read_next_message_with_timeout<S: AsyncReadExt + Timed + Unpin>(
+    stream: &mut S,
+    kind: MessageKind,
+    duration: Duration,
+) -> Result<Option<Vec<u8>>> {
+    read_next_message(stream, kind, Some(duration)).await
+}
+
+/// Fills `buf` from `stream`, failing if it takes longer than `duration`, or than the
+/// read timeout of `stream` without `duration`.
+///
+/// The outer result reports the timeout, the inner one the io error.
+async fn read_exact_within<S: AsyncReadExt + Timed + Unpin>(
+    stream: &mut S,
+    buf: &mut [u8],
+    duration: Option<Duration>,
+) -> Result<std::io::Result<usize>> {
+    match duration.or(stream.timeouts().read) {
+        Some(duration) => timeout(duration, stream.read_exact(buf))
+            .await
+            .map_err(|_| anyhow!("read_next_message timed out after {:?}", duration)),
+        None => Ok(stream.read_exact(buf).await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::{net::TcpListener, spawn, time::sleep};
+
+    use super::connect_with_backoff;
+    use crate::{
+        dec,
+        network::{
+            DaemonMessage, Message, MessageKind, SocketAddr, Stream, hello, read_next_message,
+            write_message,
+        },
+    };
+
+    /// Returns the address of a port nothing listens on.
+    fn closed_port() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        SocketAddr::from(listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn late_daemons_are_reached() {
+        let sock = closed_port();
+        let addr = sock.to_string();
+        spawn(async move {
+            sleep(Duration::from_millis(200)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            let mut stream = Stream::from(listener.accept().await.unwrap().0);
+            let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+                .await
+                .unwrap()
+                .unwrap();
+            let msg: Message<DaemonMessage> = dec!(msg).unwrap();
+            write_message(&mut stream, Message::new(hello(), msg.pid))
+                .await
+                .unwrap();
+        });
+
+        let stream = connect_with_backoff(sock, None, Duration::from_secs(5)).await;
+        assert!(stream.is_ok(), "{:?}", stream.err());
+    }
+
+    #[tokio::test]
+    async fn retries_stop_once_the_budget_is_spent() {
+        let budget = Duration::from_millis(300);
+        let start = Instant::now();
+
+        let res = connect_with_backoff(closed_port(), None, budget).await;
+        assert!(res.is_err());
+        let elapsed = start.elapsed();
+        assert!(
+            (budget..budget * 3).contains(&elapsed),
+            "Gave up after {elapsed:?}"
+        );
     }
 }
@@ -2,6 +2,7 @@ use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 use std::{fmt, path::PathBuf, str::FromStr};
 use tracing::{info, warn};
+use uuid::fmt::Hyphenated;
 
 use crate::daemon::DaemonId;
 
@@ -24,6 +25,21 @@ impl ProcessId {
         Self { id: 0, project_id }
     }
 
+    /// Replaces the numeric id of the process.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn with_id(self, id: u64) -> Self {
+        Self { id, ..self }
+    }
+
+    /// Creates a process of the local test project, see [`ProjectId::test_local`].
+    #[cfg(any(test, feature = "testing"))]
+    pub fn test_local(id: u64) -> Self {
+        Self {
+            id,
+            project_id: ProjectId::test_local(),
+        }
+    }
+
     pub fn is_process_less(&self) -> bool {
         self.id == 0
     }
@@ -43,7 +59,7 @@ impl ProcessId {
     /// Returns the socket address associated with this process.
     #[inline]
     pub fn daemon_id(&self) -> DaemonId {
-        self.project_id.daemon_id.clone()
+        self.project_id.daemon_id
     }
 
     /// Returns the numeric ID of this process.
@@ -89,6 +105,15 @@ impl ProjectId {
 
         Self { daemon_id, path }
     }
+
+    /// Creates a project of the local daemon located in `/tmp/test`.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn test_local() -> Self {
+        Self {
+            daemon_id: DaemonId::local(),
+            path: PathBuf::from("/tmp/test"),
+        }
+    }
 }
 
 impl fmt::Display for ProjectId {
@@ -101,8 +126,10 @@ impl FromStr for ProjectId {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
+        // The daemon id is a hyphenated UUID, the path starts after its fixed length.
         let (daemon_id, path_str) = s
-            .split_once('-')
+            .split_at_checked(Hyphenated::LENGTH)
+            .and_then(|(daemon_id, rest)| Some((daemon_id, rest.strip_prefix('-')?)))
             .ok_or_else(|| anyhow!("invalid ProjectId format, expected '<daemon_id>-<path>'"))?;
 
         let daemon_id: DaemonId = daemon_id
@@ -1,14 +1,79 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use directories::ProjectDirs;
 use std::path::PathBuf;
-use std::{env::var, path::Path};
-use tracing::{error, info};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env::var, fs::File, path::Path};
+use tracing::{error, info, warn};
 use which::which;
 
 use crate::env_variables::EnvVariable;
 
+/// Returns the current time in milliseconds since the Unix epoch, used to timestamp logs.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Options of `MAKEFLAGS` pointing to the jobserver of a parent make.
+const JOBSERVER_FLAGS: [&str; 2] = ["--jobserver-auth=", "--jobserver-fds="];
+
+/// Returns whether `word`, from `MAKEFLAGS` or the make arguments, points to the jobserver
+/// of a parent make, which only the processes of that make can use.
+pub fn is_jobserver_flag(word: &str) -> bool {
+    JOBSERVER_FLAGS.iter().any(|flag| word.starts_with(flag))
+}
+
+/// Computes the blake3 hash of the file at `path`, without loading it whole in memory.
+///
+/// This is blocking, async callers should run it in `spawn_blocking`.
+pub fn file_checksum(path: &Path) -> Result<[u8; 32]> {
+    Ok(*file_hasher(path)?.finalize().as_bytes())
+}
+
+/// Returns a blake3 hasher fed with the content of the file at `path`, to hash more data after it.
+///
+/// This is blocking, async callers should run it in `spawn_blocking`.
+pub fn file_hasher(path: &Path) -> Result<blake3::Hasher> {
+    let file = File::open(path).context(format!("Failed to open {path:?} to hash it."))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(file)
+        .context(format!("Failed to hash {path:?}."))?;
+    Ok(hasher)
+}
+
+/// Returns the base path of Dake's working directory.
+///
+/// In order of preference:
+/// 1. The content of `DAKE_SPACE_PATH`.
+/// 2. The local data directory given by [`directories`] (`~/.local/share/dake` on Linux).
+/// 3. `~/.local/share/dake` on Unix, or `%APPDATA%\dake` on Windows.
+///
+/// # Errors
+/// Fails if none of them can be determined.
+pub fn get_dake_path() -> Result<PathBuf> {
+    if let Ok(path) = var(EnvVariable::DakeSpacePath.to_string()) {
+        return Ok(PathBuf::from(path));
+    }
+    if let Some(dirs) = ProjectDirs::from("com", "zivo_martin", "dake") {
+        return Ok(dirs.data_local_dir().to_path_buf());
+    }
+
+    warn!("Failed to determine the project directories, falling back to the default path.");
+    let base = if cfg!(windows) {
+        var("APPDATA").map(PathBuf::from)
+    } else {
+        var("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+    };
+    base.map(|base| base.join("dake"))
+        .context("Failed to fetch dake space path.")
+}
+
 /// Attempts to locate the DAKE binary on the system.
 /// Returns the absolute path to the binary if found, or an error otherwise.
-pub fn get_dake_path() -> Result<PathBuf> {
+pub fn get_dake_binary_path() -> Result<PathBuf> {
     info!("Attempting to resolve DAKE binary path...");
 
     // Retrieve environment variable or fall back to defaults
@@ -21,10 +86,11 @@ pub fn get_dake_path() -> Result<PathBuf> {
                 "release"
             }
         );
-        Path::new(&p)
-            .exists()
-            .then(|| p)
-            .unwrap_or_else(|| "dake".to_string())
+        if Path::new(&p).exists() {
+            p
+        } else {
+            "dake".to_string()
+        }
     });
 
     info!("Raw path string resolved to '{}'", path_str);
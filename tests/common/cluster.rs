@@ -118,10 +118,26 @@ impl Cluster {
     }
 
     pub async fn start_dake(&self, dest_path: PathBuf, id: &str, output: PathBuf) -> Result<()> {
+        self.start_dake_with_env(dest_path, id, output, &[]).await
+    }
+
+    /// Same as [`Cluster::start_dake`], with the variables of `env` set for dake.
+    pub async fn start_dake_with_env(
+        &self,
+        dest_path: PathBuf,
+        id: &str,
+        output: PathBuf,
+        env: &[(&str, &str)],
+    ) -> Result<()> {
         let mut path = PathBuf::from(LOG_DIR);
         path.push(&output);
 
-        container_exec(id, "dake", vec![], dest_path, Some(path), false)
+        let command: String = env
+            .iter()
+            .map(|(name, value)| format!("{name}='{value}' "))
+            .chain(["dake".to_string()])
+            .collect();
+        container_exec(id, &command, vec![], dest_path, Some(path), false)
             .await
             .context(format!("Failed to execute dake on {id}"))?;
         Ok(())
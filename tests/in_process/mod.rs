@@ -0,0 +1,60 @@
+//! Helpers shared by the integration tests talking to an in-process daemon.
+//!
+//! Each test binary uses only part of them.
+#![allow(dead_code)]
+
+use std::{net::TcpListener, path::PathBuf, time::Duration};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon::DaemonId,
+    network::{
+        DaemonMessage, Message, MessageKind, ProcessMessage, SocketAddr, connect,
+        send_message_and_await_response,
+    },
+    process_id::{ProcessId, ProjectId},
+};
+use tokio::time::sleep;
+
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns a port nothing listens on, for the daemon of the test.
+pub fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// Waits for the daemon to accept connections on `sock`.
+pub async fn wait_for_daemon(sock: SocketAddr) -> Result<()> {
+    for _ in 0..100 {
+        if connect(sock.clone(), None).await.is_ok() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    bail!("The in-process daemon never started listening on {sock}")
+}
+
+/// Returns the process-less id of the project built in `build_dir`.
+pub fn process_less(build_dir: PathBuf) -> ProcessId {
+    ProcessId::process_less(ProjectId::new(DaemonId::default(), build_dir))
+}
+
+/// Sends `msg` for the project of `build_dir` and returns the response of the daemon.
+pub async fn request(
+    sock: SocketAddr,
+    build_dir: PathBuf,
+    msg: DaemonMessage,
+) -> Result<Message<ProcessMessage>> {
+    let msg = Message::new(msg, process_less(build_dir));
+    send_message_and_await_response(msg, sock, MessageKind::ProcessMessage, RESPONSE_TIMEOUT).await
+}
+
+/// Registers a new process for the project of `build_dir` and returns its id.
+pub async fn fresh_pid(sock: SocketAddr, build_dir: PathBuf) -> Result<ProcessId> {
+    let msg = request(sock, build_dir, DaemonMessage::FreshId).await?;
+    ensure!(
+        matches!(msg.inner, ProcessMessage::FreshId),
+        "Expected a FreshId response, got {msg:?}"
+    );
+    Ok(msg.pid)
+}
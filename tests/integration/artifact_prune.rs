@@ -0,0 +1,61 @@
+use std::{
+    env::set_var,
+    fs::{File, create_dir, write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, ensure};
+use dake::{
+    daemon::fs::{artifact_ttl_prune, get_makefile_path},
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+
+/// Creates the folder `dir` holding a file of `size` bytes, last modified `age` ago.
+fn folder(dir: &Path, size: usize, age: Duration) -> Result<()> {
+    create_dir(dir)?;
+    write(dir.join("artifact"), vec![0; size])?;
+    File::open(dir)?.set_modified(SystemTime::now() - age)?;
+    Ok(())
+}
+
+#[test]
+fn prunes_only_expired_build_folders_and_artifacts() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let day = Duration::from_secs(24 * 60 * 60);
+    let space = space_dir.path();
+    folder(&space.join("expired"), 100, day)?;
+    folder(&space.join("recent"), 10, Duration::ZERO)?;
+    folder(&space.join("active"), 10, Duration::from_secs(5 * 60))?;
+    folder(&space.join("archives"), 10, day)?;
+    folder(&space.join("cas"), 10, day)?;
+    let stored = space.join("cas").join("stored");
+    write(&stored, vec![0; 1000])?;
+    File::options()
+        .append(true)
+        .open(&stored)?
+        .set_modified(SystemTime::now() - day)?;
+    let building = ProcessId::test_local(1);
+    let build_folder = get_makefile_path(&building)?;
+    folder(&build_folder, 10, day)?;
+
+    let freed = artifact_ttl_prune(Duration::from_secs(1), &[building])?;
+
+    ensure!(freed == 1100, "Expected 1100 bytes freed, got {freed}");
+    ensure!(!space.join("expired").exists());
+    ensure!(space.join("recent").exists());
+    // Younger than the minimum age, kept despite the ttl.
+    ensure!(space.join("active").exists());
+    // The archives expire on their own.
+    ensure!(space.join("archives").exists());
+    // Shared by every build, only the unused artifacts expire.
+    ensure!(!stored.exists());
+    ensure!(space.join("cas").join("artifact").exists());
+    // Its process is still registered.
+    ensure!(build_folder.exists());
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::{
+    env::{set_current_dir, set_var},
+    fs::{read_dir, read_to_string, write},
+    os::unix::fs::MetadataExt,
+};
+
+use anyhow::{Result, ensure};
+use dake::{daemon, fetch::fetch, network::SocketAddr};
+use tempfile::tempdir;
+use tokio::spawn;
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, wait_for_daemon};
+
+const MAKEFILE: &str = "
+artifact.txt:
+\t@echo shared > artifact.txt
+";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn identical_artifacts_are_stored_once() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dirs = [tempdir()?, tempdir()?];
+    let output_dirs = [tempdir()?, tempdir()?];
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+
+    for (build_dir, output_dir) in build_dirs.iter().zip(&output_dirs) {
+        write(build_dir.path().join("Makefile"), MAKEFILE)?;
+        let pid = fresh_pid(sock.clone(), build_dir.path().to_path_buf()).await?;
+        set_current_dir(output_dir.path())?;
+        let labeled_path = Some(build_dir.path().to_path_buf());
+        fetch("artifact.txt".into(), labeled_path, pid, sock.clone()).await?;
+        ensure!(read_to_string(output_dir.path().join("artifact.txt"))? == "shared\n");
+    }
+
+    // The transfer is served from the store, the copy exists once it is over.
+    let cas = space_dir.path().join("cas");
+    let stored: Vec<_> = read_dir(&cas)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    ensure!(
+        stored.len() == 1,
+        "Expected one stored artifact, got {stored:?}"
+    );
+    ensure!(read_to_string(&stored[0])? == "shared\n");
+
+    // The build folders keep their own files, the stored one is a copy.
+    let stored = stored[0].metadata()?;
+    ensure!(
+        stored.nlink() == 1,
+        "Expected 1 link, got {}",
+        stored.nlink()
+    );
+    for build_dir in &build_dirs {
+        let built = build_dir.path().join("artifact.txt").metadata()?;
+        ensure!(built.nlink() == 1 && built.ino() != stored.ino());
+    }
+    Ok(())
+}
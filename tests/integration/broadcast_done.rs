@@ -0,0 +1,74 @@
+use std::{collections::HashMap, env::set_var, time::Duration};
+
+use anyhow::{Context, Result, bail, ensure};
+use dake::{
+    daemon::{DaemonConfig, ProcessDatas, State, broadcast_done},
+    dec,
+    network::{
+        DaemonMessage, Message, MessageKind, SocketAddr, Stream, hello, read_next_message,
+        write_message,
+    },
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+use tokio::{net::TcpListener, time::timeout};
+
+/// Accepts a single connection, answers its handshake and returns the message it carries.
+async fn receive_one(listener: TcpListener) -> Result<Message<DaemonMessage>> {
+    let (stream, _) = listener.accept().await?;
+    let mut stream = Stream::from(stream);
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Hello message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    let DaemonMessage::Hello { .. } = msg.inner else {
+        bail!("Expected a Hello message first, got {msg:?}");
+    };
+    write_message(&mut stream, Message::new(hello(), msg.pid)).await?;
+
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Done message.")?;
+    Ok(dec!(msg)?)
+}
+
+#[tokio::test]
+async fn broadcast_done_cleans_up() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let mock_daemon = SocketAddr::from(listener.local_addr()?);
+    let received = tokio::spawn(receive_one(listener));
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let pid = ProcessId::test_local(1);
+    let datas = ProcessDatas::new(
+        pid.clone(),
+        mock_daemon.clone(),
+        vec![mock_daemon],
+        Vec::new(),
+        HashMap::new(),
+    );
+    state.set_process_datas(pid.clone(), datas).await;
+
+    broadcast_done(&state, pid.clone()).await?;
+
+    ensure!(
+        state.read_process_data(&pid).await?.is_none(),
+        "The process should be removed once Done is broadcasted"
+    );
+
+    let msg = timeout(Duration::from_secs(5), received).await???;
+    ensure!(msg.pid == pid, "Unexpected pid {:?}", msg.pid);
+    ensure!(
+        matches!(msg.inner, DaemonMessage::Done),
+        "Expected a Done message, got {msg:?}"
+    );
+
+    // The process is already gone, broadcasting again is a no-op.
+    broadcast_done(&state, pid).await?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail, ensure};
+use dake::{
+    dec,
+    network::{
+        DaemonMessage, Message, MessageKind, SocketAddr, Stream, broadcast_messages, hello,
+        read_next_message, write_message,
+    },
+    process_id::ProcessId,
+};
+use tokio::{net::TcpListener, time::timeout};
+
+/// Accepts a single connection, answers its handshake and returns the message it carries.
+async fn receive_one(listener: TcpListener) -> Result<Message<DaemonMessage>> {
+    let (stream, _) = listener.accept().await?;
+    let mut stream = Stream::from(stream);
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Hello message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    let DaemonMessage::Hello { .. } = msg.inner else {
+        bail!("Expected a Hello message first, got {msg:?}");
+    };
+    write_message(&mut stream, Message::new(hello(), msg.pid)).await?;
+
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the message.")?;
+    Ok(dec!(msg)?)
+}
+
+/// Returns the address of a port nothing listens on.
+fn closed_port() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(SocketAddr::from(listener.local_addr()?))
+}
+
+fn message(n: u64) -> Message<DaemonMessage> {
+    Message::new(DaemonMessage::FreshId, ProcessId::test_local(n))
+}
+
+#[tokio::test]
+async fn unreachable_hosts_do_not_hide_the_others() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let reachable = SocketAddr::from(listener.local_addr()?);
+    let received = tokio::spawn(receive_one(listener));
+    let unreachable = closed_port()?;
+
+    let results = broadcast_messages(
+        vec![unreachable.clone(), reachable],
+        vec![message(1), message(2)],
+    )
+    .await?;
+
+    let [Err((failed, _)), Ok(_)] = results.as_slice() else {
+        bail!("Expected the first host to fail and the second to succeed, got {results:?}");
+    };
+    ensure!(
+        *failed == unreachable,
+        "The failure was reported for {failed}"
+    );
+
+    let msg = timeout(Duration::from_secs(5), received).await???;
+    ensure!(
+        msg.pid == ProcessId::test_local(2),
+        "The reachable host received the message of {:?}",
+        msg.pid
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn one_message_per_host_is_required() -> Result<()> {
+    let res = broadcast_messages(vec![closed_port()?], vec![message(1), message(2)]).await;
+    ensure!(
+        res.is_err(),
+        "Two messages for a single host should be refused"
+    );
+    Ok(())
+}
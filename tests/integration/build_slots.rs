@@ -0,0 +1,50 @@
+use std::{env::set_var, time::Duration};
+
+use anyhow::{Context, Result, ensure};
+use dake::{
+    daemon::{DaemonConfig, State},
+    network::SocketAddr,
+};
+use tempfile::tempdir;
+use tokio::time::{sleep, timeout};
+
+#[tokio::test]
+async fn builds_past_the_limit_are_queued() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_MAX_JOBS", "1");
+    }
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::load_or_generate()?);
+    let first = state
+        .acquire_build_slot()
+        .await
+        .context("Builds should be limited")?;
+
+    let second = tokio::spawn({
+        let state = state.clone();
+        async move { state.acquire_build_slot().await }
+    });
+    sleep(Duration::from_millis(100)).await;
+    ensure!(!second.is_finished(), "The second build should wait");
+    ensure!(state.queued_builds() == 1);
+
+    drop(first);
+    let second = timeout(Duration::from_secs(1), second).await??;
+    ensure!(
+        second.is_some(),
+        "The second build should get the freed slot"
+    );
+    ensure!(state.queued_builds() == 0);
+
+    // SAFETY: the only other task is over, no thread reads the environment.
+    unsafe { set_var("DAKE_MAX_JOBS", "0") };
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::load_or_generate()?);
+    ensure!(
+        state.acquire_build_slot().await.is_none(),
+        "0 should lift the build limit"
+    );
+    Ok(())
+}
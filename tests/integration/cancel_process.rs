@@ -0,0 +1,77 @@
+use std::{collections::HashMap, env::set_var, time::Duration};
+
+use anyhow::{Context, Result, bail, ensure};
+use dake::{
+    daemon::{DaemonConfig, Notif, ProcessDatas, State},
+    dec,
+    network::{
+        DaemonMessage, Message, MessageKind, SocketAddr, Stream, hello, read_next_message,
+        write_message,
+    },
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+use tokio::{net::TcpListener, time::timeout};
+
+/// Accepts a single connection, answers its handshake and returns the message it carries.
+async fn receive_one(listener: TcpListener) -> Result<Message<DaemonMessage>> {
+    let (stream, _) = listener.accept().await?;
+    let mut stream = Stream::from(stream);
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Hello message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    let DaemonMessage::Hello { .. } = msg.inner else {
+        bail!("Expected a Hello message first, got {msg:?}");
+    };
+    write_message(&mut stream, Message::new(hello(), msg.pid)).await?;
+
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Done message.")?;
+    Ok(dec!(msg)?)
+}
+
+#[tokio::test]
+async fn every_make_of_the_process_is_stopped() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let remote = SocketAddr::from(listener.local_addr()?);
+    let received = tokio::spawn(receive_one(listener));
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let pid = ProcessId::test_local(1);
+    let datas = ProcessDatas::new(
+        pid.clone(),
+        remote.clone(),
+        vec![remote],
+        Vec::new(),
+        HashMap::new(),
+    );
+    state.set_process_datas(pid.clone(), datas).await;
+    // The local make listens on the process channel.
+    let mut make = state.notifier_hub().lock().await.subscribe(&pid, 1);
+
+    state.kill_all_for_pid(pid.clone()).await;
+
+    ensure!(
+        !state.process_is_registered(&pid).await?,
+        "The cancelled process should be removed"
+    );
+    let notif = timeout(Duration::from_secs(5), make.recv())
+        .await?
+        .context("The channel of the local make was closed")?;
+    ensure!(
+        matches!(*notif, Notif::Done),
+        "The local make should be told to stop, got {notif:?}"
+    );
+    let msg = timeout(Duration::from_secs(5), received).await???;
+    ensure!(
+        msg.pid == pid && matches!(msg.inner, DaemonMessage::Done),
+        "The involved host should receive a Done, got {msg:?}"
+    );
+    Ok(())
+}
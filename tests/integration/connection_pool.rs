@@ -0,0 +1,108 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use dake::{
+    dec,
+    network::{
+        DaemonMessage, Message, MessageKind, Pool, SocketAddr, Stream, hello, read_next_message,
+        write_message,
+    },
+    process_id::ProcessId,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    spawn,
+};
+
+/// Answers the hello of a connection, then closes it on its first message.
+async fn serve(stream: TcpStream) -> Result<()> {
+    let mut stream = Stream::from(stream);
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Hello message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    let DaemonMessage::Hello { .. } = msg.inner else {
+        bail!("Expected a Hello message first, got {msg:?}");
+    };
+    write_message(&mut stream, Message::new(hello(), msg.pid)).await?;
+    read_next_message(&mut stream, MessageKind::DaemonMessage, None).await?;
+    Ok(())
+}
+
+/// Starts a peer answering every connection, returns its address and the number of
+/// connections it accepted so far.
+async fn mock_peer() -> Result<(SocketAddr, Arc<AtomicUsize>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let sock = SocketAddr::from(listener.local_addr()?);
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            spawn(serve(stream));
+        }
+    });
+    Ok((sock, accepted))
+}
+
+#[tokio::test]
+async fn idle_connections_are_reused() -> Result<()> {
+    let (sock, accepted) = mock_peer().await?;
+    let pool = Pool::new(1);
+
+    drop(pool.get(sock.clone()).await?);
+    drop(pool.get(sock).await?);
+    ensure!(
+        accepted.load(Ordering::SeqCst) == 1,
+        "The second checkout should reuse the idle connection"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn busy_connections_are_not_shared() -> Result<()> {
+    let (sock, accepted) = mock_peer().await?;
+    let pool = Pool::new(1);
+
+    let first = pool.get(sock.clone()).await?;
+    let second = pool.get(sock.clone()).await?;
+    ensure!(accepted.load(Ordering::SeqCst) == 2);
+
+    // Only one of them is kept once both are back.
+    drop((first, second));
+    let (_first, _second) = (pool.get(sock.clone()).await?, pool.get(sock).await?);
+    ensure!(
+        accepted.load(Ordering::SeqCst) == 3,
+        "The pool should keep a single idle connection per address"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn closed_connections_are_dropped() -> Result<()> {
+    let (sock, accepted) = mock_peer().await?;
+    let pool = Pool::new(1);
+
+    let mut stream = pool.get(sock.clone()).await?;
+    write_message(
+        &mut stream,
+        Message::new(DaemonMessage::FreshId, ProcessId::default()),
+    )
+    .await?;
+    let res = read_next_message(&mut stream, MessageKind::ProcessMessage, None).await;
+    ensure!(
+        matches!(res, Ok(None) | Err(_)),
+        "The peer should have closed the connection, got {res:?}"
+    );
+    drop(stream);
+
+    drop(pool.get(sock).await?);
+    ensure!(
+        accepted.load(Ordering::SeqCst) == 2,
+        "A connection closed by the peer should not go back to the pool"
+    );
+    Ok(())
+}
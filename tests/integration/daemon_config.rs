@@ -0,0 +1,74 @@
+use std::{
+    env::set_var,
+    fs::{read_to_string, write},
+    time::Duration,
+};
+
+use anyhow::{Result, ensure};
+use dake::{daemon::DaemonConfig, network::DEFAULT_PORT};
+use tempfile::tempdir;
+use tracing::Level;
+
+const CONFIG: &str = r#"
+max_concurrent_builds = 2
+allowed_peers = ["10.0.0.0/8", "192.168.1.5"]
+artifact_ttl_secs = 60
+listen_port = 1900
+log_level = "debug"
+"#;
+
+#[test]
+fn the_environment_overrides_the_file() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+    let path = space_dir.path().join("config.toml");
+
+    // The missing keys keep their default.
+    write(&path, CONFIG)?;
+    let config = DaemonConfig::load_or_generate()?;
+    ensure!(config.max_concurrent_builds() == Some(2));
+    ensure!(config.artifact_ttl() == Some(Duration::from_secs(60)));
+    ensure!(config.listen_port() == 1900);
+    ensure!(config.log_level() == Level::DEBUG);
+    let peers: Vec<_> = config
+        .allowed_peers()?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    ensure!(
+        peers == ["10.0.0.0/8", "192.168.1.5/32"],
+        "Unexpected peers {peers:?}"
+    );
+    ensure!(config.history_size() == DaemonConfig::default().history_size());
+
+    // SAFETY: no other thread was spawned since.
+    unsafe {
+        set_var("DAKE_PORT", "2000");
+        set_var("DAKE_MAX_JOBS", "0");
+        set_var("DAKE_ALLOWED_PEERS", "127.0.0.1, ");
+        set_var("DAKE_LOG_LEVEL", "not a level");
+    }
+    let config = DaemonConfig::load_or_generate()?;
+    ensure!(config.listen_port() == 2000);
+    ensure!(
+        config.max_concurrent_builds().is_none(),
+        "0 should lift the build limit"
+    );
+    ensure!(config.allowed_peers()?.len() == 1);
+    ensure!(
+        config.log_level() == Level::INFO,
+        "An invalid level should fall back to the default"
+    );
+    ensure!(
+        read_to_string(&path)? == CONFIG,
+        "The overrides should not be saved"
+    );
+
+    // An invalid file is kept aside and replaced by a fresh one.
+    write(&path, "listen_port = \"not a port\"")?;
+    DaemonConfig::load_or_generate()?;
+    ensure!(space_dir.path().join("config.toml.bak").exists());
+    ensure!(DaemonConfig::load()?.listen_port() == DEFAULT_PORT);
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::env::set_var;
+
+use anyhow::{Result, ensure};
+use dake::{daemon::DaemonId, process_id::ProcessId};
+use tempfile::tempdir;
+
+#[test]
+fn hyphenated_round_trip() -> Result<()> {
+    let id = DaemonId::generate();
+    let displayed = id.to_string();
+    ensure!(
+        displayed.len() == 36 && displayed.matches('-').count() == 4,
+        "{displayed} is not a hyphenated UUID"
+    );
+    ensure!(displayed.parse::<DaemonId>()? == id);
+
+    // The hyphens of the daemon id must not be taken for the separator of the path.
+    let pid = ProcessId::new(3, id, "/tmp/my-project".into());
+    let parsed: ProcessId = pid.to_string().parse()?;
+    ensure!(parsed == pid, "{pid} was parsed as {parsed:?}");
+    Ok(())
+}
+
+#[test]
+fn id_is_stable_across_starts() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: the other test of this binary does not read the environment.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let first = DaemonId::load_or_generate()?;
+    let second = DaemonId::load_or_generate()?;
+    ensure!(first == second, "The id changed from {first} to {second}");
+    Ok(())
+}
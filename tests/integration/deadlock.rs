@@ -0,0 +1,97 @@
+use std::{
+    env::set_var,
+    sync::{LazyLock, Once},
+    time::Duration,
+};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon::{DaemonConfig, DakeError, State},
+    network::SocketAddr,
+    process_id::ProcessId,
+};
+use tempfile::{TempDir, tempdir};
+use tokio::time::sleep;
+
+static SPACE_DIR: LazyLock<TempDir> = LazyLock::new(|| tempdir().unwrap());
+
+/// Returns a fresh state, the Dake working directory being shared by the tests.
+fn state() -> State {
+    static SET_SPACE: Once = Once::new();
+    // SAFETY: the other tests wait for the first call, nothing reads the environment before.
+    SET_SPACE.call_once(|| unsafe { set_var("DAKE_SPACE_PATH", SPACE_DIR.path()) });
+    State::with_config(SocketAddr::default(), DaemonConfig::default())
+}
+
+#[tokio::test]
+async fn crossed_locks_are_detected() -> Result<()> {
+    let state = state();
+    let first = ProcessId::test_local(1);
+    let second = ProcessId::test_local(2);
+
+    ensure!(state.try_lock_target(&first, "foo".into(), 1).await?);
+    ensure!(state.try_lock_target(&second, "bar".into(), 1).await?);
+
+    // The build of foo by the first process waits for bar, held by the second one.
+    let waiting = tokio::spawn({
+        let state = state.clone();
+        let first = first.clone();
+        async move {
+            state
+                .lock_target(&first, "bar".into(), 1, Some("foo"))
+                .await
+        }
+    });
+    sleep(Duration::from_millis(200)).await;
+    ensure!(
+        !waiting.is_finished(),
+        "bar is still held by the second process"
+    );
+
+    // The build of bar waiting for foo would close the cycle.
+    let Err(e) = state
+        .lock_target(&second, "foo".into(), 1, Some("bar"))
+        .await
+    else {
+        bail!("The second process should not wait for foo");
+    };
+    match e.downcast_ref::<DakeError>() {
+        Some(DakeError::DeadlockDetected { cycle }) => {
+            ensure!(cycle.len() == 2, "Unexpected cycle: {cycle:?}");
+            ensure!(cycle.contains(&first) && cycle.contains(&second));
+        }
+        _ => bail!("Unexpected error: {e:?}"),
+    }
+
+    // Once the second process gives up on its lock, the first one gets it.
+    state.unlock_all_targets_for_process(&second).await?;
+    tokio::time::timeout(Duration::from_secs(5), waiting).await???;
+    Ok(())
+}
+
+#[tokio::test]
+async fn crossed_top_level_waits_are_not_deadlocks() -> Result<()> {
+    let state = state();
+    let first = ProcessId::test_local(1);
+    let second = ProcessId::test_local(2);
+
+    ensure!(state.try_lock_target(&first, "foo".into(), 1).await?);
+    ensure!(state.try_lock_target(&second, "bar".into(), 1).await?);
+
+    // Both waits come from the top-level builds, foo and bar do not need each other.
+    let waits = ["bar", "foo"].map(|target| {
+        let state = state.clone();
+        let pid = if target == "bar" { &first } else { &second }.clone();
+        tokio::spawn(async move { state.lock_target(&pid, target.into(), 1, None).await })
+    });
+    sleep(Duration::from_millis(200)).await;
+    ensure!(waits.iter().all(|wait| !wait.is_finished()));
+
+    // Each build of foo and bar finishes and releases its target.
+    state.unlock_target(&first, "foo".into()).await?;
+    state.unlock_target(&second, "bar".into()).await?;
+    for wait in waits {
+        tokio::time::timeout(Duration::from_secs(5), wait).await???;
+    }
+    Ok(())
+}
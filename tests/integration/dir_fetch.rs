@@ -0,0 +1,62 @@
+use std::{
+    env::{set_current_dir, set_var},
+    fs::{read_to_string, write},
+};
+
+use anyhow::{Result, ensure};
+use dake::{daemon, fetch::fetch, network::SocketAddr};
+use tempfile::tempdir;
+use tokio::spawn;
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, wait_for_daemon};
+
+const TARGET: &str = "out";
+
+const MAKEFILE: &str = "
+out:
+\t@mkdir -p out/nested
+\t@echo first > out/first.txt
+\t@echo second > out/nested/second.txt
+";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn directory_targets_are_unpacked() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+    write(build_dir.path().join("Makefile"), MAKEFILE)?;
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+    let pid = fresh_pid(sock.clone(), build_dir.path().to_path_buf()).await?;
+
+    set_current_dir(output_dir.path())?;
+    fetch(
+        TARGET.to_string(),
+        Some(build_dir.path().to_path_buf()),
+        pid,
+        sock,
+    )
+    .await?;
+
+    let out = output_dir.path().join(TARGET);
+    ensure!(read_to_string(out.join("first.txt"))? == "first\n");
+    ensure!(read_to_string(out.join("nested/second.txt"))? == "second\n");
+    ensure!(
+        !output_dir.path().join("out.part").exists(),
+        "The archive should be removed once unpacked"
+    );
+    Ok(())
+}
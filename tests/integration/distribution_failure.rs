@@ -0,0 +1,44 @@
+use anyhow::{Context, Result, anyhow, ensure};
+use dake::{
+    daemon::report_distribution_failure,
+    dec,
+    network::{Message, MessageKind, ProcessMessage, Stream, read_next_message},
+    process_id::ProcessId,
+};
+use tokio::net::UnixStream;
+
+async fn next_process_message(stream: &mut Stream) -> Result<Message<ProcessMessage>> {
+    let msg = read_next_message(stream, MessageKind::ProcessMessage, None)
+        .await?
+        .context("The stream was closed before the expected message.")?;
+    Ok(dec!(msg)?)
+}
+
+#[tokio::test]
+async fn distribution_failure_report() -> Result<()> {
+    let pid = ProcessId::test_local(1);
+    let (daemon_side, caller_side) = UnixStream::pair()?;
+    let mut daemon_side = Stream::from(daemon_side);
+    let mut caller_side = Stream::from(caller_side);
+
+    report_distribution_failure(&mut daemon_side, &pid, &anyhow!("host unreachable")).await?;
+    drop(daemon_side);
+
+    let log = next_process_message(&mut caller_side).await?;
+    ensure!(log.pid == pid, "Unexpected pid {:?}", log.pid);
+    ensure!(
+        matches!(&log.inner, ProcessMessage::StderrLog { log, .. } if log.contains("host unreachable")),
+        "Expected the error as a stderr log, got {log:?}"
+    );
+
+    let end = next_process_message(&mut caller_side).await?;
+    ensure!(
+        matches!(end.inner, ProcessMessage::End { exit_code: 1 }),
+        "Expected a failing End, got {end:?}"
+    );
+
+    let rest = read_next_message(&mut caller_side, MessageKind::ProcessMessage, None).await?;
+    ensure!(rest.is_none(), "No message should follow the End");
+
+    Ok(())
+}
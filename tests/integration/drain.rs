@@ -0,0 +1,48 @@
+use std::{env::set_var, time::Duration};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon::{DaemonConfig, DakeError, State},
+    network::SocketAddr,
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+use tokio::time::sleep;
+
+#[tokio::test]
+async fn drain_waits_for_the_last_process() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    ensure!(!state.is_draining());
+    state.drain(Duration::from_millis(100)).await?;
+    // From now on the daemon refuses the new builds.
+    ensure!(state.is_draining());
+
+    let pid = ProcessId::test_local(1);
+    state.register_process(pid.clone()).await;
+    let Err(e) = state.drain(Duration::from_millis(100)).await else {
+        bail!("The drain should time out while a process runs");
+    };
+    match e.downcast_ref::<DakeError>() {
+        Some(DakeError::DrainTimeout { running }) => {
+            ensure!(
+                running == std::slice::from_ref(&pid),
+                "Unexpected processes: {running:?}"
+            )
+        }
+        _ => bail!("Unexpected error: {e:?}"),
+    }
+
+    let drain = tokio::spawn({
+        let state = state.clone();
+        async move { state.drain(Duration::from_secs(5)).await }
+    });
+    sleep(Duration::from_millis(100)).await;
+    ensure!(!drain.is_finished(), "The process is still running");
+    state.remove_process(&pid).await?;
+    tokio::time::timeout(Duration::from_secs(1), drain).await???;
+    Ok(())
+}
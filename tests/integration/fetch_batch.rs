@@ -0,0 +1,75 @@
+use std::{
+    env::{set_current_dir, set_var},
+    fs::{metadata, read_to_string, remove_file, write},
+    os::unix::fs::MetadataExt,
+};
+
+use anyhow::{Result, ensure};
+use dake::{daemon, fetch::fetch_batch, network::SocketAddr};
+use tempfile::tempdir;
+use tokio::spawn;
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, wait_for_daemon};
+
+const MAKEFILE: &str = "
+first.txt:
+\t@echo first > first.txt
+
+second.txt:
+\t@echo second > second.txt
+
+out:
+\t@mkdir -p out
+\t@echo third > out/third.txt
+";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batch_fetches_every_target() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+    write(build_dir.path().join("Makefile"), MAKEFILE)?;
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+    let pid = fresh_pid(sock.clone(), build_dir.path().to_path_buf()).await?;
+
+    set_current_dir(output_dir.path())?;
+    let labeled_path = Some(build_dir.path().to_path_buf());
+    let targets: Vec<_> = ["first.txt", "second.txt", "out"]
+        .into_iter()
+        .map(|target| (target.to_string(), labeled_path.clone()))
+        .collect();
+    fetch_batch(targets.clone(), pid.clone(), sock.clone()).await?;
+
+    let out = output_dir.path();
+    ensure!(read_to_string(out.join("first.txt"))? == "first\n");
+    ensure!(read_to_string(out.join("second.txt"))? == "second\n");
+    ensure!(read_to_string(out.join("out/third.txt"))? == "third\n");
+
+    // An interrupted transfer resumes, an up to date copy is not sent again.
+    remove_file(out.join("first.txt"))?;
+    write(out.join("first.txt.part"), "fir")?;
+    let second_inode = metadata(out.join("second.txt"))?.ino();
+    fetch_batch(targets, pid, sock).await?;
+
+    ensure!(read_to_string(out.join("first.txt"))? == "first\n");
+    ensure!(!out.join("first.txt.part").exists());
+    ensure!(
+        metadata(out.join("second.txt"))?.ino() == second_inode,
+        "The up to date copy of second.txt should be kept as is"
+    );
+    Ok(())
+}
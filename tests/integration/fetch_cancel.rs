@@ -0,0 +1,81 @@
+use std::{
+    env::{set_current_dir, set_var},
+    fs::write,
+    time::Duration,
+};
+
+use anyhow::{Result, ensure};
+use dake::{
+    daemon,
+    fetch::fetch,
+    network::{
+        AckMessage, DaemonMessage, Message, MessageKind, SocketAddr,
+        send_message_and_await_response,
+    },
+};
+use tempfile::tempdir;
+use tokio::{
+    spawn,
+    time::{sleep, timeout},
+};
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, wait_for_daemon};
+
+const MAKEFILE: &str = "
+broken:
+\t@false
+";
+
+/// A failed fetch waits for its process to be over, then exits right away.
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_fetch_exits_once_the_process_is_done() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+    write(build_dir.path().join("Makefile"), MAKEFILE)?;
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+    let pid = fresh_pid(sock.clone(), build_dir.path().to_path_buf()).await?;
+
+    set_current_dir(output_dir.path())?;
+    let fetcher = spawn(fetch(
+        "broken".to_string(),
+        Some(build_dir.path().to_path_buf()),
+        pid.clone(),
+        sock.clone(),
+    ));
+    sleep(Duration::from_secs(1)).await;
+    ensure!(
+        !fetcher.is_finished(),
+        "The fetcher should wait for the process to be over"
+    );
+
+    let ack: Message<AckMessage> = send_message_and_await_response(
+        Message::new(DaemonMessage::Done, pid),
+        sock,
+        MessageKind::AckMessage,
+        Duration::from_secs(5),
+    )
+    .await?;
+    ensure!(
+        matches!(ack.inner, AckMessage::Ok),
+        "Unexpected ack {ack:?}"
+    );
+
+    let result = timeout(Duration::from_secs(5), fetcher).await??;
+    ensure!(result.is_err(), "A failed fetch should report an error");
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use anyhow::{Context, Result, bail, ensure};
+use dake::{
+    dec,
+    network::{
+        DaemonMessage, Message, MessageHeader, MessageKind, Negotiated, NegotiatedVersion,
+        SocketAddr, Stream, connect, hello, read_next_message, write_message,
+    },
+    process_id::ProcessId,
+};
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+/// A message large enough to be compressed whenever the peer supports it.
+fn large_message() -> Message<DaemonMessage> {
+    Message::new(
+        DaemonMessage::Fetch {
+            target: "target".repeat(16 * 1024),
+            labeled_path: None,
+            known_checksum: None,
+            offset: 0,
+            parent: None,
+            caller_path: None,
+        },
+        ProcessId::default(),
+    )
+}
+
+/// Accepts a single connection, answers its hello unless `legacy`, and returns whether
+/// the next message it receives is compressed.
+async fn mock_peer(listener: TcpListener, legacy: bool) -> Result<bool> {
+    let (stream, _) = listener.accept().await?;
+    let mut stream = Stream::from(stream);
+    let msg = read_next_message(&mut stream, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the Hello message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    let DaemonMessage::Hello { .. } = msg.inner else {
+        bail!("Expected a Hello message first, got {msg:?}");
+    };
+    if !legacy {
+        write_message(&mut stream, Message::new(hello(), msg.pid)).await?;
+    }
+
+    let mut header = vec![0; MessageHeader::get_header_length()?];
+    stream.read_exact(&mut header).await?;
+    let header: MessageHeader = dec!(header)?;
+    Ok(header.is_compressed())
+}
+
+async fn send_large_message(legacy: bool) -> Result<(NegotiatedVersion, bool)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let sock = SocketAddr::from(listener.local_addr()?);
+    let peer = tokio::spawn(mock_peer(listener, legacy));
+
+    let mut stream = connect(sock, None).await?;
+    write_message(&mut stream, large_message()).await?;
+    let compressed = peer.await??;
+    Ok((stream.negotiated().clone(), compressed))
+}
+
+#[tokio::test]
+async fn silent_peers_fall_back_to_legacy() -> Result<()> {
+    let (negotiated, compressed) = send_large_message(true).await?;
+    ensure!(
+        negotiated == NegotiatedVersion::legacy(),
+        "A peer ignoring the hello should be legacy, got {negotiated:?}"
+    );
+    ensure!(
+        !compressed,
+        "A legacy peer should never receive compressed messages"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn negotiated_peers_receive_compressed_messages() -> Result<()> {
+    let (negotiated, compressed) = send_large_message(false).await?;
+    ensure!(
+        negotiated != NegotiatedVersion::legacy(),
+        "A peer answering the hello should not be legacy"
+    );
+    ensure!(
+        compressed,
+        "A peer supporting compression should receive large messages compressed"
+    );
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use std::env::set_var;
+
+use anyhow::{Result, ensure};
+use dake::{
+    daemon::{DaemonConfig, State},
+    network::SocketAddr,
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn every_process_is_killed() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let pids = [ProcessId::test_local(1), ProcessId::test_local(2)];
+    for pid in &pids {
+        state.register_process(pid.clone()).await;
+    }
+
+    state.kill_all("decommissioning".to_string()).await?;
+
+    ensure!(
+        state.registered_processes().await?.is_empty(),
+        "No process should be left"
+    );
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::{
+    env::{set_current_dir, set_var},
+    fs::{read, remove_file, write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::{Result, ensure};
+use dake::{daemon, fetch::fetch, network::SocketAddr, process_id::ProcessId};
+use tempfile::tempdir;
+use tokio::spawn;
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, wait_for_daemon};
+
+/// Spans more than 10,000 chunks.
+const ARTIFACT_SIZE: usize = 100 * 1024 * 1024;
+/// Kept low for noisy CI machines, a transfer reading byte per byte stays far below it.
+const MIN_THROUGHPUT: f64 = 5.0 * 1024.0 * 1024.0;
+const TARGET: &str = "output.bin";
+const PARTIAL: &str = "output.bin.part";
+
+const MAKEFILE: &str = "
+output.bin: source.bin
+\t@cp source.bin output.bin
+";
+
+/// Generates a deterministic, non-trivial payload so a chunk misplacement is detected.
+fn generate_artifact() -> Vec<u8> {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    (0..ARTIFACT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+async fn fetch_again(build_dir: &Path, pid: ProcessId, sock: SocketAddr) -> Result<()> {
+    fetch(TARGET.to_string(), Some(build_dir.to_path_buf()), pid, sock).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn large_fetch() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+
+    let artifact = generate_artifact();
+    write(build_dir.path().join("source.bin"), &artifact)?;
+    write(build_dir.path().join("Makefile"), MAKEFILE)?;
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+
+    let pid = fresh_pid(sock.clone(), build_dir.path().to_path_buf()).await?;
+
+    // The fetcher writes the artifact relatively to its working directory.
+    set_current_dir(output_dir.path())?;
+
+    let start = Instant::now();
+    fetch(
+        TARGET.to_string(),
+        Some(build_dir.path().to_path_buf()),
+        pid.clone(),
+        sock.clone(),
+    )
+    .await?;
+    let elapsed = start.elapsed();
+
+    let received = read(output_dir.path().join(TARGET))?;
+    ensure!(
+        received.len() == artifact.len(),
+        "Received {} bytes, expected {}",
+        received.len(),
+        artifact.len()
+    );
+    ensure!(
+        received == artifact,
+        "The fetched artifact differs from the source"
+    );
+
+    // An interrupted fetch resumes after the bytes it kept.
+    let resumed = ARTIFACT_SIZE / 3;
+    remove_file(output_dir.path().join(TARGET))?;
+    write(output_dir.path().join(PARTIAL), &artifact[..resumed])?;
+    fetch_again(build_dir.path(), pid.clone(), sock.clone()).await?;
+    ensure!(
+        read(output_dir.path().join(TARGET))? == artifact,
+        "The resumed artifact differs from the source"
+    );
+    ensure!(!output_dir.path().join(PARTIAL).exists());
+
+    // A partial file that does not match the artifact is fetched again from the start.
+    remove_file(output_dir.path().join(TARGET))?;
+    write(output_dir.path().join(PARTIAL), vec![0; resumed])?;
+    fetch_again(build_dir.path(), pid.clone(), sock.clone()).await?;
+    ensure!(
+        read(output_dir.path().join(TARGET))? == artifact,
+        "The corrupted partial file was not fetched again"
+    );
+
+    let throughput = ARTIFACT_SIZE as f64 / elapsed.as_secs_f64();
+    println!(
+        "Fetched {ARTIFACT_SIZE} bytes in {elapsed:?} ({:.2} MB/s)",
+        throughput / (1024.0 * 1024.0)
+    );
+    ensure!(
+        throughput >= MIN_THROUGHPUT,
+        "Fetch throughput too low: {:.2} MB/s",
+        throughput / (1024.0 * 1024.0)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use anyhow::{Result, ensure};
+use dake::{
+    dec, enc,
+    network::{DaemonMessage, Message},
+    process_id::ProcessId,
+};
+
+/// Every peer frames its messages as a pid followed by the payload, fields added to
+/// [`Message`] would break the peers that do not know them.
+#[test]
+fn envelopes_are_a_pid_and_a_payload() -> Result<()> {
+    let pid = ProcessId::test_local(1);
+    let bytes = enc!((pid.clone(), DaemonMessage::QueryStatus))?;
+    let msg: Message<DaemonMessage> = dec!(bytes)?;
+    ensure!(msg.pid == pid, "Unexpected pid {:?}", msg.pid);
+    ensure!(
+        matches!(msg.inner, DaemonMessage::QueryStatus),
+        "Unexpected payload {:?}",
+        msg.inner
+    );
+    Ok(())
+}
+
+/// A message whose variant was added after this build, as a newer peer would send it.
+#[test]
+fn unknown_variants_of_newer_peers_decode() -> Result<()> {
+    let pid = ProcessId::test_local(1);
+    // Postcard writes the variant index then its fields, any index past the known
+    // variants stands for a newer one.
+    let newer_variant = u32::MAX;
+    let bytes = enc!((
+        pid.clone(),
+        newer_variant,
+        "field of a newer variant",
+        42u64
+    ))?;
+    let msg: Message<DaemonMessage> = dec!(bytes)?;
+    ensure!(msg.pid == pid, "Unexpected pid {:?}", msg.pid);
+    ensure!(
+        matches!(msg.inner, DaemonMessage::Unknown),
+        "A variant unknown to this build should decode as Unknown, got {:?}",
+        msg.inner
+    );
+    Ok(())
+}
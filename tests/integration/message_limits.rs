@@ -0,0 +1,104 @@
+use anyhow::{Context, Result, ensure};
+use dake::{
+    dec, enc,
+    network::{
+        DaemonMessage, FetcherMessage, Message, MessageHeader, MessageKind, NegotiatedVersion,
+        PROTOCOL_VERSION, Stream, local_capabilities, read_next_message, write_message,
+    },
+    process_id::ProcessId,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    spawn,
+};
+
+/// More than the maximum size of a message.
+const OVERSIZED: usize = 128 * 1024 * 1024;
+
+#[tokio::test]
+async fn oversized_messages_are_rejected_before_allocation() -> Result<()> {
+    let (reader, mut writer) = UnixStream::pair()?;
+    let mut reader = Stream::from(reader);
+    let header = enc!(MessageHeader::new(u64::MAX, MessageKind::DaemonMessage, 0))?;
+    writer.write_all(&header).await?;
+
+    let res = read_next_message(&mut reader, MessageKind::DaemonMessage, None).await;
+    ensure!(
+        res.as_ref()
+            .is_err_and(|e| e.to_string().contains("exceeds the maximum")),
+        "A header announcing {} bytes should be rejected, got {res:?}",
+        u64::MAX
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_decompressed_messages_are_rejected() -> Result<()> {
+    let bomb = zstd::encode_all(vec![0; OVERSIZED].as_slice(), 0)?;
+    let message = MessageHeader::wrap(bomb, MessageKind::DaemonMessage, MessageHeader::COMPRESSED)?;
+    let (reader, mut writer) = UnixStream::pair()?;
+    let mut reader = Stream::from(reader);
+    spawn(async move { writer.write_all(&message).await });
+
+    let res = read_next_message(&mut reader, MessageKind::DaemonMessage, None).await;
+    ensure!(
+        res.as_ref()
+            .is_err_and(|e| e.to_string().contains("exceeds the maximum")),
+        "A payload decompressing to {OVERSIZED} bytes should be rejected"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn objects_are_never_compressed() -> Result<()> {
+    let (writer, mut reader) = UnixStream::pair()?;
+    let mut writer = Stream::from(writer);
+    writer.set_negotiated(NegotiatedVersion::negotiate(
+        PROTOCOL_VERSION,
+        &local_capabilities(),
+    ));
+
+    let object = FetcherMessage::Object(vec![0; 64 * 1024]);
+    write_message(&mut writer, Message::new(object, ProcessId::default())).await?;
+
+    let mut header = vec![0; MessageHeader::get_header_length()?];
+    reader.read_exact(&mut header).await?;
+    let header: MessageHeader = dec!(header)?;
+    ensure!(!header.is_compressed(), "Objects should be sent as is");
+    Ok(())
+}
+
+#[tokio::test]
+async fn compressed_messages_round_trip() -> Result<()> {
+    let (writer, reader) = UnixStream::pair()?;
+    let (mut writer, mut reader) = (Stream::from(writer), Stream::from(reader));
+    writer.set_negotiated(NegotiatedVersion::negotiate(
+        PROTOCOL_VERSION,
+        &local_capabilities(),
+    ));
+
+    let target = "target".repeat(64 * 1024);
+    let msg = Message::new(
+        DaemonMessage::Fetch {
+            target: target.clone(),
+            labeled_path: None,
+            known_checksum: None,
+            offset: 0,
+            parent: None,
+            caller_path: None,
+        },
+        ProcessId::default(),
+    );
+    spawn(async move { write_message(&mut writer, msg).await });
+
+    let msg = read_next_message(&mut reader, MessageKind::DaemonMessage, None)
+        .await?
+        .context("The stream was closed before the message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    ensure!(
+        matches!(&msg.inner, DaemonMessage::Fetch { target: received, .. } if *received == target),
+        "The decompressed message differs from the sent one"
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use anyhow::{Result, ensure};
+use dake::daemon::DakeMetrics;
+
+fn value_of(rendered: &str, metric: &str) -> Option<String> {
+    rendered
+        .lines()
+        .find_map(|line| line.strip_prefix(metric)?.strip_prefix(' '))
+        .map(str::to_string)
+}
+
+#[test]
+fn builds_are_counted() -> Result<()> {
+    let metrics = DakeMetrics::default();
+    metrics.build_started();
+    metrics.build_started();
+    metrics.build_ended(0, Duration::from_secs(3));
+    metrics.add_makefile_bytes(120);
+    metrics.add_artifact_bytes(4096);
+
+    let rendered = metrics.render();
+    let expected = [
+        ("dake_active_builds", "1"),
+        ("dake_completed_builds_total", "1"),
+        ("dake_failed_builds_total", "0"),
+        ("dake_makefile_bytes_distributed_total", "120"),
+        ("dake_artifact_bytes_transferred_total", "4096"),
+        ("dake_build_duration_seconds_bucket{le=\"1\"}", "0"),
+        ("dake_build_duration_seconds_bucket{le=\"5\"}", "1"),
+        ("dake_build_duration_seconds_bucket{le=\"+Inf\"}", "1"),
+        ("dake_build_duration_seconds_sum", "3"),
+        ("dake_build_duration_seconds_count", "1"),
+    ];
+    for (metric, value) in expected {
+        let found = value_of(&rendered, metric);
+        ensure!(
+            found.as_deref() == Some(value),
+            "{metric} is {found:?} instead of {value} in:\n{rendered}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn failed_builds_are_counted() -> Result<()> {
+    let metrics = DakeMetrics::default();
+    metrics.build_started();
+    metrics.build_ended(2, Duration::from_secs(7200));
+
+    let rendered = metrics.render();
+    ensure!(value_of(&rendered, "dake_active_builds").as_deref() == Some("0"));
+    ensure!(value_of(&rendered, "dake_failed_builds_total").as_deref() == Some("1"));
+    ensure!(
+        value_of(&rendered, "dake_build_duration_seconds_bucket{le=\"3600\"}").as_deref()
+            == Some("0"),
+        "A build longer than every bucket must only count in +Inf:\n{rendered}"
+    );
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::{env::set_var, path::PathBuf, time::Duration};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon,
+    network::{DaemonMessage, ProcessMessage, SocketAddr},
+};
+use tempfile::tempdir;
+use tokio::{spawn, time::sleep};
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, request, wait_for_daemon};
+
+/// Pings the daemon, returns its uptime and its amount of processes.
+async fn ping(sock: SocketAddr, build_dir: PathBuf) -> Result<(u64, usize)> {
+    match request(sock, build_dir, DaemonMessage::Ping).await?.inner {
+        ProcessMessage::Pong {
+            uptime_secs,
+            active_processes,
+        } => Ok((uptime_secs, active_processes)),
+        inner => bail!("Expected a Pong response, got {inner:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pong_reports_the_load_of_the_daemon() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+    let build_dir = build_dir.path().to_path_buf();
+
+    let (_, active_processes) = ping(sock.clone(), build_dir.clone()).await?;
+    ensure!(
+        active_processes == 0,
+        "A fresh daemon has no process, got {active_processes}"
+    );
+
+    fresh_pid(sock.clone(), build_dir.clone()).await?;
+    sleep(Duration::from_secs(1)).await;
+
+    let (uptime_secs, active_processes) = ping(sock, build_dir).await?;
+    ensure!(
+        active_processes == 1,
+        "The registered process should be counted, got {active_processes}"
+    );
+    ensure!(uptime_secs >= 1, "The daemon runs for {uptime_secs}s");
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::{env::set_var, path::PathBuf, time::Duration};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon,
+    network::{DaemonMessage, ProcessMessage, ProcessSummary, SocketAddr},
+};
+use tempfile::tempdir;
+use tokio::{spawn, time::sleep};
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{free_port, fresh_pid, request, wait_for_daemon};
+
+async fn query_status(
+    sock: SocketAddr,
+    build_dir: PathBuf,
+) -> Result<(Vec<ProcessSummary>, usize)> {
+    match request(sock, build_dir, DaemonMessage::QueryStatus)
+        .await?
+        .inner
+    {
+        ProcessMessage::StatusReport {
+            processes,
+            queued_builds,
+        } => Ok((processes, queued_builds)),
+        inner => bail!("Expected a StatusReport response, got {inner:?}"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn builds_in_progress_are_listed_oldest_first() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+
+    spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    wait_for_daemon(sock.clone()).await?;
+    let build_dir = build_dir.path().to_path_buf();
+
+    let (processes, queued_builds) = query_status(sock.clone(), build_dir.clone()).await?;
+    ensure!(processes.is_empty(), "Unexpected processes: {processes:?}");
+    ensure!(queued_builds == 0);
+
+    let mut pids = Vec::new();
+    for _ in 0..2 {
+        pids.push(fresh_pid(sock.clone(), build_dir.clone()).await?);
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    let (processes, queued_builds) = query_status(sock.clone(), build_dir.clone()).await?;
+    let listed: Vec<_> = processes.iter().map(|p| p.pid.clone()).collect();
+    ensure!(
+        listed == pids,
+        "Expected {pids:?} in this order, got {listed:?}"
+    );
+    ensure!(queued_builds == 0);
+
+    // The running time is measured by the daemon.
+    sleep(Duration::from_secs(1)).await;
+    let (processes, _) = query_status(sock, build_dir).await?;
+    for process in &processes {
+        ensure!(
+            process.elapsed_secs >= 1,
+            "{:?} runs for {}s",
+            process.pid,
+            process.elapsed_secs
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::{
+    env::set_var,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, ensure};
+use dake::{
+    daemon::{DaemonConfig, ProcessDatas, State},
+    network::SocketAddr,
+    process_id::ProcessId,
+};
+use tempfile::tempdir;
+
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[tokio::test]
+async fn stale_processes_are_reaped() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let stale = ProcessId::test_local(1);
+    let fresh = ProcessId::test_local(2);
+    let running = ProcessId::test_local(3);
+
+    let datas = ProcessDatas {
+        start_time: SystemTime::now() - 2 * MAX_AGE,
+        ..Default::default()
+    };
+    state.set_process_datas(stale.clone(), datas.clone()).await;
+    state.set_process_datas(running.clone(), datas).await;
+    state.register_process(fresh.clone()).await;
+    // A make of the process listens on its channel until it is over.
+    let _make = state.notifier_hub().lock().await.subscribe(&running, 1);
+
+    ensure!(state.process_duration(&stale).await? >= 2 * MAX_AGE);
+    ensure!(state.process_duration(&fresh).await? < MAX_AGE);
+
+    // The stale process holds the only slot of the target, the fresh one gets it once reaped.
+    ensure!(state.try_lock_target(&stale, "all".into(), 1).await?);
+    ensure!(!state.try_lock_target(&fresh, "all".into(), 1).await?);
+
+    ensure!(
+        state.reap_stale(MAX_AGE).await? == 1,
+        "Exactly one process is stale"
+    );
+    ensure!(!state.process_is_registered(&stale).await?);
+    ensure!(state.process_is_registered(&fresh).await?);
+    ensure!(
+        state.process_is_registered(&running).await?,
+        "A process whose make still runs should be kept"
+    );
+    ensure!(
+        state.try_lock_target(&fresh, "all".into(), 1).await?,
+        "The target of the reaped process should be released"
+    );
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::{env::set_var, path::PathBuf, process::Command, time::Duration};
+
+use anyhow::{Result, bail, ensure};
+use dake::{
+    daemon,
+    network::{
+        AckMessage, DaemonMessage, Message, MessageKind, ProcessMessage, SocketAddr,
+        send_message_and_await_response,
+    },
+};
+use tempfile::tempdir;
+use tokio::{
+    spawn,
+    time::{sleep, timeout},
+};
+
+#[path = "../in_process/mod.rs"]
+mod in_process;
+
+use in_process::{RESPONSE_TIMEOUT, free_port, fresh_pid, request};
+
+async fn ping(sock: SocketAddr, build_dir: PathBuf) -> Result<()> {
+    let msg = request(sock, build_dir, DaemonMessage::Ping).await?;
+    ensure!(
+        matches!(msg.inner, ProcessMessage::Pong { .. }),
+        "Expected a Pong response, got {msg:?}"
+    );
+    Ok(())
+}
+
+/// Waits for the daemon to answer, its signal handlers are installed by then.
+async fn wait_for_pong(sock: SocketAddr, build_dir: PathBuf) -> Result<()> {
+    for _ in 0..100 {
+        if ping(sock.clone(), build_dir.clone()).await.is_ok() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    bail!("The in-process daemon never answered on {sock}")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sigterm_drains_the_daemon() -> Result<()> {
+    let space_dir = tempdir()?;
+    let build_dir = tempdir()?;
+    let port = free_port()?;
+
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_SPACE_PATH", space_dir.path());
+        set_var("DAKE_IP", "127.0.0.1");
+        set_var("DAKE_PORT", port.to_string());
+    }
+
+    let daemon = spawn(daemon::start());
+    let sock: SocketAddr = format!("127.0.0.1:{port}").parse()?;
+    let build_dir = build_dir.path().to_path_buf();
+    wait_for_pong(sock.clone(), build_dir.clone()).await?;
+
+    let pid = fresh_pid(sock.clone(), build_dir.clone()).await?;
+
+    let status = Command::new("kill")
+        .args(["-TERM", &std::process::id().to_string()])
+        .status()?;
+    ensure!(status.success(), "Failed to send SIGTERM");
+
+    // The process in progress keeps the daemon up, and served.
+    sleep(Duration::from_millis(200)).await;
+    ensure!(
+        !daemon.is_finished(),
+        "The daemon should wait for its process"
+    );
+    ping(sock.clone(), build_dir).await?;
+
+    let msg = Message::new(DaemonMessage::CancelProcess { pid: pid.clone() }, pid);
+    let ack: Message<AckMessage> =
+        send_message_and_await_response(msg, sock, MessageKind::AckMessage, RESPONSE_TIMEOUT)
+            .await?;
+    ensure!(
+        matches!(ack.inner, AckMessage::Ok),
+        "Unexpected ack {ack:?}"
+    );
+
+    timeout(RESPONSE_TIMEOUT, daemon).await???;
+    Ok(())
+}
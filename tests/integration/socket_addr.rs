@@ -0,0 +1,64 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use anyhow::{Context, Result, ensure};
+use dake::network::SocketAddr;
+
+const IPV6_SOCK: &str = "[::1]:1808";
+
+#[test]
+fn ipv6_round_trip() -> Result<()> {
+    let sock: SocketAddr = IPV6_SOCK.parse()?;
+    ensure!(sock.is_tcp(), "{IPV6_SOCK} was parsed as {sock:?}");
+    ensure!(
+        sock.ip() == Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        "Unexpected ip for {sock:?}"
+    );
+
+    ensure!(sock.to_string() == IPV6_SOCK, "Display gave {sock}");
+    ensure!(
+        sock.to_display_string() == IPV6_SOCK,
+        "to_display_string gave {}",
+        sock.to_display_string()
+    );
+
+    let parsed: SocketAddr = sock.to_display_string().parse()?;
+    ensure!(parsed == sock, "Round trip gave {parsed:?}");
+    Ok(())
+}
+
+#[test]
+fn ipv6_from_new_tcp() -> Result<()> {
+    let sock = SocketAddr::new_tcp(IpAddr::V6(Ipv6Addr::LOCALHOST), 1808)?;
+    ensure!(
+        sock.to_display_string() == IPV6_SOCK,
+        "to_display_string gave {}",
+        sock.to_display_string()
+    );
+    Ok(())
+}
+
+#[test]
+fn privileged_port_rejected() -> Result<()> {
+    ensure!(
+        "192.168.1.5:80".parse::<SocketAddr>().is_err(),
+        "A privileged port was accepted"
+    );
+    ensure!(
+        SocketAddr::new_tcp(IpAddr::V6(Ipv6Addr::LOCALHOST), 443).is_err(),
+        "new_tcp accepted a privileged port"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn host_names_are_resolved() -> Result<()> {
+    let sock = SocketAddr::from_hostname("localhost", 1900).await?;
+    let addr = sock
+        .get_tcp()
+        .context("A host name should resolve to a TCP address")?;
+    ensure!(
+        addr.ip().is_loopback() && addr.port() == 1900,
+        "localhost resolved to {sock}"
+    );
+    Ok(())
+}
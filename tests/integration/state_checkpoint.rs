@@ -0,0 +1,32 @@
+use std::env::set_var;
+
+use anyhow::{Result, ensure};
+use dake::{
+    daemon::{DaemonConfig, State},
+    network::SocketAddr,
+    process_id::ProjectId,
+};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn ids_survive_a_restart() -> Result<()> {
+    let space_dir = tempdir()?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe { set_var("DAKE_SPACE_PATH", space_dir.path()) };
+
+    let project = ProjectId::test_local();
+    let state = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let first = state.get_fresh_id(project.clone()).await?;
+    let second = state.get_fresh_id(project.clone()).await?;
+    ensure!(second == first + 1, "Ids should follow each other");
+    drop(state);
+
+    // The new daemon continues from the checkpoint instead of starting over.
+    let restarted = State::with_config(SocketAddr::default(), DaemonConfig::default());
+    let third = restarted.get_fresh_id(project).await?;
+    ensure!(
+        third == second + 1,
+        "The restarted daemon gave {third} after {second}"
+    );
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result, ensure};
+use dake::{
+    dec,
+    network::{
+        DaemonMessage, Message, MessageKind, Stream, StreamTimeouts, TimedStream, hello,
+        read_next_message, write_message,
+    },
+    process_id::ProcessId,
+};
+use tokio::{
+    net::UnixStream,
+    time::{sleep, timeout},
+};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Returns a stream reading with [`READ_TIMEOUT`] and its peer.
+fn timed_pair() -> Result<(TimedStream, Stream)> {
+    let (reader, writer) = UnixStream::pair()?;
+    let timeouts = StreamTimeouts {
+        read: Some(READ_TIMEOUT),
+        write: None,
+    };
+    Ok((
+        TimedStream::new(Stream::from(reader), timeouts),
+        Stream::from(writer),
+    ))
+}
+
+#[tokio::test]
+async fn silent_peers_time_out() -> Result<()> {
+    let (mut reader, _writer) = timed_pair()?;
+    let res = read_next_message(&mut reader, MessageKind::DaemonMessage, None).await;
+    ensure!(
+        res.as_ref()
+            .is_err_and(|e| e.to_string().contains("timed out")),
+        "A read on a silent peer should time out, got {res:?}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn cancelled_reads_do_not_shorten_the_next_one() -> Result<()> {
+    let (mut reader, mut writer) = timed_pair()?;
+    let cancelled = timeout(
+        READ_TIMEOUT / 2,
+        read_next_message(&mut reader, MessageKind::DaemonMessage, None),
+    )
+    .await;
+    ensure!(cancelled.is_err(), "The first read should be cancelled");
+
+    // The read timeout of the cancelled read is over by the time the next one starts.
+    sleep(READ_TIMEOUT).await;
+    let send = async {
+        sleep(READ_TIMEOUT / 2).await;
+        write_message(&mut writer, Message::new(hello(), ProcessId::default())).await
+    };
+    let (msg, sent) = tokio::join!(
+        read_next_message(&mut reader, MessageKind::DaemonMessage, None),
+        send
+    );
+    sent?;
+    let msg = msg?.context("The stream was closed before the message.")?;
+    let msg: Message<DaemonMessage> = dec!(msg)?;
+    ensure!(
+        matches!(msg.inner, DaemonMessage::Hello { .. }),
+        "Expected the hello, got {msg:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::{env::set_var, fs::write, net::Ipv4Addr, path::Path, time::Duration};
+
+use anyhow::{Context, Result, ensure};
+use dake::network::{Stream, TlsConfig};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use tempfile::tempdir;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+/// Well above the handshake timeout of the daemons.
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes an authority and a certificate for 127.0.0.1 signed by it in `dir`, and
+/// points the TLS variables at them.
+fn generate_certificates(dir: &Path) -> Result<()> {
+    let ca_key = KeyPair::generate()?;
+    let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key)?;
+
+    let key = KeyPair::generate()?;
+    let cert = CertificateParams::new(vec![Ipv4Addr::LOCALHOST.to_string()])?
+        .signed_by(&key, &ca, &ca_key)?;
+
+    write(dir.join("ca.pem"), ca.pem())?;
+    write(dir.join("cert.pem"), cert.pem())?;
+    write(dir.join("key.pem"), key.serialize_pem())?;
+    // SAFETY: this test binary only runs this test, no other thread reads the environment yet.
+    unsafe {
+        set_var("DAKE_TLS_CA", dir.join("ca.pem"));
+        set_var("DAKE_TLS_CERT", dir.join("cert.pem"));
+        set_var("DAKE_TLS_KEY", dir.join("key.pem"));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn silent_peers_fail_the_tls_handshake() -> Result<()> {
+    let dir = tempdir()?;
+    generate_certificates(dir.path())?;
+    let tls = TlsConfig::get()?.context("TLS should be configured")?;
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let addr = listener.local_addr()?;
+
+    // Two hosts sharing the authority authenticate each other.
+    let client = async {
+        let stream = tls
+            .connect(addr.ip(), TcpStream::connect(addr).await?)
+            .await?;
+        let mut stream = Stream::from(stream);
+        stream.write_all(b"ping").await?;
+        stream.flush().await?;
+        anyhow::Ok(stream)
+    };
+    let server = async {
+        let (stream, _) = listener.accept().await?;
+        let mut stream = Stream::from(tls.accept(stream).await?);
+        let mut received = [0; 4];
+        stream.read_exact(&mut received).await?;
+        anyhow::Ok(received)
+    };
+    let (client, received) = tokio::join!(client, server);
+    let _client = client?;
+    ensure!(
+        &received? == b"ping",
+        "The message should cross the TLS stream"
+    );
+
+    // A peer that never starts the handshake is dropped.
+    let _silent = TcpStream::connect(addr).await?;
+    let (stream, _) = listener.accept().await?;
+    let res = timeout(TEST_TIMEOUT, tls.accept(stream))
+        .await
+        .context("The handshake of a silent peer should be bounded")?;
+    ensure!(
+        res.as_ref()
+            .is_err_and(|e| e.to_string().contains("timed out")),
+        "The handshake of a silent peer should time out, got {:?}",
+        res.map(|_| ())
+    );
+    Ok(())
+}
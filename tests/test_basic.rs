@@ -1,5 +1,5 @@
 use std::path::PathBuf;
-const MAKEFILE: &'static str = "
+const MAKEFILE: &str = "
 #!ROOT_DEF NODE-1 = /test_basic
 #!ROOT_DEF NODE-2 = /test_basic
 
@@ -19,7 +19,7 @@ c.o[NODE-2]: c.c
 	$(CC) -c c.c -o c.o
 ";
 
-const MAIN: &'static str = r#"
+const MAIN: &str = r#"
 #include <stdio.h>
 int a(void);
 int b(void);
@@ -29,9 +29,9 @@ int main() {
     return 0;
 }"#;
 
-const A: &'static str = "int a(void) { return 1; }\n";
-const B: &'static str = "int b(void) { return 2; }\n";
-const C: &'static str = "int c(void) { return 3; }\n";
+const A: &str = "int a(void) { return 1; }\n";
+const B: &str = "int b(void) { return 2; }\n";
+const C: &str = "int c(void) { return 3; }\n";
 
 pub fn test_basic_build() -> (Vec<(PathBuf, String)>, PathBuf, String) {
     (
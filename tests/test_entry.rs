@@ -4,12 +4,15 @@ use anyhow::Result;
 pub mod common;
 mod test_basic;
 mod test_fetch_chain;
+mod test_makeflags;
 mod test_redundant;
 
+#[allow(unused_imports)]
 use crate::{
     common::cluster::{Cluster, clean_cluster, setup_cluster},
     test_basic::test_basic_build,
     test_fetch_chain::test_fetch_chain_build,
+    test_makeflags::test_makeflags_build,
     test_redundant::test_redundant_build,
 };
 
@@ -17,14 +20,16 @@ async fn run(
     cluster: &Cluster,
     (files, work_path, expected): (Vec<(PathBuf, String)>, PathBuf, String),
     caller: usize,
+    env: &[(&str, &str)],
 ) -> Result<()> {
     cluster.push_files(files, &work_path).await?;
 
     cluster
-        .start_dake(
+        .start_dake_with_env(
             work_path.clone(),
             &cluster.nodes[caller],
             PathBuf::from(format!("caller_{caller}")),
+            env,
         )
         .await?;
 
@@ -46,9 +51,10 @@ async fn integration_suite() -> Result<()> {
     let cluster = setup_cluster().await?;
 
     let result = tokio::try_join!(
-        run(cluster, test_basic_build(), 0),
-        // run(cluster, test_fetch_chain_build(), 1),
-        // run(cluster, test_redundant_build(), 0),
+        run(cluster, test_basic_build(), 0, &[]),
+        run(cluster, test_makeflags_build(), 3, &[("MAKEFLAGS", "-j4")]),
+        // run(cluster, test_fetch_chain_build(), 1, &[]),
+        // run(cluster, test_redundant_build(), 0, &[]),
     );
 
     clean_cluster().await?;
@@ -1,6 +1,8 @@
+#![allow(dead_code)]
+
 use std::path::PathBuf;
 
-const MAKEFILE: &'static str = "
+const MAKEFILE: &str = "
 #!ROOT_DEF NODE-1 = /test_fetch_chain
 #!ROOT_DEF NODE-2 = /test_fetch_chain
 #!ROOT_DEF NODE-3 = /test_fetch_chain
@@ -19,7 +21,7 @@ b.o[NODE-2]: b.c c.o
 c.o[NODE-3]: c.c
 	$(CC) -c c.c -o c.o";
 
-const MAIN: &'static str = r#"
+const MAIN: &str = r#"
 #include <stdio.h>
 int a(void);
 int main() {
@@ -27,15 +29,15 @@ int main() {
     return 0;
 }"#;
 
-const A: &'static str = "
+const A: &str = "
 int b(void);
 int a(void) { return 1 + b(); }\n";
 
-const B: &'static str = "
+const B: &str = "
 int c(void);
 int b(void) { return 2 + c(); }\n";
 
-const C: &'static str = "int c(void) { return 3; }\n";
+const C: &str = "int c(void) { return 3; }\n";
 
 pub fn test_fetch_chain_build() -> (Vec<(PathBuf, String)>, PathBuf, String) {
     (
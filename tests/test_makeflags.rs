@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+// `jobs.o` is built by NODE-1, its value is the job count of the make running there.
+const MAKEFILE: &str = "
+#!ROOT_DEF NODE-1 = /test_makeflags
+
+main: main.c jobs.o
+	$(CC) -o main main.c jobs.o
+
+jobs.o[NODE-1]:
+	echo 'int jobs(void) { return $(patsubst -j%,%,$(filter -j%,$(MAKEFLAGS))); }' > jobs.c
+	$(CC) -c jobs.c -o jobs.o
+";
+
+const MAIN: &str = r#"
+#include <stdio.h>
+int jobs(void);
+int main() {
+    printf("jobs = %d\n", jobs());
+    return 0;
+}"#;
+
+pub fn test_makeflags_build() -> (Vec<(PathBuf, String)>, PathBuf, String) {
+    (
+        vec![
+            (PathBuf::from("Makefile"), MAKEFILE.to_string()),
+            (PathBuf::from("main.c"), MAIN.to_string()),
+        ],
+        PathBuf::from("/test_makeflags"),
+        "jobs = 4\n".to_string(),
+    )
+}
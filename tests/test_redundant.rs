@@ -1,6 +1,8 @@
+#![allow(dead_code)]
+
 use std::path::PathBuf;
 
-const MAKEFILE: &'static str = "
+const MAKEFILE: &str = "
 #!ROOT_DEF NODE-1 = /test_redundant
 #!ROOT_DEF NODE-2 = /test_redundant
 #!ROOT_DEF NODE-3 = /test_redundant
@@ -21,7 +23,7 @@ c.o[NODE-2]: c.c
 	$(CC) -c c.c -o c.o
 ";
 
-const MAIN: &'static str = r#"
+const MAIN: &str = r#"
 #include <stdio.h>
 int a(void);
 int b(void);
@@ -30,15 +32,15 @@ int main() {
     return 0;
 }"#;
 
-const A: &'static str = "
+const A: &str = "
 int c(void);
 int a(void) { return 1 + c(); }\n";
 
-const B: &'static str = "
+const B: &str = "
 int c(void);
 int b(void) { return 2 + c(); }\n";
 
-const C: &'static str = "
+const C: &str = "
 int c(void) { return 3; }\n";
 
 pub fn test_redundant_build() -> (Vec<(PathBuf, String)>, PathBuf, String) {